@@ -0,0 +1,29 @@
+//! Generates the C header from the exported functions into OUT_DIR, where
+//! tests/ffi.rs compares it with the committed include/tokenizer_ffi.h, so a
+//! signature change fails the tests until the header is updated, and a
+//! source cbindgen cannot read fails the build. Set UPDATE_FFI_HEADER=1 to
+//! write the generated header over the committed one.
+
+use std::path::Path;
+
+fn main() {
+    let crate_dir =
+        std::env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR is set by cargo");
+    let out_dir = std::env::var("OUT_DIR").expect("OUT_DIR is set by cargo");
+    let config = cbindgen::Config::from_file(Path::new(&crate_dir).join("cbindgen.toml"))
+        .expect("cbindgen.toml is readable");
+
+    let bindings = cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("the FFI surface is understood by cbindgen");
+    bindings.write_to_file(Path::new(&out_dir).join("tokenizer_ffi.h"));
+    if std::env::var_os("UPDATE_FFI_HEADER").is_some_and(|v| v == "1") {
+        bindings.write_to_file(Path::new(&crate_dir).join("include/tokenizer_ffi.h"));
+    }
+
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-env-changed=UPDATE_FFI_HEADER");
+}
@@ -0,0 +1,1123 @@
+#ifndef HF_TOKENIZER_FFI_H
+#define HF_TOKENIZER_FFI_H
+
+/* Generated by cbindgen from TokenizerRust/src; edit the Rust sources, not this file. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Null when no callback is set
+typedef void (*LogCallback)(int level, const char *message);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Initialize the tokenizer from a tokenizer.json file path
+// Returns 0 on success, negative on error
+// Can be called multiple times to reinitialize with a different tokenizer
+int tokenizer_initialize(const char *path);
+
+// Initialize the tokenizer from tokenizer.json contents held in memory
+// The buffer is fully parsed before returning, so the caller may free it
+// immediately afterwards. Reinitialization and `tokenizer_free` behave exactly
+// as with `tokenizer_initialize`.
+// Returns 0 on success, negative on error:
+//   -1 null or empty buffer, -3 parse failed
+int tokenizer_initialize_from_bytes(const uint8_t *data, uintptr_t len);
+
+// Encode text to token IDs with special tokens added
+// Only the first `max_len` IDs are copied and the copied count is returned,
+// so a result equal to `max_len` may mean the encoding was cut short; use
+// `tokenizer_encode_v2` to detect that. A `max_len` of 0 or above the limit
+// set by `tokenizer_set_length_limit` fails with -19, and an `out_ids` that
+// is misaligned or overlaps `text` with -20, before anything is written;
+// every export that encodes text into caller buffers (IDs, masks, type IDs,
+// word groups), `tokenizer_pretokenize` and the `encoding_copy_*` accessors
+// check them the same way.
+// Returns number of tokens on success, negative on error; an ID that does
+// not fit in a `c_int` returns -11 instead of wrapping (see `tokenizer_encode_u32`)
+int tokenizer_encode(const char *text, int *out_ids, uintptr_t max_len);
+
+// Same as `tokenizer_encode`, with `add_special_tokens` chosen by the caller
+// Pass 0 when encoding a fragment that will be concatenated with other
+// token sequences, so no BOS/CLS/SEP tokens leak into the middle of it
+int tokenizer_encode_opts(const char *text,
+                          int add_special_tokens,
+                          int *out_ids,
+                          uintptr_t max_len);
+
+// Encode without ever truncating: the full token count is always returned
+// If the count fits in `max_len` the IDs are written; otherwise nothing is
+// written, so a result greater than `max_len` means "call again with a buffer
+// this large". A null `out_ids` with `max_len` 0 is a size query.
+// Returns the token count, negative on error (as `tokenizer_encode_opts`,
+// plus -1 for a null `out_ids` with non-zero `max_len`)
+int tokenizer_encode_v2(const char *text, int add_special_tokens, int *out_ids, uintptr_t max_len);
+
+// Encode with special tokens added and report where each token came from
+// `out_starts[i]`/`out_ends[i]` receive the byte range of token `i` in `text`
+// (UTF-8 byte offsets, end exclusive), so multi-byte characters are counted
+// by their encoded length. The ranges index `text` as passed in, never its
+// normalized form: a lowercased or accent-stripped token spans the original
+// characters it came from, and the tokens of one expanded character (NFKC's
+// `ﬁ` as `f` + `i`) or several byte-fallback tokens of one character all
+// span that whole character; `tokenizer_encode_with_offsets_ex` reports
+// normalized-text ranges instead. Ranges never run backwards and come in text
+// order. Special tokens inserted by the post-processor have no source span
+// and report (-1, -1).
+// Returns number of tokens on success, negative on error (as `tokenizer_encode`)
+int tokenizer_encode_with_offsets(const char *text,
+                                  int *out_ids,
+                                  int *out_starts,
+                                  int *out_ends,
+                                  uintptr_t max_len);
+
+// `tokenizer_encode_with_offsets` with the ranges in UTF-16 code units
+// `out_starts[i]`/`out_ends[i]` index the UTF-16 form of `text`, as a C#
+// `string` does, so `text.Substring(start, end - start)` is the token's
+// source; characters outside the BMP count as two units. A range that starts
+// or ends inside a character covers the whole character, so the ranges stay
+// in bounds and never run backwards.
+// Returns number of tokens on success, negative on error (as `tokenizer_encode`)
+int tokenizer_encode_with_offsets_u16(const char *text,
+                                      int *out_ids,
+                                      int *out_starts,
+                                      int *out_ends,
+                                      uintptr_t max_len);
+
+// `tokenizer_encode_with_offsets` with the text the ranges index chosen
+// `referential` 0 reports UTF-8 byte ranges of `text` exactly as
+// `tokenizer_encode_with_offsets` does; 1 reports UTF-8 byte ranges of the
+// normalized text, as `tokenizer_normalize` returns it, so lowercased,
+// accent-stripped or NFKC-expanded pieces can be looked up where they stand
+// (NFKC turns `ﬁ` into `fi`, which may then be two tokens of one byte each).
+// Special tokens report (-1, -1) in both.
+// Returns number of tokens on success, negative on error (as
+// `tokenizer_encode`), or -9 for an unknown `referential`
+int tokenizer_encode_with_offsets_ex(const char *text,
+                                     int referential,
+                                     int *out_ids,
+                                     int *out_starts,
+                                     int *out_ends,
+                                     uintptr_t max_len);
+
+// Encode with special tokens added and report which word each token belongs to
+// Words are the pieces the tokenizer's own pre-tokenizer splits the text
+// into, numbered from 0, so punctuation usually forms words of its own
+// ("don't!" is `don`, `'`, `t`, `!` for BERT); a tokenizer without a
+// pre-tokenizer sees the whole text as word 0. Special tokens inserted by the
+// post-processor belong to no word and report -1. Combine with
+// `tokenizer_encode_with_offsets` or `tokenizer_pretokenize` for the byte
+// span of each word.
+// Returns number of tokens on success, negative on error (as `tokenizer_encode`)
+int tokenizer_encode_with_word_ids(const char *text,
+                                   int *out_ids,
+                                   int *out_word_ids,
+                                   uintptr_t max_len);
+
+// Group the tokens of `text` by word, for masking whole words together
+// Encodes with special tokens added, exactly as `tokenizer_encode`, and
+// writes for each word (as numbered by `tokenizer_encode_with_word_ids`) the
+// index of its first token into `out_group_starts` and its number of tokens
+// into `out_group_lens`, in text order. Special tokens, padding included,
+// belong to no group. Should a tokenizer ever spread one word over tokens
+// that are not consecutive, each consecutive run is a group of its own, so
+// `start..start + len` always covers the tokens of exactly one word. Only the
+// first `max_groups` groups are written; both outputs may be null when
+// `max_groups` is 0.
+// Returns the total number of groups, negative on error:
+//   -1 null argument, -2 invalid UTF-8, -3 not initialized, -4 encode failed,
+//   -19/-20 as for `tokenizer_encode`
+int tokenizer_word_groups(const char *text,
+                          int *out_group_starts,
+                          int *out_group_lens,
+                          uintptr_t max_groups);
+
+// Encode with special tokens added and return each token's piece string
+// Pieces come from the encoding as the model sees them (`▁Hello`, `Ġworld`,
+// `<0xE2>`, `<|im_start|>`), packed NUL-terminated one after another into
+// `out_tokens_buf`; `out_token_offsets[i]` receives the byte offset where
+// piece `i` starts. Only the first `max_tokens` tokens are written. A null
+// `out_tokens_buf` or zero `buf_capacity` is a size query: nothing is written
+// and the bytes needed for those pieces, NULs included, are returned.
+// Returns number of tokens (or the required size for a size query), negative
+// on error (as `tokenizer_encode`, plus -6 buffer too small)
+int tokenizer_encode_with_tokens(const char *text,
+                                 int *out_ids,
+                                 char *out_tokens_buf,
+                                 int *out_token_offsets,
+                                 uintptr_t buf_capacity,
+                                 uintptr_t max_tokens);
+
+// Encode a (query, passage) pair as one sequence for cross-encoders/rerankers
+// The post-processor adds the pair structure (e.g. `[CLS] a [SEP] b [SEP]`),
+// and `out_type_ids` (may be null) receives the segment ID of each token.
+// Truncation configured with `tokenizer_set_truncation` applies to the pair as
+// a whole, trimming the longer sequence first.
+// Returns number of tokens on success, negative on error (as `tokenizer_encode`)
+int tokenizer_encode_pair(const char *text_a,
+                          const char *text_b,
+                          int *out_ids,
+                          int *out_type_ids,
+                          uintptr_t max_len);
+
+// Same as `tokenizer_encode_pair`, with `add_special_tokens` chosen by the caller
+int tokenizer_encode_pair_opts(const char *text_a,
+                               const char *text_b,
+                               int add_special_tokens,
+                               int *out_ids,
+                               int *out_type_ids,
+                               uintptr_t max_len);
+
+// Count tokens without writing IDs anywhere
+// No `max_len` clamp applies, so the true count of long documents is returned,
+// and offsets are not tracked since only the length is needed
+// Returns the token count on success, negative on error (as `tokenizer_encode`)
+int tokenizer_count_tokens(const char *text, int add_special_tokens);
+
+// Encode text with special tokens added into unsigned 32-bit IDs
+// Same as `tokenizer_encode`, but IDs are written as the tokenizer's native
+// `u32`, so no ID can be out of range. C# callers pass a `uint[]`.
+// The count is returned as `i64` so it cannot overflow for huge documents.
+// Returns number of tokens on success, negative on error (as `tokenizer_encode`)
+int64_t tokenizer_encode_u32(const char *text, uint32_t *out_ids, uintptr_t max_len);
+
+// Encode many texts in one call with special tokens added
+// `out_ids` holds `count * max_len_per_item` slots; item `i` starts at
+// `i * max_len_per_item` and its token count is written to `out_lengths[i]`
+// (clamped to `max_len_per_item` like `tokenizer_encode`).
+// A null or non-UTF-8 item does not abort the batch: its length is set to
+// -1 or -2 respectively and the remaining items are still encoded. An item
+// whose IDs do not fit in a `c_int` gets -11.
+// Returns the number of items encoded successfully, negative on error:
+//   -1 null arguments, -3 not initialized, -4 encode failed,
+//   -19 `count * max_len_per_item` overflows or exceeds the length limit,
+//   -20 a misaligned output
+int tokenizer_encode_batch(const char *const *texts,
+                           uintptr_t count,
+                           int *out_ids,
+                           int *out_lengths,
+                           uintptr_t max_len_per_item);
+
+// Same as `tokenizer_encode_batch`, with `add_special_tokens` applied to every item
+int tokenizer_encode_batch_opts(const char *const *texts,
+                                uintptr_t count,
+                                int add_special_tokens,
+                                int *out_ids,
+                                int *out_lengths,
+                                uintptr_t max_len_per_item);
+
+// `tokenizer_encode_batch_opts` that also writes the attention mask
+// `out_attention_mask` uses the same `count * max_len_per_item` layout as
+// `out_ids`: 1 for real tokens (special tokens included), 0 for padding.
+// With `tokenizer_set_padding` enabled every valid item has the same length.
+int tokenizer_encode_batch_with_mask(const char *const *texts,
+                                     uintptr_t count,
+                                     int add_special_tokens,
+                                     int *out_ids,
+                                     int *out_attention_mask,
+                                     int *out_lengths,
+                                     uintptr_t max_len_per_item);
+
+// Decode token IDs back into text, skipping special tokens (BOS/EOS/PAD...)
+// Writes a NUL-terminated UTF-8 string into `out_text`
+// Returns bytes written (excluding NUL) on success, negative on error:
+//   -1 null `ids` with non-zero `len`, -3 not initialized, -4 decode failed
+//   (including negative IDs), -6 buffer too small
+// Pass a null `out_text` or zero `out_capacity` to get the required size
+int tokenizer_decode(const int *ids, uintptr_t len, char *out_text, uintptr_t out_capacity);
+
+// Same as `tokenizer_decode`, with `skip_special_tokens` chosen by the caller
+// (non-zero strips special tokens, zero keeps them for debugging)
+int tokenizer_decode_ex(const int *ids,
+                        uintptr_t len,
+                        int skip_special_tokens,
+                        char *out_text,
+                        uintptr_t out_capacity);
+
+// `tokenizer_decode_ex` with an extra `clean_up_tokenization_spaces` pass
+// `skip_special_tokens` strips every token the tokenizer marks as special,
+// added tokens such as `<|im_end|>` included. Non-zero `cleanup` removes the
+// spaces tokenization leaves before punctuation and English contractions
+// ("hello , world" becomes "hello, world"), like `transformers` does. Zero
+// leaves the decoder's output as is; decoders that clean up on their own,
+// like BERT's WordPiece, still do.
+int tokenizer_decode_opts(const int *ids,
+                          uintptr_t len,
+                          int skip_special_tokens,
+                          int cleanup,
+                          char *out_text,
+                          uintptr_t out_capacity);
+
+// `tokenizer_decode_ex` for the unsigned IDs produced by `tokenizer_encode_u32`
+int tokenizer_decode_u32(const uint32_t *ids,
+                         uintptr_t len,
+                         int skip_special_tokens,
+                         char *out_text,
+                         uintptr_t out_capacity);
+
+// Free the tokenizer and allow reinitialization
+// An async load still in flight is discarded when it finishes.
+void tokenizer_free(void);
+
+// Start loading a tokenizer.json on a background thread and return at once
+// On success the new tokenizer replaces the current one, exactly as
+// `tokenizer_initialize` would; on failure the current one stays. Calling
+// this again while a load is in flight supersedes that load, which is then
+// discarded. Poll `tokenizer_init_status` for the outcome.
+// Returns 0 once loading has started, negative on error:
+//   -1 null path, -2 invalid UTF-8, -3 the loader thread could not start
+int tokenizer_initialize_async(const char *path);
+
+// Report the state of the global tokenizer
+// Returns 0 when a tokenizer is ready, 1 while an async load is in flight,
+// or the failing load's negative code, whose message is then available from
+// `tokenizer_last_error` on the calling thread:
+//   -1/-2/-3 as from `tokenizer_initialize`, -3 also when nothing is loaded
+int tokenizer_init_status(void);
+
+// Choose what tokenizer calls do while an async load is in flight
+// With `wait` (the default) they block until the load finishes and then use
+// its result; without it they return `ERR_STILL_LOADING` (-15) immediately.
+void tokenizer_set_wait_for_load(bool wait);
+
+// Decode token IDs to the raw bytes they stand for, skipping special tokens
+// Unlike `tokenizer_decode`, bytes of a character split across tokens are
+// kept as they are instead of becoming U+FFFD, so the output may end in (or,
+// for malformed ID sequences, contain) incomplete UTF-8. Nothing is
+// NUL-terminated. A null `out_bytes` or zero `capacity` is a size query.
+// Returns the byte count (the required size for a size query), negative on error:
+//   -1 null `ids` with non-zero `len`, -3 not initialized, -4 decode failed
+//   (including negative IDs), -6 buffer too small
+int tokenizer_decode_bytes(const int *ids,
+                           uintptr_t len,
+                           unsigned char *out_bytes,
+                           uintptr_t capacity);
+
+// Enable caching of encode results for up to `n_entries` texts, or disable
+// and free the cache with 0
+// Shrinking evicts the least recently used entries; the hit and miss
+// counters restart from zero.
+void tokenizer_set_cache_capacity(uintptr_t n_entries);
+
+// Bound the memory held by the cache, counting cached text and IDs
+// 0 removes the bound (entries are then limited by count only); the default
+// is 64 MiB. Texts too large for the bound are never cached.
+void tokenizer_set_cache_max_bytes(uintptr_t max_bytes);
+
+// Report cache hits and misses since the capacity was last set
+// Returns the number of cached entries, negative on error:
+//   -1 null output pointer
+int tokenizer_cache_stats(uint64_t *out_hits, uint64_t *out_misses);
+
+// Load the `chat_template` of a tokenizer_config.json, replacing any previous one
+// `bos_token`/`eos_token` declared next to it are used when rendering;
+// otherwise they are looked up in the loaded tokenizer at render time.
+// Returns 0 on success, negative on error:
+//   -1 null path, -2 invalid UTF-8, -3 file unreadable or without a chat
+//   template, -13 the template does not compile, -18 not a JSON object
+int tokenizer_load_chat_template(const char *path_to_config);
+
+// Format a conversation with the loaded chat template
+// `roles[i]`/`contents[i]` describe message `i` ("system", "user",
+// "assistant", ...). With `add_generation_prompt` non-zero the template's
+// assistant header is appended so the model continues as the assistant.
+// Writes the prompt with the `tokenizer_decode` buffer contract.
+// Returns bytes written (excluding NUL) or the required size, negative on error:
+//   -1 null arguments, -2 invalid UTF-8, -6 buffer too small,
+//   -12 no chat template loaded, -13 the template failed to render
+int tokenizer_apply_chat_template(const char *const *roles,
+                                  const char *const *contents,
+                                  uintptr_t count,
+                                  int add_generation_prompt,
+                                  char *out_text,
+                                  uintptr_t capacity);
+
+// `tokenizer_apply_chat_template` straight to token IDs
+// The template already places BOS and other markers, so the prompt is encoded
+// without the post-processor's special tokens.
+// Returns number of tokens on success, negative on error (as
+// `tokenizer_apply_chat_template`, plus `tokenizer_encode` errors)
+int tokenizer_apply_chat_template_ids(const char *const *roles,
+                                      const char *const *contents,
+                                      uintptr_t count,
+                                      int add_generation_prompt,
+                                      int *out_ids,
+                                      uintptr_t max_len);
+
+// Exact token count of a conversation as `tokenizer_apply_chat_template_ids`
+// would encode it, template overhead (role headers, BOS, end markers)
+// included, without a maximum length
+// Returns the token count on success, negative on error (as
+// `tokenizer_apply_chat_template_ids`)
+int tokenizer_count_chat_tokens(const char *const *roles,
+                                const char *const *contents,
+                                uintptr_t count,
+                                int add_generation_prompt);
+
+// `tokenizer_count_chat_tokens`, also splitting the count across messages
+// `out_counts[i]` receives how many tokens message `i` adds to the prompt
+// rendered from the messages before it (its content plus its own template
+// overhead). Whatever the template emits around the messages, such as a
+// preamble or the generation prompt, belongs to no message, so the counts
+// sum to at most the returned total. Every prefix of the conversation is
+// rendered, so this costs more than a single count; confirm the total of a
+// trimmed conversation with `tokenizer_count_chat_tokens`, since a template
+// may format the first remaining message differently.
+// Returns the total token count on success, negative on error (as
+// `tokenizer_count_chat_tokens`)
+int tokenizer_count_chat_tokens_per_message(const char *const *roles,
+                                            const char *const *contents,
+                                            uintptr_t count,
+                                            int add_generation_prompt,
+                                            int *out_counts);
+
+// Keep the most recent messages that fit in `max_tokens`
+// Finds the longest suffix of the conversation whose prompt, counted as by
+// `tokenizer_count_chat_tokens` with the generation prompt added, is at most
+// `max_tokens` tokens, and writes 1 (kept) or 0 (dropped) into
+// `out_keep_flags[i]` for each message. With `keep_system` non-zero a
+// leading system message is always kept in front of the suffix. A
+// conversation that fits whole comes back unchanged; otherwise the suffix
+// starts at a user message when there is one to start at, since many
+// templates reject a conversation that opens with an assistant turn. Only
+// about log2(`count`) prompts are rendered, which relies on a longer
+// conversation never rendering to fewer tokens.
+// Returns the token count of the kept conversation, negative on error (as
+// `tokenizer_count_chat_tokens`), or -21 when not even the system message
+// (if kept) and the latest user message fit, in which case the flags are
+// left unwritten
+int tokenizer_fit_chat(const char *const *roles,
+                       const char *const *contents,
+                       uintptr_t count,
+                       uintptr_t max_tokens,
+                       int keep_system,
+                       int *out_keep_flags);
+
+// Cut `text` into windows of at most `max_tokens` tokens, special tokens included
+// Consecutive windows share `stride` tokens, and every window carries the
+// special tokens the post-processor adds (e.g. `[CLS] ... [SEP]`), so each
+// one can be fed to the model as is. Truncation and padding configured with
+// `tokenizer_set_truncation`/`tokenizer_set_padding` are ignored here.
+// Window IDs are packed back to back into `out_ids`, which must hold
+// `max_chunks * max_tokens` slots; `out_chunk_lengths[i]` receives the length
+// of window `i`. `out_chunk_offsets` (may be null) receives the UTF-8 byte
+// offset in `text` where each window's first token starts.
+// Only the first `max_chunks` windows are written.
+// Returns the total number of windows, negative on error:
+//   -1 null arguments, -2 invalid UTF-8, -3 not initialized, -4 encode failed,
+//   -9 `max_tokens` leaves no room for text or `stride` is
+//   not smaller than that room, -19 `max_chunks * max_tokens` overflows or
+//   exceeds the length limit, -20 `out_ids` is misaligned or overlaps `text`
+int tokenizer_encode_chunked(const char *text,
+                             uintptr_t max_tokens,
+                             uintptr_t stride,
+                             int *out_ids,
+                             int *out_chunk_lengths,
+                             uintptr_t max_chunks,
+                             int *out_chunk_offsets);
+
+// `tokenizer_encode_chunked` with the windows laid out from either end
+// `direction` 0 starts the first window at the start of the text, so only
+// the last window may be short; 1 ends the last window at the end of the
+// text, so that one is always full and the first may be short. Windows are
+// written in text order either way.
+// Returns the total number of windows, negative on error (as
+// `tokenizer_encode_chunked`, with -9 also for an invalid direction)
+int tokenizer_encode_chunked_ex(const char *text,
+                                uintptr_t max_tokens,
+                                uintptr_t stride,
+                                int direction,
+                                int *out_ids,
+                                int *out_chunk_lengths,
+                                uintptr_t max_chunks,
+                                int *out_chunk_offsets);
+
+// Split `text` into overlapping pieces of at most `max_tokens` tokens and
+// report their byte ranges, for callers that re-encode each piece themselves
+// Tokens are counted without the post-processor's special tokens. Each piece
+// after the first starts `overlap_tokens` tokens before the previous one
+// ends. A piece ends early, by up to a quarter of `max_tokens`, if that lets
+// it end before a word instead of inside one, and never between the
+// byte-fallback tokens of one character: should those not fit in the
+// quarter, the piece grows past `max_tokens` to the character's last token
+// instead. `out_starts[i]`/`out_ends[i]`
+// receive piece `i` as the UTF-8 byte range `[start, end)` of `text`, always
+// on character boundaries and without surrounding whitespace. A text that
+// fits in `max_tokens` is one piece, and a text without tokens has none.
+// Only the first `max_chunks` ranges are written.
+// Returns the total number of pieces, negative on error:
+//   -1 null arguments, -2 invalid UTF-8, -3 not initialized, -4 encode failed,
+//   -9 `max_tokens` is 0 or `overlap_tokens` is not smaller than it
+int tokenizer_chunk_text(const char *text,
+                         uintptr_t max_tokens,
+                         uintptr_t overlap_tokens,
+                         int *out_starts,
+                         int *out_ends,
+                         uintptr_t max_chunks);
+
+// Cut `text` to at most `max_tokens` tokens and return the kept substring
+// Only the text's own tokens count: the budget excludes the special tokens
+// the post-processor would add, and configured truncation and padding are
+// ignored. `direction` 0 keeps the start of the text, 1 keeps the end. The cut
+// falls on token boundaries taken from the encoding's offsets, never inside a
+// character, and whitespace at the cut is dropped; a character spread over
+// several byte-fallback tokens is kept only if all of them fit. A text that
+// already fits is returned unchanged. `out_token_count` (may be null)
+// receives the token count of the kept text, which is at most `max_tokens`.
+// Writes the text with the `tokenizer_decode` buffer contract.
+// Returns bytes written (excluding NUL) or the required size, negative on error:
+//   -1 null `text`, -2 invalid UTF-8, -3 not initialized, -4 encode failed,
+//   -6 buffer too small, -9 invalid direction
+int tokenizer_truncate_text(const char *text,
+                            uintptr_t max_tokens,
+                            int direction,
+                            char *out_text,
+                            uintptr_t capacity,
+                            int *out_token_count);
+
+// Compare the tokenizer.json files at `path_a` and `path_b` on sample texts
+// and write the findings into `out_report_json` as a JSON object
+// Both files are loaded only for this call, so the tokenizer installed with
+// `tokenizer_initialize*` stays as it is. Each of the `sample_count` texts is
+// encoded with special tokens added by both. Fields: `a` and `b` as
+// `{"path", "model_type", "vocab_size", "vocab_size_with_added"}`;
+// `special_tokens` with `identical`, `only_in_a`/`only_in_b` as
+// `{"id", "content"}` sorted by content and `changed_ids` as
+// `{"content", "id_a", "id_b"}`; `samples` as `{"tokens_a", "tokens_b",
+// "identical_ids"}` per sample, in order; and `all_samples_identical`.
+// Writes the JSON with the `tokenizer_decode` buffer contract.
+// Returns bytes written (excluding NUL) or the required size, negative on error:
+//   -1 null argument, -2 invalid UTF-8, -3 a file failed to load (the error
+//   message names `path_a` or `path_b`), -4 encode failed, -6 buffer too small
+int tokenizer_compare(const char *path_a,
+                      const char *path_b,
+                      const char *const *sample_texts,
+                      uintptr_t sample_count,
+                      char *out_report_json,
+                      uintptr_t capacity);
+
+// Load a tokenizer_config.json, replacing any previously loaded one
+// `model_max_length` becomes available through `tokenizer_model_max_length`,
+// `bos_token`/`eos_token` take precedence over the tokenizer's own when BOS
+// and EOS are looked up, `padding_side` is what `tokenizer_set_padding` uses
+// for direction -1, and a `chat_template` is loaded as by
+// `tokenizer_load_chat_template` (a config without one keeps the current
+// template). Loading another tokenizer or freeing this one drops all of it.
+// Returns 0 on success, negative on error:
+//   -1 null path, -2 invalid UTF-8, -3 file unreadable, -13 the chat template
+//   does not compile, -18 not a JSON object
+int tokenizer_load_config(const char *path);
+
+// `model_max_length` from the loaded tokenizer_config.json
+// Returns the length, or -1 when no config is loaded, it does not declare
+// one, or it declares the "unspecified" sentinel `transformers` writes (1e30)
+int tokenizer_model_max_length(void);
+
+// Encode `text` and keep the result behind a new encoding handle
+// Nothing is copied out until an `encoding_*` accessor asks for it; release
+// the handle with `encoding_free`.
+// Returns a positive handle on success, negative on error:
+//   -1 null `text`, -2 invalid UTF-8, -3 not initialized, -4 encode failed
+int64_t tokenizer_encode_new(const char *text, int add_special_tokens);
+
+// Number of tokens in an encoding, the length every `encoding_copy_*` array needs
+// Returns the length, -7 unknown or freed handle
+int encoding_len(int64_t handle);
+
+// Copy the token IDs of an encoding, at most `max_len` of them
+// `out_ids` is checked like the buffer of `tokenizer_encode` unless `max_len` is 0.
+// Returns the number copied, negative on error:
+//   -1 null `out_ids` with non-zero `max_len`, -7 unknown or freed handle,
+//   -11 ID out of range, -19/-20 as for `tokenizer_encode`
+int encoding_copy_ids(int64_t handle, int *out_ids, uintptr_t max_len);
+
+// Copy the byte range of each token in the encoded text, at most `max_len`
+// Ranges are as from `tokenizer_encode_with_offsets`: UTF-8 byte offsets,
+// end exclusive, and (-1, -1) for special tokens added by the post-processor.
+// Both outputs are checked as in `encoding_copy_ids`.
+// Returns the number copied, negative on error:
+//   -1 null output with non-zero `max_len`, -7 unknown or freed handle,
+//   -19/-20 as for `tokenizer_encode`
+int encoding_copy_offsets(int64_t handle, int *out_starts, int *out_ends, uintptr_t max_len);
+
+// Copy the word index of each token, at most `max_len`, as from
+// `tokenizer_encode_with_word_ids` (-1 for special tokens)
+// Returns the number copied, negative on error:
+//   -1 null `out_word_ids` with non-zero `max_len`, -7 unknown or freed handle,
+//   -19/-20 as for `encoding_copy_ids`
+int encoding_copy_word_ids(int64_t handle, int *out_word_ids, uintptr_t max_len);
+
+// Copy the type (segment) ID of each token, at most `max_len`: 0 for the
+// first sequence, 1 for the second of a pair
+// Returns the number copied, negative on error:
+//   -1 null `out_type_ids` with non-zero `max_len`, -7 unknown or freed handle,
+//   -19/-20 as for `encoding_copy_ids`
+int encoding_copy_type_ids(int64_t handle, int *out_type_ids, uintptr_t max_len);
+
+// Release an encoding handle
+// Returns 0 on success, -7 unknown or already freed
+int encoding_free(int64_t handle);
+
+// Copy the calling thread's last error message into `out_buf`
+// Messages are per thread: a failure on one thread never shows up on another.
+// Successful calls do not clear the message.
+// Returns the message length (0 if nothing failed yet), the required size for
+// a null `out_buf` or zero `capacity`, or -6 if the buffer is too small.
+// This function never overwrites the stored message itself.
+int tokenizer_last_error(char *out_buf, uintptr_t capacity);
+
+// Estimate how many tokens `text` encodes to, without encoding it
+// This is an approximation of `tokenizer_count_tokens(text, 0)` for live
+// feedback (an editor counter, a file list), not a budget check: for
+// natural-language text it aims to be within 10% of the exact count, but
+// unusual input (long digit runs, repeated characters, rare scripts) can be
+// further off. Use `tokenizer_count_tokens` before sending anything that must
+// fit. Cost is one pass over the bytes, so megabytes take well under a
+// millisecond; the first call after a tokenizer loads also calibrates it,
+// which costs two encodes of about a kilobyte. Invalid UTF-8 is tolerated
+// and never rejected.
+// Returns the estimate, negative on error:
+//   -1 null `text`, -3 not initialized, -4 calibration failed
+int tokenizer_estimate_tokens(const char *text);
+
+// Initialize the global tokenizer from the vocabulary embedded in a GGUF model
+// Supports the "llama" (Llama 2, Mistral), "gpt2" (Llama 3 and other
+// byte-level BPE) and "t5" tokenizer models. BOS/EOS are added as the file's
+// `add_bos_token`/`add_eos_token` flags say, and control tokens become special
+// tokens. Reinitialization and `tokenizer_free` behave as with
+// `tokenizer_initialize`.
+// Returns 0 on success, negative on error:
+//   -1 null path, -2 invalid UTF-8, -3 unreadable, not a GGUF file, or an
+//   unsupported tokenizer model
+int tokenizer_initialize_from_gguf(const char *path);
+
+// Log every call rejected by the buffer checks with its argument values
+// The checks themselves always run; strict mode only adds a warn-level
+// record per rejection naming the export, the buffer pointer, its length and
+// the input pointer, for tracking down the binding that passed them.
+// Returns 0
+int tokenizer_set_strict_mode(int enabled);
+
+// Set the largest output length, in elements, a call accepts
+// Longer lengths fail with -19 before anything is written, since they
+// almost always come from a marshaling bug. The default is 16M (16777216).
+// Returns 0 on success, -9 for a zero limit
+int tokenizer_set_length_limit(uintptr_t limit);
+
+// Load a tokenizer.json into a new instance independent of the global one
+// Returns a positive handle on success, negative on error:
+//   -1 null path, -2 invalid UTF-8, -3 load failed
+// Handles are never reused, so a destroyed handle stays invalid
+int64_t tokenizer_create(const char *path);
+
+// `tokenizer_encode` for a handle created by `tokenizer_create`
+// Returns number of tokens on success, negative on error:
+//   -1 null pointer, -2 invalid UTF-8, -4 encode failed,
+//   -7 unknown or destroyed handle
+int tokenizer_encode_h(int64_t handle, const char *text, int *out_ids, uintptr_t max_len);
+
+// `tokenizer_decode_ex` for a handle created by `tokenizer_create`
+// Returns bytes written (excluding NUL) or the required size for a null
+// `out_text`, negative on error (as `tokenizer_decode`, plus -7 bad handle)
+int tokenizer_decode_h(int64_t handle,
+                       const int *ids,
+                       uintptr_t len,
+                       int skip_special_tokens,
+                       char *out_text,
+                       uintptr_t out_capacity);
+
+// Release a handle; calls already using it finish on their own reference
+// Returns 0 on success, -7 unknown or already destroyed
+int tokenizer_destroy(int64_t handle);
+
+// Encode a prompt for token healing, with special tokens added
+// If the text ends in a letter or digit, its last token is removed (with the
+// byte-fallback tokens of the same character, should it be split), and the
+// removed pieces are written to `out_suffix` as they read inside a text, so
+// "hello wor" gives " wor"; this may start with a space the text itself does
+// not have, where the tokenizer adds one before the first word. Special
+// tokens the post-processor appends stay in place. Text ending in
+// whitespace or punctuation loses nothing and the suffix is empty. A suffix
+// buffer of the text's length plus 8 bytes always suffices. Only the first
+// `max_len` remaining IDs are copied, as with `tokenizer_encode`.
+// Returns number of tokens copied, negative on error:
+//   -1 null pointer, -2 invalid UTF-8, -3 not initialized, -4 encode failed,
+//   -6 suffix buffer too small (nothing is written), -11 ID out of range
+int tokenizer_prepare_healing(const char *text,
+                              int *out_ids,
+                              uintptr_t max_len,
+                              char *out_suffix,
+                              uintptr_t suffix_capacity);
+
+// IDs of the tokens that can follow a prompt shortened by
+// `tokenizer_prepare_healing`: every non-special token whose piece, read as
+// text, starts with `suffix` (" wor" matches `▁wor` and `▁world`, and `Ġwor`
+// for byte-level tokenizers). IDs are written in ascending order, at most
+// `max_out` of them.
+// Returns the total number of candidates, 0 when there are none (including
+// for an empty suffix), in which case sampling should be left unconstrained;
+// negative on error:
+//   -1 null argument, -2 invalid UTF-8, -3 not initialized, -4 a piece could
+//   not be decoded
+int tokenizer_healing_candidates(const char *suffix, int *out_ids, uintptr_t max_out);
+
+// Describe the loaded tokenizer as a JSON object
+// Fields: `loaded`, `source` (the path it was loaded from, or "from_bytes"),
+// `model_type` ("BPE", "WordPiece", "WordLevel" or "Unigram"), `vocab_size`
+// and `vocab_size_with_added`, `truncation` and `padding` as currently set
+// (null when off; a null padding `length` pads to the longest in the batch)
+// and `special_tokens` as `{"id", "content"}` sorted by ID. Without a
+// tokenizer (or while one is still loading) the object is `{"loaded":false}`.
+// Writes the JSON with the `tokenizer_decode` buffer contract.
+// Returns bytes written (excluding NUL) or the required size, -6 buffer too small
+int tokenizer_get_info(char *out_json, uintptr_t capacity);
+
+// The version of this library, e.g. "0.1.0"
+// Writes the text with the `tokenizer_decode` buffer contract.
+// Returns bytes written (excluding NUL) or the required size, -6 buffer too small
+int tokenizer_library_version(char *out, uintptr_t capacity);
+
+// Run only the loaded tokenizer's normalizer over `text`
+// Input without a normalizer is echoed unchanged. The result may be longer than
+// the input (e.g. Llama's `▁` is three bytes), so size it with a query first.
+// Writes the text with the `tokenizer_decode` buffer contract.
+// Returns bytes written (excluding NUL) or the required size, negative on error:
+//   -1 null `text`, -2 invalid UTF-8, -3 not initialized, -4 normalizer failed,
+//   -6 buffer too small
+int tokenizer_normalize(const char *text, char *out, uintptr_t capacity);
+
+// Split `text` the way the loaded pre-tokenizer does, before the model runs
+// The normalizer runs first, as in a real encode, but each piece is reported
+// as the UTF-8 byte range `[out_starts[i], out_ends[i])` of the original
+// `text`, so byte-level and `▁` rewrites never leak into the offsets.
+// Without a pre-tokenizer the whole input is one piece. Only the first
+// `max_items` pieces are written; unless `max_items` is 0 both outputs are
+// checked like the buffer of `tokenizer_encode`.
+// Returns the total number of pieces, negative on error:
+//   -1 null arguments, -2 invalid UTF-8, -3 not initialized,
+//   -4 normalizer or pre-tokenizer failed, -19/-20 as for `tokenizer_encode`
+int tokenizer_pretokenize(const char *text, int *out_starts, int *out_ends, uintptr_t max_items);
+
+// Route library diagnostics to `callback`, or stop logging with a null one
+// `min_level` is the least severe level delivered: 0 debug, 1 info (loads,
+// with source, vocab size and elapsed time), 2 warn (repairs such as lossy
+// UTF-8), 3 error (every failure recorded for `tokenizer_last_error`). The
+// callback may be called on any thread, including the background loader,
+// and concurrently from several threads. `message` is NUL-terminated UTF-8
+// that is only valid during the call, so copy it before returning. The
+// callback must not call back into the library; what the library would log
+// meanwhile on that thread is dropped.
+// Returns 0 on success, -9 for a level outside 0..=3
+int tokenizer_set_log_callback(LogCallback callback, int min_level);
+
+// Encode `text` with special tokens into `seq_len` slots of each output,
+// ready to be wrapped as ONNX Runtime tensors
+// Longer text is truncated (special tokens are kept), shorter text padded
+// with the tokenizer's pad token on the side `tokenizer_set_padding` or
+// tokenizer_config.json chose, the right by default. `out_mask` is 1 for
+// real tokens, special tokens included, and 0 for padding; `out_type_ids`
+// (may be null, for models without that input) holds the post-processor's
+// segment IDs. All three arrays must hold `seq_len` values.
+// Returns the number of real tokens, negative on error:
+//   -1 null argument, -2 invalid UTF-8, -3 not initialized, -4 encode failed,
+//   -9 `seq_len` leaves no room for text, -10 the tokenizer has no pad token,
+//   -19/-20 an output fails the checks of `tokenizer_encode`
+int tokenizer_encode_for_model(const char *text,
+                               uintptr_t seq_len,
+                               int64_t *out_ids,
+                               int64_t *out_mask,
+                               int64_t *out_type_ids);
+
+// `tokenizer_encode_for_model` for a (query, passage) pair
+// The post-processor lays out both segments (e.g. `[CLS] a [SEP] b [SEP]`,
+// with type ID 1 for the second), and truncation trims the longer segment
+// first unless configured otherwise.
+// Returns the number of real tokens, negative on error (as
+// `tokenizer_encode_for_model`)
+int tokenizer_encode_pair_for_model(const char *text_a,
+                                    const char *text_b,
+                                    uintptr_t seq_len,
+                                    int64_t *out_ids,
+                                    int64_t *out_mask,
+                                    int64_t *out_type_ids);
+
+// Load a tokenizer.json and register it under `name`
+// An existing registration with the same name is replaced atomically; calls
+// already running on the old tokenizer complete normally. If loading fails the
+// old registration is kept. Names must be non-empty and free of newlines.
+// Returns 0 on success, negative on error:
+//   -1 null argument, -2 invalid UTF-8, -3 load failed, -9 invalid name
+int tokenizer_register(const char *name, const char *path);
+
+// Remove the tokenizer registered under `name`
+// Calls already using it finish on their own reference.
+// Returns 0 on success, negative on error:
+//   -1 null name, -2 invalid UTF-8, -7 nothing registered under `name`
+int tokenizer_unregister(const char *name);
+
+// `tokenizer_encode_opts` for the tokenizer registered under `name`
+// Returns number of tokens on success, negative on error:
+//   -1 null pointer, -2 invalid UTF-8, -4 encode failed,
+//   -7 nothing registered under `name`, -11 ID out of `c_int` range
+int tokenizer_encode_named(const char *name,
+                           const char *text,
+                           int add_special_tokens,
+                           int *out_ids,
+                           uintptr_t max_len);
+
+// `tokenizer_count_tokens` for the tokenizer registered under `name`
+// Returns the token count on success, negative on error
+// (as `tokenizer_encode_named`)
+int tokenizer_count_tokens_named(const char *name, const char *text, int add_special_tokens);
+
+// `tokenizer_decode_ex` for the tokenizer registered under `name`
+// Returns bytes written (excluding NUL) or the required size for a null
+// `out_text`, negative on error (as `tokenizer_decode`, plus -7 unknown name)
+int tokenizer_decode_named(const char *name,
+                           const int *ids,
+                           uintptr_t len,
+                           int skip_special_tokens,
+                           char *out_text,
+                           uintptr_t out_capacity);
+
+// Write the registered names, sorted and separated by `\n`, into `out`
+// Returns bytes written (excluding NUL), the required size for a null `out`
+// or zero `capacity`, or -6 if the buffer is too small; 0 when nothing is
+// registered
+int tokenizer_registered_names(char *out, uintptr_t capacity);
+
+// Number of leading token IDs the encodings of `text_a` and `text_b` share,
+// with `add_special_tokens` as for `tokenizer_encode_opts`
+// When the two tokenizations part ways with tokens left on both sides (not
+// just one being a prefix of the other), the result is one less than the
+// shared run: the last shared token sits at the point where merges start to
+// differ ("Hello wor" vs "Hello world" end in `▁wor` and `▁world`), and
+// re-running it costs one token of prefill while keeping the reuse safe for
+// tokenizers whose merges reach back across that point. If one encoding is a
+// prefix of the other, its full length is returned.
+// Returns the prefix length, negative on error:
+//   -1 null text, -2 invalid UTF-8, -3 not initialized, -4 encode failed
+int tokenizer_common_prefix_len(const char *text_a, const char *text_b, int add_special_tokens);
+
+// Number of leading IDs two token sequences share
+// Plain comparison with no back-off, since the IDs are already fixed.
+// Returns the prefix length, -1 for a null array with non-zero length
+int tokenizer_ids_common_prefix_len(const int *ids_a,
+                                    uintptr_t len_a,
+                                    const int *ids_b,
+                                    uintptr_t len_b);
+
+// Whether `ids` decode to a prefix of (or exactly) `target_text`
+// The IDs are decoded to raw bytes as by `tokenizer_decode_bytes`, but with
+// special tokens kept and no cleanup, so `<s>` must be in the target if it
+// is in the IDs, and the first token loses its marker space as it does in
+// `tokenizer_decode` (Metaspace) or keeps it (byte-level). A decode that ends
+// partway through a character of the target still matches. The number of
+// target characters (Unicode scalar values, not UTF-16 units) covered by the
+// matching bytes, a character cut short not included, is written to
+// `out_chars_matched` when it is not null: for a mismatch this is where the
+// texts diverge.
+// Returns 1 for a prefix match, 0 for a mismatch, negative on error:
+//   -1 null argument, -2 invalid UTF-8, -3 not initialized, -4 decode failed
+//   (including negative IDs)
+int tokenizer_match_prefix(const int *ids,
+                           uintptr_t len,
+                           const char *target_text,
+                           int *out_chars_matched);
+
+// Write token `id` rendered for display into `out` as NUL-terminated UTF-8
+// `mode` 0 gives the raw piece (as `tokenizer_id_to_token`, markers such as
+// `▁`, `Ġ`, `<0x0A>` intact); 1 what `tokenizer_decode_ex` gives for this one
+// ID with special tokens kept, through the tokenizer's decoder, so
+// byte-fallback and byte-level pieces become the bytes they stand for; 2 the
+// piece as it reads inside a text (a marker space stays a space) with spaces,
+// newlines and tabs shown as the symbols set by `tokenizer_set_render_symbols`
+// and other control characters as Unicode control pictures. In modes 1 and 2
+// bytes that are not valid UTF-8 on their own, such as half of a character,
+// appear as `<0xNN>`. A null `out` or zero `capacity` is a size query.
+// Returns bytes written (excluding NUL) or the required size, negative on error:
+//   -3 not initialized, -4 decode failed, -6 buffer too small,
+//   -9 unknown mode, or `id` is negative or outside the vocabulary
+int tokenizer_render_token(int id, int mode, char *out, uintptr_t capacity);
+
+// Choose the symbols display mode of `tokenizer_render_token` shows for a
+// space, a newline and a tab
+// Each is any UTF-8 string, empty included; a null argument restores that
+// one's default ("·", "⏎", "→"). The symbols apply process-wide.
+// Returns 0 on success, negative on error (nothing changes):
+//   -2 invalid UTF-8
+int tokenizer_set_render_symbols(const char *space, const char *newline, const char *tab);
+
+// Save the loaded tokenizer to `path` as a standard tokenizer.json
+// Tokens added with `tokenizer_add_tokens`/`tokenizer_add_special_tokens`
+// are written into its `added_tokens`, and the settings of
+// `tokenizer_set_truncation`/`tokenizer_set_padding` into its `truncation`
+// and `padding` sections, so `tokenizer_initialize` on the file restores all
+// of them and every text encodes to the same IDs as before. A padding side
+// taken from tokenizer_config.json is saved as the side itself. Settings
+// outside the tokenizer (tokenizer_config.json, chat template, cache, UTF-8
+// policy) are not saved. `pretty` non-zero indents the JSON. An existing
+// file is overwritten.
+// Returns 0 on success, negative on error:
+//   -1 null `path`, -2 invalid UTF-8, -3 not initialized,
+//   -16 the file could not be written
+int tokenizer_save(const char *path, int pretty);
+
+// Make every encode return at most `max_length` tokens, special tokens included
+// `direction`: 0 drops tokens from the end (keeps the start), 1 drops them
+// from the start (keeps the end). `stride` is the overlap kept between
+// overflowing windows and must be smaller than the room left after special tokens.
+// Truncation happens inside the tokenizer before special tokens are added, so
+// BOS/CLS/SEP are never cut off.
+// Returns 0 on success, negative on error:
+//   -3 not initialized, -9 invalid direction, `max_length` not above the
+//   special tokens' count, or stride not below the room left
+int tokenizer_set_truncation(uintptr_t max_length, int direction, uintptr_t stride);
+
+// Turn truncation off again
+// Returns 0 on success, -3 not initialized
+int tokenizer_clear_truncation(void);
+
+// Pad every encoding to a common length using the tokenizer's own pad token
+// `length`: a fixed length, or -1 to pad to the longest item of each batch.
+// `pad_to_multiple_of`: round the padded length up to a multiple, 0 for none.
+// `direction`: 0 pads at the end, 1 pads at the start, -1 pads on the
+// `padding_side` of the loaded tokenizer_config.json (the end without one).
+// The pad token is looked up like `tokenizer_pad_id`.
+// Returns 0 on success, negative on error:
+//   -3 not initialized, -9 invalid argument,
+//   -10 the tokenizer defines no pad token
+int tokenizer_set_padding(int length, int pad_to_multiple_of, int direction);
+
+// Turn padding off again
+// Returns 0 on success, -3 not initialized
+int tokenizer_clear_padding(void);
+
+// Look up a special token ID: `kind` 0 = BOS, 1 = EOS, 2 = PAD, 3 = UNK
+// BOS/EOS come from a loaded tokenizer_config.json, else the post-processor
+// (`[CLS]`/`[SEP]` for BERT, template specials for Llama-style), PAD from the padding config, UNK from the model;
+// each falls back to conventionally named tokens in the vocabulary.
+// Returns the ID, -1 when this tokenizer has no such token, or an error:
+//   -3 not initialized, -9 unknown `kind`
+int tokenizer_get_special_token_id(int kind);
+
+// BOS ID, as `tokenizer_get_special_token_id(0)`
+int tokenizer_bos_id(void);
+
+// EOS ID, as `tokenizer_get_special_token_id(1)`
+int tokenizer_eos_id(void);
+
+// PAD ID, as `tokenizer_get_special_token_id(2)`
+int tokenizer_pad_id(void);
+
+// UNK ID, as `tokenizer_get_special_token_id(3)`
+int tokenizer_unk_id(void);
+
+// Encode `text` without the post-processor's special tokens, then put BOS in
+// front and/or EOS at the end as `add_bos`/`add_eos` ask
+// BOS and EOS are the tokens `tokenizer_get_special_token_id` reports.
+// With `add_bos` = 1 and `add_eos` = 0 a Llama tokenizer gives what
+// llama.cpp produces for the same text; 0/0 suits continuation chunks.
+// Truncation set with `tokenizer_set_truncation` counts BOS and EOS, so the
+// text is cut further to leave room for them, and padding set with
+// `tokenizer_set_padding` is added around the result, never between the text
+// and EOS. Only the first `max_len` IDs are copied, as with `tokenizer_encode`.
+// Returns number of tokens copied, negative on error:
+//   -1 null pointer, -2 invalid UTF-8, -3 not initialized, -4 encode failed,
+//   -11 ID out of range, -17 BOS or EOS requested but the tokenizer has none
+int tokenizer_encode_bos_eos(const char *text,
+                             int add_bos,
+                             int add_eos,
+                             int *out_ids,
+                             uintptr_t max_len);
+
+// Initialize the global tokenizer from a SentencePiece `tokenizer.model`
+// BPE models (Llama, Mistral) get BOS added and byte fallback for unknown
+// characters; Unigram models (T5 and friends) keep their normalizer and get
+// EOS appended, as `transformers` converts them. Reinitialization and
+// `tokenizer_free` behave as with `tokenizer_initialize`.
+// Returns 0 on success, negative on error:
+//   -1 null path, -2 invalid UTF-8, -3 unreadable or not a SentencePiece model,
+//   -14 unsupported model type (word or char models)
+int tokenizer_initialize_from_spm(const char *path);
+
+// Start a stream that skips special tokens (BOS/EOS are not shown)
+// Returns a positive stream handle
+int64_t tokenizer_stream_create(void);
+
+// `tokenizer_stream_create` with `skip_special_tokens` chosen by the caller
+int64_t tokenizer_stream_create_ex(int skip_special_tokens);
+
+// Feed one generated token and receive the text it completes, if any
+// Writes the newly printable text (possibly empty while a multi-byte
+// character is still incomplete) into `out_text` as NUL-terminated UTF-8 and
+// returns its length. If `out_text` is null or too small the text is kept
+// queued: the call returns the required size (null) or -6 (too small), and
+// the text is delivered by the next push or `tokenizer_stream_flush`.
+// Returns negative on error:
+//   -3 not initialized, -4 decode failed, -6 buffer too
+//   small, -7 unknown stream, -9 negative `token_id`
+//
+// Once a stop sequence has matched, further pushes decode nothing and
+// deliver only what is still queued; see `tokenizer_stream_push_ex` and
+// `tokenizer_stream_stop_index`.
+int tokenizer_stream_push(int64_t stream, int token_id, char *out_text, uintptr_t capacity);
+
+// `tokenizer_stream_push` that also reports stop-sequence matches
+// `out_stop_index` (optional) receives the index of the matched stop sequence
+// or -1. `out_trimmed` (optional) receives how many bytes were cut off at the
+// match: the stop sequence plus anything decoded after it. Because possible
+// stop prefixes are held back, delivered text never contains any part of a
+// stop sequence and never needs trimming on the caller's side.
+int tokenizer_stream_push_ex(int64_t stream,
+                             int token_id,
+                             char *out_text,
+                             uintptr_t capacity,
+                             int *out_stop_index,
+                             int *out_trimmed);
+
+// Watch the stream for `count` stop sequences (replacing any previous set)
+// Passing `count` 0 removes them. Text held back as a possible stop prefix
+// under the old set is released.
+// Returns 0 on success, negative on error:
+//   -1 null `sequences` or entry, -2 invalid UTF-8,
+//   -7 unknown stream, -9 empty stop sequence
+int tokenizer_stream_set_stop_sequences(int64_t stream,
+                                        const char *const *sequences,
+                                        uintptr_t count);
+
+// Index of the stop sequence that ended the stream, or -1 if none matched yet
+// Returns -7 unknown stream
+int tokenizer_stream_stop_index(int64_t stream);
+
+// Deliver everything still held by the stream, including an incomplete
+// trailing character (rendered with U+FFFD) and a never-completed stop
+// prefix, and reset it for reuse (stop sequences are kept)
+// Same buffer contract and errors as `tokenizer_stream_push`
+int tokenizer_stream_flush(int64_t stream, char *out_text, uintptr_t capacity);
+
+// Release a stream
+// Returns 0 on success, -7 unknown or already freed
+int tokenizer_stream_free(int64_t stream);
+
+// Limit batch calls to `n` tokenization threads, or with 0 use all cores
+// Takes effect for the next batch call; batches already running finish on
+// the previous pool.
+// Returns 0 on success, -9 for a negative `n` or when the threads cannot be
+// started
+int tokenizer_set_num_threads(int n);
+
+// Initialize the global tokenizer from a tiktoken rank file
+// `pattern` is the regex that splits text before BPE (e.g. cl100k_base's).
+// The special tokens of the well-known encoding with the same number of
+// ranks are added (`<|endoftext|>`, `<|fim_prefix|>`, ...); for other files
+// only `<|endoftext|>` is added, right after the last rank. Counts and IDs
+// then match tiktoken's `encode` with all special tokens allowed.
+// Returns 0 on success, negative on error:
+//   -1 null arguments, -2 invalid UTF-8, -3 unreadable or malformed file or
+//   invalid pattern
+int tokenizer_initialize_tiktoken(const char *path, const char *pattern);
+
+// Initialize the global tokenizer from the rank file of a well-known encoding
+// `name` is "o200k_base", "cl100k_base", "p50k_base" or "r50k_base" and picks
+// the split regex and special tokens; `path` is that encoding's rank file.
+// Returns 0 on success, negative on error:
+//   -1 null arguments, -2 invalid UTF-8, -3 unreadable or malformed file,
+//   -9 unknown encoding name
+int tokenizer_initialize_tiktoken_named(const char *path, const char *name);
+
+// Choose how text arguments with invalid UTF-8 are handled
+// `policy`: 0 rejects them with -2 (the default), 1 replaces each invalid
+// sequence with U+FFFD, 2 drops the invalid bytes. In the lossy modes the
+// call succeeds and `tokenizer_last_error` reports that a repair happened and
+// the byte offset of the first invalid sequence. The policy covers every
+// function that tokenizes caller text (encode, count, pair, batch, chunked,
+// handle and named variants, normalize/pretokenize); paths and names are
+// always strict.
+// Returns 0 on success, -9 for an unknown policy
+int tokenizer_set_invalid_utf8_policy(int policy);
+
+// Number of entries in the vocabulary
+// Non-zero `with_added_tokens` includes tokens from the added-token table.
+// Returns the size, or -3 not initialized
+int tokenizer_vocab_size(int with_added_tokens);
+
+// ID of an exact token piece (e.g. "▁hello", "<|im_end|>")
+// Returns the ID, -1 if the piece is not in the vocabulary (or `token` is
+// null), -2 invalid UTF-8, -3 not initialized
+int tokenizer_token_to_id(const char *token);
+
+// Write the raw piece for `id` into `out` as NUL-terminated UTF-8
+// Returns bytes written (excluding NUL) or the required size for a null
+// `out`/zero `capacity`, negative on error:
+//   -3 not initialized, -6 buffer too small,
+//   -9 `id` is negative or outside the vocabulary
+int tokenizer_id_to_token(int id, char *out, uintptr_t capacity);
+
+// Write the whole vocabulary, added tokens included, to the file at `path`
+// Entries are sorted by ID and carry the raw piece (markers intact), whether
+// it is an added token and whether it is special. `format` 0 writes a JSON
+// array of `{"id", "piece", "added", "special"}` objects; 1 writes TSV with a
+// header line and `\\`, `\t`, `\n` and `\r` escaped inside pieces, so every
+// entry stays on one line. An existing file is overwritten.
+// Returns the number of entries written, negative on error:
+//   -1 null path, -2 invalid UTF-8, -3 not initialized, -9 unknown format,
+//   -16 the file could not be written
+int tokenizer_export_vocab(const char *path, int format);
+
+// Find every token whose piece, read as text, matches `query`
+// Pieces are compared as they read inside a text, so byte-level and
+// SentencePiece markers become spaces: " world" finds `Ġworld`/`▁world`,
+// while "world" finds the in-word continuation. Added tokens compare as
+// written. `match_mode`: 0 exact, 1 the piece starts with `query`, 2 the
+// piece contains `query` ignoring case. IDs are written in ascending order,
+// at most `max_out` of them. The first search after loading builds a table
+// of all pieces, which later searches reuse.
+// Returns the total number of matching tokens, negative on error:
+//   -1 null argument, -2 invalid UTF-8, -3 not initialized, -4 a piece could
+//   not be decoded, -9 unknown `match_mode` or empty `query`
+int tokenizer_find_token_ids(const char *query, int match_mode, int *out_ids, uintptr_t max_out);
+
+// Add `count` special tokens such as `<|tool_call|>` to the loaded tokenizer
+// They are matched whole before normal tokenization, so each occurrence in
+// a prompt encodes to exactly one ID, and skip-special decoding drops them.
+// New tokens get the next free IDs; look them up with `tokenizer_token_to_id`.
+// Returns how many were actually added (tokens already known are not counted),
+// negative on error:
+//   -1 null `tokens` or entry, -2 invalid UTF-8, -3 not initialized,
+//   -9 empty token
+int tokenizer_add_special_tokens(const char *const *tokens, uintptr_t count);
+
+// Same as `tokenizer_add_special_tokens` for ordinary tokens, which are
+// matched whole as well but kept by skip-special decoding
+int tokenizer_add_tokens(const char *const *tokens, uintptr_t count);
+
+// `tokenizer_initialize` taking a UTF-16 path
+// Returns 0 on success, negative on error:
+//   -1 null path, -3 load failed, -8 invalid UTF-16
+int tokenizer_initialize_w(const uint16_t *path);
+
+// `tokenizer_encode` taking UTF-16 text
+// Returns number of tokens on success, negative on error
+// (as `tokenizer_encode`, with -8 for invalid UTF-16 instead of -2)
+int tokenizer_encode_w(const uint16_t *text, int *out_ids, uintptr_t max_len);
+
+// `tokenizer_decode_ex` writing UTF-16 into `out_text`
+// `out_capacity` and the return value are in `u16` code units (excluding NUL);
+// a null `out_text` or zero capacity returns the required size
+// Errors as `tokenizer_decode`
+int tokenizer_decode_w(const int *ids,
+                       uintptr_t len,
+                       int skip_special_tokens,
+                       uint16_t *out_text,
+                       uintptr_t out_capacity);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* HF_TOKENIZER_FFI_H */
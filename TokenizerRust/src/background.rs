@@ -0,0 +1,268 @@
+//! Loading the global tokenizer on a background thread.
+//!
+//! Every `tokenizer_initialize_async` call starts a new load generation. A
+//! finished load installs its tokenizer only if nothing superseded it in the
+//! meantime (a later async load, `tokenizer_initialize*` or `tokenizer_free`),
+//! so the most recent request always wins and stale loads are dropped. While a
+//! load is in flight, calls that use the global tokenizer wait for it, or fail
+//! fast with `ERR_STILL_LOADING` after `tokenizer_set_wait_for_load(false)`.
+
+use std::ffi::{c_char, c_int};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::Instant;
+
+use crate::{
+    c_str_arg, catch_panic, fail, last_error_message, load_from_file, log_loaded, replace_global,
+    try_with_tokenizer, ERR_NOT_INITIALIZED,
+};
+
+enum Phase {
+    /// No load in flight; the global tokenizer is whatever was installed last
+    Settled,
+    Loading,
+    /// The latest async load failed with this code and message
+    Failed(c_int, String),
+}
+
+struct LoadState {
+    generation: u64,
+    phase: Phase,
+}
+
+/// Lock order: `STATE` before `TOKENIZER`, never the other way round
+static STATE: Mutex<LoadState> = Mutex::new(LoadState {
+    generation: 0,
+    phase: Phase::Settled,
+});
+static LOAD_FINISHED: Condvar = Condvar::new();
+/// Mirrors `Phase::Loading` so tokenizer calls skip `STATE` when nothing loads
+static LOADING: AtomicBool = AtomicBool::new(false);
+static WAIT_FOR_LOAD: AtomicBool = AtomicBool::new(true);
+
+fn state() -> MutexGuard<'static, LoadState> {
+    STATE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Settle `state` into `phase` and wake everyone waiting for the load
+fn finish(state: &mut LoadState, phase: Phase) {
+    state.phase = phase;
+    LOADING.store(false, Ordering::Release);
+    LOAD_FINISHED.notify_all();
+}
+
+/// Run `install` (which replaces the global tokenizer) as a new generation,
+/// so any async load still in flight is dropped when it finishes
+pub(crate) fn supersede(install: impl FnOnce()) {
+    let mut state = state();
+    state.generation += 1;
+    install();
+    finish(&mut state, Phase::Settled);
+}
+
+/// Block until an in-flight load finishes, or return false when one is in
+/// flight and the caller asked not to wait
+pub(crate) fn wait_for_load() -> bool {
+    if !LOADING.load(Ordering::Acquire) {
+        return true;
+    }
+    if !WAIT_FOR_LOAD.load(Ordering::Relaxed) {
+        return false;
+    }
+
+    let mut state = state();
+    while matches!(state.phase, Phase::Loading) {
+        state = LOAD_FINISHED
+            .wait(state)
+            .unwrap_or_else(PoisonError::into_inner);
+    }
+    true
+}
+
+/// Start loading a tokenizer.json on a background thread and return at once
+/// On success the new tokenizer replaces the current one, exactly as
+/// `tokenizer_initialize` would; on failure the current one stays. Calling
+/// this again while a load is in flight supersedes that load, which is then
+/// discarded. Poll `tokenizer_init_status` for the outcome.
+/// Returns 0 once loading has started, negative on error:
+///   -1 null path, -2 invalid UTF-8, -3 the loader thread could not start
+#[no_mangle]
+pub extern "C" fn tokenizer_initialize_async(path: *const c_char) -> c_int {
+    catch_panic(|| {
+        let path = match c_str_arg(path) {
+            Ok(path) => path.to_owned(),
+            Err(code) => return code,
+        };
+
+        let generation = {
+            let mut state = state();
+            state.generation += 1;
+            state.phase = Phase::Loading;
+            LOADING.store(true, Ordering::Release);
+            state.generation
+        };
+
+        let started = Instant::now();
+        let spawned = std::thread::Builder::new()
+            .name("tokenizer-load".to_owned())
+            .spawn(move || {
+                let mut loaded = None;
+                let code = catch_panic(|| match load_from_file(&path) {
+                    Ok(tokenizer) => {
+                        loaded = Some(tokenizer);
+                        0
+                    }
+                    Err(code) => code,
+                });
+
+                if let Some(tokenizer) = &loaded {
+                    log_loaded(tokenizer, &path, started);
+                }
+                let mut state = state();
+                if state.generation != generation {
+                    return;
+                }
+                let phase = match loaded {
+                    Some(tokenizer) => {
+                        replace_global(Some((tokenizer, path)));
+                        Phase::Settled
+                    }
+                    None => Phase::Failed(code, last_error_message()),
+                };
+                finish(&mut state, phase);
+            });
+
+        match spawned {
+            Ok(_) => 0,
+            Err(e) => {
+                let message = format!("could not start the tokenizer loader thread: {e}");
+                let mut state = state();
+                if state.generation == generation {
+                    finish(&mut state, Phase::Failed(-3, message.clone()));
+                }
+                fail(-3, message)
+            }
+        }
+    })
+}
+
+/// Report the state of the global tokenizer
+/// Returns 0 when a tokenizer is ready, 1 while an async load is in flight,
+/// or the failing load's negative code, whose message is then available from
+/// `tokenizer_last_error` on the calling thread:
+///   -1/-2/-3 as from `tokenizer_initialize`, -3 also when nothing is loaded
+#[no_mangle]
+pub extern "C" fn tokenizer_init_status() -> c_int {
+    catch_panic(|| {
+        let state = state();
+        match &state.phase {
+            Phase::Loading => 1,
+            Phase::Failed(code, message) => fail(*code, message.clone()),
+            Phase::Settled => match try_with_tokenizer(|_| ()) {
+                Some(()) => 0,
+                None => fail(ERR_NOT_INITIALIZED, "tokenizer is not initialized"),
+            },
+        }
+    })
+}
+
+/// Choose what tokenizer calls do while an async load is in flight
+/// With `wait` (the default) they block until the load finishes and then use
+/// its result; without it they return `ERR_STILL_LOADING` (-15) immediately.
+#[no_mangle]
+pub extern "C" fn tokenizer_set_wait_for_load(wait: bool) {
+    catch_panic(|| {
+        WAIT_FOR_LOAD.store(wait, Ordering::Relaxed);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{bert_json, install, llama_json, serial, write_temp};
+    use crate::{ERR_STILL_LOADING, TOKENIZER};
+    use std::ffi::CString;
+
+    fn load_async(json: &str, name: &str) -> c_int {
+        let path = write_temp(name, json);
+        let path = CString::new(path.to_str().unwrap()).unwrap();
+        tokenizer_initialize_async(path.as_ptr())
+    }
+
+    fn encode(text: &str) -> c_int {
+        let text = CString::new(text).unwrap();
+        let mut ids = [0; 16];
+        let n = crate::tokenizer_encode(text.as_ptr(), ids.as_mut_ptr(), ids.len());
+        if n > 0 {
+            ids[0]
+        } else {
+            n
+        }
+    }
+
+    fn settled_status() -> c_int {
+        loop {
+            match tokenizer_init_status() {
+                1 => std::thread::yield_now(),
+                status => return status,
+            }
+        }
+    }
+
+    #[test]
+    fn encodes_wait_for_the_load_to_finish() {
+        let _guard = serial();
+        tokenizer_set_wait_for_load(true);
+        crate::tokenizer_free();
+
+        assert_eq!(load_async(&bert_json(), "async_bert"), 0);
+        // [CLS] comes from the BERT tokenizer, not "not initialized"
+        assert_eq!(encode("hello"), 2);
+        assert_eq!(tokenizer_init_status(), 0);
+    }
+
+    #[test]
+    fn the_latest_load_wins() {
+        let _guard = serial();
+        tokenizer_set_wait_for_load(true);
+
+        assert_eq!(load_async(&llama_json(), "async_first"), 0);
+        assert_eq!(load_async(&bert_json(), "async_second"), 0);
+        assert_eq!(settled_status(), 0);
+        assert_eq!(encode("hello"), 2);
+
+        // A synchronous initialization supersedes an async load too
+        assert_eq!(load_async(&bert_json(), "async_stale"), 0);
+        install(&llama_json());
+        assert_eq!(settled_status(), 0);
+        assert_eq!(encode("hello"), 1);
+    }
+
+    #[test]
+    fn failed_loads_keep_the_current_tokenizer() {
+        let _guard = serial();
+        install(&llama_json());
+
+        let path = CString::new("/definitely/missing/async.json").unwrap();
+        assert_eq!(tokenizer_initialize_async(path.as_ptr()), 0);
+        assert_eq!(settled_status(), -3);
+        assert!(last_error_message().contains("/definitely/missing/async.json"));
+        assert_eq!(encode("hello"), 1);
+    }
+
+    #[test]
+    fn without_waiting_calls_report_still_loading() {
+        let _guard = serial();
+        install(&llama_json());
+        tokenizer_set_wait_for_load(false);
+
+        // Holding the tokenizer lock keeps the load from installing its result
+        let lock = TOKENIZER.write().unwrap_or_else(PoisonError::into_inner);
+        assert_eq!(load_async(&bert_json(), "async_held"), 0);
+        assert_eq!(encode("hello"), ERR_STILL_LOADING);
+        drop(lock);
+
+        tokenizer_set_wait_for_load(true);
+        assert_eq!(encode("hello"), 2);
+    }
+}
@@ -0,0 +1,301 @@
+//! Decoding token IDs to raw bytes rather than text.
+//!
+//! Byte-fallback pieces (`<0xE2>`) and byte-level tokens can each carry part of
+//! a multi-byte character, which text decoding turns into U+FFFD. Here the
+//! tokenizer's decoder chain runs with those two steps swapped for lossless
+//! versions that park every raw byte on a private-use code point, so all the
+//! other steps (spacing, stripping, WordPiece joins) still apply and the bytes
+//! come out intact at the end. The parking range is picked per call among
+//! code points the decoded tokens do not use, so a real private-use character
+//! is never mistaken for a parked byte.
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::{c_int, c_uchar};
+use std::sync::OnceLock;
+use tokenizers::{Decoder, DecoderWrapper, Tokenizer};
+
+use crate::{catch_panic, fail, ids_arg, tokenizer_failed, with_tokenizer, ERR_BUFFER_TOO_SMALL};
+
+/// The 256-code-point blocks (code point >> 8) of private-use planes 15 and
+/// 16; raw byte `b` travels through the decoder chain as the start of a free
+/// block plus `b`, U+10FF00 + `b` unless the tokens use that block
+const PARKING_BLOCKS: std::ops::RangeInclusive<u32> = 0xF00..=0x10FF;
+
+/// The start of a parking block none of `texts` has a character in
+/// Planes 15 and 16 hold 512 blocks, so only texts using a character from
+/// every one of them leave none free.
+fn parking_base<'a>(texts: impl IntoIterator<Item = &'a str>) -> tokenizers::Result<u32> {
+    let used: HashSet<u32> = texts
+        .into_iter()
+        .flat_map(str::chars)
+        .map(|c| c as u32 >> 8)
+        .filter(|block| PARKING_BLOCKS.contains(block))
+        .collect();
+    PARKING_BLOCKS
+        .rev()
+        .find(|block| !used.contains(block))
+        .map(|block| block << 8)
+        .ok_or_else(|| "the tokens use every private-use block raw bytes could be parked on".into())
+}
+
+fn raw_byte(base: u32, byte: u8) -> char {
+    char::from_u32(base + byte as u32).unwrap()
+}
+
+/// The byte behind a `<0xNN>` piece, parsed as the ByteFallback decoder does
+fn fallback_byte(token: &str) -> Option<u8> {
+    if token.len() == 6 && token.starts_with("<0x") && token.ends_with('>') {
+        u8::from_str_radix(&token[3..5], 16).ok()
+    } else {
+        None
+    }
+}
+
+fn byte_level_values() -> &'static HashMap<char, u8> {
+    static VALUES: OnceLock<HashMap<char, u8>> = OnceLock::new();
+    VALUES.get_or_init(|| {
+        crate::tiktoken::byte_chars()
+            .iter()
+            .enumerate()
+            .map(|(b, &c)| (c, b as u8))
+            .collect()
+    })
+}
+
+/// `decoder.decode_chain`, keeping the bytes of byte tokens as raw-byte chars
+/// parked on the block at `base`
+fn decode_chain(
+    decoder: &DecoderWrapper,
+    tokens: Vec<String>,
+    base: u32,
+) -> tokenizers::Result<Vec<String>> {
+    match decoder {
+        DecoderWrapper::Sequence(sequence) => sequence
+            .get_decoders()
+            .iter()
+            .try_fold(tokens, |tokens, decoder| {
+                decode_chain(decoder, tokens, base)
+            }),
+        DecoderWrapper::ByteFallback(_) => Ok(tokens
+            .into_iter()
+            .map(|token| match fallback_byte(&token) {
+                Some(byte) => raw_byte(base, byte).to_string(),
+                None => token,
+            })
+            .collect()),
+        DecoderWrapper::ByteLevel(_) => {
+            let values = byte_level_values();
+            let mut text = String::new();
+            for token in tokens {
+                // Tokens outside the byte alphabet (added tokens) pass through
+                match token
+                    .chars()
+                    .map(|c| values.get(&c))
+                    .collect::<Option<Vec<_>>>()
+                {
+                    Some(bytes) => text.extend(bytes.into_iter().map(|&b| {
+                        if b.is_ascii() {
+                            b as char
+                        } else {
+                            raw_byte(base, b)
+                        }
+                    })),
+                    None => text.push_str(&token),
+                }
+            }
+            Ok(vec![text])
+        }
+        other => other.decode_chain(tokens),
+    }
+}
+
+/// `Tokenizer::decode` producing the exact bytes, incomplete characters included
+pub(crate) fn decode_bytes(
+    tokenizer: &Tokenizer,
+    ids: &[u32],
+    skip_special_tokens: bool,
+) -> tokenizers::Result<Vec<u8>> {
+    let added = tokenizer.get_added_vocabulary();
+    let tokens: Vec<String> = ids
+        .iter()
+        .filter_map(|&id| tokenizer.id_to_token(id))
+        .filter(|token| !skip_special_tokens || !added.is_special_token(token))
+        .collect();
+    let base = parking_base(tokens.iter().map(String::as_str))?;
+    let text = match tokenizer.get_decoder() {
+        Some(decoder) => decode_chain(decoder, tokens, base)?.concat(),
+        None => tokens.join(" "),
+    };
+    Ok(unpark_bytes(&text, base))
+}
+
+/// The bytes of one vocabulary piece as it reads in the middle of a text
+/// Unlike decoding it on its own, marker spaces are kept (`▁world` and
+/// `Ġworld` give " world") and leading/trailing stripping is not applied; a
+/// WordPiece piece without the continuation prefix starts a new word with a
+/// space, and `##ing` gives "ing".
+pub(crate) fn piece_bytes(tokenizer: &Tokenizer, piece: &str) -> tokenizers::Result<Vec<u8>> {
+    let base = parking_base([piece])?;
+    let text = match tokenizer.get_decoder() {
+        Some(decoder) => piece_chain(decoder, piece.to_owned(), base)?,
+        None => piece.to_owned(),
+    };
+    Ok(unpark_bytes(&text, base))
+}
+
+fn piece_chain(decoder: &DecoderWrapper, piece: String, base: u32) -> tokenizers::Result<String> {
+    match decoder {
+        DecoderWrapper::Sequence(sequence) => sequence
+            .get_decoders()
+            .iter()
+            .try_fold(piece, |piece, decoder| piece_chain(decoder, piece, base)),
+        DecoderWrapper::Metaspace(metaspace) => Ok(piece.replace(metaspace.get_replacement(), " ")),
+        DecoderWrapper::WordPiece(wordpiece) => Ok(match piece.strip_prefix(&wordpiece.prefix) {
+            Some(rest) => rest.to_owned(),
+            None => format!(" {piece}"),
+        }),
+        DecoderWrapper::Strip(_) => Ok(piece),
+        other => Ok(decode_chain(other, vec![piece], base)?.concat()),
+    }
+}
+
+/// Turn raw-byte chars parked on the block at `base` back into the bytes they
+/// stand for
+fn unpark_bytes(text: &str, base: u32) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(text.len());
+    for c in text.chars() {
+        match (c as u32)
+            .checked_sub(base)
+            .filter(|&offset| offset < 0x100)
+        {
+            Some(byte) => bytes.push(byte as u8),
+            None => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    bytes
+}
+
+/// Decode token IDs to the raw bytes they stand for, skipping special tokens
+/// Unlike `tokenizer_decode`, bytes of a character split across tokens are
+/// kept as they are instead of becoming U+FFFD, so the output may end in (or,
+/// for malformed ID sequences, contain) incomplete UTF-8. Nothing is
+/// NUL-terminated. A null `out_bytes` or zero `capacity` is a size query.
+/// Returns the byte count (the required size for a size query), negative on error:
+///   -1 null `ids` with non-zero `len`, -3 not initialized, -4 decode failed
+///   (including negative IDs), -6 buffer too small
+#[no_mangle]
+pub extern "C" fn tokenizer_decode_bytes(
+    ids: *const c_int,
+    len: usize,
+    out_bytes: *mut c_uchar,
+    capacity: usize,
+) -> c_int {
+    catch_panic(|| {
+        let ids = match ids_arg(ids, len) {
+            Ok(ids) => ids,
+            Err(code) => return code,
+        };
+
+        with_tokenizer(|tokenizer| {
+            let bytes = match decode_bytes(tokenizer, &ids, true) {
+                Ok(bytes) => bytes,
+                Err(e) => return tokenizer_failed("decode", e),
+            };
+            let Ok(needed) = c_int::try_from(bytes.len()) else {
+                return fail(
+                    ERR_BUFFER_TOO_SMALL,
+                    format!("{} bytes exceed the C int range", bytes.len()),
+                );
+            };
+            if out_bytes.is_null() || capacity == 0 {
+                return needed;
+            }
+            if capacity < bytes.len() {
+                return fail(
+                    ERR_BUFFER_TOO_SMALL,
+                    format!("output needs {needed} bytes, buffer holds {capacity}"),
+                );
+            }
+
+            unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), out_bytes, bytes.len()) };
+            needed
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{bert_json, byte_level_json, install, llama_json, serial};
+    use std::ffi::CString;
+
+    fn encode(text: &str) -> Vec<c_int> {
+        let text = CString::new(text).unwrap();
+        let mut ids = vec![0; 256];
+        let n = crate::tokenizer_encode(text.as_ptr(), ids.as_mut_ptr(), ids.len());
+        assert!(n >= 0, "encode failed with {n}");
+        ids.truncate(n as usize);
+        ids
+    }
+
+    fn decode(ids: &[c_int]) -> Vec<u8> {
+        let needed = tokenizer_decode_bytes(ids.as_ptr(), ids.len(), std::ptr::null_mut(), 0);
+        assert!(needed >= 0, "size query failed with {needed}");
+        let mut buf = vec![0; needed as usize];
+        let n = tokenizer_decode_bytes(ids.as_ptr(), ids.len(), buf.as_mut_ptr(), buf.len());
+        assert_eq!(n, needed);
+        buf
+    }
+
+    #[test]
+    fn split_characters_keep_their_bytes() {
+        let _guard = serial();
+        for json in [llama_json(), byte_level_json()] {
+            install(&json);
+            let ids = encode("zażółć 🚀");
+            assert_eq!(decode(&ids), "zażółć 🚀".as_bytes());
+
+            // Only part of the rocket: its leading bytes, not U+FFFD
+            let rocket = &ids[ids.len() - 4..ids.len() - 2];
+            assert_eq!(decode(rocket), [0xf0, 0x9f]);
+        }
+    }
+
+    #[test]
+    fn other_decoders_behave_as_in_text_decoding() {
+        let _guard = serial();
+        install(&bert_json());
+        let ids = encode("hello tokenizers world");
+        assert_eq!(decode(&ids), b"hello tokenizers world");
+
+        let mut small = [0u8; 4];
+        let rc = tokenizer_decode_bytes(ids.as_ptr(), ids.len(), small.as_mut_ptr(), 4);
+        assert_eq!(rc, ERR_BUFFER_TOO_SMALL);
+    }
+
+    #[test]
+    fn private_use_characters_are_not_taken_for_bytes() {
+        let _guard = serial();
+        install(&llama_json());
+        // U+10FF41 is where byte 0x41 would be parked by default
+        let tokens = [CString::new("\u{10FF41}").unwrap()];
+        let ptrs = [tokens[0].as_ptr()];
+        assert_eq!(crate::vocab::tokenizer_add_tokens(ptrs.as_ptr(), 1), 1);
+        let id = crate::vocab::tokenizer_token_to_id(tokens[0].as_ptr());
+        let fallback = CString::new("<0xF0>").unwrap();
+        let byte = crate::vocab::tokenizer_token_to_id(fallback.as_ptr());
+        assert!(id >= 0 && byte >= 0);
+
+        let mut expected = "\u{10FF41}".as_bytes().to_vec();
+        assert_eq!(decode(&[id]), expected);
+        expected.push(0xf0);
+        assert_eq!(decode(&[id, byte]), expected);
+
+        // Every block taken leaves nowhere to park the bytes
+        let crowded: String = PARKING_BLOCKS
+            .map(|block| char::from_u32(block << 8).unwrap())
+            .collect();
+        assert!(parking_base([crowded.as_str()]).is_err());
+        assert_eq!(parking_base(["a\u{10FF41}"]).unwrap(), 0x10FE00);
+    }
+}
@@ -0,0 +1,326 @@
+//! Opt-in LRU cache of encode results for the global tokenizer.
+//!
+//! Keyed by (text, add_special_tokens) and consulted by `tokenizer_encode`,
+//! `tokenizer_encode_opts`, `tokenizer_encode_v2` and `tokenizer_count_tokens`.
+//! The cache belongs to one tokenizer generation: every change to the global
+//! tokenizer clears it for the new generation, and calls still running on an
+//! older per-thread snapshot neither read nor fill it, so a hit always matches
+//! what a fresh encode would return.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::c_int;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use tokenizers::Encoding;
+
+use crate::{catch_panic, null_output};
+
+/// Default bound on cached texts and IDs, so a few huge prompts cannot pin
+/// unbounded memory even with a generous entry count
+const DEFAULT_MAX_BYTES: usize = 64 << 20;
+/// Rough per-entry bookkeeping cost counted against the byte bound
+const ENTRY_OVERHEAD: usize = 64;
+
+struct Entry {
+    ids: Arc<[u32]>,
+    last_used: u64,
+}
+
+struct EncodeCache {
+    /// Tokenizer generation the entries were encoded with
+    generation: u64,
+    /// One map per `add_special_tokens` value, so lookups borrow the text
+    entries: [HashMap<Arc<str>, Entry>; 2],
+    /// Least recently used first
+    order: BTreeMap<u64, (usize, Arc<str>)>,
+    tick: u64,
+    bytes: usize,
+}
+
+static CACHE: Mutex<Option<EncodeCache>> = Mutex::new(None);
+/// Mirrors whether `CACHE` is enabled so uncached encodes skip the mutex
+static CAPACITY: AtomicUsize = AtomicUsize::new(0);
+static MAX_BYTES: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_BYTES);
+static HITS: AtomicU64 = AtomicU64::new(0);
+static MISSES: AtomicU64 = AtomicU64::new(0);
+
+fn cache() -> MutexGuard<'static, Option<EncodeCache>> {
+    CACHE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn entry_bytes(text: &str, ids: &[u32]) -> usize {
+    text.len() + std::mem::size_of_val(ids) + ENTRY_OVERHEAD
+}
+
+impl EncodeCache {
+    fn new(generation: u64) -> Self {
+        Self {
+            generation,
+            entries: [HashMap::new(), HashMap::new()],
+            order: BTreeMap::new(),
+            tick: 0,
+            bytes: 0,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    fn get(&mut self, text: &str, special: usize) -> Option<Arc<[u32]>> {
+        let entry = self.entries[special].get_mut(text)?;
+        let (_, key) = self.order.remove(&entry.last_used)?;
+        self.tick += 1;
+        entry.last_used = self.tick;
+        self.order.insert(self.tick, (special, key));
+        Some(entry.ids.clone())
+    }
+
+    /// Returns how many entries were evicted to make room
+    fn insert(&mut self, text: &str, special: usize, ids: Arc<[u32]>) -> usize {
+        let size = entry_bytes(text, &ids);
+        let max_bytes = MAX_BYTES.load(Ordering::Relaxed);
+        if (max_bytes != 0 && size > max_bytes) || self.entries[special].contains_key(text) {
+            return 0;
+        }
+
+        let capacity = CAPACITY.load(Ordering::Relaxed);
+        let mut evicted = 0;
+        while self.len() >= capacity || (max_bytes != 0 && self.bytes + size > max_bytes) {
+            if !self.evict_oldest() {
+                break;
+            }
+            evicted += 1;
+        }
+
+        let key: Arc<str> = text.into();
+        self.tick += 1;
+        self.bytes += size;
+        self.order.insert(self.tick, (special, key.clone()));
+        self.entries[special].insert(
+            key,
+            Entry {
+                ids,
+                last_used: self.tick,
+            },
+        );
+        evicted
+    }
+
+    fn evict_oldest(&mut self) -> bool {
+        let Some((_, (special, key))) = self.order.pop_first() else {
+            return false;
+        };
+        if let Some(entry) = self.entries[special].remove(&key) {
+            self.bytes -= entry_bytes(&key, &entry.ids);
+        }
+        true
+    }
+
+    fn shrink_to(&mut self, capacity: usize, max_bytes: usize) {
+        while self.len() > capacity || (max_bytes != 0 && self.bytes > max_bytes) {
+            self.evict_oldest();
+        }
+    }
+}
+
+/// The IDs of `text`, from the cache when enabled, else from `encode`
+/// Must be called inside `with_tokenizer`, whose snapshot generation decides
+/// whether the cache applies.
+pub(crate) fn cached_ids(
+    text: &str,
+    add_special_tokens: bool,
+    encode: impl FnOnce() -> tokenizers::Result<Encoding>,
+) -> tokenizers::Result<Arc<[u32]>> {
+    if CAPACITY.load(Ordering::Relaxed) == 0 {
+        return encode().map(|encoding| encoding.get_ids().into());
+    }
+
+    let special = add_special_tokens as usize;
+    let generation = crate::snapshot_generation();
+    let hit = cache()
+        .as_mut()
+        .filter(|c| c.generation == generation)
+        .and_then(|c| c.get(text, special));
+    if let Some(ids) = hit {
+        HITS.fetch_add(1, Ordering::Relaxed);
+        return Ok(ids);
+    }
+
+    MISSES.fetch_add(1, Ordering::Relaxed);
+    let ids: Arc<[u32]> = encode()?.get_ids().into();
+    let evicted = cache()
+        .as_mut()
+        .filter(|c| c.generation == generation)
+        .map_or(0, |cache| cache.insert(text, special, ids.clone()));
+    // Logged once the cache lock is released
+    if evicted > 0 {
+        crate::log::log(crate::log::LOG_DEBUG, || {
+            format!("encode cache evicted {evicted} entries")
+        });
+    }
+    Ok(ids)
+}
+
+/// Drop every cached encoding; called whenever the global tokenizer changes
+pub(crate) fn clear() {
+    if CAPACITY.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    if let Some(cache) = cache().as_mut() {
+        *cache = EncodeCache::new(crate::current_generation());
+    }
+}
+
+/// Enable caching of encode results for up to `n_entries` texts, or disable
+/// and free the cache with 0
+/// Shrinking evicts the least recently used entries; the hit and miss
+/// counters restart from zero.
+#[no_mangle]
+pub extern "C" fn tokenizer_set_cache_capacity(n_entries: usize) {
+    catch_panic(|| {
+        let mut cache = cache();
+        CAPACITY.store(n_entries, Ordering::Relaxed);
+        HITS.store(0, Ordering::Relaxed);
+        MISSES.store(0, Ordering::Relaxed);
+
+        if n_entries == 0 {
+            *cache = None;
+        } else {
+            let max_bytes = MAX_BYTES.load(Ordering::Relaxed);
+            cache
+                .get_or_insert_with(|| EncodeCache::new(crate::current_generation()))
+                .shrink_to(n_entries, max_bytes);
+        }
+    })
+}
+
+/// Bound the memory held by the cache, counting cached text and IDs
+/// 0 removes the bound (entries are then limited by count only); the default
+/// is 64 MiB. Texts too large for the bound are never cached.
+#[no_mangle]
+pub extern "C" fn tokenizer_set_cache_max_bytes(max_bytes: usize) {
+    catch_panic(|| {
+        let mut cache = cache();
+        MAX_BYTES.store(max_bytes, Ordering::Relaxed);
+        if let Some(cache) = cache.as_mut() {
+            cache.shrink_to(CAPACITY.load(Ordering::Relaxed), max_bytes);
+        }
+    })
+}
+
+/// Report cache hits and misses since the capacity was last set
+/// Returns the number of cached entries, negative on error:
+///   -1 null output pointer
+#[no_mangle]
+pub extern "C" fn tokenizer_cache_stats(out_hits: *mut u64, out_misses: *mut u64) -> c_int {
+    catch_panic(|| {
+        if out_hits.is_null() || out_misses.is_null() {
+            return null_output("out_hits/out_misses");
+        }
+
+        let cache = cache();
+        unsafe {
+            *out_hits = HITS.load(Ordering::Relaxed);
+            *out_misses = MISSES.load(Ordering::Relaxed);
+        }
+        cache.as_ref().map_or(0, |c| c.len() as c_int)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{bert_json, install, llama_json, serial};
+    use std::ffi::CString;
+
+    fn encode(text: &str) -> Vec<c_int> {
+        let text = CString::new(text).unwrap();
+        let mut ids = vec![0; 64];
+        let n = crate::tokenizer_encode(text.as_ptr(), ids.as_mut_ptr(), ids.len());
+        assert!(n >= 0, "encode failed with {n}");
+        ids.truncate(n as usize);
+        ids
+    }
+
+    fn stats() -> (u64, u64, c_int) {
+        let (mut hits, mut misses) = (0, 0);
+        let entries = tokenizer_cache_stats(&mut hits, &mut misses);
+        (hits, misses, entries)
+    }
+
+    /// Run `f` with the cache enabled, disabling it again even if `f` panics
+    fn with_cache(capacity: usize, f: impl FnOnce()) {
+        struct Disable;
+        impl Drop for Disable {
+            fn drop(&mut self) {
+                tokenizer_set_cache_max_bytes(DEFAULT_MAX_BYTES);
+                tokenizer_set_cache_capacity(0);
+            }
+        }
+        let _disable = Disable;
+        tokenizer_set_cache_capacity(capacity);
+        f();
+    }
+
+    #[test]
+    fn hits_return_the_same_ids_as_fresh_encodes() {
+        let _guard = serial();
+        install(&llama_json());
+        let fresh = encode("hello world");
+
+        with_cache(8, || {
+            assert_eq!(encode("hello world"), fresh);
+            assert_eq!(encode("hello world"), fresh);
+            let text = CString::new("hello world").unwrap();
+            assert_eq!(
+                crate::tokenizer_count_tokens(text.as_ptr(), 1),
+                fresh.len() as c_int
+            );
+            // Without special tokens is a different entry
+            assert_eq!(crate::tokenizer_count_tokens(text.as_ptr(), 0), 2);
+            assert_eq!(stats(), (2, 2, 2));
+        });
+        assert_eq!(stats(), (0, 0, 0));
+    }
+
+    #[test]
+    fn least_recently_used_entries_are_evicted() {
+        let _guard = serial();
+        install(&llama_json());
+
+        with_cache(2, || {
+            encode("hello");
+            encode("world");
+            encode("hello");
+            encode("hello world");
+            assert_eq!(stats(), (1, 3, 2));
+            // "world" was evicted, "hello" survived
+            encode("hello");
+            encode("world");
+            assert_eq!(stats(), (2, 4, 2));
+
+            tokenizer_set_cache_max_bytes(ENTRY_OVERHEAD + 32);
+            assert_eq!(stats().2, 1);
+            encode(&"hello ".repeat(20));
+            assert_eq!(stats().2, 1);
+        });
+    }
+
+    #[test]
+    fn tokenizer_changes_invalidate_the_cache() {
+        let _guard = serial();
+        install(&llama_json());
+
+        with_cache(8, || {
+            encode("hello world");
+            install(&bert_json());
+            assert_eq!(stats().2, 0);
+            assert_eq!(encode("hello world"), [2, 5, 6, 3]);
+
+            assert_eq!(crate::settings::tokenizer_set_truncation(3, 0, 0), 0);
+            assert_eq!(stats().2, 0);
+            assert_eq!(encode("hello world"), [2, 5, 3]);
+        });
+    }
+}
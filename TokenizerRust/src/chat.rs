@@ -0,0 +1,733 @@
+//! Chat prompt formatting with the Jinja `chat_template` shipped in
+//! tokenizer_config.json.
+//!
+//! Templates are rendered the way `transformers` does it: `trim_blocks` and
+//! `lstrip_blocks` on, Python string methods available, and `raise_exception`
+//! for templates that reject a conversation.
+
+use minijinja::{context, Environment, Error, ErrorKind};
+use serde_json::{json, Value};
+use std::ffi::{c_char, c_int};
+use std::sync::{PoisonError, RwLock};
+
+use crate::config::{config_token, read_config};
+use crate::special::{special_token, SpecialKind};
+use crate::{
+    c_str_arg, catch_panic, copy_ids, fail, guard, last_error_message, null_output, set_last_error,
+    tokenizer_failed, try_with_tokenizer, with_tokenizer, write_c_str, ERR_DOES_NOT_FIT,
+    ERR_NO_CHAT_TEMPLATE, ERR_TEMPLATE_FAILED,
+};
+
+pub(crate) struct ChatTemplate {
+    source: String,
+    /// `bos_token`/`eos_token` from the config, when it declares them
+    bos_token: Option<String>,
+    eos_token: Option<String>,
+    /// Installed by `tokenizer_load_config`, so dropped along with that config
+    from_config: bool,
+}
+
+static CHAT_TEMPLATE: RwLock<Option<ChatTemplate>> = RwLock::new(None);
+
+fn environment() -> Environment<'static> {
+    let mut env = Environment::new();
+    env.set_trim_blocks(true);
+    env.set_lstrip_blocks(true);
+    env.set_unknown_method_callback(minijinja_contrib::pycompat::unknown_method_callback);
+    env.add_function(
+        "raise_exception",
+        |message: String| -> Result<String, Error> {
+            Err(Error::new(ErrorKind::InvalidOperation, message))
+        },
+    );
+    env
+}
+
+/// `chat_template` is a string, or a list of named templates of which we use "default"
+fn config_template(config: &Value) -> Option<String> {
+    match &config["chat_template"] {
+        Value::String(s) => Some(s.clone()),
+        Value::Array(named) => named
+            .iter()
+            .find(|t| t["name"] == "default")
+            .or_else(|| named.first())
+            .and_then(|t| t["template"].as_str())
+            .map(str::to_owned),
+        _ => None,
+    }
+}
+
+/// Load the `chat_template` of a tokenizer_config.json, replacing any previous one
+/// `bos_token`/`eos_token` declared next to it are used when rendering;
+/// otherwise they are looked up in the loaded tokenizer at render time.
+/// Returns 0 on success, negative on error:
+///   -1 null path, -2 invalid UTF-8, -3 file unreadable or without a chat
+///   template, -13 the template does not compile, -18 not a JSON object
+#[no_mangle]
+pub extern "C" fn tokenizer_load_chat_template(path_to_config: *const c_char) -> c_int {
+    catch_panic(|| {
+        let path = match c_str_arg(path_to_config) {
+            Ok(p) => p,
+            Err(code) => return code,
+        };
+
+        let config = match read_config(path) {
+            Ok(config) => config,
+            Err(code) => return code,
+        };
+        match template_from_config(&config) {
+            Ok(Some(template)) => {
+                install_template(template, false);
+                0
+            }
+            Ok(None) => fail(-3, format!("'{path}' has no chat_template")),
+            Err(code) => code,
+        }
+    })
+}
+
+/// The chat template of a parsed tokenizer_config.json, compiled to check it,
+/// or `None` if the config has none
+/// Errors: -13 the template does not compile
+pub(crate) fn template_from_config(config: &Value) -> Result<Option<ChatTemplate>, c_int> {
+    let Some(source) = config_template(config) else {
+        return Ok(None);
+    };
+    if let Err(e) = environment().template_from_str(&source) {
+        return Err(fail(
+            ERR_TEMPLATE_FAILED,
+            format!("chat template is invalid: {e}"),
+        ));
+    }
+    Ok(Some(ChatTemplate {
+        source,
+        bos_token: config_token(config, "bos_token"),
+        eos_token: config_token(config, "eos_token"),
+        from_config: false,
+    }))
+}
+
+/// Make `template` the one every chat call renders, replacing any previous one
+/// `from_config` marks a template that `clear_config_template` drops.
+pub(crate) fn install_template(mut template: ChatTemplate, from_config: bool) {
+    template.from_config = from_config;
+    *CHAT_TEMPLATE
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = Some(template);
+}
+
+/// Drop the template if `tokenizer_load_config` installed it
+pub(crate) fn clear_config_template() {
+    let mut guard = CHAT_TEMPLATE
+        .write()
+        .unwrap_or_else(PoisonError::into_inner);
+    if guard.as_ref().is_some_and(|t| t.from_config) {
+        *guard = None;
+    }
+}
+
+/// Read `count` (role, content) pairs into template messages
+fn messages_arg(
+    roles: *const *const c_char,
+    contents: *const *const c_char,
+    count: usize,
+) -> Result<Vec<Value>, c_int> {
+    if count == 0 {
+        return Ok(Vec::new());
+    }
+    if roles.is_null() || contents.is_null() {
+        return Err(null_output("roles/contents"));
+    }
+
+    let roles = unsafe { std::slice::from_raw_parts(roles, count) };
+    let contents = unsafe { std::slice::from_raw_parts(contents, count) };
+    roles
+        .iter()
+        .zip(contents)
+        .map(|(&role, &content)| {
+            Ok(json!({ "role": c_str_arg(role)?, "content": c_str_arg(content)? }))
+        })
+        .collect()
+}
+
+/// Fold a leading system message into the first user message
+fn merge_system_message(messages: &[Value]) -> Option<Vec<Value>> {
+    let (system, rest) = messages.split_first()?;
+    if system["role"] != "system" {
+        return None;
+    }
+
+    let mut merged = rest.to_vec();
+    let system = system["content"].as_str().unwrap_or_default();
+    match merged.iter_mut().find(|m| m["role"] == "user") {
+        Some(user) => {
+            let content = user["content"].as_str().unwrap_or_default();
+            user["content"] = json!(format!("{system}\n\n{content}"));
+        }
+        None => merged.insert(0, json!({ "role": "user", "content": system })),
+    }
+    Some(merged)
+}
+
+/// Render the loaded template, recording failures as error codes
+/// A template that rejects the conversation while it starts with a system
+/// message is retried with that message folded into the first user turn, for
+/// templates (Gemma, Mistral) that have no system role.
+fn render(messages: &[Value], add_generation_prompt: bool) -> Result<String, c_int> {
+    let guard = CHAT_TEMPLATE.read().unwrap_or_else(PoisonError::into_inner);
+    let template = match guard.as_ref() {
+        Some(t) => t,
+        None => {
+            return Err(fail(
+                ERR_NO_CHAT_TEMPLATE,
+                "no chat template loaded; call tokenizer_load_chat_template first",
+            ))
+        }
+    };
+
+    let lookup =
+        |kind| try_with_tokenizer(|t| special_token(t, kind).map(|(content, _)| content)).flatten();
+    let bos_token = template
+        .bos_token
+        .clone()
+        .or_else(|| lookup(SpecialKind::Bos));
+    let eos_token = template
+        .eos_token
+        .clone()
+        .or_else(|| lookup(SpecialKind::Eos));
+
+    let env = environment();
+    let render = |messages: &[Value]| {
+        env.render_str(
+            &template.source,
+            context! { messages, add_generation_prompt, bos_token, eos_token },
+        )
+    };
+
+    render(messages)
+        .or_else(|e| match merge_system_message(messages) {
+            Some(merged) => render(&merged).map_err(|_| e),
+            None => Err(e),
+        })
+        .map_err(|e| fail(ERR_TEMPLATE_FAILED, format!("chat template failed: {e:#}")))
+}
+
+/// Format a conversation with the loaded chat template
+/// `roles[i]`/`contents[i]` describe message `i` ("system", "user",
+/// "assistant", ...). With `add_generation_prompt` non-zero the template's
+/// assistant header is appended so the model continues as the assistant.
+/// Writes the prompt with the `tokenizer_decode` buffer contract.
+/// Returns bytes written (excluding NUL) or the required size, negative on error:
+///   -1 null arguments, -2 invalid UTF-8, -6 buffer too small,
+///   -12 no chat template loaded, -13 the template failed to render
+#[no_mangle]
+pub extern "C" fn tokenizer_apply_chat_template(
+    roles: *const *const c_char,
+    contents: *const *const c_char,
+    count: usize,
+    add_generation_prompt: c_int,
+    out_text: *mut c_char,
+    capacity: usize,
+) -> c_int {
+    catch_panic(|| {
+        let messages = match messages_arg(roles, contents, count) {
+            Ok(m) => m,
+            Err(code) => return code,
+        };
+
+        match render(&messages, add_generation_prompt != 0) {
+            Ok(prompt) => write_c_str(&prompt, out_text, capacity),
+            Err(code) => code,
+        }
+    })
+}
+
+/// `tokenizer_apply_chat_template` straight to token IDs
+/// The template already places BOS and other markers, so the prompt is encoded
+/// without the post-processor's special tokens.
+/// Returns number of tokens on success, negative on error (as
+/// `tokenizer_apply_chat_template`, plus `tokenizer_encode` errors)
+#[no_mangle]
+pub extern "C" fn tokenizer_apply_chat_template_ids(
+    roles: *const *const c_char,
+    contents: *const *const c_char,
+    count: usize,
+    add_generation_prompt: c_int,
+    out_ids: *mut c_int,
+    max_len: usize,
+) -> c_int {
+    catch_panic(|| {
+        if let Err(code) = guard::out_buffer(
+            "tokenizer_apply_chat_template_ids",
+            "out_ids",
+            out_ids,
+            max_len,
+            std::ptr::null(),
+        ) {
+            return code;
+        }
+        let messages = match messages_arg(roles, contents, count) {
+            Ok(m) => m,
+            Err(code) => return code,
+        };
+        let prompt = match render(&messages, add_generation_prompt != 0) {
+            Ok(prompt) => prompt,
+            Err(code) => return code,
+        };
+
+        with_tokenizer(|tokenizer| match tokenizer.encode(prompt.as_str(), false) {
+            Ok(encoding) => copy_ids(encoding.get_ids(), out_ids, max_len),
+            Err(e) => tokenizer_failed("encode", e),
+        })
+    })
+}
+
+/// Exact token count of a conversation as `tokenizer_apply_chat_template_ids`
+/// would encode it, template overhead (role headers, BOS, end markers)
+/// included, without a maximum length
+/// Returns the token count on success, negative on error (as
+/// `tokenizer_apply_chat_template_ids`)
+#[no_mangle]
+pub extern "C" fn tokenizer_count_chat_tokens(
+    roles: *const *const c_char,
+    contents: *const *const c_char,
+    count: usize,
+    add_generation_prompt: c_int,
+) -> c_int {
+    catch_panic(|| {
+        let messages = match messages_arg(roles, contents, count) {
+            Ok(m) => m,
+            Err(code) => return code,
+        };
+        let prompt = match render(&messages, add_generation_prompt != 0) {
+            Ok(prompt) => prompt,
+            Err(code) => return code,
+        };
+
+        with_tokenizer(
+            |tokenizer| match tokenizer.encode_fast(prompt.as_str(), false) {
+                Ok(encoding) => encoding.len() as c_int,
+                Err(e) => tokenizer_failed("encode", e),
+            },
+        )
+    })
+}
+
+/// `tokenizer_count_chat_tokens`, also splitting the count across messages
+/// `out_counts[i]` receives how many tokens message `i` adds to the prompt
+/// rendered from the messages before it (its content plus its own template
+/// overhead). Whatever the template emits around the messages, such as a
+/// preamble or the generation prompt, belongs to no message, so the counts
+/// sum to at most the returned total. Every prefix of the conversation is
+/// rendered, so this costs more than a single count; confirm the total of a
+/// trimmed conversation with `tokenizer_count_chat_tokens`, since a template
+/// may format the first remaining message differently.
+/// Returns the total token count on success, negative on error (as
+/// `tokenizer_count_chat_tokens`)
+#[no_mangle]
+pub extern "C" fn tokenizer_count_chat_tokens_per_message(
+    roles: *const *const c_char,
+    contents: *const *const c_char,
+    count: usize,
+    add_generation_prompt: c_int,
+    out_counts: *mut c_int,
+) -> c_int {
+    catch_panic(|| {
+        if out_counts.is_null() && count > 0 {
+            return null_output("out_counts");
+        }
+        let messages = match messages_arg(roles, contents, count) {
+            Ok(m) => m,
+            Err(code) => return code,
+        };
+
+        // Templates that refuse an empty conversation simply have no preamble,
+        // which is not worth reporting as an error
+        let previous_error = last_error_message();
+        let empty = render(&[], false).unwrap_or_else(|_| {
+            set_last_error(previous_error);
+            String::new()
+        });
+        let mut prefixes = vec![empty];
+        for end in 1..=count {
+            match render(&messages[..end], false) {
+                Ok(prompt) => prefixes.push(prompt),
+                Err(code) => return code,
+            }
+        }
+        let full = match render(&messages, add_generation_prompt != 0) {
+            Ok(prompt) => prompt,
+            Err(code) => return code,
+        };
+
+        with_tokenizer(|tokenizer| {
+            let mut lengths = Vec::with_capacity(prefixes.len());
+            for prompt in prefixes.iter().chain([&full]) {
+                match tokenizer.encode_fast(prompt.as_str(), false) {
+                    Ok(encoding) => lengths.push(encoding.len() as c_int),
+                    Err(e) => return tokenizer_failed("encode", e),
+                }
+            }
+
+            for (i, pair) in lengths[..=count].windows(2).enumerate() {
+                unsafe { *out_counts.add(i) = pair[1] - pair[0] };
+            }
+            lengths[count + 1]
+        })
+    })
+}
+
+/// Keep the most recent messages that fit in `max_tokens`
+/// Finds the longest suffix of the conversation whose prompt, counted as by
+/// `tokenizer_count_chat_tokens` with the generation prompt added, is at most
+/// `max_tokens` tokens, and writes 1 (kept) or 0 (dropped) into
+/// `out_keep_flags[i]` for each message. With `keep_system` non-zero a
+/// leading system message is always kept in front of the suffix. A
+/// conversation that fits whole comes back unchanged; otherwise the suffix
+/// starts at a user message when there is one to start at, since many
+/// templates reject a conversation that opens with an assistant turn. Only
+/// about log2(`count`) prompts are rendered, which relies on a longer
+/// conversation never rendering to fewer tokens.
+/// Returns the token count of the kept conversation, negative on error (as
+/// `tokenizer_count_chat_tokens`), or -21 when not even the system message
+/// (if kept) and the latest user message fit, in which case the flags are
+/// left unwritten
+#[no_mangle]
+pub extern "C" fn tokenizer_fit_chat(
+    roles: *const *const c_char,
+    contents: *const *const c_char,
+    count: usize,
+    max_tokens: usize,
+    keep_system: c_int,
+    out_keep_flags: *mut c_int,
+) -> c_int {
+    catch_panic(|| {
+        if out_keep_flags.is_null() && count > 0 {
+            return null_output("out_keep_flags");
+        }
+        let messages = match messages_arg(roles, contents, count) {
+            Ok(m) => m,
+            Err(code) => return code,
+        };
+        if messages.is_empty() {
+            return 0;
+        }
+
+        let system = keep_system != 0 && messages[0]["role"] == "system";
+        let first = system as usize;
+        // Where a kept suffix may start, oldest first: the whole conversation,
+        // then each user message; the last one always keeps the latest user
+        // message (or, without one, the latest message)
+        let mut starts: Vec<usize> = (first..count)
+            .filter(|&i| i == first || messages[i]["role"] == "user")
+            .collect();
+        if starts.len() == 1 && messages[first]["role"] != "user" {
+            starts = (first..count).collect();
+        }
+
+        with_tokenizer(|tokenizer| {
+            let tokens_from = |start: usize| -> Result<usize, c_int> {
+                let kept: Vec<Value> = messages[..first]
+                    .iter()
+                    .chain(&messages[start..])
+                    .cloned()
+                    .collect();
+                let prompt = render(&kept, true)?;
+                match tokenizer.encode_fast(prompt.as_str(), false) {
+                    Ok(encoding) => Ok(encoding.len()),
+                    Err(e) => Err(tokenizer_failed("encode", e)),
+                }
+            };
+
+            // The earliest start that fits, and its token count
+            let mut fit = None;
+            let (mut lo, mut hi) = (0, starts.len());
+            while lo < hi {
+                let mid = lo + (hi - lo) / 2;
+                match tokens_from(starts[mid]) {
+                    Ok(tokens) if tokens <= max_tokens => {
+                        fit = Some((starts[mid], tokens));
+                        hi = mid;
+                    }
+                    Ok(_) => lo = mid + 1,
+                    Err(code) => return code,
+                }
+            }
+            // A conversation of only the system message
+            if starts.is_empty() {
+                fit = match tokens_from(count) {
+                    Ok(tokens) if tokens <= max_tokens => Some((count, tokens)),
+                    Ok(_) => None,
+                    Err(code) => return code,
+                };
+            }
+
+            let Some((start, tokens)) = fit else {
+                return fail(
+                    ERR_DOES_NOT_FIT,
+                    format!("the latest message does not fit in {max_tokens} tokens"),
+                );
+            };
+            for i in 0..count {
+                let kept = i < first || i >= start;
+                unsafe { *out_keep_flags.add(i) = kept as c_int };
+            }
+            tokens.min(c_int::MAX as usize) as c_int
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{install, llama_json, serial, write_temp};
+    use std::ffi::{CStr, CString};
+
+    const CHATML: &str = "{% for message in messages %}<|im_start|>{{ message['role'] }}\n\
+        {{ message['content'] }}<|im_end|>\n{% endfor %}\
+        {% if add_generation_prompt %}<|im_start|>assistant\n{% endif %}";
+
+    /// Gemma-like: no system role, and needs `bos_token`
+    const NO_SYSTEM: &str = "{{ bos_token }}{% for message in messages %}\
+        {% if message['role'] == 'system' %}{{ raise_exception('System role not supported') }}{% endif %}\
+        [{{ message['role'] }}] {{ message['content'].strip() }}\n{% endfor %}";
+
+    fn load(template: &str, extra: Value) -> c_int {
+        let mut config = json!({ "chat_template": template });
+        config
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        let path = write_temp("chat_config", &config.to_string());
+        let path = CString::new(path.to_str().unwrap()).unwrap();
+        tokenizer_load_chat_template(path.as_ptr())
+    }
+
+    fn apply(messages: &[(&str, &str)], add_generation_prompt: bool) -> Result<String, c_int> {
+        let roles: Vec<CString> = messages
+            .iter()
+            .map(|m| CString::new(m.0).unwrap())
+            .collect();
+        let contents: Vec<CString> = messages
+            .iter()
+            .map(|m| CString::new(m.1).unwrap())
+            .collect();
+        let roles: Vec<*const c_char> = roles.iter().map(|r| r.as_ptr()).collect();
+        let contents: Vec<*const c_char> = contents.iter().map(|c| c.as_ptr()).collect();
+
+        let call = |out: *mut c_char, cap| {
+            tokenizer_apply_chat_template(
+                roles.as_ptr(),
+                contents.as_ptr(),
+                messages.len(),
+                add_generation_prompt as c_int,
+                out,
+                cap,
+            )
+        };
+        let needed = call(std::ptr::null_mut(), 0);
+        if needed < 0 {
+            return Err(needed);
+        }
+        let mut buf = vec![0u8; needed as usize + 1];
+        assert_eq!(call(buf.as_mut_ptr() as *mut c_char, buf.len()), needed);
+        Ok(CStr::from_bytes_with_nul(&buf)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_owned())
+    }
+
+    #[test]
+    fn renders_chatml_with_generation_prompt() {
+        let _guard = serial();
+        assert_eq!(load(CHATML, json!({})), 0);
+
+        let prompt = apply(&[("system", "Be brief."), ("user", "Hi")], true).unwrap();
+        assert_eq!(
+            prompt,
+            "<|im_start|>system\nBe brief.<|im_end|>\n<|im_start|>user\nHi<|im_end|>\n\
+             <|im_start|>assistant\n"
+        );
+    }
+
+    #[test]
+    fn system_message_is_folded_into_the_first_user_turn() {
+        let _guard = serial();
+        assert_eq!(
+            load(NO_SYSTEM, json!({ "bos_token": { "content": "<bos>" } })),
+            0
+        );
+
+        let prompt = apply(&[("system", "Be brief."), ("user", " Hi ")], false).unwrap();
+        assert_eq!(prompt, "<bos>[user] Be brief.\n\n Hi\n");
+    }
+
+    #[test]
+    fn special_tokens_default_to_the_loaded_tokenizer() {
+        let _guard = serial();
+        install(&llama_json());
+        assert_eq!(load(NO_SYSTEM, json!({})), 0);
+
+        assert_eq!(
+            apply(&[("user", "hello")], false).unwrap(),
+            "<s>[user] hello\n"
+        );
+
+        let (role, content) = (
+            CString::new("user").unwrap(),
+            CString::new("hello").unwrap(),
+        );
+        let mut ids = [0; 16];
+        let n = tokenizer_apply_chat_template_ids(
+            &role.as_ptr(),
+            &content.as_ptr(),
+            1,
+            0,
+            ids.as_mut_ptr(),
+            ids.len(),
+        );
+        assert!(n > 0);
+        // The template's BOS is the only one
+        assert_eq!(ids[..n as usize].iter().filter(|&&id| id == 1).count(), 1);
+        assert_eq!(ids[0], 1);
+    }
+
+    #[test]
+    fn load_and_render_failures_have_their_own_codes() {
+        let _guard = serial();
+        assert_eq!(load("{% for %}", json!({})), ERR_TEMPLATE_FAILED);
+
+        let path = write_temp("chat_config_empty", "{}");
+        let path = CString::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(tokenizer_load_chat_template(path.as_ptr()), -3);
+        // Malformed JSON is rejected as by tokenizer_load_config
+        let path = write_temp("chat_config_bad", r#"{"chat_template": "#);
+        let path = CString::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(
+            tokenizer_load_chat_template(path.as_ptr()),
+            crate::ERR_INVALID_CONFIG
+        );
+
+        assert_eq!(load("{{ raise_exception('nope') }}", json!({})), 0);
+        assert_eq!(apply(&[("user", "hi")], false), Err(ERR_TEMPLATE_FAILED));
+    }
+
+    #[test]
+    fn chat_counts_match_the_encoded_prompt() {
+        let _guard = serial();
+        install(&llama_json());
+        assert_eq!(load(CHATML, json!({})), 0);
+
+        let roles = [c"system".as_ptr(), c"user".as_ptr(), c"assistant".as_ptr()];
+        let contents = [
+            c"hello".as_ptr(),
+            c"hello world".as_ptr(),
+            c"world".as_ptr(),
+        ];
+        let mut ids = [0; 256];
+        let n = tokenizer_apply_chat_template_ids(
+            roles.as_ptr(),
+            contents.as_ptr(),
+            3,
+            1,
+            ids.as_mut_ptr(),
+            ids.len(),
+        );
+        assert!(n > 0);
+        assert_eq!(
+            tokenizer_count_chat_tokens(roles.as_ptr(), contents.as_ptr(), 3, 1),
+            n
+        );
+
+        let mut counts = [0; 3];
+        let total = tokenizer_count_chat_tokens_per_message(
+            roles.as_ptr(),
+            contents.as_ptr(),
+            3,
+            1,
+            counts.as_mut_ptr(),
+        );
+        assert_eq!(total, n);
+        // Only the generation prompt belongs to no message
+        let without_prompt = tokenizer_count_chat_tokens(roles.as_ptr(), contents.as_ptr(), 3, 0);
+        assert_eq!(counts.iter().sum::<c_int>(), without_prompt);
+        let first_two = tokenizer_count_chat_tokens(roles.as_ptr(), contents.as_ptr(), 2, 0);
+        assert_eq!(counts[2], without_prompt - first_two);
+        assert!(counts.iter().all(|&c| c > 0));
+    }
+
+    fn fit(
+        messages: &[(&CStr, &CStr)],
+        max_tokens: usize,
+        keep_system: c_int,
+    ) -> (c_int, Vec<c_int>) {
+        let roles: Vec<_> = messages.iter().map(|(role, _)| role.as_ptr()).collect();
+        let contents: Vec<_> = messages
+            .iter()
+            .map(|(_, content)| content.as_ptr())
+            .collect();
+        let mut flags = vec![-1; messages.len()];
+        let rc = tokenizer_fit_chat(
+            roles.as_ptr(),
+            contents.as_ptr(),
+            messages.len(),
+            max_tokens,
+            keep_system,
+            flags.as_mut_ptr(),
+        );
+        (rc, flags)
+    }
+
+    fn count(messages: &[(&CStr, &CStr)]) -> c_int {
+        let roles: Vec<_> = messages.iter().map(|(role, _)| role.as_ptr()).collect();
+        let contents: Vec<_> = messages
+            .iter()
+            .map(|(_, content)| content.as_ptr())
+            .collect();
+        tokenizer_count_chat_tokens(roles.as_ptr(), contents.as_ptr(), messages.len(), 1)
+    }
+
+    #[test]
+    fn fitting_drops_the_oldest_turns_first() {
+        let _guard = serial();
+        install(&llama_json());
+        assert_eq!(load(CHATML, json!({})), 0);
+
+        let messages = [
+            (c"system", c"be brief"),
+            (c"user", c"hello world hello world"),
+            (c"assistant", c"hello"),
+            (c"user", c"world"),
+            (c"assistant", c"hello world"),
+            (c"user", c"hello"),
+        ];
+        let everything = count(&messages);
+        assert_eq!(
+            fit(&messages, everything as usize, 1),
+            (everything, vec![1; 6])
+        );
+
+        // One token short: the first exchange goes, the system message stays
+        let (tokens, flags) = fit(&messages, everything as usize - 1, 1);
+        assert_eq!(flags, [1, 0, 0, 1, 1, 1]);
+        let kept = [messages[0], messages[3], messages[4], messages[5]];
+        assert_eq!(tokens, count(&kept));
+
+        let latest = [messages[0], messages[5]];
+        let (tokens, flags) = fit(&messages, count(&latest) as usize, 1);
+        assert_eq!((tokens, flags), (count(&latest), vec![1, 0, 0, 0, 0, 1]));
+        let (tokens, flags) = fit(&messages, count(&latest) as usize, 0);
+        assert_eq!(flags, [0, 0, 0, 0, 0, 1]);
+        assert_eq!(tokens, count(&messages[5..]));
+
+        // Without keep_system the system message still stays when all fits
+        assert_eq!(
+            fit(&messages, everything as usize, 0),
+            (everything, vec![1; 6])
+        );
+
+        let (rc, flags) = fit(&messages, count(&latest) as usize - 1, 1);
+        assert_eq!((rc, flags), (ERR_DOES_NOT_FIT, vec![-1; 6]));
+    }
+}
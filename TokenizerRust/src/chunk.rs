@@ -0,0 +1,670 @@
+//! Splitting long documents into overlapping, model-ready token windows, and
+//! trimming them to a token budget.
+
+use std::borrow::Cow;
+use std::ffi::{c_char, c_int};
+use tokenizers::{Encoding, PostProcessor, Tokenizer, TruncationDirection};
+
+use crate::settings::truncation_direction;
+use crate::{
+    catch_panic, copy_ids, fail, guard, null_output, text_arg, tokenizer_failed, with_tokenizer,
+    write_c_str, ERR_INVALID_ARGUMENT, ERR_INVALID_LENGTH,
+};
+
+/// The tokenizer with any configured truncation and padding switched off
+/// Only clones when there is something to switch off.
+pub(crate) fn without_limits(tokenizer: &Tokenizer) -> Cow<'_, Tokenizer> {
+    if tokenizer.get_truncation().is_none() && tokenizer.get_padding().is_none() {
+        return Cow::Borrowed(tokenizer);
+    }
+
+    let mut unlimited = tokenizer.clone();
+    unlimited.with_padding(None);
+    // Clearing truncation cannot fail
+    let _ = unlimited.with_truncation(None);
+    Cow::Owned(unlimited)
+}
+
+/// Cut `text` into windows of at most `max_tokens` tokens, special tokens included
+/// Consecutive windows share `stride` tokens, and every window carries the
+/// special tokens the post-processor adds (e.g. `[CLS] ... [SEP]`), so each
+/// one can be fed to the model as is. Truncation and padding configured with
+/// `tokenizer_set_truncation`/`tokenizer_set_padding` are ignored here.
+/// Window IDs are packed back to back into `out_ids`, which must hold
+/// `max_chunks * max_tokens` slots; `out_chunk_lengths[i]` receives the length
+/// of window `i`. `out_chunk_offsets` (may be null) receives the UTF-8 byte
+/// offset in `text` where each window's first token starts.
+/// Only the first `max_chunks` windows are written.
+/// Returns the total number of windows, negative on error:
+///   -1 null arguments, -2 invalid UTF-8, -3 not initialized, -4 encode failed,
+///   -9 `max_tokens` leaves no room for text or `stride` is
+///   not smaller than that room, -19 `max_chunks * max_tokens` overflows or
+///   exceeds the length limit, -20 `out_ids` is misaligned or overlaps `text`
+#[no_mangle]
+pub extern "C" fn tokenizer_encode_chunked(
+    text: *const c_char,
+    max_tokens: usize,
+    stride: usize,
+    out_ids: *mut c_int,
+    out_chunk_lengths: *mut c_int,
+    max_chunks: usize,
+    out_chunk_offsets: *mut c_int,
+) -> c_int {
+    catch_panic(|| {
+        tokenizer_encode_chunked_ex(
+            text,
+            max_tokens,
+            stride,
+            0,
+            out_ids,
+            out_chunk_lengths,
+            max_chunks,
+            out_chunk_offsets,
+        )
+    })
+}
+
+/// `tokenizer_encode_chunked` with the windows laid out from either end
+/// `direction` 0 starts the first window at the start of the text, so only
+/// the last window may be short; 1 ends the last window at the end of the
+/// text, so that one is always full and the first may be short. Windows are
+/// written in text order either way.
+/// Returns the total number of windows, negative on error (as
+/// `tokenizer_encode_chunked`, with -9 also for an invalid direction)
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub extern "C" fn tokenizer_encode_chunked_ex(
+    text: *const c_char,
+    max_tokens: usize,
+    stride: usize,
+    direction: c_int,
+    out_ids: *mut c_int,
+    out_chunk_lengths: *mut c_int,
+    max_chunks: usize,
+    out_chunk_offsets: *mut c_int,
+) -> c_int {
+    catch_panic(|| {
+        let direction = match truncation_direction(direction) {
+            Ok(d) => d,
+            Err(code) => return code,
+        };
+        if max_chunks > 0 && (out_ids.is_null() || out_chunk_lengths.is_null()) {
+            return null_output("out_ids/out_chunk_lengths");
+        }
+        let text_str = match text_arg(text) {
+            Ok(s) => s,
+            Err(code) => return code,
+        };
+
+        with_tokenizer(|tokenizer| {
+            let processor = tokenizer.get_post_processor();
+            let added = processor.map_or(0, |p| p.added_tokens(false));
+            let room = max_tokens.saturating_sub(added);
+            if room == 0 || stride >= room {
+                return fail(
+                    ERR_INVALID_ARGUMENT,
+                    format!(
+                        "max_tokens {max_tokens} leaves {room} tokens after {added} special \
+                         tokens, which must exceed stride {stride}"
+                    ),
+                );
+            }
+
+            if max_chunks > 0 {
+                let Some(slots) = max_chunks.checked_mul(max_tokens) else {
+                    return fail(
+                        ERR_INVALID_LENGTH,
+                        format!(
+                            "{max_chunks} chunks of {max_tokens} tokens overflow the buffer size"
+                        ),
+                    );
+                };
+                if let Err(code) = guard::out_buffer(
+                    "tokenizer_encode_chunked_ex",
+                    "out_ids",
+                    out_ids,
+                    slots,
+                    text,
+                ) {
+                    return code;
+                }
+            }
+
+            let mut encoding = match without_limits(tokenizer).encode(text_str, false) {
+                Ok(enc) => enc,
+                Err(e) => return tokenizer_failed("encode", e),
+            };
+            encoding.truncate(room, stride, direction);
+            let overflowing = encoding.take_overflowing();
+            let mut windows: Vec<Encoding> = std::iter::once(encoding).chain(overflowing).collect();
+            // Left truncation keeps the last window and overflows backwards
+            if direction == TruncationDirection::Left {
+                windows.reverse();
+            }
+
+            let mut packed = 0;
+            for (i, window) in windows.iter().take(max_chunks).enumerate() {
+                let start = window.get_offsets().first().map_or(0, |&(s, _)| s);
+                let window = match processor {
+                    Some(p) => match p.process(window.clone(), None, true) {
+                        Ok(enc) => enc,
+                        Err(e) => return tokenizer_failed("post-process", e),
+                    },
+                    None => window.clone(),
+                };
+
+                let len = copy_ids(window.get_ids(), unsafe { out_ids.add(packed) }, max_tokens);
+                if len < 0 {
+                    return len;
+                }
+                packed += len as usize;
+                unsafe {
+                    *out_chunk_lengths.add(i) = len;
+                    if !out_chunk_offsets.is_null() {
+                        *out_chunk_offsets.add(i) = start as c_int;
+                    }
+                }
+            }
+
+            windows.len() as c_int
+        })
+    })
+}
+
+/// Split `text` into overlapping pieces of at most `max_tokens` tokens and
+/// report their byte ranges, for callers that re-encode each piece themselves
+/// Tokens are counted without the post-processor's special tokens. Each piece
+/// after the first starts `overlap_tokens` tokens before the previous one
+/// ends. A piece ends early, by up to a quarter of `max_tokens`, if that lets
+/// it end before a word instead of inside one, and never between the
+/// byte-fallback tokens of one character: should those not fit in the
+/// quarter, the piece grows past `max_tokens` to the character's last token
+/// instead. `out_starts[i]`/`out_ends[i]`
+/// receive piece `i` as the UTF-8 byte range `[start, end)` of `text`, always
+/// on character boundaries and without surrounding whitespace. A text that
+/// fits in `max_tokens` is one piece, and a text without tokens has none.
+/// Only the first `max_chunks` ranges are written.
+/// Returns the total number of pieces, negative on error:
+///   -1 null arguments, -2 invalid UTF-8, -3 not initialized, -4 encode failed,
+///   -9 `max_tokens` is 0 or `overlap_tokens` is not smaller than it
+#[no_mangle]
+pub extern "C" fn tokenizer_chunk_text(
+    text: *const c_char,
+    max_tokens: usize,
+    overlap_tokens: usize,
+    out_starts: *mut c_int,
+    out_ends: *mut c_int,
+    max_chunks: usize,
+) -> c_int {
+    catch_panic(|| {
+        if max_chunks > 0 && (out_starts.is_null() || out_ends.is_null()) {
+            return null_output("out_starts/out_ends");
+        }
+        if overlap_tokens >= max_tokens {
+            return fail(
+                ERR_INVALID_ARGUMENT,
+                format!("overlap {overlap_tokens} must be smaller than max_tokens {max_tokens}"),
+            );
+        }
+        let text_str = match text_arg(text) {
+            Ok(s) => s,
+            Err(code) => return code,
+        };
+
+        with_tokenizer(|tokenizer| {
+            let offsets = match without_limits(tokenizer).encode(&*text_str, false) {
+                Ok(enc) => enc.get_offsets().to_vec(),
+                Err(e) => return tokenizer_failed("encode", e),
+            };
+
+            let pieces = token_pieces(&text_str, &offsets, max_tokens, overlap_tokens);
+            for (i, &(first, end)) in pieces.iter().take(max_chunks).enumerate() {
+                let start = floor_char_boundary(&text_str, offsets[first].0);
+                let end = ceil_char_boundary(&text_str, offsets[end - 1].1);
+                // Offsets of `▁word`-style tokens include the space before them
+                let piece = &text_str[start..end];
+                let start = start + (piece.len() - piece.trim_start().len());
+                let end = start.max(end - (piece.len() - piece.trim_end().len()));
+                unsafe {
+                    *out_starts.add(i) = start as c_int;
+                    *out_ends.add(i) = end as c_int;
+                }
+            }
+            pieces.len() as c_int
+        })
+    })
+}
+
+/// Token index ranges `[first, end)` for `tokenizer_chunk_text`
+fn token_pieces(
+    text: &str,
+    offsets: &[(usize, usize)],
+    max_tokens: usize,
+    overlap: usize,
+) -> Vec<(usize, usize)> {
+    // Cutting before token `i` keeps characters whole, and ideally words too
+    let splits_nothing = |i: usize| offsets[i - 1].1 <= offsets[i].0;
+    let starts_word = |i: usize| {
+        text.get(offsets[i - 1].1..)
+            .and_then(|rest| rest.chars().next())
+            .is_some_and(char::is_whitespace)
+    };
+
+    let mut pieces = Vec::new();
+    let mut first = 0;
+    while first < offsets.len() {
+        let mut end = (first + max_tokens).min(offsets.len());
+        if end < offsets.len() {
+            let earliest = (first + overlap + 1).max(end - (max_tokens / 4).min(end - 1));
+            let cuts = (earliest..=end).rev();
+            end = cuts
+                .clone()
+                .find(|&i| splits_nothing(i) && starts_word(i))
+                .or_else(|| cuts.clone().find(|&i| splits_nothing(i)))
+                // One character has more byte-fallback tokens than leave room
+                // for a cut: the piece takes all of them
+                .or_else(|| (end + 1..offsets.len()).find(|&i| splits_nothing(i)))
+                .unwrap_or(offsets.len());
+        }
+        pieces.push((first, end));
+        if end == offsets.len() {
+            break;
+        }
+        first = end - overlap;
+    }
+    pieces
+}
+
+/// Cut `text` to at most `max_tokens` tokens and return the kept substring
+/// Only the text's own tokens count: the budget excludes the special tokens
+/// the post-processor would add, and configured truncation and padding are
+/// ignored. `direction` 0 keeps the start of the text, 1 keeps the end. The cut
+/// falls on token boundaries taken from the encoding's offsets, never inside a
+/// character, and whitespace at the cut is dropped; a character spread over
+/// several byte-fallback tokens is kept only if all of them fit. A text that
+/// already fits is returned unchanged. `out_token_count` (may be null)
+/// receives the token count of the kept text, which is at most `max_tokens`.
+/// Writes the text with the `tokenizer_decode` buffer contract.
+/// Returns bytes written (excluding NUL) or the required size, negative on error:
+///   -1 null `text`, -2 invalid UTF-8, -3 not initialized, -4 encode failed,
+///   -6 buffer too small, -9 invalid direction
+#[no_mangle]
+pub extern "C" fn tokenizer_truncate_text(
+    text: *const c_char,
+    max_tokens: usize,
+    direction: c_int,
+    out_text: *mut c_char,
+    capacity: usize,
+    out_token_count: *mut c_int,
+) -> c_int {
+    catch_panic(|| {
+        let direction = match truncation_direction(direction) {
+            Ok(d) => d,
+            Err(code) => return code,
+        };
+        let text_str = match text_arg(text) {
+            Ok(s) => s,
+            Err(code) => return code,
+        };
+
+        with_tokenizer(|tokenizer| {
+            let tokenizer = without_limits(tokenizer);
+            let offsets = match tokenizer.encode(&*text_str, false) {
+                Ok(enc) => enc.get_offsets().to_vec(),
+                Err(e) => return tokenizer_failed("encode", e),
+            };
+
+            // Keep `kept` of the original tokens; should the kept piece encode to
+            // more tokens on its own (a split character, different merges at the
+            // cut), keep one token less until it fits
+            let mut kept = offsets.len().min(max_tokens);
+            let (piece, count) = loop {
+                let piece = match direction {
+                    _ if kept == offsets.len() => &*text_str,
+                    _ if kept == 0 => "",
+                    TruncationDirection::Right => {
+                        let cut = offsets[kept - 1].1;
+                        text_str[..floor_char_boundary(&text_str, cut)].trim_end()
+                    }
+                    TruncationDirection::Left => {
+                        let cut = offsets[offsets.len() - kept].0;
+                        text_str[ceil_char_boundary(&text_str, cut)..].trim_start()
+                    }
+                };
+                let count = match tokenizer.encode_fast(piece, false) {
+                    Ok(enc) => enc.len(),
+                    Err(e) => return tokenizer_failed("encode", e),
+                };
+                if count <= max_tokens || kept == 0 {
+                    break (piece, count);
+                }
+                kept -= 1;
+            };
+
+            if !out_token_count.is_null() {
+                unsafe { *out_token_count = count as c_int };
+            }
+            write_c_str(piece, out_text, capacity)
+        })
+    })
+}
+
+fn floor_char_boundary(text: &str, mut at: usize) -> usize {
+    at = at.min(text.len());
+    while !text.is_char_boundary(at) {
+        at -= 1;
+    }
+    at
+}
+
+fn ceil_char_boundary(text: &str, mut at: usize) -> usize {
+    at = at.min(text.len());
+    while !text.is_char_boundary(at) {
+        at += 1;
+    }
+    at
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{bert_json, install, llama_json, serial};
+    use std::ffi::CString;
+
+    struct Chunks {
+        total: c_int,
+        windows: Vec<Vec<c_int>>,
+        offsets: Vec<c_int>,
+    }
+
+    fn chunk(text: &str, max_tokens: usize, stride: usize, max_chunks: usize) -> Chunks {
+        chunk_from(text, max_tokens, stride, 0, max_chunks)
+    }
+
+    fn chunk_from(
+        text: &str,
+        max_tokens: usize,
+        stride: usize,
+        direction: c_int,
+        max_chunks: usize,
+    ) -> Chunks {
+        let c_text = CString::new(text).unwrap();
+        let mut ids = vec![0; max_tokens * max_chunks];
+        let (mut lengths, mut offsets) = (vec![0; max_chunks], vec![0; max_chunks]);
+        let total = tokenizer_encode_chunked_ex(
+            c_text.as_ptr(),
+            max_tokens,
+            stride,
+            direction,
+            ids.as_mut_ptr(),
+            lengths.as_mut_ptr(),
+            max_chunks,
+            offsets.as_mut_ptr(),
+        );
+        assert!(total >= 0, "chunking failed with {total}");
+
+        let written = (total as usize).min(max_chunks);
+        let mut rest = ids.as_slice();
+        let windows = lengths[..written]
+            .iter()
+            .map(|&len| {
+                let (window, tail) = rest.split_at(len as usize);
+                rest = tail;
+                window.to_vec()
+            })
+            .collect();
+        offsets.truncate(written);
+        Chunks {
+            total,
+            windows,
+            offsets,
+        }
+    }
+
+    #[test]
+    fn windows_overlap_and_carry_special_tokens() {
+        let _guard = serial();
+        install(&bert_json());
+
+        // 8 text tokens, 3 per window after [CLS]/[SEP], 1 shared between windows
+        let text = "hello world the token hello world the token";
+        let chunks = chunk(text, 5, 1, 8);
+        assert_eq!(chunks.total, 4);
+        assert_eq!(
+            chunks.windows,
+            vec![
+                vec![2, 5, 6, 7, 3],
+                vec![2, 7, 8, 5, 3],
+                vec![2, 5, 6, 7, 3],
+                vec![2, 7, 8, 3],
+            ]
+        );
+        assert_eq!(chunks.offsets, vec![0, 12, 22, 34]);
+    }
+
+    #[test]
+    fn left_windows_end_at_the_end_of_the_text() {
+        let _guard = serial();
+        install(&bert_json());
+
+        let text = "hello world the token hello world the token";
+        // The short window is now the first one; the last ends with the text
+        let chunks = chunk_from(text, 5, 1, 1, 8);
+        assert_eq!(chunks.total, 4);
+        assert_eq!(
+            chunks.windows,
+            vec![
+                vec![2, 5, 6, 3],
+                vec![2, 6, 7, 8, 3],
+                vec![2, 8, 5, 6, 3],
+                vec![2, 6, 7, 8, 3],
+            ]
+        );
+        assert_eq!(chunks.offsets, vec![0, 6, 16, 28]);
+    }
+
+    #[test]
+    fn total_is_reported_even_when_buffers_are_short() {
+        let _guard = serial();
+        install(&llama_json());
+
+        let chunks = chunk("hello world hello world hello world", 3, 0, 2);
+        assert_eq!(chunks.total, 3);
+        assert_eq!(chunks.windows, vec![vec![1, 296, 301], vec![1, 296, 301]]);
+    }
+
+    #[test]
+    fn configured_truncation_does_not_limit_chunking() {
+        let _guard = serial();
+        install(&bert_json());
+        assert_eq!(crate::settings::tokenizer_set_truncation(3, 0, 0), 0);
+
+        let chunks = chunk("hello world the token", 4, 0, 4);
+        assert_eq!(chunks.windows, vec![vec![2, 5, 6, 3], vec![2, 7, 8, 3]]);
+    }
+
+    #[test]
+    fn stride_must_be_smaller_than_the_room_for_text() {
+        let _guard = serial();
+        install(&bert_json());
+
+        let text = CString::new("hello world").unwrap();
+        let (mut ids, mut lengths) = ([0; 8], [0; 4]);
+        for (max_tokens, stride) in [(2, 0), (4, 2), (1, 0)] {
+            let rc = tokenizer_encode_chunked(
+                text.as_ptr(),
+                max_tokens,
+                stride,
+                ids.as_mut_ptr(),
+                lengths.as_mut_ptr(),
+                lengths.len(),
+                std::ptr::null_mut(),
+            );
+            assert_eq!(
+                rc, ERR_INVALID_ARGUMENT,
+                "max_tokens {max_tokens}, stride {stride}"
+            );
+        }
+    }
+
+    #[test]
+    fn window_buffer_sizes_are_checked() {
+        let _guard = serial();
+        install(&bert_json());
+
+        let text = CString::new("hello world").unwrap();
+        let (mut ids, mut lengths) = ([0; 8], [0; 4]);
+        let (ids, lengths) = (ids.as_mut_ptr(), lengths.as_mut_ptr());
+        let chunked = |max_tokens, max_chunks| {
+            tokenizer_encode_chunked(
+                text.as_ptr(),
+                max_tokens,
+                0,
+                ids,
+                lengths,
+                max_chunks,
+                std::ptr::null_mut(),
+            )
+        };
+        // The product of the two wraps around to a small number
+        assert_eq!(chunked(usize::MAX / 2 + 1, 2), ERR_INVALID_LENGTH);
+        assert_eq!(chunked(1 << 40, 4), ERR_INVALID_LENGTH);
+        assert_eq!(chunked(4, 2), 1);
+    }
+
+    fn truncate(text: &str, max_tokens: usize, direction: c_int) -> (String, c_int) {
+        let c_text = CString::new(text).unwrap();
+        let mut buf = vec![0u8; text.len() + 1];
+        let mut count = -1;
+        let n = tokenizer_truncate_text(
+            c_text.as_ptr(),
+            max_tokens,
+            direction,
+            buf.as_mut_ptr() as *mut c_char,
+            buf.len(),
+            &mut count,
+        );
+        assert!(n >= 0, "truncate failed with {n}");
+        buf.truncate(n as usize);
+        (String::from_utf8(buf).unwrap(), count)
+    }
+
+    #[test]
+    fn truncated_text_is_a_prefix_or_suffix_within_budget() {
+        let _guard = serial();
+        install(&llama_json());
+        assert_eq!(truncate("hello world", 1, 0), ("hello".to_owned(), 1));
+        assert_eq!(truncate("hello world", 1, 1), ("world".to_owned(), 1));
+        assert_eq!(
+            truncate("hello world", 10, 0),
+            ("hello world".to_owned(), 2)
+        );
+
+        // Cuts between and inside the byte-fallback tokens of ż, ó, ł, ć and 🚀
+        let text = "hello zażółć 🚀";
+        for max_tokens in 0..=32 {
+            let (start, count) = truncate(text, max_tokens, 0);
+            assert!(text.starts_with(&start) && count as usize <= max_tokens);
+            let (end, count) = truncate(text, max_tokens, 1);
+            assert!(text.ends_with(&end) && count as usize <= max_tokens);
+        }
+        assert_eq!(truncate(text, 0, 0), (String::new(), 0));
+
+        let c_text = CString::new(text).unwrap();
+        let rc = tokenizer_truncate_text(
+            c_text.as_ptr(),
+            4,
+            2,
+            std::ptr::null_mut(),
+            0,
+            std::ptr::null_mut(),
+        );
+        assert_eq!(rc, ERR_INVALID_ARGUMENT);
+    }
+
+    #[test]
+    fn special_tokens_do_not_count_against_the_budget() {
+        let _guard = serial();
+        install(&bert_json());
+        assert_eq!(
+            truncate("hello world the token", 2, 0),
+            ("hello world".to_owned(), 2)
+        );
+        assert_eq!(
+            truncate("hello world the token", 2, 1),
+            ("the token".to_owned(), 2)
+        );
+    }
+
+    fn pieces(text: &str, max_tokens: usize, overlap: usize) -> Vec<&str> {
+        let c_text = CString::new(text).unwrap();
+        let (mut starts, mut ends) = (vec![0; 64], vec![0; 64]);
+        let n = tokenizer_chunk_text(
+            c_text.as_ptr(),
+            max_tokens,
+            overlap,
+            starts.as_mut_ptr(),
+            ends.as_mut_ptr(),
+            64,
+        );
+        assert!(n >= 0, "chunking failed with {n}");
+        (0..n as usize)
+            .map(|i| &text[starts[i] as usize..ends[i] as usize])
+            .collect()
+    }
+
+    fn count(text: &str) -> usize {
+        let text = CString::new(text).unwrap();
+        crate::tokenizer_count_tokens(text.as_ptr(), 0) as usize
+    }
+
+    #[test]
+    fn text_pieces_overlap_and_end_between_words() {
+        let _guard = serial();
+        install(&llama_json());
+        let text = "hello world hello world hello world hello world hello";
+        let chunks = pieces(text, 4, 1);
+        assert_eq!(chunks.first(), Some(&"hello world hello world"));
+        assert_eq!(chunks.get(1), Some(&"world hello world hello"));
+        assert!(text.ends_with(chunks.last().unwrap()));
+        assert!(chunks.iter().all(|c| count(c) <= 4));
+
+        assert_eq!(pieces("hello world", 4, 1), ["hello world"]);
+        assert!(pieces("", 4, 1).is_empty());
+
+        // Byte-fallback characters are never cut apart
+        let text = "zażółć gęślą jaźń 🚀";
+        for max_tokens in 2..8 {
+            let chunks = pieces(text, max_tokens, 1);
+            assert!(!chunks.is_empty());
+            assert!(text.starts_with(chunks[0]) && text.ends_with(chunks.last().unwrap()));
+        }
+        // A cut after 3 tokens would fall inside the emoji's four byte tokens;
+        // the piece grows to keep it whole and no piece repeats it
+        let text = "hello 🚀 world";
+        assert_eq!(pieces(text, 3, 0), ["hello 🚀", "world"]);
+    }
+
+    #[test]
+    fn chunk_overlap_must_leave_room() {
+        let _guard = serial();
+        install(&bert_json());
+        let text = CString::new("hello world").unwrap();
+        let (mut starts, mut ends) = ([0; 4], [0; 4]);
+        for (max_tokens, overlap) in [(2, 2), (0, 0)] {
+            let rc = tokenizer_chunk_text(
+                text.as_ptr(),
+                max_tokens,
+                overlap,
+                starts.as_mut_ptr(),
+                ends.as_mut_ptr(),
+                4,
+            );
+            assert_eq!(rc, ERR_INVALID_ARGUMENT);
+        }
+        assert_eq!(
+            pieces("hello world the token", 2, 0),
+            ["hello world", "the token"]
+        );
+    }
+}
@@ -0,0 +1,226 @@
+//! Side-by-side checks of two tokenizer.json files, for validating a model
+//! migration before switching to the new tokenizer.
+//!
+//! Both tokenizers are loaded just for the comparison and dropped afterwards;
+//! the global tokenizer is never touched.
+
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::ffi::{c_char, c_int};
+use tokenizers::Tokenizer;
+
+use crate::info::{model_type, special_tokens};
+use crate::{c_str_arg, catch_panic, fail, null_output, text_arg, tokenizer_failed, write_c_str};
+
+/// Load the tokenizer at `path`, naming the argument it came from on failure
+/// Errors: -1 null path, -2 invalid UTF-8, -3 load failed
+fn load(name: &str, path: *const c_char) -> Result<(String, Tokenizer), c_int> {
+    if path.is_null() {
+        return Err(null_output(name));
+    }
+    let path = c_str_arg(path)?;
+    match Tokenizer::from_file(path) {
+        Ok(tokenizer) => Ok((path.to_owned(), tokenizer)),
+        Err(e) => Err(fail(-3, format!("failed to load {name} '{path}': {e}"))),
+    }
+}
+
+fn describe(path: &str, tokenizer: &Tokenizer) -> Value {
+    json!({
+        "path": path,
+        "model_type": model_type(tokenizer),
+        "vocab_size": tokenizer.get_vocab_size(false),
+        "vocab_size_with_added": tokenizer.get_vocab_size(true),
+    })
+}
+
+/// Special tokens present in only one tokenizer, and those in both under
+/// different IDs
+fn special_differences(a: &Tokenizer, b: &Tokenizer) -> Value {
+    let by_content = |tokenizer| -> BTreeMap<String, u32> {
+        special_tokens(tokenizer)
+            .into_iter()
+            .map(|(id, content)| (content, id))
+            .collect()
+    };
+    let (a, b) = (by_content(a), by_content(b));
+    let only_in = |this: &BTreeMap<String, u32>, other: &BTreeMap<String, u32>| -> Vec<Value> {
+        this.iter()
+            .filter(|(content, _)| !other.contains_key(*content))
+            .map(|(content, id)| json!({ "id": id, "content": content }))
+            .collect()
+    };
+    let changed: Vec<Value> = a
+        .iter()
+        .filter_map(|(content, &id_a)| {
+            let id_b = *b.get(content)?;
+            (id_a != id_b).then(|| json!({ "content": content, "id_a": id_a, "id_b": id_b }))
+        })
+        .collect();
+
+    json!({
+        "identical": a == b,
+        "only_in_a": only_in(&a, &b),
+        "only_in_b": only_in(&b, &a),
+        "changed_ids": changed,
+    })
+}
+
+/// Compare the tokenizer.json files at `path_a` and `path_b` on sample texts
+/// and write the findings into `out_report_json` as a JSON object
+/// Both files are loaded only for this call, so the tokenizer installed with
+/// `tokenizer_initialize*` stays as it is. Each of the `sample_count` texts is
+/// encoded with special tokens added by both. Fields: `a` and `b` as
+/// `{"path", "model_type", "vocab_size", "vocab_size_with_added"}`;
+/// `special_tokens` with `identical`, `only_in_a`/`only_in_b` as
+/// `{"id", "content"}` sorted by content and `changed_ids` as
+/// `{"content", "id_a", "id_b"}`; `samples` as `{"tokens_a", "tokens_b",
+/// "identical_ids"}` per sample, in order; and `all_samples_identical`.
+/// Writes the JSON with the `tokenizer_decode` buffer contract.
+/// Returns bytes written (excluding NUL) or the required size, negative on error:
+///   -1 null argument, -2 invalid UTF-8, -3 a file failed to load (the error
+///   message names `path_a` or `path_b`), -4 encode failed, -6 buffer too small
+#[no_mangle]
+pub extern "C" fn tokenizer_compare(
+    path_a: *const c_char,
+    path_b: *const c_char,
+    sample_texts: *const *const c_char,
+    sample_count: usize,
+    out_report_json: *mut c_char,
+    capacity: usize,
+) -> c_int {
+    catch_panic(|| {
+        if sample_texts.is_null() && sample_count > 0 {
+            return null_output("sample_texts");
+        }
+        let samples = if sample_count == 0 {
+            &[][..]
+        } else {
+            unsafe { std::slice::from_raw_parts(sample_texts, sample_count) }
+        };
+        let mut texts = Vec::with_capacity(samples.len());
+        for (i, &text) in samples.iter().enumerate() {
+            if text.is_null() {
+                return null_output(&format!("sample_texts[{i}]"));
+            }
+            match text_arg(text) {
+                Ok(text) => texts.push(text),
+                Err(code) => return code,
+            }
+        }
+        let loaded = load("path_a", path_a).and_then(|a| Ok((a, load("path_b", path_b)?)));
+        let ((path_a, a), (path_b, b)) = match loaded {
+            Ok(both) => both,
+            Err(code) => return code,
+        };
+
+        let mut report_samples = Vec::with_capacity(texts.len());
+        let mut all_identical = true;
+        for text in &texts {
+            let encoded = a
+                .encode(&**text, true)
+                .and_then(|ea| Ok((ea, b.encode(&**text, true)?)));
+            let (ea, eb) = match encoded {
+                Ok(pair) => pair,
+                Err(e) => return tokenizer_failed("encode", e),
+            };
+            let identical = ea.get_ids() == eb.get_ids();
+            all_identical &= identical;
+            report_samples.push(json!({
+                "tokens_a": ea.len(),
+                "tokens_b": eb.len(),
+                "identical_ids": identical,
+            }));
+        }
+
+        let report = json!({
+            "a": describe(&path_a, &a),
+            "b": describe(&path_b, &b),
+            "special_tokens": special_differences(&a, &b),
+            "samples": report_samples,
+            "all_samples_identical": all_identical,
+        });
+        write_c_str(&report.to_string(), out_report_json, capacity)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{bert_json, install, llama_json, serial, write_temp};
+    use std::ffi::{CStr, CString};
+
+    fn compare(a: &CString, b: &CString, samples: &[&str]) -> Result<Value, c_int> {
+        let samples: Vec<CString> = samples.iter().map(|s| CString::new(*s).unwrap()).collect();
+        let ptrs: Vec<_> = samples.iter().map(|s| s.as_ptr()).collect();
+        let call = |out: *mut c_char, capacity| {
+            tokenizer_compare(
+                a.as_ptr(),
+                b.as_ptr(),
+                ptrs.as_ptr(),
+                ptrs.len(),
+                out,
+                capacity,
+            )
+        };
+        let needed = call(std::ptr::null_mut(), 0);
+        if needed < 0 {
+            return Err(needed);
+        }
+        let mut buf = vec![0u8; needed as usize + 1];
+        assert_eq!(call(buf.as_mut_ptr() as *mut c_char, buf.len()), needed);
+        let json = CStr::from_bytes_with_nul(&buf).unwrap().to_str().unwrap();
+        Ok(serde_json::from_str(json).unwrap())
+    }
+
+    fn path(name: &str, json: &str) -> CString {
+        CString::new(write_temp(name, json).to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn reports_differences_without_touching_the_global_tokenizer() {
+        let _guard = serial();
+        install(&bert_json());
+        let size = crate::vocab::tokenizer_vocab_size(1);
+
+        let llama = path("compare_llama", &llama_json());
+        let mut extended: Value = serde_json::from_str(&llama_json()).unwrap();
+        // The next ID after ▁world (301)
+        let id = 302;
+        extended["added_tokens"]
+            .as_array_mut()
+            .unwrap()
+            .push(json!({
+                "id": id, "content": "<|tool|>", "single_word": false, "lstrip": false,
+                "rstrip": false, "normalized": false, "special": true
+            }));
+        let extended = path("compare_extended", &extended.to_string());
+
+        let samples = ["hello world", "calling <|tool|>"];
+        let report = compare(&llama, &llama, &samples).unwrap();
+        assert_eq!(report["special_tokens"]["identical"], true);
+        assert_eq!(report["all_samples_identical"], true);
+        assert_eq!(report["a"]["vocab_size"], report["b"]["vocab_size"]);
+
+        let report = compare(&llama, &extended, &samples).unwrap();
+        let special = &report["special_tokens"];
+        assert_eq!(special["identical"], false);
+        assert_eq!(special["only_in_a"], json!([]));
+        assert_eq!(
+            special["only_in_b"],
+            json!([{ "id": id, "content": "<|tool|>" }])
+        );
+        assert_eq!(report["samples"][0]["identical_ids"], true);
+        let second = &report["samples"][1];
+        assert_eq!(second["identical_ids"], false);
+        assert!(second["tokens_a"].as_u64() > second["tokens_b"].as_u64());
+        assert_eq!(report["all_samples_identical"], false);
+        assert_eq!(crate::vocab::tokenizer_vocab_size(1), size);
+
+        let missing = CString::new("/nonexistent/tokenizer.json").unwrap();
+        assert_eq!(compare(&llama, &missing, &samples), Err(-3));
+        assert!(crate::last_error_message().contains("path_b"));
+        assert_eq!(compare(&missing, &llama, &[]), Err(-3));
+        assert!(crate::last_error_message().contains("path_a"));
+    }
+}
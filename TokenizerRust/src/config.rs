@@ -0,0 +1,266 @@
+//! Settings from the tokenizer_config.json shipped next to tokenizer.json:
+//! the model's maximum sequence length, BOS/EOS overrides, padding side and
+//! chat template.
+//!
+//! The config describes the tokenizer it ships with, so loading or freeing a
+//! tokenizer drops it, chat template included; load the config after
+//! `tokenizer_initialize`.
+
+use serde_json::Value;
+use std::ffi::{c_char, c_int};
+use std::sync::{PoisonError, RwLock};
+use tokenizers::PaddingDirection;
+
+use crate::chat::{clear_config_template, install_template, template_from_config};
+use crate::special::SpecialKind;
+use crate::{c_str_arg, catch_panic, fail, ERR_INVALID_CONFIG};
+
+struct TokenizerConfig {
+    model_max_length: Option<usize>,
+    bos_token: Option<String>,
+    eos_token: Option<String>,
+    padding_side: Option<PaddingDirection>,
+}
+
+static CONFIG: RwLock<Option<TokenizerConfig>> = RwLock::new(None);
+
+/// Config tokens are either plain strings or serialized `AddedToken`s
+pub(crate) fn config_token(config: &Value, key: &str) -> Option<String> {
+    match &config[key] {
+        Value::String(s) => Some(s.clone()),
+        Value::Object(token) => token.get("content")?.as_str().map(str::to_owned),
+        _ => None,
+    }
+}
+
+/// `model_max_length`, unless absent or too large to be real
+/// `transformers` writes int(1e30) when the length is unknown, which parses
+/// as a float here; anything beyond the C int range is treated the same way.
+fn model_max_length(config: &Value) -> Option<usize> {
+    config["model_max_length"]
+        .as_u64()
+        .filter(|&n| n <= c_int::MAX as u64)
+        .map(|n| n as usize)
+}
+
+fn padding_side(config: &Value) -> Option<PaddingDirection> {
+    match config["padding_side"].as_str()? {
+        "left" => Some(PaddingDirection::Left),
+        "right" => Some(PaddingDirection::Right),
+        _ => None,
+    }
+}
+
+/// The BOS or EOS token the loaded config names, if any
+pub(crate) fn token_override(kind: SpecialKind) -> Option<String> {
+    let guard = CONFIG.read().unwrap_or_else(PoisonError::into_inner);
+    let config = guard.as_ref()?;
+    match kind {
+        SpecialKind::Bos => config.bos_token.clone(),
+        SpecialKind::Eos => config.eos_token.clone(),
+        SpecialKind::Pad | SpecialKind::Unk => None,
+    }
+}
+
+/// The padding side the loaded config asks for, if any
+pub(crate) fn configured_padding_side() -> Option<PaddingDirection> {
+    let guard = CONFIG.read().unwrap_or_else(PoisonError::into_inner);
+    guard.as_ref()?.padding_side
+}
+
+/// Forget the loaded config and the chat template it installed, as when the
+/// tokenizer it belongs to is replaced
+pub(crate) fn clear() {
+    *CONFIG.write().unwrap_or_else(PoisonError::into_inner) = None;
+    clear_config_template();
+}
+
+/// Read and parse a tokenizer_config.json
+/// Errors: -3 file unreadable, -18 not a JSON object
+pub(crate) fn read_config(path: &str) -> Result<Value, c_int> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => return Err(fail(-3, format!("failed to read '{path}': {e}"))),
+    };
+    match serde_json::from_str(&text) {
+        Ok(config @ Value::Object(_)) => Ok(config),
+        Ok(_) => Err(fail(
+            ERR_INVALID_CONFIG,
+            format!("'{path}' is not a JSON object"),
+        )),
+        Err(e) => Err(fail(
+            ERR_INVALID_CONFIG,
+            format!("'{path}' is not valid JSON: {e}"),
+        )),
+    }
+}
+
+/// Load a tokenizer_config.json, replacing any previously loaded one
+/// `model_max_length` becomes available through `tokenizer_model_max_length`,
+/// `bos_token`/`eos_token` take precedence over the tokenizer's own when BOS
+/// and EOS are looked up, `padding_side` is what `tokenizer_set_padding` uses
+/// for direction -1, and a `chat_template` is loaded as by
+/// `tokenizer_load_chat_template` (a config without one keeps the current
+/// template). Loading another tokenizer or freeing this one drops all of it.
+/// Returns 0 on success, negative on error:
+///   -1 null path, -2 invalid UTF-8, -3 file unreadable, -13 the chat template
+///   does not compile, -18 not a JSON object
+#[no_mangle]
+pub extern "C" fn tokenizer_load_config(path: *const c_char) -> c_int {
+    catch_panic(|| {
+        let path = match c_str_arg(path) {
+            Ok(p) => p,
+            Err(code) => return code,
+        };
+
+        let config = match read_config(path) {
+            Ok(config) => config,
+            Err(code) => return code,
+        };
+        let template = match template_from_config(&config) {
+            Ok(template) => template,
+            Err(code) => return code,
+        };
+
+        *CONFIG.write().unwrap_or_else(PoisonError::into_inner) = Some(TokenizerConfig {
+            model_max_length: model_max_length(&config),
+            bos_token: config_token(&config, "bos_token"),
+            eos_token: config_token(&config, "eos_token"),
+            padding_side: padding_side(&config),
+        });
+        if let Some(template) = template {
+            install_template(template, true);
+        }
+        0
+    })
+}
+
+/// `model_max_length` from the loaded tokenizer_config.json
+/// Returns the length, or -1 when no config is loaded, it does not declare
+/// one, or it declares the "unspecified" sentinel `transformers` writes (1e30)
+#[no_mangle]
+pub extern "C" fn tokenizer_model_max_length() -> c_int {
+    catch_panic(|| {
+        let guard = CONFIG.read().unwrap_or_else(PoisonError::into_inner);
+        guard
+            .as_ref()
+            .and_then(|config| config.model_max_length)
+            .map_or(-1, |n| n as c_int)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{bert_json, install, llama_json, serial, write_temp};
+    use std::ffi::CString;
+
+    fn load(name: &str, contents: &str) -> c_int {
+        let path = write_temp(name, contents);
+        let path = CString::new(path.to_str().unwrap()).unwrap();
+        tokenizer_load_config(path.as_ptr())
+    }
+
+    #[test]
+    fn max_length_and_the_unspecified_sentinel() {
+        let _guard = serial();
+        assert_eq!(load("config_len", r#"{"model_max_length": 4096}"#), 0);
+        assert_eq!(tokenizer_model_max_length(), 4096);
+
+        let sentinel = r#"{"model_max_length": 1000000000000000019884624838656}"#;
+        assert_eq!(load("config_sentinel", sentinel), 0);
+        assert_eq!(tokenizer_model_max_length(), -1);
+        assert_eq!(load("config_empty", "{}"), 0);
+        assert_eq!(tokenizer_model_max_length(), -1);
+    }
+
+    #[test]
+    fn overrides_feed_special_tokens_and_padding() {
+        let _guard = serial();
+        install(&llama_json());
+        let config = r#"{"eos_token": {"content": "<unk>"}, "padding_side": "left"}"#;
+        assert_eq!(load("config_overrides", config), 0);
+
+        assert_eq!(crate::special::tokenizer_eos_id(), 0);
+        assert_eq!(crate::special::tokenizer_bos_id(), 1);
+
+        install(&bert_json());
+        assert_eq!(load("config_overrides", config), 0);
+        assert_eq!(crate::settings::tokenizer_set_padding(5, 0, -1), 0);
+        let text = CString::new("hello").unwrap();
+        let mut ids = [9; 8];
+        assert_eq!(
+            crate::tokenizer_encode(text.as_ptr(), ids.as_mut_ptr(), 8),
+            5
+        );
+        assert_eq!(ids[..5], [0, 0, 2, 5, 3]);
+
+        install(&llama_json());
+        assert_eq!(load("config_reset", "{}"), 0);
+        assert_eq!(crate::special::tokenizer_eos_id(), 2);
+    }
+
+    #[test]
+    fn loading_or_freeing_a_tokenizer_drops_the_config() {
+        let _guard = serial();
+        install(&llama_json());
+        let config = r#"{"model_max_length": 4096, "eos_token": "<unk>"}"#;
+        assert_eq!(load("config_dropped", config), 0);
+        assert_eq!(crate::special::tokenizer_eos_id(), 0);
+
+        install(&llama_json());
+        assert_eq!(tokenizer_model_max_length(), -1);
+        assert_eq!(crate::special::tokenizer_eos_id(), 2);
+
+        assert_eq!(load("config_freed", config), 0);
+        crate::tokenizer_free();
+        assert_eq!(tokenizer_model_max_length(), -1);
+    }
+
+    #[test]
+    fn only_the_config_template_is_dropped_with_the_tokenizer() {
+        let _guard = serial();
+        let (role, content) = (
+            CString::new("user").unwrap(),
+            CString::new("hello").unwrap(),
+        );
+        let count = || {
+            let roles = [role.as_ptr()];
+            let contents = [content.as_ptr()];
+            crate::chat::tokenizer_count_chat_tokens(roles.as_ptr(), contents.as_ptr(), 1, 0)
+        };
+        let config = r#"{"chat_template": "{{ messages[0]['content'] }}"}"#;
+
+        install(&llama_json());
+        assert_eq!(load("config_chat", config), 0);
+        assert!(count() > 0);
+        install(&llama_json());
+        assert_eq!(count(), crate::ERR_NO_CHAT_TEMPLATE);
+
+        // A template loaded on its own is not part of any config
+        let path = write_temp("config_chat_only", config);
+        let path = CString::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(crate::chat::tokenizer_load_chat_template(path.as_ptr()), 0);
+        install(&llama_json());
+        assert!(count() > 0);
+    }
+
+    #[test]
+    fn malformed_configs_keep_the_previous_one() {
+        let _guard = serial();
+        assert_eq!(load("config_good", r#"{"model_max_length": 512}"#), 0);
+
+        assert_eq!(
+            load("config_bad", r#"{"model_max_length": 5"#),
+            ERR_INVALID_CONFIG
+        );
+        assert!(crate::last_error_message().contains("not valid JSON"));
+        assert_eq!(load("config_array", "[]"), ERR_INVALID_CONFIG);
+        let broken = r#"{"model_max_length": 8, "chat_template": "{% for %}"}"#;
+        assert_eq!(load("config_template", broken), crate::ERR_TEMPLATE_FAILED);
+        assert_eq!(tokenizer_model_max_length(), 512);
+
+        let missing = CString::new("/definitely/missing/tokenizer_config.json").unwrap();
+        assert_eq!(tokenizer_load_config(missing.as_ptr()), -3);
+    }
+}
@@ -0,0 +1,298 @@
+//! Encodings kept on the library side behind a handle, so callers can size
+//! their arrays from `encoding_len` and copy only the fields they need.
+//!
+//! A handle owns its `Encoding` outright, so it stays valid (and unchanged)
+//! when the tokenizer that produced it is reinitialized or freed.
+
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int};
+use std::sync::{OnceLock, PoisonError, RwLock};
+use tokenizers::Encoding;
+
+use crate::handles::next_handle;
+use crate::{
+    catch_panic, copy_ids, fail, guard, text_arg, tokenizer_failed, with_tokenizer,
+    ERR_INVALID_HANDLE,
+};
+
+fn registry() -> &'static RwLock<HashMap<i64, Encoding>> {
+    static ENCODINGS: OnceLock<RwLock<HashMap<i64, Encoding>>> = OnceLock::new();
+    ENCODINGS.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+fn invalid(handle: i64) -> c_int {
+    fail(
+        ERR_INVALID_HANDLE,
+        format!("encoding handle {handle} is unknown or already freed"),
+    )
+}
+
+/// Run `f` on the encoding behind `handle`
+fn with_encoding(handle: i64, f: impl FnOnce(&Encoding) -> c_int) -> c_int {
+    let encodings = registry().read().unwrap_or_else(PoisonError::into_inner);
+    match encodings.get(&handle) {
+        Some(encoding) => f(encoding),
+        None => invalid(handle),
+    }
+}
+
+/// Check an output buffer of `call` like `tokenizer_encode` does, unless
+/// `max_len` is 0 and nothing will be written
+fn check_out(call: &str, name: &str, out: *mut c_int, max_len: usize) -> Result<(), c_int> {
+    match max_len {
+        0 => Ok(()),
+        _ => guard::out_buffer(call, name, out, max_len, std::ptr::null()),
+    }
+}
+
+/// Copy at most `max_len` values of one field into `out`, the buffer `name`
+/// of `call`
+fn copy_field<T: Copy>(
+    call: &str,
+    name: &str,
+    handle: i64,
+    out: *mut c_int,
+    max_len: usize,
+    field: impl Fn(&Encoding) -> &[T],
+    convert: impl Fn(T) -> c_int,
+) -> c_int {
+    if let Err(code) = check_out(call, name, out, max_len) {
+        return code;
+    }
+    with_encoding(handle, |encoding| {
+        let values = field(encoding);
+        let len = values.len().min(max_len);
+        for (i, &value) in values[..len].iter().enumerate() {
+            unsafe { *out.add(i) = convert(value) };
+        }
+        len as c_int
+    })
+}
+
+/// Encode `text` and keep the result behind a new encoding handle
+/// Nothing is copied out until an `encoding_*` accessor asks for it; release
+/// the handle with `encoding_free`.
+/// Returns a positive handle on success, negative on error:
+///   -1 null `text`, -2 invalid UTF-8, -3 not initialized, -4 encode failed
+#[no_mangle]
+pub extern "C" fn tokenizer_encode_new(text: *const c_char, add_special_tokens: c_int) -> i64 {
+    catch_panic(|| {
+        let text_str = match text_arg(text) {
+            Ok(s) => s,
+            Err(code) => return code as i64,
+        };
+
+        let mut encoding = None;
+        let rc = with_tokenizer(|tokenizer| {
+            match tokenizer.encode(&*text_str, add_special_tokens != 0) {
+                Ok(e) => {
+                    encoding = Some(e);
+                    0
+                }
+                Err(e) => tokenizer_failed("encode", e),
+            }
+        });
+        let Some(encoding) = encoding else {
+            return rc as i64;
+        };
+
+        let handle = next_handle();
+        registry()
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(handle, encoding);
+        handle
+    })
+}
+
+/// Number of tokens in an encoding, the length every `encoding_copy_*` array needs
+/// Returns the length, -7 unknown or freed handle
+#[no_mangle]
+pub extern "C" fn encoding_len(handle: i64) -> c_int {
+    catch_panic(|| with_encoding(handle, |encoding| encoding.len() as c_int))
+}
+
+/// Copy the token IDs of an encoding, at most `max_len` of them
+/// `out_ids` is checked like the buffer of `tokenizer_encode` unless `max_len` is 0.
+/// Returns the number copied, negative on error:
+///   -1 null `out_ids` with non-zero `max_len`, -7 unknown or freed handle,
+///   -11 ID out of range, -19/-20 as for `tokenizer_encode`
+#[no_mangle]
+pub extern "C" fn encoding_copy_ids(handle: i64, out_ids: *mut c_int, max_len: usize) -> c_int {
+    catch_panic(|| {
+        if let Err(code) = check_out("encoding_copy_ids", "out_ids", out_ids, max_len) {
+            return code;
+        }
+        with_encoding(handle, |encoding| {
+            copy_ids(encoding.get_ids(), out_ids, max_len)
+        })
+    })
+}
+
+/// Copy the byte range of each token in the encoded text, at most `max_len`
+/// Ranges are as from `tokenizer_encode_with_offsets`: UTF-8 byte offsets,
+/// end exclusive, and (-1, -1) for special tokens added by the post-processor.
+/// Both outputs are checked as in `encoding_copy_ids`.
+/// Returns the number copied, negative on error:
+///   -1 null output with non-zero `max_len`, -7 unknown or freed handle,
+///   -19/-20 as for `tokenizer_encode`
+#[no_mangle]
+pub extern "C" fn encoding_copy_offsets(
+    handle: i64,
+    out_starts: *mut c_int,
+    out_ends: *mut c_int,
+    max_len: usize,
+) -> c_int {
+    catch_panic(|| {
+        let call = "encoding_copy_offsets";
+        let checked = check_out(call, "out_starts", out_starts, max_len)
+            .and_then(|_| check_out(call, "out_ends", out_ends, max_len));
+        if let Err(code) = checked {
+            return code;
+        }
+        with_encoding(handle, |encoding| {
+            let spans = encoding
+                .get_offsets()
+                .iter()
+                .zip(encoding.get_special_tokens_mask());
+            let len = encoding.len().min(max_len);
+            for (i, (&(start, end), &special)) in spans.take(len).enumerate() {
+                let (start, end) = if special == 1 {
+                    (-1, -1)
+                } else {
+                    (start as c_int, end as c_int)
+                };
+                unsafe {
+                    *out_starts.add(i) = start;
+                    *out_ends.add(i) = end;
+                }
+            }
+            len as c_int
+        })
+    })
+}
+
+/// Copy the word index of each token, at most `max_len`, as from
+/// `tokenizer_encode_with_word_ids` (-1 for special tokens)
+/// Returns the number copied, negative on error:
+///   -1 null `out_word_ids` with non-zero `max_len`, -7 unknown or freed handle,
+///   -19/-20 as for `encoding_copy_ids`
+#[no_mangle]
+pub extern "C" fn encoding_copy_word_ids(
+    handle: i64,
+    out_word_ids: *mut c_int,
+    max_len: usize,
+) -> c_int {
+    catch_panic(|| {
+        copy_field(
+            "encoding_copy_word_ids",
+            "out_word_ids",
+            handle,
+            out_word_ids,
+            max_len,
+            Encoding::get_word_ids,
+            |word| word.map_or(-1, |w| w as c_int),
+        )
+    })
+}
+
+/// Copy the type (segment) ID of each token, at most `max_len`: 0 for the
+/// first sequence, 1 for the second of a pair
+/// Returns the number copied, negative on error:
+///   -1 null `out_type_ids` with non-zero `max_len`, -7 unknown or freed handle,
+///   -19/-20 as for `encoding_copy_ids`
+#[no_mangle]
+pub extern "C" fn encoding_copy_type_ids(
+    handle: i64,
+    out_type_ids: *mut c_int,
+    max_len: usize,
+) -> c_int {
+    catch_panic(|| {
+        copy_field(
+            "encoding_copy_type_ids",
+            "out_type_ids",
+            handle,
+            out_type_ids,
+            max_len,
+            Encoding::get_type_ids,
+            |type_id| type_id as c_int,
+        )
+    })
+}
+
+/// Release an encoding handle
+/// Returns 0 on success, -7 unknown or already freed
+#[no_mangle]
+pub extern "C" fn encoding_free(handle: i64) -> c_int {
+    catch_panic(|| {
+        let mut encodings = registry().write().unwrap_or_else(PoisonError::into_inner);
+        match encodings.remove(&handle) {
+            Some(_) => 0,
+            None => invalid(handle),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{bert_json, install, llama_json, serial};
+    use std::ffi::CString;
+
+    fn encode_new(text: &str) -> i64 {
+        let text = CString::new(text).unwrap();
+        let handle = tokenizer_encode_new(text.as_ptr(), 1);
+        assert!(handle > 0, "encode failed with {handle}");
+        handle
+    }
+
+    #[test]
+    fn accessors_copy_each_field() {
+        let _guard = serial();
+        install(&bert_json());
+        let handle = encode_new("hello world");
+        let len = encoding_len(handle);
+        assert_eq!(len, 4);
+
+        let mut ids = vec![0; len as usize];
+        assert_eq!(encoding_copy_ids(handle, ids.as_mut_ptr(), ids.len()), len);
+        assert_eq!(ids, [2, 5, 6, 3]);
+
+        let (mut starts, mut ends) = (vec![0; 4], vec![0; 4]);
+        let n = encoding_copy_offsets(handle, starts.as_mut_ptr(), ends.as_mut_ptr(), 4);
+        assert_eq!(n, 4);
+        assert_eq!((starts, ends), (vec![-1, 0, 6, -1], vec![-1, 5, 11, -1]));
+
+        let mut words = vec![0; 4];
+        assert_eq!(encoding_copy_word_ids(handle, words.as_mut_ptr(), 4), 4);
+        assert_eq!(words, [-1, 0, 1, -1]);
+
+        let mut types = vec![9; 4];
+        assert_eq!(encoding_copy_type_ids(handle, types.as_mut_ptr(), 2), 2);
+        assert_eq!(types, [0, 0, 9, 9]);
+
+        assert_eq!(encoding_free(handle), 0);
+    }
+
+    #[test]
+    fn handles_outlive_the_tokenizer_and_reject_reuse() {
+        let _guard = serial();
+        install(&bert_json());
+        let handle = encode_new("hello");
+        install(&llama_json());
+        crate::tokenizer_free();
+
+        let mut ids = [0; 8];
+        assert_eq!(encoding_copy_ids(handle, ids.as_mut_ptr(), 8), 3);
+        assert_eq!(ids[..3], [2, 5, 3]);
+
+        assert_eq!(encoding_free(handle), 0);
+        assert_eq!(encoding_free(handle), ERR_INVALID_HANDLE);
+        assert_eq!(encoding_len(handle), ERR_INVALID_HANDLE);
+        assert_eq!(
+            encoding_copy_ids(handle, ids.as_mut_ptr(), 8),
+            ERR_INVALID_HANDLE
+        );
+        assert_eq!(encoding_len(0), ERR_INVALID_HANDLE);
+    }
+}
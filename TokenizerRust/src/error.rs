@@ -0,0 +1,249 @@
+//! Error codes and the per-thread last-error message behind `tokenizer_last_error`.
+//!
+//! Every failing call records a human-readable message before returning its
+//! negative code. Successful calls leave the previous message untouched, so the
+//! message always describes the most recent failure on the calling thread.
+//!
+//! Every export runs its body under `catch_panic`, so a panic (inside the
+//! tokenizers crate or our own code) never unwinds into the caller and is
+//! reported as `ERR_PANICKED` (-100); exports without a status code just
+//! return. Locks shrug off poisoning, so the library keeps working after such
+//! a failure.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::log;
+
+// Error codes shared by the functions added after `tokenizer_encode`.
+// They deliberately reuse the numbering `tokenizer_encode` already exposes.
+pub(crate) const ERR_NULL_POINTER: c_int = -1;
+pub(crate) const ERR_INVALID_UTF8: c_int = -2;
+pub(crate) const ERR_NOT_INITIALIZED: c_int = -3;
+pub(crate) const ERR_TOKENIZER_FAILED: c_int = -4;
+// -5 used to mean "lock poisoned"; poisoned locks are now recovered instead
+pub(crate) const ERR_BUFFER_TOO_SMALL: c_int = -6;
+pub(crate) const ERR_INVALID_HANDLE: c_int = -7;
+pub(crate) const ERR_INVALID_UTF16: c_int = -8;
+pub(crate) const ERR_INVALID_ARGUMENT: c_int = -9;
+pub(crate) const ERR_NO_PAD_TOKEN: c_int = -10;
+pub(crate) const ERR_ID_OUT_OF_RANGE: c_int = -11;
+pub(crate) const ERR_NO_CHAT_TEMPLATE: c_int = -12;
+pub(crate) const ERR_TEMPLATE_FAILED: c_int = -13;
+pub(crate) const ERR_UNSUPPORTED_MODEL: c_int = -14;
+pub(crate) const ERR_STILL_LOADING: c_int = -15;
+pub(crate) const ERR_WRITE_FAILED: c_int = -16;
+pub(crate) const ERR_NO_SPECIAL_TOKEN: c_int = -17;
+pub(crate) const ERR_INVALID_CONFIG: c_int = -18;
+pub(crate) const ERR_INVALID_LENGTH: c_int = -19;
+pub(crate) const ERR_BAD_POINTER: c_int = -20;
+pub(crate) const ERR_DOES_NOT_FIT: c_int = -21;
+pub(crate) const ERR_PANICKED: c_int = -100;
+
+thread_local! {
+    static LAST_ERROR: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// Record `message` as the calling thread's last error, log it, and return `code`
+pub(crate) fn fail(code: c_int, message: impl Into<String>) -> c_int {
+    let message = message.into();
+    log::log(log::LOG_ERROR, || format!("error {code}: {message}"));
+    set_last_error(message);
+    code
+}
+
+/// Record `message` without failing (and without logging it), for calls that
+/// succeed with warnings or restore an earlier error
+pub(crate) fn set_last_error(message: impl Into<String>) {
+    let message = message.into();
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+}
+
+/// What an export returns when its body panicked
+pub(crate) trait PanicResult {
+    const PANICKED: Self;
+}
+
+impl PanicResult for c_int {
+    const PANICKED: c_int = ERR_PANICKED;
+}
+
+impl PanicResult for i64 {
+    const PANICKED: i64 = ERR_PANICKED as i64;
+}
+
+impl PanicResult for () {
+    const PANICKED: () = ();
+}
+
+/// Run `f`, turning a panic into `ERR_PANICKED` with the panic message recorded
+/// Every export runs its body through this, since unwinding into the C caller
+/// is undefined behavior.
+pub(crate) fn catch_panic<R: PanicResult>(f: impl FnOnce() -> R) -> R {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        fail(
+            ERR_PANICKED,
+            format!("panicked: {}", panic_message(&*payload)),
+        );
+        R::PANICKED
+    })
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s
+    } else {
+        "unknown panic payload"
+    }
+}
+
+pub(crate) fn last_error_message() -> String {
+    LAST_ERROR.with(|last| last.borrow().clone())
+}
+
+/// Copy the calling thread's last error message into `out_buf`
+/// Messages are per thread: a failure on one thread never shows up on another.
+/// Successful calls do not clear the message.
+/// Returns the message length (0 if nothing failed yet), the required size for
+/// a null `out_buf` or zero `capacity`, or -6 if the buffer is too small.
+/// This function never overwrites the stored message itself.
+#[no_mangle]
+pub extern "C" fn tokenizer_last_error(out_buf: *mut c_char, capacity: usize) -> c_int {
+    catch_panic(|| LAST_ERROR.with(|last| crate::copy_c_str(&last.borrow(), out_buf, capacity)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{install, llama_json, serial};
+    use std::ffi::{CStr, CString};
+
+    fn read_last_error() -> String {
+        let needed = tokenizer_last_error(std::ptr::null_mut(), 0);
+        let mut buf = vec![0u8; needed as usize + 1];
+        let n = tokenizer_last_error(buf.as_mut_ptr() as *mut c_char, buf.len());
+        assert_eq!(n, needed);
+        CStr::from_bytes_with_nul(&buf)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    #[test]
+    fn load_failures_keep_the_underlying_reason() {
+        let path = CString::new("/definitely/missing/tokenizer.json").unwrap();
+        assert_eq!(crate::tokenizer_initialize(path.as_ptr()), -3);
+        let message = read_last_error();
+        assert!(
+            message.contains("/definitely/missing/tokenizer.json"),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn invalid_utf8_reports_the_byte_index() {
+        let _guard = serial();
+        install(&llama_json());
+
+        let text = CString::new(vec![b'a', b'b', 0xc3, b'c']).unwrap();
+        let mut ids = [0; 8];
+        let rc = crate::tokenizer_encode(text.as_ptr(), ids.as_mut_ptr(), ids.len());
+        assert_eq!(rc, ERR_INVALID_UTF8);
+        assert_eq!(read_last_error(), "invalid UTF-8 at byte 2");
+
+        // Successful calls leave the message alone
+        let ok = CString::new("hello").unwrap();
+        assert!(crate::tokenizer_encode(ok.as_ptr(), ids.as_mut_ptr(), ids.len()) > 0);
+        assert_eq!(read_last_error(), "invalid UTF-8 at byte 2");
+    }
+
+    #[test]
+    fn size_query_does_not_disturb_the_message() {
+        fail(ERR_NOT_INITIALIZED, "example failure");
+        let mut small = [0 as c_char; 4];
+        assert_eq!(
+            tokenizer_last_error(small.as_mut_ptr(), small.len()),
+            ERR_BUFFER_TOO_SMALL
+        );
+        assert_eq!(read_last_error(), "example failure");
+    }
+
+    #[test]
+    fn panics_become_error_codes_and_the_library_keeps_working() {
+        let _guard = serial();
+        install(&llama_json());
+
+        let rc = crate::with_tokenizer(|_| panic!("injected failure"));
+        assert_eq!(rc, ERR_PANICKED);
+        assert_eq!(read_last_error(), "panicked: injected failure");
+
+        let text = CString::new("hello").unwrap();
+        let mut ids = [0; 8];
+        assert!(crate::tokenizer_encode(text.as_ptr(), ids.as_mut_ptr(), ids.len()) > 0);
+    }
+
+    #[test]
+    fn tokenizers_crate_panics_are_contained() {
+        let _guard = serial();
+        install(&crate::test_support::bert_json());
+
+        // Set on a private copy, behind the settings' back: after [CLS] and [SEP]
+        // only one token is left, and the tokenizers crate asserts that the
+        // stride is below that
+        let rc = crate::with_tokenizer(|tokenizer| {
+            let mut tokenizer = tokenizer.clone();
+            let params = tokenizers::TruncationParams {
+                max_length: 3,
+                stride: 1,
+                ..Default::default()
+            };
+            tokenizer.with_truncation(Some(params)).unwrap();
+            match tokenizer.encode("hello world the token", true) {
+                Ok(encoding) => encoding.len() as c_int,
+                Err(_) => -4,
+            }
+        });
+        assert_eq!(rc, ERR_PANICKED);
+        assert!(read_last_error().starts_with("panicked: `stride`"));
+
+        let text = CString::new("hello world the token").unwrap();
+        let mut ids = [0; 16];
+        assert_eq!(
+            crate::tokenizer_encode(text.as_ptr(), ids.as_mut_ptr(), ids.len()),
+            6
+        );
+
+        // Exports returning i64 or nothing are covered too
+        assert_eq!(catch_panic(|| -> i64 { panic!("in a handle call") }), -100);
+        catch_panic(|| -> () { panic!("in a setter") });
+        assert_eq!(read_last_error(), "panicked: in a setter");
+    }
+
+    #[test]
+    fn poisoned_lock_is_recovered() {
+        let _guard = serial();
+        install(&llama_json());
+
+        let _ = std::thread::spawn(|| {
+            let _lock = crate::TOKENIZER.write().unwrap();
+            panic!("poison the tokenizer lock");
+        })
+        .join();
+        assert!(crate::TOKENIZER.is_poisoned());
+
+        let text = CString::new("hello").unwrap();
+        let mut ids = [0; 8];
+        assert!(crate::tokenizer_encode(text.as_ptr(), ids.as_mut_ptr(), ids.len()) > 0);
+        crate::tokenizer_free();
+        assert_eq!(
+            crate::tokenizer_encode(text.as_ptr(), ids.as_mut_ptr(), ids.len()),
+            ERR_NOT_INITIALIZED
+        );
+        install(&llama_json());
+    }
+}
@@ -0,0 +1,164 @@
+//! Approximate token counts for live feedback, without running the encoder
+//! over the caller's text.
+//!
+//! The loaded tokenizer is calibrated once, on a built-in English sample and a
+//! multilingual one: that gives its tokens per ASCII byte and per non-ASCII
+//! character. An estimate then only counts those two in the text.
+
+use std::ffi::{c_char, c_int, CStr};
+use std::sync::{Mutex, PoisonError};
+use tokenizers::Tokenizer;
+
+use crate::chunk::without_limits;
+use crate::{catch_panic, null_output, snapshot_generation, tokenizer_failed, with_tokenizer};
+
+/// Plain English prose, code and numbers: ASCII only
+const ENGLISH_SAMPLE: &str = "The quick brown fox jumps over the lazy dog. \
+    Tokenizers split text into pieces that a language model can read, and the \
+    number of pieces decides how much of a document fits into its context \
+    window. Short, common words usually become a single token, while rare \
+    words, names and numbers such as 1234567 or 3.14159 are split into several. \
+    fn main() { let total = items.iter().map(|x| x * 2).sum::<u32>(); } \
+    Please send the report by Friday, 14 October, and call me if anything is \
+    unclear. It was the best of times, it was the worst of times.";
+
+/// Text in other scripts, mixing words with and without ASCII letters
+const MULTILINGUAL_SAMPLE: &str = "Zażółć gęślą jaźń, a potem wróć do domu. \
+    Über den Wolken muss die Freiheit wohl grenzenlos sein. \
+    Où est la bibliothèque la plus proche ? Mañana será otro día. \
+    Съешь же ещё этих мягких французских булок, да выпей чаю. \
+    我们明天在图书馆见面，然后一起去吃饭。 \
+    東京は日本の首都で、人口が最も多い都市です。 \
+    서울은 대한민국의 수도입니다. Καλημέρα σε όλους. \
+    مرحبا بكم في عالم النماذج اللغوية. 🚀✨";
+
+#[derive(Clone, Copy)]
+struct Calibration {
+    per_ascii_byte: f64,
+    per_other_char: f64,
+}
+
+/// Calibration of the tokenizer generation it was computed for
+static CALIBRATION: Mutex<Option<(u64, Calibration)>> = Mutex::new(None);
+
+/// ASCII bytes and non-ASCII characters in possibly invalid UTF-8
+/// Every non-ASCII character has exactly one lead byte (0xC0 and up); both
+/// loops compile to vector code.
+fn classify(bytes: &[u8]) -> (usize, usize) {
+    let ascii = bytes.iter().filter(|&&b| b < 0x80).count();
+    let other = bytes.iter().filter(|&&b| b >= 0xC0).count();
+    (ascii, other)
+}
+
+fn calibrate(tokenizer: &Tokenizer) -> tokenizers::Result<Calibration> {
+    let tokenizer = without_limits(tokenizer);
+    let count = |text: &str| -> tokenizers::Result<f64> {
+        Ok(tokenizer.encode_fast(text, false)?.len() as f64)
+    };
+
+    let (english_ascii, _) = classify(ENGLISH_SAMPLE.as_bytes());
+    let per_ascii_byte = count(ENGLISH_SAMPLE)? / english_ascii as f64;
+    // Whatever the ASCII part of the multilingual sample does not explain is
+    // put down to its other characters
+    let (ascii, other) = classify(MULTILINGUAL_SAMPLE.as_bytes());
+    let rest = count(MULTILINGUAL_SAMPLE)? - per_ascii_byte * ascii as f64;
+    Ok(Calibration {
+        per_ascii_byte,
+        per_other_char: (rest / other as f64).max(0.0),
+    })
+}
+
+fn calibration(tokenizer: &Tokenizer) -> tokenizers::Result<Calibration> {
+    let generation = snapshot_generation();
+    if let Some((tagged, calibration)) = *CALIBRATION.lock().unwrap_or_else(PoisonError::into_inner)
+    {
+        if tagged == generation {
+            return Ok(calibration);
+        }
+    }
+
+    let calibration = calibrate(tokenizer)?;
+    *CALIBRATION.lock().unwrap_or_else(PoisonError::into_inner) = Some((generation, calibration));
+    Ok(calibration)
+}
+
+/// Estimate how many tokens `text` encodes to, without encoding it
+/// This is an approximation of `tokenizer_count_tokens(text, 0)` for live
+/// feedback (an editor counter, a file list), not a budget check: for
+/// natural-language text it aims to be within 10% of the exact count, but
+/// unusual input (long digit runs, repeated characters, rare scripts) can be
+/// further off. Use `tokenizer_count_tokens` before sending anything that must
+/// fit. Cost is one pass over the bytes, so megabytes take well under a
+/// millisecond; the first call after a tokenizer loads also calibrates it,
+/// which costs two encodes of about a kilobyte. Invalid UTF-8 is tolerated
+/// and never rejected.
+/// Returns the estimate, negative on error:
+///   -1 null `text`, -3 not initialized, -4 calibration failed
+#[no_mangle]
+pub extern "C" fn tokenizer_estimate_tokens(text: *const c_char) -> c_int {
+    catch_panic(|| {
+        if text.is_null() {
+            return null_output("text");
+        }
+        let bytes = unsafe { CStr::from_ptr(text) }.to_bytes();
+
+        with_tokenizer(|tokenizer| {
+            let calibration = match calibration(tokenizer) {
+                Ok(calibration) => calibration,
+                Err(e) => return tokenizer_failed("calibration encode", e),
+            };
+            let (ascii, other) = classify(bytes);
+            let estimate = calibration.per_ascii_byte * ascii as f64
+                + calibration.per_other_char * other as f64;
+            estimate.round().min(c_int::MAX as f64) as c_int
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{bert_json, byte_level_json, install, llama_json, serial};
+    use std::ffi::CString;
+
+    fn exact_and_estimate(text: &str) -> (c_int, c_int) {
+        let text = CString::new(text).unwrap();
+        let exact = crate::tokenizer_count_tokens(text.as_ptr(), 0);
+        (exact, tokenizer_estimate_tokens(text.as_ptr()))
+    }
+
+    #[test]
+    fn estimates_stay_close_to_the_exact_count() {
+        let _guard = serial();
+        let texts = [
+            "Hello world, this is a longer paragraph of ordinary English text that \
+             someone might paste into the prompt box before asking a question.",
+            "Dzień dobry! Jak się masz? Wszystko w porządku, dziękuję bardzo.",
+            "Ein kurzer Satz über das Wetter, und noch ein zweiter danach.",
+        ];
+        for json in [llama_json(), byte_level_json()] {
+            install(&json);
+            for text in texts {
+                let (exact, estimate) = exact_and_estimate(text);
+                let error = (estimate - exact).abs() as f64 / exact as f64;
+                assert!(error <= 0.1, "{estimate} vs {exact} for {text:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn calibration_follows_the_loaded_tokenizer() {
+        let _guard = serial();
+        install(&byte_level_json());
+        let (_, byte_level) = exact_and_estimate("hello world");
+        install(&bert_json());
+        let (_, bert) = exact_and_estimate("hello world");
+        assert_ne!(byte_level, bert);
+
+        assert_eq!(exact_and_estimate("").1, 0);
+        assert_eq!(
+            tokenizer_estimate_tokens(std::ptr::null()),
+            crate::ERR_NULL_POINTER
+        );
+    }
+}
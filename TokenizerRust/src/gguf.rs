@@ -0,0 +1,581 @@
+//! Building a tokenizer from the vocabulary embedded in a GGUF model file.
+//!
+//! Only the metadata section at the start of the file is read; values we do not
+//! need are skipped by seeking, so multi-GB models load as fast as small ones.
+//! The metadata is translated into tokenizer.json form and loaded from that,
+//! mirroring what llama.cpp does for each `tokenizer.ggml.model`:
+//! "llama" (SentencePiece BPE with byte fallback), "gpt2" (byte-level BPE) and
+//! "t5" (Unigram). The SentencePiece conversions are shared with `spm`.
+
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int};
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::time::Instant;
+use tokenizers::Tokenizer;
+
+use crate::spm::{self, added_tokens, vocab_map};
+use crate::{c_str_arg, catch_panic, fail, set_global};
+
+const GGUF_MAGIC: &[u8; 4] = b"GGUF";
+
+/// Metadata value types from the GGUF specification
+const TYPE_U8: u32 = 0;
+const TYPE_I8: u32 = 1;
+const TYPE_U16: u32 = 2;
+const TYPE_I16: u32 = 3;
+const TYPE_U32: u32 = 4;
+const TYPE_I32: u32 = 5;
+const TYPE_F32: u32 = 6;
+const TYPE_BOOL: u32 = 7;
+const TYPE_STRING: u32 = 8;
+const TYPE_ARRAY: u32 = 9;
+const TYPE_U64: u32 = 10;
+const TYPE_I64: u32 = 11;
+const TYPE_F64: u32 = 12;
+
+/// Llama 3 splits text exactly like cl100k_base
+const LLAMA3_PATTERN: &str = crate::tiktoken::CL100K_PATTERN;
+
+#[derive(Debug)]
+enum Meta {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    Array(Vec<Meta>),
+}
+
+impl Meta {
+    fn as_int(&self) -> Option<i64> {
+        match self {
+            Meta::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Meta::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn as_array(&self) -> Option<&[Meta]> {
+        match self {
+            Meta::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+struct GgufReader {
+    inner: BufReader<File>,
+}
+
+impl GgufReader {
+    fn bytes<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut buf = [0; N];
+        self.inner.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        self.bytes().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        self.bytes().map(u64::from_le_bytes)
+    }
+
+    fn skip(&mut self, len: u64) -> io::Result<()> {
+        let len = i64::try_from(len).map_err(|_| invalid_data("length out of range"))?;
+        self.inner.seek_relative(len)
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.u64()?;
+        let mut buf = Vec::new();
+        (&mut self.inner).take(len).read_to_end(&mut buf)?;
+        if buf.len() as u64 != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        String::from_utf8(buf).map_err(|_| invalid_data("string is not UTF-8"))
+    }
+
+    fn value(&mut self, ty: u32) -> io::Result<Meta> {
+        Ok(match ty {
+            TYPE_U8 => Meta::Int(u8::from_le_bytes(self.bytes()?) as i64),
+            TYPE_I8 => Meta::Int(i8::from_le_bytes(self.bytes()?) as i64),
+            TYPE_U16 => Meta::Int(u16::from_le_bytes(self.bytes()?) as i64),
+            TYPE_I16 => Meta::Int(i16::from_le_bytes(self.bytes()?) as i64),
+            TYPE_U32 => Meta::Int(self.u32()? as i64),
+            TYPE_I32 => Meta::Int(i32::from_le_bytes(self.bytes()?) as i64),
+            TYPE_U64 => Meta::Int(self.u64()? as i64),
+            TYPE_I64 => Meta::Int(i64::from_le_bytes(self.bytes()?)),
+            TYPE_F32 => Meta::Float(f32::from_le_bytes(self.bytes()?) as f64),
+            TYPE_F64 => Meta::Float(f64::from_le_bytes(self.bytes()?)),
+            TYPE_BOOL => Meta::Bool(self.bytes::<1>()?[0] != 0),
+            TYPE_STRING => Meta::Str(self.string()?),
+            TYPE_ARRAY => {
+                let item_ty = self.u32()?;
+                let len = self.u64()?;
+                // Cap the preallocation: `len` comes straight from the file
+                let mut items = Vec::with_capacity(len.min(1 << 20) as usize);
+                for _ in 0..len {
+                    items.push(self.value(item_ty)?);
+                }
+                Meta::Array(items)
+            }
+            other => return Err(invalid_data(&format!("unknown value type {other}"))),
+        })
+    }
+
+    /// Skip a value without materializing it
+    fn skip_value(&mut self, ty: u32) -> io::Result<()> {
+        match ty {
+            TYPE_STRING => {
+                let len = self.u64()?;
+                self.skip(len)
+            }
+            TYPE_ARRAY => {
+                let item_ty = self.u32()?;
+                let len = self.u64()?;
+                match fixed_size(item_ty) {
+                    Some(size) => self.skip(len.saturating_mul(size)),
+                    None => (0..len).try_for_each(|_| self.skip_value(item_ty)),
+                }
+            }
+            _ => match fixed_size(ty) {
+                Some(size) => self.skip(size),
+                None => Err(invalid_data(&format!("unknown value type {ty}"))),
+            },
+        }
+    }
+}
+
+fn fixed_size(ty: u32) -> Option<u64> {
+    match ty {
+        TYPE_U8 | TYPE_I8 | TYPE_BOOL => Some(1),
+        TYPE_U16 | TYPE_I16 => Some(2),
+        TYPE_U32 | TYPE_I32 | TYPE_F32 => Some(4),
+        TYPE_U64 | TYPE_I64 | TYPE_F64 => Some(8),
+        _ => None,
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_owned())
+}
+
+/// Read the `tokenizer.*` metadata keys, skipping everything else
+fn read_tokenizer_metadata(path: &str) -> io::Result<HashMap<String, Meta>> {
+    let mut reader = GgufReader {
+        inner: BufReader::new(File::open(path)?),
+    };
+
+    if &reader.bytes::<4>()? != GGUF_MAGIC {
+        return Err(invalid_data("not a GGUF file"));
+    }
+    let version = reader.u32()?;
+    if !(2..=3).contains(&version) {
+        return Err(invalid_data(&format!("unsupported GGUF version {version}")));
+    }
+    let _tensor_count = reader.u64()?;
+    let kv_count = reader.u64()?;
+
+    let mut metadata = HashMap::new();
+    for _ in 0..kv_count {
+        let key = reader.string()?;
+        let ty = reader.u32()?;
+        if key.starts_with("tokenizer.") {
+            metadata.insert(key, reader.value(ty)?);
+        } else {
+            reader.skip_value(ty)?;
+        }
+    }
+    Ok(metadata)
+}
+
+/// Translate GGUF tokenizer metadata into tokenizer.json
+fn tokenizer_json(metadata: &HashMap<String, Meta>) -> Result<Value, String> {
+    let get = |key: &str| metadata.get(&format!("tokenizer.ggml.{key}"));
+    let model = get("model")
+        .and_then(Meta::as_str)
+        .ok_or("missing tokenizer.ggml.model")?;
+    let tokens: Vec<&str> = get("tokens")
+        .and_then(Meta::as_array)
+        .ok_or("missing tokenizer.ggml.tokens")?
+        .iter()
+        .map(|t| t.as_str().ok_or("tokenizer.ggml.tokens holds a non-string"))
+        .collect::<Result<_, _>>()?;
+    let types: Vec<i64> = match get("token_type").and_then(Meta::as_array) {
+        Some(types) => types.iter().map(|t| t.as_int().unwrap_or(1)).collect(),
+        None => vec![1; tokens.len()],
+    };
+    let scores: Vec<f64> = match get("scores").and_then(Meta::as_array) {
+        Some(scores) => scores
+            .iter()
+            .map(|s| match s {
+                Meta::Float(f) => *f,
+                _ => 0.0,
+            })
+            .collect(),
+        None => vec![0.0; tokens.len()],
+    };
+    let token_id = |key: &str| {
+        get(key)
+            .and_then(Meta::as_int)
+            .and_then(|id| usize::try_from(id).ok())
+            .filter(|&id| id < tokens.len())
+    };
+    let flag = |key: &str, default: bool| match get(key) {
+        Some(Meta::Bool(b)) => *b,
+        _ => default,
+    };
+
+    let (add_bos, add_eos) = match model {
+        "t5" => (flag("add_bos_token", false), flag("add_eos_token", true)),
+        _ => (flag("add_bos_token", true), flag("add_eos_token", false)),
+    };
+    let bos = token_id("bos_token_id").filter(|_| add_bos);
+    let eos = token_id("eos_token_id").filter(|_| add_eos);
+
+    let mut json = match model {
+        "llama" => {
+            let add_space_prefix = flag("add_space_prefix", true);
+            spm::bpe_from_scores(
+                &tokens,
+                &types,
+                &scores,
+                token_id("unknown_token_id"),
+                add_space_prefix,
+                true,
+            )
+        }
+        "gpt2" => {
+            let merges = get("merges")
+                .and_then(Meta::as_array)
+                .ok_or("missing tokenizer.ggml.merges")?;
+            let pre = get("pre").and_then(Meta::as_str).unwrap_or("default");
+            byte_level_bpe(&tokens, merges, pre)?
+        }
+        "t5" => spm::unigram(
+            &tokens,
+            &scores,
+            token_id("unknown_token_id"),
+            Value::Null,
+            true,
+            false,
+        ),
+        other => return Err(format!("unsupported tokenizer model '{other}'")),
+    };
+
+    json["version"] = json!("1.0");
+    json["truncation"] = Value::Null;
+    json["padding"] = Value::Null;
+    json["added_tokens"] = json!(added_tokens(&tokens, &types));
+    json["post_processor"] = spm::post_processor(&tokens, bos, eos);
+    Ok(json)
+}
+
+fn byte_level_bpe(tokens: &[&str], merges: &[Meta], pre: &str) -> Result<Value, String> {
+    let merges: Vec<Value> = merges
+        .iter()
+        .map(|m| {
+            let merge = m
+                .as_str()
+                .ok_or("tokenizer.ggml.merges holds a non-string")?;
+            let (left, right) = merge
+                .split_once(' ')
+                .ok_or_else(|| format!("malformed merge '{merge}'"))?;
+            Ok(json!([left, right]))
+        })
+        .collect::<Result<_, String>>()?;
+
+    let byte_level = |use_regex| {
+        json!({
+            "type": "ByteLevel",
+            "add_prefix_space": false,
+            "trim_offsets": true,
+            "use_regex": use_regex
+        })
+    };
+    // Llama 3 splits with its own regex before the byte-level mapping
+    let (pre_tokenizer, ignore_merges) = match pre {
+        "llama-bpe" | "llama3" => (
+            json!({
+                "type": "Sequence",
+                "pretokenizers": [
+                    {
+                        "type": "Split",
+                        "pattern": { "Regex": LLAMA3_PATTERN },
+                        "behavior": "Isolated",
+                        "invert": false
+                    },
+                    byte_level(false)
+                ]
+            }),
+            true,
+        ),
+        _ => (byte_level(true), false),
+    };
+
+    Ok(json!({
+        "normalizer": null,
+        "pre_tokenizer": pre_tokenizer,
+        "decoder": byte_level(true),
+        "model": {
+            "type": "BPE",
+            "dropout": null,
+            "unk_token": null,
+            "continuing_subword_prefix": null,
+            "end_of_word_suffix": null,
+            "fuse_unk": false,
+            "byte_fallback": false,
+            "ignore_merges": ignore_merges,
+            "vocab": vocab_map(tokens),
+            "merges": merges
+        }
+    }))
+}
+
+/// Build the tokenizer described by a GGUF file's metadata
+/// Errors: -1 null path, -2 invalid UTF-8, -3 unreadable, not GGUF, or an
+/// unsupported tokenizer model
+pub(crate) fn load_from_gguf(path: *const c_char) -> Result<Tokenizer, c_int> {
+    let path = c_str_arg(path)?;
+    let load_failed = |e: String| fail(-3, format!("failed to load GGUF '{path}': {e}"));
+
+    let metadata = read_tokenizer_metadata(path).map_err(|e| load_failed(e.to_string()))?;
+    let json = tokenizer_json(&metadata).map_err(load_failed)?;
+    json.to_string()
+        .parse::<Tokenizer>()
+        .map_err(|e| load_failed(e.to_string()))
+}
+
+/// Initialize the global tokenizer from the vocabulary embedded in a GGUF model
+/// Supports the "llama" (Llama 2, Mistral), "gpt2" (Llama 3 and other
+/// byte-level BPE) and "t5" tokenizer models. BOS/EOS are added as the file's
+/// `add_bos_token`/`add_eos_token` flags say, and control tokens become special
+/// tokens. Reinitialization and `tokenizer_free` behave as with
+/// `tokenizer_initialize`.
+/// Returns 0 on success, negative on error:
+///   -1 null path, -2 invalid UTF-8, -3 unreadable, not a GGUF file, or an
+///   unsupported tokenizer model
+#[no_mangle]
+pub extern "C" fn tokenizer_initialize_from_gguf(path: *const c_char) -> c_int {
+    catch_panic(|| {
+        let started = Instant::now();
+        match load_from_gguf(path) {
+            Ok(tokenizer) => set_global(tokenizer, c_str_arg(path).unwrap_or_default(), started),
+            Err(code) => code,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{install, llama_json, serial, write_temp};
+    use std::ffi::CString;
+
+    /// Minimal GGUF writer for the metadata section
+    #[derive(Default)]
+    struct Gguf(Vec<u8>, u64);
+
+    impl Gguf {
+        fn key(&mut self, key: &str, ty: u32) -> &mut Vec<u8> {
+            self.1 += 1;
+            put_str(&mut self.0, key);
+            self.0.extend(ty.to_le_bytes());
+            &mut self.0
+        }
+
+        fn string(mut self, key: &str, value: &str) -> Self {
+            put_str(self.key(key, TYPE_STRING), value);
+            self
+        }
+
+        fn u32(mut self, key: &str, value: u32) -> Self {
+            self.key(key, TYPE_U32).extend(value.to_le_bytes());
+            self
+        }
+
+        fn strings(mut self, key: &str, values: &[String]) -> Self {
+            let buf = self.key(key, TYPE_ARRAY);
+            buf.extend(TYPE_STRING.to_le_bytes());
+            buf.extend((values.len() as u64).to_le_bytes());
+            for v in values {
+                put_str(buf, v);
+            }
+            self
+        }
+
+        fn numbers<T: Copy>(
+            mut self,
+            key: &str,
+            ty: u32,
+            values: &[T],
+            to: fn(T) -> [u8; 4],
+        ) -> Self {
+            let buf = self.key(key, TYPE_ARRAY);
+            buf.extend(ty.to_le_bytes());
+            buf.extend((values.len() as u64).to_le_bytes());
+            for &v in values {
+                buf.extend(to(v));
+            }
+            self
+        }
+
+        fn write(self, name: &str) -> CString {
+            let mut file = GGUF_MAGIC.to_vec();
+            file.extend(3u32.to_le_bytes());
+            file.extend(0u64.to_le_bytes());
+            file.extend(self.1.to_le_bytes());
+            file.extend(self.0);
+            // Stand-in for tensor data that must never be read
+            file.extend(vec![0xAB; 4096]);
+
+            let path = write_temp(name, "");
+            std::fs::write(&path, file).unwrap();
+            CString::new(path.to_str().unwrap()).unwrap()
+        }
+    }
+
+    fn put_str(buf: &mut Vec<u8>, s: &str) {
+        buf.extend((s.len() as u64).to_le_bytes());
+        buf.extend(s.as_bytes());
+    }
+
+    fn encode(text: &str) -> Vec<c_int> {
+        let text = CString::new(text).unwrap();
+        let mut ids = vec![0; 64];
+        let n = crate::tokenizer_encode(text.as_ptr(), ids.as_mut_ptr(), ids.len());
+        assert!(n >= 0, "encode failed with {n}");
+        ids.truncate(n as usize);
+        ids
+    }
+
+    /// The `llama_json` fixture as llama.cpp stores it: merge ranks become scores
+    fn llama_gguf() -> CString {
+        let fixture: Value = serde_json::from_str(&llama_json()).unwrap();
+        let vocab = fixture["model"]["vocab"].as_object().unwrap();
+        let mut tokens = vec![String::new(); vocab.len()];
+        for (piece, id) in vocab {
+            tokens[id.as_u64().unwrap() as usize] = piece.clone();
+        }
+        let merged: Vec<String> = fixture["model"]["merges"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| m.as_str().unwrap().replace(' ', ""))
+            .collect();
+        let scores: Vec<f32> = tokens
+            .iter()
+            .map(|t| {
+                merged
+                    .iter()
+                    .position(|m| m == t)
+                    .map_or(-1000.0, |rank| -(rank as f32))
+            })
+            .collect();
+        let types: Vec<i32> = (0..tokens.len())
+            .map(|id| match id {
+                0 => 2,
+                1 | 2 => 3,
+                3..=258 => 6,
+                _ => 1,
+            })
+            .collect();
+
+        Gguf::default()
+            .string("general.architecture", "llama")
+            .strings("general.tags", &vec!["skipped".to_owned(); 3])
+            .string("tokenizer.ggml.model", "llama")
+            .strings("tokenizer.ggml.tokens", &tokens)
+            .numbers("tokenizer.ggml.scores", TYPE_F32, &scores, f32::to_le_bytes)
+            .numbers(
+                "tokenizer.ggml.token_type",
+                TYPE_I32,
+                &types,
+                i32::to_le_bytes,
+            )
+            .u32("tokenizer.ggml.bos_token_id", 1)
+            .u32("tokenizer.ggml.eos_token_id", 2)
+            .u32("tokenizer.ggml.unknown_token_id", 0)
+            .write("llama_gguf")
+    }
+
+    #[test]
+    fn sentencepiece_gguf_matches_the_equivalent_tokenizer_json() {
+        let _guard = serial();
+        let prompts = ["hello world", "Hello, world!", "zaż 🚀 hello", "  world  "];
+
+        install(&llama_json());
+        let expected: Vec<_> = prompts.iter().map(|p| encode(p)).collect();
+
+        assert_eq!(tokenizer_initialize_from_gguf(llama_gguf().as_ptr()), 0);
+        let got: Vec<_> = prompts.iter().map(|p| encode(p)).collect();
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn byte_level_gguf_uses_merges_and_control_tokens() {
+        let _guard = serial();
+        let mut tokens: Vec<String> = tokenizers::pre_tokenizers::byte_level::ByteLevel::alphabet()
+            .into_iter()
+            .map(String::from)
+            .collect();
+        tokens.sort();
+        tokens.extend(["he", "ll", "hell", "hello", "Ġw", "Ġwo"].map(String::from));
+        tokens.push("<|begin_of_text|>".to_owned());
+        let bos = tokens.len() as u32 - 1;
+        let merges = ["h e", "l l", "he ll", "hell o", "Ġ w", "Ġw o"].map(String::from);
+        let mut types = vec![1i32; tokens.len()];
+        types[bos as usize] = 3;
+
+        let path = Gguf::default()
+            .string("tokenizer.ggml.model", "gpt2")
+            .string("tokenizer.ggml.pre", "llama-bpe")
+            .strings("tokenizer.ggml.tokens", &tokens)
+            .numbers(
+                "tokenizer.ggml.token_type",
+                TYPE_I32,
+                &types,
+                i32::to_le_bytes,
+            )
+            .strings("tokenizer.ggml.merges", &merges)
+            .u32("tokenizer.ggml.bos_token_id", bos)
+            .write("gpt2_gguf");
+        assert_eq!(tokenizer_initialize_from_gguf(path.as_ptr()), 0);
+
+        let id = |t: &str| tokens.iter().position(|x| x == t).unwrap() as c_int;
+        let ids = encode("hello world");
+        assert_eq!(&ids[..3], &[bos as c_int, id("hello"), id("Ġwo")]);
+
+        let mut buf = [0u8; 32];
+        let n = crate::tokenizer_decode(
+            ids.as_ptr(),
+            ids.len(),
+            buf.as_mut_ptr() as *mut c_char,
+            buf.len(),
+        );
+        assert_eq!(std::str::from_utf8(&buf[..n as usize]), Ok("hello world"));
+    }
+
+    #[test]
+    fn non_gguf_files_fail_to_load() {
+        let _guard = serial();
+        let path = write_temp("not_gguf", "{}");
+        let path = CString::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(tokenizer_initialize_from_gguf(path.as_ptr()), -3);
+
+        let unsupported = Gguf::default()
+            .string("tokenizer.ggml.model", "rwkv")
+            .strings("tokenizer.ggml.tokens", &["a".to_owned()])
+            .write("rwkv_gguf");
+        assert_eq!(tokenizer_initialize_from_gguf(unsupported.as_ptr()), -3);
+    }
+}
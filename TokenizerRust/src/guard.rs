@@ -0,0 +1,270 @@
+//! Sanity checks on the output buffers callers hand in.
+//!
+//! A foreign pointer's real size cannot be known, but the usual marshaling
+//! bugs can be caught before anything is written: a zero length, a length no
+//! real buffer has (a negative `int` widened to `usize`, an uninitialized
+//! field), an output that overlaps the input text, and a pointer misaligned
+//! for its element type. Strict mode additionally logs each rejected call
+//! with its argument values.
+
+use std::ffi::{c_char, c_int};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::log::{log, LOG_WARN};
+use crate::{catch_panic, fail, null_output, ERR_BAD_POINTER, ERR_INVALID_ARGUMENT};
+use crate::{ERR_INVALID_LENGTH, ERR_NULL_POINTER};
+
+const DEFAULT_LENGTH_LIMIT: usize = 16 << 20;
+
+static STRICT: AtomicBool = AtomicBool::new(false);
+static LENGTH_LIMIT: AtomicUsize = AtomicUsize::new(DEFAULT_LENGTH_LIMIT);
+
+/// Check the output buffer `out` of `len` elements named `name`, written by
+/// `call` while it reads the NUL-terminated `input` (null if none)
+/// Errors: -1 null `out`, -19 zero or absurd `len`, -20 `out` is misaligned
+/// or overlaps the start of `input`
+pub(crate) fn out_buffer<T>(
+    call: &str,
+    name: &str,
+    out: *mut T,
+    len: usize,
+    input: *const c_char,
+) -> Result<(), c_int> {
+    let limit = LENGTH_LIMIT.load(Ordering::Relaxed);
+    out_buffer_with_limit(limit, call, name, out, len, input)
+}
+
+/// `out_buffer` against an explicit length limit instead of the global one
+fn out_buffer_with_limit<T>(
+    limit: usize,
+    call: &str,
+    name: &str,
+    out: *mut T,
+    len: usize,
+    input: *const c_char,
+) -> Result<(), c_int> {
+    let reject = |code: c_int, reason: String| {
+        if STRICT.load(Ordering::Relaxed) {
+            log(LOG_WARN, || {
+                format!("rejected {call}({name}={out:p}, len={len}, input={input:p}): {reason}")
+            });
+        }
+        Err(if code == ERR_NULL_POINTER {
+            null_output(name)
+        } else {
+            fail(code, format!("{call}: {reason}"))
+        })
+    };
+
+    let start = out as usize;
+    let end = len
+        .checked_mul(std::mem::size_of::<T>())
+        .and_then(|bytes| start.checked_add(bytes));
+    if out.is_null() {
+        reject(ERR_NULL_POINTER, format!("`{name}` is null"))
+    } else if len == 0 {
+        reject(ERR_INVALID_LENGTH, format!("`{name}` has length 0"))
+    } else if len > limit || end.is_none() {
+        reject(
+            ERR_INVALID_LENGTH,
+            format!("`{name}` length {len} exceeds the limit of {limit}"),
+        )
+    } else if !out.is_aligned() {
+        reject(
+            ERR_BAD_POINTER,
+            format!(
+                "`{name}` at {out:p} is not aligned to {} bytes",
+                std::mem::align_of::<T>()
+            ),
+        )
+    } else if (start..end.unwrap_or(start)).contains(&(input as usize)) {
+        reject(
+            ERR_BAD_POINTER,
+            format!("`{name}` at {out:p} overlaps the input text"),
+        )
+    } else {
+        Ok(())
+    }
+}
+
+/// Log every call rejected by the buffer checks with its argument values
+/// The checks themselves always run; strict mode only adds a warn-level
+/// record per rejection naming the export, the buffer pointer, its length and
+/// the input pointer, for tracking down the binding that passed them.
+/// Returns 0
+#[no_mangle]
+pub extern "C" fn tokenizer_set_strict_mode(enabled: c_int) -> c_int {
+    catch_panic(|| {
+        STRICT.store(enabled != 0, Ordering::Relaxed);
+        0
+    })
+}
+
+/// Set the largest output length, in elements, a call accepts
+/// Longer lengths fail with -19 before anything is written, since they
+/// almost always come from a marshaling bug. The default is 16M (16777216).
+/// Returns 0 on success, -9 for a zero limit
+#[no_mangle]
+pub extern "C" fn tokenizer_set_length_limit(limit: usize) -> c_int {
+    catch_panic(|| {
+        if limit == 0 {
+            return fail(ERR_INVALID_ARGUMENT, "the length limit must be positive");
+        }
+        LENGTH_LIMIT.store(limit, Ordering::Relaxed);
+        0
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::{
+        encoding_copy_offsets, encoding_copy_type_ids, encoding_copy_word_ids, encoding_free,
+        tokenizer_encode_new,
+    };
+    use crate::test_support::{install, llama_json, serial};
+    use std::ffi::{CStr, CString};
+    use std::sync::Mutex;
+
+    static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    extern "C" fn record(level: c_int, message: *const c_char) {
+        if level == LOG_WARN {
+            let message = unsafe { CStr::from_ptr(message) }.to_string_lossy();
+            WARNINGS.lock().unwrap().push(message.into_owned());
+        }
+    }
+
+    #[test]
+    fn marshaling_bugs_are_rejected_before_writing() {
+        let _guard = serial();
+        install(&llama_json());
+        let text = CString::new("hello").unwrap();
+        let mut ids = [7; 8];
+        let encode = |out: *mut c_int, len: usize| crate::tokenizer_encode(text.as_ptr(), out, len);
+
+        assert_eq!(encode(ids.as_mut_ptr(), 0), ERR_INVALID_LENGTH);
+        // A negative int widened to usize
+        assert_eq!(encode(ids.as_mut_ptr(), -1i64 as usize), ERR_INVALID_LENGTH);
+        let misaligned = unsafe { (ids.as_mut_ptr() as *mut u8).add(1) } as *mut c_int;
+        assert_eq!(encode(misaligned, 4), ERR_BAD_POINTER);
+        let mut shared = *b"hello\0\0\0\0\0\0\0";
+        let rc = crate::tokenizer_encode(
+            shared.as_ptr() as *const c_char,
+            shared.as_mut_ptr() as *mut c_int,
+            3,
+        );
+        assert_eq!(rc, ERR_BAD_POINTER);
+        assert_eq!(ids, [7; 8]);
+
+        assert_eq!(encode(ids.as_mut_ptr(), 3), 2);
+    }
+
+    #[test]
+    fn lengths_above_the_limit_are_rejected() {
+        // The global limit stays put: tests encoding in parallel rely on it
+        let mut ids = [0; 4];
+        let out = ids.as_mut_ptr();
+        let check = |limit: usize, len: usize| {
+            out_buffer_with_limit(limit, "test", "out_ids", out, len, std::ptr::null())
+        };
+        assert_eq!(check(2, 3), Err(ERR_INVALID_LENGTH));
+        assert!(crate::last_error_message().contains("exceeds the limit of 2"));
+        assert_eq!(check(3, 3), Ok(()));
+
+        assert_eq!(tokenizer_set_length_limit(0), ERR_INVALID_ARGUMENT);
+        assert_eq!(tokenizer_set_length_limit(DEFAULT_LENGTH_LIMIT), 0);
+    }
+
+    #[test]
+    fn batch_pair_and_group_buffers_are_checked_too() {
+        let _guard = serial();
+        install(&llama_json());
+        let text = CString::new("hello world").unwrap();
+        let texts = [text.as_ptr(); 2];
+        let (mut ids, mut lengths) = ([0; 8], [0; 2]);
+        let misaligned = unsafe { (ids.as_mut_ptr() as *mut u8).add(1) } as *mut c_int;
+        let lengths = lengths.as_mut_ptr();
+
+        let batch = |out: *mut c_int, max_len: usize| {
+            crate::tokenizer_encode_batch(texts.as_ptr(), 2, out, lengths, max_len)
+        };
+        assert_eq!(
+            batch(ids.as_mut_ptr(), usize::MAX / 2 + 1),
+            ERR_INVALID_LENGTH
+        );
+        assert_eq!(batch(misaligned, 4), ERR_BAD_POINTER);
+        let pair = crate::tokenizer_encode_pair(
+            text.as_ptr(),
+            text.as_ptr(),
+            ids.as_mut_ptr(),
+            misaligned,
+            4,
+        );
+        assert_eq!(pair, ERR_BAD_POINTER);
+        let groups = crate::tokenizer_word_groups(text.as_ptr(), ids.as_mut_ptr(), misaligned, 2);
+        assert_eq!(groups, ERR_BAD_POINTER);
+        assert_eq!(ids, [0; 8]);
+    }
+
+    #[test]
+    fn encoding_accessors_and_pretokenize_are_checked_too() {
+        let _guard = serial();
+        install(&llama_json());
+        let text = CString::new("hello world").unwrap();
+        let handle = tokenizer_encode_new(text.as_ptr(), 1);
+        assert!(handle > 0);
+        let mut out = [0; 8];
+        let (ids, misaligned) = (
+            out.as_mut_ptr(),
+            unsafe { (out.as_mut_ptr() as *mut u8).add(1) } as *mut c_int,
+        );
+
+        assert_eq!(
+            encoding_copy_offsets(handle, ids, misaligned, 4),
+            ERR_BAD_POINTER
+        );
+        assert_eq!(
+            encoding_copy_word_ids(handle, ids, usize::MAX),
+            ERR_INVALID_LENGTH
+        );
+        assert_eq!(
+            encoding_copy_type_ids(handle, misaligned, 4),
+            ERR_BAD_POINTER
+        );
+        // Nothing is written for a zero length, so nothing is checked
+        assert_eq!(encoding_copy_type_ids(handle, std::ptr::null_mut(), 0), 0);
+        let pieces = crate::inspect::tokenizer_pretokenize(text.as_ptr(), misaligned, ids, 4);
+        assert_eq!(pieces, ERR_BAD_POINTER);
+        assert_eq!(out, [0; 8]);
+        assert_eq!(encoding_free(handle), 0);
+    }
+
+    #[test]
+    fn strict_mode_logs_the_arguments() {
+        let _guard = serial();
+        install(&llama_json());
+        let text = CString::new("hello").unwrap();
+        let mut ids = [0; 4];
+
+        assert_eq!(
+            crate::log::tokenizer_set_log_callback(Some(record), LOG_WARN),
+            0
+        );
+        crate::tokenizer_encode(text.as_ptr(), ids.as_mut_ptr(), 0);
+        assert_eq!(tokenizer_set_strict_mode(1), 0);
+        crate::tokenizer_encode(text.as_ptr(), ids.as_mut_ptr(), 0);
+        assert_eq!(tokenizer_set_strict_mode(0), 0);
+        assert_eq!(crate::log::tokenizer_set_log_callback(None, LOG_WARN), 0);
+
+        let warnings = WARNINGS.lock().unwrap();
+        // Other tests may log warnings meanwhile
+        let warnings: Vec<_> = warnings
+            .iter()
+            .filter(|w| w.starts_with("rejected"))
+            .collect();
+        assert_eq!(warnings.len(), 1, "{warnings:?}");
+        assert!(warnings[0].starts_with("rejected tokenizer_encode_opts(out_ids=0x"));
+        assert!(warnings[0].contains("len=0"), "{}", warnings[0]);
+    }
+}
@@ -0,0 +1,223 @@
+//! Handle-based tokenizer instances, so several tokenizers can be loaded at once.
+//!
+//! Each handle owns an immutable `Arc<Tokenizer>`. Calls only hold the registry
+//! read lock long enough to clone the `Arc`, so encodes on different handles
+//! (or on the same handle) run in parallel without contending on a lock.
+
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, OnceLock, PoisonError, RwLock};
+use tokenizers::Tokenizer;
+
+use crate::{
+    catch_panic, decode_into, encode_into, fail, guard, ids_arg, load_from_path, text_arg,
+    ERR_INVALID_HANDLE,
+};
+
+static NEXT_HANDLE: AtomicI64 = AtomicI64::new(1);
+
+/// A fresh handle, unique across tokenizer and encoding handles
+pub(crate) fn next_handle() -> i64 {
+    NEXT_HANDLE.fetch_add(1, Ordering::Relaxed)
+}
+
+fn registry() -> &'static RwLock<HashMap<i64, Arc<Tokenizer>>> {
+    static HANDLES: OnceLock<RwLock<HashMap<i64, Arc<Tokenizer>>>> = OnceLock::new();
+    HANDLES.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+fn lookup(handle: i64) -> Result<Arc<Tokenizer>, c_int> {
+    let handles = registry().read().unwrap_or_else(PoisonError::into_inner);
+    handles.get(&handle).cloned().ok_or_else(|| invalid(handle))
+}
+
+fn invalid(handle: i64) -> c_int {
+    fail(
+        ERR_INVALID_HANDLE,
+        format!("handle {handle} is unknown or already destroyed"),
+    )
+}
+
+/// Load a tokenizer.json into a new instance independent of the global one
+/// Returns a positive handle on success, negative on error:
+///   -1 null path, -2 invalid UTF-8, -3 load failed
+/// Handles are never reused, so a destroyed handle stays invalid
+#[no_mangle]
+pub extern "C" fn tokenizer_create(path: *const c_char) -> i64 {
+    catch_panic(|| {
+        let tokenizer = match load_from_path(path) {
+            Ok(t) => t,
+            Err(code) => return code as i64,
+        };
+
+        let handle = next_handle();
+        registry()
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(handle, Arc::new(tokenizer));
+        handle
+    })
+}
+
+/// `tokenizer_encode` for a handle created by `tokenizer_create`
+/// Returns number of tokens on success, negative on error:
+///   -1 null pointer, -2 invalid UTF-8, -4 encode failed,
+///   -7 unknown or destroyed handle
+#[no_mangle]
+pub extern "C" fn tokenizer_encode_h(
+    handle: i64,
+    text: *const c_char,
+    out_ids: *mut c_int,
+    max_len: usize,
+) -> c_int {
+    catch_panic(|| {
+        if let Err(code) =
+            guard::out_buffer("tokenizer_encode_h", "out_ids", out_ids, max_len, text)
+        {
+            return code;
+        }
+        let text = match text_arg(text) {
+            Ok(s) => s,
+            Err(code) => return code,
+        };
+        let tokenizer = match lookup(handle) {
+            Ok(t) => t,
+            Err(code) => return code,
+        };
+
+        encode_into(&tokenizer, &text, true, out_ids, max_len)
+    })
+}
+
+/// `tokenizer_decode_ex` for a handle created by `tokenizer_create`
+/// Returns bytes written (excluding NUL) or the required size for a null
+/// `out_text`, negative on error (as `tokenizer_decode`, plus -7 bad handle)
+#[no_mangle]
+pub extern "C" fn tokenizer_decode_h(
+    handle: i64,
+    ids: *const c_int,
+    len: usize,
+    skip_special_tokens: c_int,
+    out_text: *mut c_char,
+    out_capacity: usize,
+) -> c_int {
+    catch_panic(|| {
+        let ids = match ids_arg(ids, len) {
+            Ok(ids) => ids,
+            Err(code) => return code,
+        };
+        let tokenizer = match lookup(handle) {
+            Ok(t) => t,
+            Err(code) => return code,
+        };
+
+        decode_into(
+            &tokenizer,
+            &ids,
+            skip_special_tokens != 0,
+            out_text,
+            out_capacity,
+        )
+    })
+}
+
+/// Release a handle; calls already using it finish on their own reference
+/// Returns 0 on success, -7 unknown or already destroyed
+#[no_mangle]
+pub extern "C" fn tokenizer_destroy(handle: i64) -> c_int {
+    catch_panic(|| {
+        let mut handles = registry().write().unwrap_or_else(PoisonError::into_inner);
+        match handles.remove(&handle) {
+            Some(_) => 0,
+            None => invalid(handle),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{bert_json, llama_json, write_temp};
+    use std::ffi::CString;
+
+    fn create(name: &str, json: &str) -> i64 {
+        let path = write_temp(name, json);
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+        let handle = tokenizer_create(c_path.as_ptr());
+        assert!(handle > 0, "create failed with {handle}");
+        handle
+    }
+
+    fn encode(handle: i64, text: &str) -> Vec<c_int> {
+        let text = CString::new(text).unwrap();
+        let mut ids = vec![0; 64];
+        let n = tokenizer_encode_h(handle, text.as_ptr(), ids.as_mut_ptr(), ids.len());
+        assert!(n >= 0, "encode failed with {n}");
+        ids.truncate(n as usize);
+        ids
+    }
+
+    #[test]
+    fn two_handles_keep_their_own_tokenizer() {
+        let llama = create("handles_llama", &llama_json());
+        let bert = create("handles_bert", &bert_json());
+
+        assert_eq!(encode(llama, "hello")[0], 1);
+        assert_eq!(encode(bert, "hello"), vec![2, 5, 3]);
+
+        let ids = encode(bert, "hello world");
+        let mut buf = vec![0 as c_char; 64];
+        let n = tokenizer_decode_h(
+            bert,
+            ids.as_ptr(),
+            ids.len(),
+            1,
+            buf.as_mut_ptr(),
+            buf.len(),
+        );
+        assert_eq!(n, "hello world".len() as c_int);
+
+        assert_eq!(tokenizer_destroy(llama), 0);
+        assert_eq!(tokenizer_destroy(bert), 0);
+    }
+
+    #[test]
+    fn stale_handles_return_errors() {
+        let handle = create("handles_stale", &llama_json());
+        assert_eq!(tokenizer_destroy(handle), 0);
+        assert_eq!(tokenizer_destroy(handle), ERR_INVALID_HANDLE);
+
+        let text = CString::new("hello").unwrap();
+        let mut ids = [0; 8];
+        let rc = tokenizer_encode_h(handle, text.as_ptr(), ids.as_mut_ptr(), ids.len());
+        assert_eq!(rc, ERR_INVALID_HANDLE);
+        assert_eq!(
+            tokenizer_encode_h(0, text.as_ptr(), ids.as_mut_ptr(), 8),
+            ERR_INVALID_HANDLE
+        );
+    }
+
+    #[test]
+    fn handles_encode_in_parallel() {
+        let llama = create("handles_par_llama", &llama_json());
+        let bert = create("handles_par_bert", &bert_json());
+
+        let workers: Vec<_> = [(llama, vec![1, 296, 301]), (bert, vec![2, 5, 6, 3])]
+            .into_iter()
+            .map(|(handle, expected)| {
+                std::thread::spawn(move || {
+                    for _ in 0..200 {
+                        assert_eq!(encode(handle, "hello world"), expected);
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        tokenizer_destroy(llama);
+        tokenizer_destroy(bert);
+    }
+}
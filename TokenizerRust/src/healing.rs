@@ -0,0 +1,217 @@
+//! Token healing: a prompt that ends inside a word loses its last token, and
+//! generation is constrained to tokens that start with the removed text, so
+//! the model can pick the token it would have chosen for the whole word.
+
+use std::ffi::{c_char, c_int};
+
+use crate::bytes::piece_bytes;
+use crate::vocab::pieces;
+use crate::{
+    c_str_arg, catch_panic, copy_ids, guard, null_output, text_arg, tokenizer_failed,
+    with_tokenizer, write_c_str,
+};
+
+/// Encode a prompt for token healing, with special tokens added
+/// If the text ends in a letter or digit, its last token is removed (with the
+/// byte-fallback tokens of the same character, should it be split), and the
+/// removed pieces are written to `out_suffix` as they read inside a text, so
+/// "hello wor" gives " wor"; this may start with a space the text itself does
+/// not have, where the tokenizer adds one before the first word. Special
+/// tokens the post-processor appends stay in place. Text ending in
+/// whitespace or punctuation loses nothing and the suffix is empty. A suffix
+/// buffer of the text's length plus 8 bytes always suffices. Only the first
+/// `max_len` remaining IDs are copied, as with `tokenizer_encode`.
+/// Returns number of tokens copied, negative on error:
+///   -1 null pointer, -2 invalid UTF-8, -3 not initialized, -4 encode failed,
+///   -6 suffix buffer too small (nothing is written), -11 ID out of range
+#[no_mangle]
+pub extern "C" fn tokenizer_prepare_healing(
+    text: *const c_char,
+    out_ids: *mut c_int,
+    max_len: usize,
+    out_suffix: *mut c_char,
+    suffix_capacity: usize,
+) -> c_int {
+    catch_panic(|| {
+        if let Err(code) = guard::out_buffer(
+            "tokenizer_prepare_healing",
+            "out_ids",
+            out_ids,
+            max_len,
+            text,
+        ) {
+            return code;
+        }
+        if out_suffix.is_null() || suffix_capacity == 0 {
+            return null_output("out_suffix");
+        }
+        let text_str = match text_arg(text) {
+            Ok(s) => s,
+            Err(code) => return code,
+        };
+
+        with_tokenizer(|tokenizer| {
+            let encoding = match tokenizer.encode(&*text_str, true) {
+                Ok(enc) => enc,
+                Err(e) => return tokenizer_failed("encode", e),
+            };
+            let ids = encoding.get_ids();
+            let offsets = encoding.get_offsets();
+            let special = encoding.get_special_tokens_mask();
+
+            let ends_in_word = text_str.chars().last().is_some_and(char::is_alphanumeric);
+            let last = (0..ids.len()).rev().find(|&i| special[i] == 0);
+            let (first, last) = match last {
+                Some(last) if ends_in_word => {
+                    let start = offsets[last].0;
+                    let first = (0..=last)
+                        .rev()
+                        .take_while(|&i| special[i] == 0 && offsets[i].0 == start)
+                        .last()
+                        .unwrap_or(last);
+                    (first, last)
+                }
+                _ => (ids.len(), ids.len()),
+            };
+
+            let mut suffix = Vec::new();
+            for &id in &ids[first..(last + 1).min(ids.len())] {
+                let piece = tokenizer.id_to_token(id).unwrap_or_default();
+                match piece_bytes(tokenizer, &piece) {
+                    Ok(bytes) => suffix.extend(bytes),
+                    Err(e) => return tokenizer_failed("piece decode", e),
+                }
+            }
+            let written = write_c_str(
+                &String::from_utf8_lossy(&suffix),
+                out_suffix,
+                suffix_capacity,
+            );
+            if written < 0 {
+                return written;
+            }
+
+            let kept: Vec<u32> = ids[..first]
+                .iter()
+                .chain(ids.get(last + 1..).unwrap_or_default())
+                .copied()
+                .collect();
+            copy_ids(&kept, out_ids, max_len)
+        })
+    })
+}
+
+/// IDs of the tokens that can follow a prompt shortened by
+/// `tokenizer_prepare_healing`: every non-special token whose piece, read as
+/// text, starts with `suffix` (" wor" matches `▁wor` and `▁world`, and `Ġwor`
+/// for byte-level tokenizers). IDs are written in ascending order, at most
+/// `max_out` of them.
+/// Returns the total number of candidates, 0 when there are none (including
+/// for an empty suffix), in which case sampling should be left unconstrained;
+/// negative on error:
+///   -1 null argument, -2 invalid UTF-8, -3 not initialized, -4 a piece could
+///   not be decoded
+#[no_mangle]
+pub extern "C" fn tokenizer_healing_candidates(
+    suffix: *const c_char,
+    out_ids: *mut c_int,
+    max_out: usize,
+) -> c_int {
+    catch_panic(|| {
+        if out_ids.is_null() && max_out > 0 {
+            return null_output("out_ids");
+        }
+        let suffix = match c_str_arg(suffix) {
+            Ok(s) => s,
+            Err(code) => return code,
+        };
+        if suffix.is_empty() {
+            return 0;
+        }
+
+        with_tokenizer(|tokenizer| {
+            let table = match pieces(tokenizer) {
+                Ok(table) => table,
+                Err(code) => return code,
+            };
+            let added = tokenizer.get_added_vocabulary();
+            let candidates = table
+                .pieces
+                .iter()
+                .filter(|(_, piece)| piece.starts_with(suffix) && !added.is_special_token(piece))
+                .map(|(id, _)| *id);
+
+            let mut total = 0;
+            for id in candidates {
+                if total < max_out {
+                    unsafe { *out_ids.add(total) = id as c_int };
+                }
+                total += 1;
+            }
+            total as c_int
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{byte_level_json, install, llama_json, serial};
+    use std::ffi::{CStr, CString};
+
+    fn prepare(text: &str) -> (Vec<c_int>, String) {
+        let text = CString::new(text).unwrap();
+        let (mut ids, mut suffix) = (vec![0; 64], vec![0u8; 64]);
+        let n = tokenizer_prepare_healing(
+            text.as_ptr(),
+            ids.as_mut_ptr(),
+            ids.len(),
+            suffix.as_mut_ptr() as *mut c_char,
+            suffix.len(),
+        );
+        assert!(n >= 0, "prepare failed with {n}");
+        ids.truncate(n as usize);
+        let suffix = CStr::from_bytes_until_nul(&suffix).unwrap();
+        (ids, suffix.to_str().unwrap().to_owned())
+    }
+
+    fn candidates(suffix: &str) -> Vec<c_int> {
+        let suffix = CString::new(suffix).unwrap();
+        let mut ids = vec![0; 512];
+        let n = tokenizer_healing_candidates(suffix.as_ptr(), ids.as_mut_ptr(), ids.len());
+        assert!(n >= 0, "candidates failed with {n}");
+        ids.truncate(n as usize);
+        ids
+    }
+
+    #[test]
+    fn the_partial_word_is_removed_and_matched() {
+        let _guard = serial();
+        install(&llama_json());
+
+        let (ids, suffix) = prepare("hello wor");
+        assert_eq!(ids, [1, 296]);
+        assert_eq!(suffix, " wor");
+        // ▁wor and ▁world
+        assert_eq!(candidates(&suffix), [299, 301]);
+
+        // Whitespace or punctuation at the end: nothing to heal
+        assert_eq!(prepare("hello "), (vec![1, 296, 259], String::new()));
+        assert_eq!(prepare("hello!").1, "");
+        assert!(candidates("").is_empty());
+        assert!(candidates("zzz").is_empty());
+    }
+
+    #[test]
+    fn split_characters_are_removed_whole() {
+        let _guard = serial();
+        install(&byte_level_json());
+
+        // Ġ, then the two bytes of ż sharing its offsets
+        let (ids, suffix) = prepare("hi ż");
+        assert_eq!(ids, [104, 105, 32]);
+        assert_eq!(suffix, "ż");
+        assert_eq!(prepare("hi wo"), (vec![104, 105, 32, 119], "o".to_owned()));
+        assert_eq!(candidates("o"), [111]);
+    }
+}
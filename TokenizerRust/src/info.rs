@@ -0,0 +1,157 @@
+//! A summary of the loaded tokenizer and the library build, for bug reports
+//! and diagnostics pages.
+
+use serde_json::{json, Value};
+use std::ffi::{c_char, c_int};
+use tokenizers::models::ModelWrapper;
+use tokenizers::{PaddingDirection, PaddingStrategy, Tokenizer, TruncationDirection};
+
+use crate::{catch_panic, global_source, try_with_tokenizer, write_c_str};
+
+pub(crate) fn model_type(tokenizer: &Tokenizer) -> &'static str {
+    match tokenizer.get_model() {
+        ModelWrapper::BPE(_) => "BPE",
+        ModelWrapper::WordPiece(_) => "WordPiece",
+        ModelWrapper::WordLevel(_) => "WordLevel",
+        ModelWrapper::Unigram(_) => "Unigram",
+    }
+}
+
+/// The (ID, content) of every added token marked special, sorted by ID
+pub(crate) fn special_tokens(tokenizer: &Tokenizer) -> Vec<(u32, String)> {
+    let mut special: Vec<_> = tokenizer
+        .get_added_tokens_decoder()
+        .into_iter()
+        .filter(|(_, token)| token.special)
+        .map(|(id, token)| (id, token.content))
+        .collect();
+    special.sort_unstable_by_key(|&(id, _)| id);
+    special
+}
+
+fn info(tokenizer: &Tokenizer) -> Value {
+    let truncation = tokenizer.get_truncation().map(|params| {
+        json!({
+            "max_length": params.max_length,
+            "stride": params.stride,
+            "direction": match params.direction {
+                TruncationDirection::Right => "right",
+                TruncationDirection::Left => "left",
+            },
+        })
+    });
+    let padding = tokenizer.get_padding().map(|params| {
+        json!({
+            "length": match params.strategy {
+                PaddingStrategy::BatchLongest => Value::Null,
+                PaddingStrategy::Fixed(length) => json!(length),
+            },
+            "pad_to_multiple_of": params.pad_to_multiple_of,
+            "pad_id": params.pad_id,
+            "pad_token": params.pad_token,
+            "direction": match params.direction {
+                PaddingDirection::Right => "right",
+                PaddingDirection::Left => "left",
+            },
+        })
+    });
+
+    let special_tokens: Vec<Value> = special_tokens(tokenizer)
+        .into_iter()
+        .map(|(id, content)| json!({ "id": id, "content": content }))
+        .collect();
+
+    json!({
+        "loaded": true,
+        "source": global_source(),
+        "model_type": model_type(tokenizer),
+        "vocab_size": tokenizer.get_vocab_size(false),
+        "vocab_size_with_added": tokenizer.get_vocab_size(true),
+        "truncation": truncation,
+        "padding": padding,
+        "special_tokens": special_tokens,
+    })
+}
+
+/// Describe the loaded tokenizer as a JSON object
+/// Fields: `loaded`, `source` (the path it was loaded from, or "from_bytes"),
+/// `model_type` ("BPE", "WordPiece", "WordLevel" or "Unigram"), `vocab_size`
+/// and `vocab_size_with_added`, `truncation` and `padding` as currently set
+/// (null when off; a null padding `length` pads to the longest in the batch)
+/// and `special_tokens` as `{"id", "content"}` sorted by ID. Without a
+/// tokenizer (or while one is still loading) the object is `{"loaded":false}`.
+/// Writes the JSON with the `tokenizer_decode` buffer contract.
+/// Returns bytes written (excluding NUL) or the required size, -6 buffer too small
+#[no_mangle]
+pub extern "C" fn tokenizer_get_info(out_json: *mut c_char, capacity: usize) -> c_int {
+    catch_panic(|| {
+        let info = try_with_tokenizer(info).unwrap_or_else(|| json!({ "loaded": false }));
+        write_c_str(&info.to_string(), out_json, capacity)
+    })
+}
+
+/// The version of this library, e.g. "0.1.0"
+/// Writes the text with the `tokenizer_decode` buffer contract.
+/// Returns bytes written (excluding NUL) or the required size, -6 buffer too small
+#[no_mangle]
+pub extern "C" fn tokenizer_library_version(out: *mut c_char, capacity: usize) -> c_int {
+    catch_panic(|| write_c_str(env!("CARGO_PKG_VERSION"), out, capacity))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{bert_json, install, serial};
+    use std::ffi::CStr;
+
+    fn read(f: extern "C" fn(*mut c_char, usize) -> c_int) -> String {
+        let needed = f(std::ptr::null_mut(), 0);
+        assert!(needed >= 0, "size query failed with {needed}");
+        let mut buf = vec![0u8; needed as usize + 1];
+        assert_eq!(f(buf.as_mut_ptr() as *mut c_char, buf.len()), needed);
+        CStr::from_bytes_with_nul(&buf)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    fn get_info() -> Value {
+        serde_json::from_str(&read(tokenizer_get_info)).unwrap()
+    }
+
+    #[test]
+    fn describes_the_loaded_tokenizer() {
+        let _guard = serial();
+        crate::tokenizer_free();
+        assert_eq!(get_info(), json!({ "loaded": false }));
+
+        install(&bert_json());
+        assert_eq!(crate::settings::tokenizer_set_truncation(8, 1, 0), 0);
+        let info = get_info();
+        assert_eq!(info["loaded"], true);
+        assert!(info["source"].as_str().unwrap().ends_with("install.json"));
+        assert_eq!(info["model_type"], "WordPiece");
+        assert_eq!(
+            info["vocab_size_with_added"],
+            crate::vocab::tokenizer_vocab_size(1)
+        );
+        assert_eq!(
+            info["truncation"],
+            json!({ "max_length": 8, "stride": 0, "direction": "left" })
+        );
+        assert_eq!(info["padding"], Value::Null);
+        let special = info["special_tokens"].as_array().unwrap();
+        assert!(special.contains(&json!({ "id": 2, "content": "[CLS]" })));
+        assert!(special.contains(&json!({ "id": 3, "content": "[SEP]" })));
+
+        let bytes = bert_json();
+        crate::tokenizer_initialize_from_bytes(bytes.as_ptr(), bytes.len());
+        assert_eq!(get_info()["source"], "from_bytes");
+    }
+
+    #[test]
+    fn reports_the_crate_version() {
+        assert_eq!(read(tokenizer_library_version), env!("CARGO_PKG_VERSION"));
+    }
+}
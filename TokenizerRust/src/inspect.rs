@@ -0,0 +1,274 @@
+//! Diagnostics that run single stages of the tokenization pipeline, for
+//! "what the model actually sees" views.
+
+use std::ffi::{c_char, c_int};
+use tokenizers::normalizer::Range;
+use tokenizers::{
+    Encoding, Model, NormalizedString, Normalizer, OffsetReferential, OffsetType,
+    PreTokenizedString, PreTokenizer, Tokenizer,
+};
+
+use crate::{catch_panic, guard, text_arg, tokenizer_failed, with_tokenizer, write_c_str};
+
+/// `text` run through the normalizer of `tokenizer`, alignments kept
+/// Errors: -4 normalizer failed
+pub(crate) fn normalized(tokenizer: &Tokenizer, text: &str) -> Result<NormalizedString, c_int> {
+    let mut normalized = NormalizedString::from(text);
+    if let Some(normalizer) = tokenizer.get_normalizer() {
+        normalizer
+            .normalize(&mut normalized)
+            .map_err(|e| tokenizer_failed("normalize", e))?;
+    }
+    Ok(normalized)
+}
+
+/// Encode `text` with special tokens added as `Tokenizer::encode` does, but
+/// with each offset a byte range of `normalized(text)` rather than of `text`
+/// The pipeline is rerun by hand since encodings only keep original offsets,
+/// which cannot tell apart tokens from one expanded character. A split the
+/// pre-tokenizer rewrote (byte-level, Metaspace) no longer reads as a slice of
+/// the normalized text; its tokens get their original range converted instead.
+/// Errors: -4 a pipeline stage failed
+pub(crate) fn encode_in_normalized(tokenizer: &Tokenizer, text: &str) -> Result<Encoding, c_int> {
+    let whole = normalized(tokenizer, text)?;
+    let mut pretokenized = tokenizer
+        .get_added_vocabulary()
+        .extract_and_normalize(tokenizer.get_normalizer(), text);
+    let encoded = tokenizer
+        .get_pre_tokenizer()
+        .map_or(Ok(()), |p| p.pre_tokenize(&mut pretokenized))
+        .and_then(|()| pretokenized.tokenize(|split| tokenizer.get_model().tokenize(split.get())))
+        .and_then(|()| {
+            pretokenized
+                .clone()
+                .into_encoding(None, 0, OffsetType::Byte)
+        });
+    let mut encoding = encoded.map_err(|e| tokenizer_failed("encode", e))?;
+
+    let to_normalized = |start, end| {
+        let range = whole.convert_offsets(Range::Original(start..end))?;
+        Some((range.start, range.end))
+    };
+    let mut offsets = encoding.get_offsets_mut().iter_mut();
+    for (piece, (start, end), tokens) in
+        pretokenized.get_splits(OffsetReferential::Original, OffsetType::Byte)
+    {
+        let span = to_normalized(start, end);
+        let at = span.filter(|&(a, b)| whole.get().get(a..b) == Some(piece));
+        for (token, offset) in tokens.iter().flatten().zip(&mut offsets) {
+            *offset = match at {
+                Some((a, _)) => (a + token.offsets.0, a + token.offsets.1),
+                None => to_normalized(offset.0, offset.1)
+                    .or(span)
+                    .unwrap_or_default(),
+            };
+        }
+    }
+
+    tokenizer
+        .post_process(encoding, None, true)
+        .map_err(|e| tokenizer_failed("encode", e))
+}
+
+/// Run only the loaded tokenizer's normalizer over `text`
+/// Input without a normalizer is echoed unchanged. The result may be longer than
+/// the input (e.g. Llama's `▁` is three bytes), so size it with a query first.
+/// Writes the text with the `tokenizer_decode` buffer contract.
+/// Returns bytes written (excluding NUL) or the required size, negative on error:
+///   -1 null `text`, -2 invalid UTF-8, -3 not initialized, -4 normalizer failed,
+///   -6 buffer too small
+#[no_mangle]
+pub extern "C" fn tokenizer_normalize(
+    text: *const c_char,
+    out: *mut c_char,
+    capacity: usize,
+) -> c_int {
+    catch_panic(|| {
+        let text_str = match text_arg(text) {
+            Ok(s) => s,
+            Err(code) => return code,
+        };
+
+        with_tokenizer(|tokenizer| match normalized(tokenizer, &text_str) {
+            Ok(normalized) => write_c_str(normalized.get(), out, capacity),
+            Err(code) => code,
+        })
+    })
+}
+
+/// Split `text` the way the loaded pre-tokenizer does, before the model runs
+/// The normalizer runs first, as in a real encode, but each piece is reported
+/// as the UTF-8 byte range `[out_starts[i], out_ends[i])` of the original
+/// `text`, so byte-level and `▁` rewrites never leak into the offsets.
+/// Without a pre-tokenizer the whole input is one piece. Only the first
+/// `max_items` pieces are written; unless `max_items` is 0 both outputs are
+/// checked like the buffer of `tokenizer_encode`.
+/// Returns the total number of pieces, negative on error:
+///   -1 null arguments, -2 invalid UTF-8, -3 not initialized,
+///   -4 normalizer or pre-tokenizer failed, -19/-20 as for `tokenizer_encode`
+#[no_mangle]
+pub extern "C" fn tokenizer_pretokenize(
+    text: *const c_char,
+    out_starts: *mut c_int,
+    out_ends: *mut c_int,
+    max_items: usize,
+) -> c_int {
+    catch_panic(|| {
+        if max_items > 0 {
+            let out = |name, buffer| {
+                guard::out_buffer("tokenizer_pretokenize", name, buffer, max_items, text)
+            };
+            let checked = out("out_starts", out_starts).and_then(|_| out("out_ends", out_ends));
+            if let Err(code) = checked {
+                return code;
+            }
+        }
+        let text_str = match text_arg(text) {
+            Ok(s) => s,
+            Err(code) => return code,
+        };
+
+        with_tokenizer(|tokenizer| {
+            let normalized = match normalized(tokenizer, &text_str) {
+                Ok(normalized) => normalized,
+                Err(code) => return code,
+            };
+
+            let mut pretokenized = PreTokenizedString::from(normalized);
+            if let Some(pre_tokenizer) = tokenizer.get_pre_tokenizer() {
+                if let Err(e) = pre_tokenizer.pre_tokenize(&mut pretokenized) {
+                    return tokenizer_failed("pre-tokenize", e);
+                }
+            }
+
+            let splits = pretokenized.get_splits(OffsetReferential::Original, OffsetType::Byte);
+            for (i, (_, (start, end), _)) in splits.iter().take(max_items).enumerate() {
+                unsafe {
+                    *out_starts.add(i) = *start as c_int;
+                    *out_ends.add(i) = *end as c_int;
+                }
+            }
+            splits.len() as c_int
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{bert_json, install, llama_json, serial};
+    use crate::ERR_BUFFER_TOO_SMALL;
+    use std::ffi::{CStr, CString};
+
+    fn normalize(text: &str) -> String {
+        let text = CString::new(text).unwrap();
+        let needed = tokenizer_normalize(text.as_ptr(), std::ptr::null_mut(), 0);
+        assert!(needed >= 0, "size query failed with {needed}");
+        let mut buf = vec![0u8; needed as usize + 1];
+        let written =
+            tokenizer_normalize(text.as_ptr(), buf.as_mut_ptr() as *mut c_char, buf.len());
+        assert_eq!(written, needed);
+        CStr::from_bytes_with_nul(&buf)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    #[test]
+    fn shows_the_normalizer_output() {
+        let _guard = serial();
+        install(&bert_json());
+        assert_eq!(normalize("Hello WORLD"), "hello world");
+
+        install(&llama_json());
+        assert_eq!(normalize("zaż ó"), "▁zaż▁ó");
+    }
+
+    #[test]
+    fn growing_output_respects_the_capacity() {
+        let _guard = serial();
+        install(&llama_json());
+
+        // Two input bytes become "▁a▁b", eight bytes
+        let text = CString::new("a b").unwrap();
+        assert_eq!(
+            tokenizer_normalize(text.as_ptr(), std::ptr::null_mut(), 0),
+            8
+        );
+        let mut buf = [b'x' as c_char; 8];
+        assert_eq!(
+            tokenizer_normalize(text.as_ptr(), buf.as_mut_ptr(), buf.len()),
+            ERR_BUFFER_TOO_SMALL
+        );
+        assert!(buf.iter().all(|&b| b == b'x' as c_char));
+    }
+
+    #[test]
+    fn tokenizers_without_normalizer_echo_the_input() {
+        let _guard = serial();
+        let mut json: serde_json::Value = serde_json::from_str(&bert_json()).unwrap();
+        json["normalizer"] = serde_json::Value::Null;
+        install(&json.to_string());
+
+        assert_eq!(normalize("Zażółć Gęślą"), "Zażółć Gęślą");
+    }
+
+    fn pieces(text: &str) -> Vec<&str> {
+        let c_text = CString::new(text).unwrap();
+        let (mut starts, mut ends) = (vec![0; 32], vec![0; 32]);
+        let n = tokenizer_pretokenize(
+            c_text.as_ptr(),
+            starts.as_mut_ptr(),
+            ends.as_mut_ptr(),
+            starts.len(),
+        );
+        assert!(n >= 0, "pretokenize failed with {n}");
+        (0..n as usize)
+            .map(|i| &text[starts[i] as usize..ends[i] as usize])
+            .collect()
+    }
+
+    fn with_pre_tokenizer(pre_tokenizer: serde_json::Value) -> String {
+        let mut json: serde_json::Value = serde_json::from_str(&bert_json()).unwrap();
+        json["pre_tokenizer"] = pre_tokenizer;
+        json.to_string()
+    }
+
+    #[test]
+    fn bert_splits_whitespace_and_punctuation() {
+        let _guard = serial();
+        install(&bert_json());
+        assert_eq!(pieces("Hello, world!"), ["Hello", ",", "world", "!"]);
+    }
+
+    #[test]
+    fn byte_level_offsets_point_into_the_original_text() {
+        let _guard = serial();
+        install(&with_pre_tokenizer(serde_json::json!({
+            "type": "ByteLevel", "add_prefix_space": false, "trim_offsets": true, "use_regex": true
+        })));
+        assert_eq!(pieces("héllo wörld"), ["héllo", " wörld"]);
+    }
+
+    #[test]
+    fn metaspace_and_whitespace_variants() {
+        let _guard = serial();
+        install(&with_pre_tokenizer(serde_json::json!({
+            "type": "Metaspace", "replacement": "▁", "prepend_scheme": "always", "split": true
+        })));
+        assert_eq!(pieces("hello big world"), ["hello", " big", " world"]);
+
+        install(&with_pre_tokenizer(
+            serde_json::json!({ "type": "WhitespaceSplit" }),
+        ));
+        assert_eq!(pieces("hello  big\tworld"), ["hello", "big", "world"]);
+    }
+
+    #[test]
+    fn no_pre_tokenizer_gives_one_piece() {
+        let _guard = serial();
+        install(&llama_json());
+        assert_eq!(pieces("hello big world"), ["hello big world"]);
+    }
+}
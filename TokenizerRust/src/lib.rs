@@ -1,88 +1,2234 @@
+// FFI entry points take raw pointers from C callers by design; every
+// dereference is guarded by a null check and wrapped in an `unsafe` block.
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
+use std::cell::RefCell;
 use std::ffi::{c_char, c_int, CStr};
-use std::sync::Mutex;
-use tokenizers::Tokenizer;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Instant;
+use tokenizers::{Encoding, Tokenizer};
+
+mod background;
+mod bytes;
+mod cache;
+mod chat;
+mod chunk;
+mod compare;
+mod config;
+mod encoding;
+mod error;
+mod estimate;
+mod gguf;
+mod guard;
+mod handles;
+mod healing;
+mod info;
+mod inspect;
+mod log;
+mod model_input;
+mod named;
+mod prefix;
+mod render;
+mod save;
+mod settings;
+mod special;
+mod spm;
+mod stream;
+mod threads;
+mod tiktoken;
+mod utf8;
+mod vocab;
+mod wide;
+
+#[cfg(test)]
+mod test_support;
+
+pub(crate) use error::*;
+pub(crate) use utf8::text_arg;
+
+/// The tokenizer every global call uses, replaced as a whole by
+/// (re)initialization, `tokenizer_free` and settings changes
+/// Calls do not read it directly but through a per-thread snapshot, so
+/// steady-state encodes on many threads share no lock; each change bumps
+/// `GENERATION`, which tells every thread to take a fresh snapshot.
+static TOKENIZER: RwLock<Option<Arc<Tokenizer>>> = RwLock::new(None);
+/// Bumped under the `TOKENIZER` write lock whenever it changes
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+/// Where the global tokenizer came from (a path or "from_bytes"), replaced
+/// under the `TOKENIZER` write lock along with it
+static SOURCE: RwLock<Option<String>> = RwLock::new(None);
+
+struct Snapshot {
+    generation: u64,
+    tokenizer: Option<Arc<Tokenizer>>,
+}
+
+thread_local! {
+    /// Starts out of date, so the thread's first call takes a snapshot
+    static SNAPSHOT: RefCell<Snapshot> = const {
+        RefCell::new(Snapshot { generation: u64::MAX, tokenizer: None })
+    };
+}
+
+/// Run `f` on this thread's snapshot of the global tokenizer, refreshing it
+/// first if the tokenizer changed since it was taken
+/// The lock is only taken for a refresh. A call nested in another keeps using
+/// the snapshot the outer call is working on.
+fn with_snapshot<R>(f: impl FnOnce(Option<&Tokenizer>) -> R) -> R {
+    let current = GENERATION.load(Ordering::Acquire);
+    SNAPSHOT.with(|cell| {
+        if let Ok(mut snapshot) = cell.try_borrow_mut() {
+            if snapshot.generation != current {
+                let guard = TOKENIZER.read().unwrap_or_else(PoisonError::into_inner);
+                snapshot.tokenizer = guard.clone();
+                snapshot.generation = GENERATION.load(Ordering::Acquire);
+            }
+        }
+        f(cell.borrow().tokenizer.as_deref())
+    })
+}
+
+/// Generation of the snapshot the running call works on, for caches that must
+/// not mix results of different tokenizers
+pub(crate) fn snapshot_generation() -> u64 {
+    SNAPSHOT.with_borrow(|snapshot| snapshot.generation)
+}
+
+/// Where the global tokenizer was loaded from, if one is loaded
+pub(crate) fn global_source() -> Option<String> {
+    SOURCE
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
+
+/// Generation of the global tokenizer as of now
+pub(crate) fn current_generation() -> u64 {
+    GENERATION.load(Ordering::Acquire)
+}
+
+/// Copy `s` into a caller-provided buffer as a NUL-terminated UTF-8 string.
+///
+/// A null buffer or zero capacity is a size query: nothing is written and the
+/// required length (excluding NUL) is returned. A buffer that cannot hold the
+/// string plus its NUL returns `ERR_BUFFER_TOO_SMALL` and is left untouched.
+/// Otherwise the string and NUL are written and the length is returned.
+///
+/// Every export that returns one string (decode, normalize, chat templates,
+/// piece lookups, stream fragments, last error...) writes it through this
+/// helper, or `wide::write_wide` for UTF-16, so a single two-call wrapper on
+/// the caller's side fits them all: query the size, allocate size + 1, call
+/// again.
+pub(crate) fn write_c_str(s: &str, out: *mut c_char, capacity: usize) -> c_int {
+    match copy_c_str(s, out, capacity) {
+        ERR_BUFFER_TOO_SMALL => fail(
+            ERR_BUFFER_TOO_SMALL,
+            format!(
+                "output needs {} bytes plus NUL, buffer holds {capacity}",
+                s.len()
+            ),
+        ),
+        written => written,
+    }
+}
+
+/// `write_c_str` without recording a last error, for `tokenizer_last_error`
+fn copy_c_str(s: &str, out: *mut c_char, capacity: usize) -> c_int {
+    let len = match c_int::try_from(s.len()) {
+        Ok(len) => len,
+        Err(_) => return ERR_BUFFER_TOO_SMALL,
+    };
+
+    if out.is_null() || capacity == 0 {
+        return len;
+    }
+    if capacity <= s.len() {
+        return ERR_BUFFER_TOO_SMALL;
+    }
 
-static TOKENIZER: Mutex<Option<Tokenizer>> = Mutex::new(None);
+    unsafe {
+        std::ptr::copy_nonoverlapping(s.as_ptr(), out as *mut u8, s.len());
+        *out.add(s.len()) = 0;
+    }
+
+    len
+}
+
+/// Borrow a NUL-terminated UTF-8 argument, mapping failures to error codes.
+pub(crate) fn c_str_arg<'a>(ptr: *const c_char) -> Result<&'a str, c_int> {
+    if ptr.is_null() {
+        return Err(fail(ERR_NULL_POINTER, "string argument is null"));
+    }
+    unsafe { CStr::from_ptr(ptr) }.to_str().map_err(|e| {
+        fail(
+            ERR_INVALID_UTF8,
+            format!("invalid UTF-8 at byte {}", e.valid_up_to()),
+        )
+    })
+}
+
+/// Record a null output-pointer failure
+pub(crate) fn null_output(name: &str) -> c_int {
+    fail(ERR_NULL_POINTER, format!("`{name}` is null"))
+}
 
 /// Initialize the tokenizer from a tokenizer.json file path
 /// Returns 0 on success, negative on error
 /// Can be called multiple times to reinitialize with a different tokenizer
 #[no_mangle]
 pub extern "C" fn tokenizer_initialize(path: *const c_char) -> c_int {
-    if path.is_null() {
-        return -1;
+    catch_panic(|| {
+        let started = Instant::now();
+        let path = match c_str_arg(path) {
+            Ok(p) => p,
+            Err(code) => return code,
+        };
+
+        match load_from_file(path) {
+            Ok(tokenizer) => set_global(tokenizer, path, started),
+            Err(code) => code,
+        }
+    })
+}
+
+/// Initialize the tokenizer from tokenizer.json contents held in memory
+/// The buffer is fully parsed before returning, so the caller may free it
+/// immediately afterwards. Reinitialization and `tokenizer_free` behave exactly
+/// as with `tokenizer_initialize`.
+/// Returns 0 on success, negative on error:
+///   -1 null or empty buffer, -3 parse failed
+#[no_mangle]
+pub extern "C" fn tokenizer_initialize_from_bytes(data: *const u8, len: usize) -> c_int {
+    catch_panic(|| {
+        let started = Instant::now();
+        if data.is_null() || len == 0 {
+            return fail(ERR_NULL_POINTER, "tokenizer buffer is null or empty");
+        }
+
+        let bytes = unsafe { std::slice::from_raw_parts(data, len) };
+        match Tokenizer::from_bytes(bytes) {
+            Ok(tokenizer) => set_global(tokenizer, "from_bytes", started),
+            Err(e) => fail(-3, format!("failed to parse tokenizer from bytes: {e}")),
+        }
+    })
+}
+
+/// Install `tokenizer`, loaded from `source` in a load that began at
+/// `started`, as the global one, superseding any async load in flight;
+/// always returns 0
+pub(crate) fn set_global(tokenizer: Tokenizer, source: &str, started: Instant) -> c_int {
+    log_loaded(&tokenizer, source, started);
+    background::supersede(|| replace_global(Some((tokenizer, source.to_owned()))));
+    0
+}
+
+/// Log a successful load at info level
+pub(crate) fn log_loaded(tokenizer: &Tokenizer, source: &str, started: Instant) {
+    log::log(log::LOG_INFO, || {
+        format!(
+            "loaded tokenizer from '{source}': vocab size {} in {} ms",
+            tokenizer.get_vocab_size(true),
+            started.elapsed().as_millis()
+        )
+    });
+}
+
+/// Swap the global tokenizer, dropping encodings and pieces cached for the old one
+/// Threads still holding a snapshot of the old tokenizer release it on their
+/// next call.
+pub(crate) fn replace_global(loaded: Option<(Tokenizer, String)>) {
+    let mut guard = TOKENIZER.write().unwrap_or_else(PoisonError::into_inner);
+    let (tokenizer, source) = loaded.unzip();
+    *guard = tokenizer.map(Arc::new);
+    *SOURCE.write().unwrap_or_else(PoisonError::into_inner) = source;
+    GENERATION.fetch_add(1, Ordering::AcqRel);
+    cache::clear();
+    vocab::clear_pieces();
+    config::clear();
+}
+
+/// Record that an async load is still in flight
+fn still_loading() -> c_int {
+    fail(
+        ERR_STILL_LOADING,
+        "tokenizer is still loading; poll tokenizer_init_status",
+    )
+}
+
+/// Run `f` against the global tokenizer, mapping initialization failures and
+/// panics to error codes
+pub(crate) fn with_tokenizer(f: impl FnOnce(&Tokenizer) -> c_int) -> c_int {
+    if !background::wait_for_load() {
+        return still_loading();
     }
 
-    let c_str = unsafe { CStr::from_ptr(path) };
-    let path_str = match c_str.to_str() {
-        Ok(s) => s,
-        Err(_) => return -2,
-    };
+    with_snapshot(|tokenizer| match tokenizer {
+        Some(t) => catch_panic(|| f(t)),
+        None => fail(
+            ERR_NOT_INITIALIZED,
+            "tokenizer is not initialized; call tokenizer_initialize first",
+        ),
+    })
+}
 
-    let tokenizer = match Tokenizer::from_file(path_str) {
-        Ok(t) => t,
-        Err(_) => return -3,
-    };
+/// Run `f` against the global tokenizer if one is loaded, without recording
+/// an error otherwise, for lookups that have a fallback
+pub(crate) fn try_with_tokenizer<R>(f: impl FnOnce(&Tokenizer) -> R) -> Option<R> {
+    if !background::wait_for_load() {
+        return None;
+    }
+    with_snapshot(|tokenizer| tokenizer.map(f))
+}
 
-    match TOKENIZER.lock() {
-        Ok(mut guard) => {
-            *guard = Some(tokenizer);
-            0
+/// `with_tokenizer` with exclusive access, for calls that reconfigure the tokenizer
+/// Snapshots other threads still use are left alone: `f` changes a copy,
+/// which becomes the next generation only if `f` returns 0 or more. On an
+/// error or a panic the copy is thrown away and the tokenizer stays as it was.
+pub(crate) fn with_tokenizer_mut(f: impl FnOnce(&mut Tokenizer) -> c_int) -> c_int {
+    if !background::wait_for_load() {
+        return still_loading();
+    }
+    let mut guard = TOKENIZER.write().unwrap_or_else(PoisonError::into_inner);
+
+    match guard.as_mut() {
+        Some(t) => {
+            let mut next = Tokenizer::clone(t);
+            let rc = catch_panic(|| f(&mut next));
+            if rc >= 0 {
+                *t = Arc::new(next);
+                GENERATION.fetch_add(1, Ordering::AcqRel);
+                cache::clear();
+                vocab::clear_pieces();
+            }
+            rc
         }
-        Err(_) => -4, // Lock poisoned
+        None => fail(
+            ERR_NOT_INITIALIZED,
+            "tokenizer is not initialized; call tokenizer_initialize first",
+        ),
     }
 }
 
+/// Load a tokenizer.json from a C path
+/// Errors: -1 null path, -2 invalid UTF-8, -3 load failed
+pub(crate) fn load_from_path(path: *const c_char) -> Result<Tokenizer, c_int> {
+    let path_str = c_str_arg(path)?;
+    load_from_file(path_str)
+}
+
+/// `Tokenizer::from_file` with the underlying error recorded under -3
+pub(crate) fn load_from_file(path: &str) -> Result<Tokenizer, c_int> {
+    Tokenizer::from_file(path).map_err(|e| fail(-3, format!("failed to load '{path}': {e}")))
+}
+
+/// Record an error reported by the tokenizers crate under -4
+pub(crate) fn tokenizer_failed(what: &str, e: tokenizers::Error) -> c_int {
+    fail(ERR_TOKENIZER_FAILED, format!("{what} failed: {e}"))
+}
+
 /// Encode text to token IDs with special tokens added
-/// Returns number of tokens on success, negative on error
+/// Only the first `max_len` IDs are copied and the copied count is returned,
+/// so a result equal to `max_len` may mean the encoding was cut short; use
+/// `tokenizer_encode_v2` to detect that. A `max_len` of 0 or above the limit
+/// set by `tokenizer_set_length_limit` fails with -19, and an `out_ids` that
+/// is misaligned or overlaps `text` with -20, before anything is written;
+/// every export that encodes text into caller buffers (IDs, masks, type IDs,
+/// word groups), `tokenizer_pretokenize` and the `encoding_copy_*` accessors
+/// check them the same way.
+/// Returns number of tokens on success, negative on error; an ID that does
+/// not fit in a `c_int` returns -11 instead of wrapping (see `tokenizer_encode_u32`)
 #[no_mangle]
 pub extern "C" fn tokenizer_encode(
     text: *const c_char,
     out_ids: *mut c_int,
     max_len: usize,
 ) -> c_int {
-    if text.is_null() || out_ids.is_null() {
-        return -1;
-    }
+    catch_panic(|| tokenizer_encode_opts(text, 1, out_ids, max_len))
+}
 
-    let c_str = unsafe { CStr::from_ptr(text) };
-    let text_str = match c_str.to_str() {
-        Ok(s) => s,
-        Err(_) => return -2,
-    };
+/// Same as `tokenizer_encode`, with `add_special_tokens` chosen by the caller
+/// Pass 0 when encoding a fragment that will be concatenated with other
+/// token sequences, so no BOS/CLS/SEP tokens leak into the middle of it
+#[no_mangle]
+pub extern "C" fn tokenizer_encode_opts(
+    text: *const c_char,
+    add_special_tokens: c_int,
+    out_ids: *mut c_int,
+    max_len: usize,
+) -> c_int {
+    catch_panic(|| {
+        if let Err(code) =
+            guard::out_buffer("tokenizer_encode_opts", "out_ids", out_ids, max_len, text)
+        {
+            return code;
+        }
+        let text_str = match text_arg(text) {
+            Ok(s) => s,
+            Err(code) => return code,
+        };
 
-    let guard = match TOKENIZER.lock() {
-        Ok(g) => g,
-        Err(_) => return -5, // Lock poisoned
-    };
+        let add_special_tokens = add_special_tokens != 0;
+        with_tokenizer(|tokenizer| {
+            let encode = || tokenizer.encode(&*text_str, add_special_tokens);
+            match cache::cached_ids(&text_str, add_special_tokens, encode) {
+                Ok(ids) => copy_ids(&ids, out_ids, max_len),
+                Err(e) => tokenizer_failed("encode", e),
+            }
+        })
+    })
+}
 
-    let tokenizer = match guard.as_ref() {
-        Some(t) => t,
-        None => return -3, // Not initialized
-    };
+/// Encode without ever truncating: the full token count is always returned
+/// If the count fits in `max_len` the IDs are written; otherwise nothing is
+/// written, so a result greater than `max_len` means "call again with a buffer
+/// this large". A null `out_ids` with `max_len` 0 is a size query.
+/// Returns the token count, negative on error (as `tokenizer_encode_opts`,
+/// plus -1 for a null `out_ids` with non-zero `max_len`)
+#[no_mangle]
+pub extern "C" fn tokenizer_encode_v2(
+    text: *const c_char,
+    add_special_tokens: c_int,
+    out_ids: *mut c_int,
+    max_len: usize,
+) -> c_int {
+    catch_panic(|| {
+        // A null `out_ids` with `max_len` 0 is the size query
+        if !out_ids.is_null() || max_len > 0 {
+            if let Err(code) =
+                guard::out_buffer("tokenizer_encode_v2", "out_ids", out_ids, max_len, text)
+            {
+                return code;
+            }
+        }
+        let text_str = match text_arg(text) {
+            Ok(s) => s,
+            Err(code) => return code,
+        };
+
+        let add_special_tokens = add_special_tokens != 0;
+        with_tokenizer(|tokenizer| {
+            let encode = || tokenizer.encode(&*text_str, add_special_tokens);
+            match cache::cached_ids(&text_str, add_special_tokens, encode) {
+                Ok(ids) if ids.len() > max_len => match c_int::try_from(ids.len()) {
+                    Ok(needed) => needed,
+                    Err(_) => fail(
+                        ERR_BUFFER_TOO_SMALL,
+                        format!("{} tokens exceed the C int range", ids.len()),
+                    ),
+                },
+                Ok(ids) => copy_ids(&ids, out_ids, max_len),
+                Err(e) => tokenizer_failed("encode", e),
+            }
+        })
+    })
+}
+
+/// Encode with special tokens added and report where each token came from
+/// `out_starts[i]`/`out_ends[i]` receive the byte range of token `i` in `text`
+/// (UTF-8 byte offsets, end exclusive), so multi-byte characters are counted
+/// by their encoded length. The ranges index `text` as passed in, never its
+/// normalized form: a lowercased or accent-stripped token spans the original
+/// characters it came from, and the tokens of one expanded character (NFKC's
+/// `ﬁ` as `f` + `i`) or several byte-fallback tokens of one character all
+/// span that whole character; `tokenizer_encode_with_offsets_ex` reports
+/// normalized-text ranges instead. Ranges never run backwards and come in text
+/// order. Special tokens inserted by the post-processor have no source span
+/// and report (-1, -1).
+/// Returns number of tokens on success, negative on error (as `tokenizer_encode`)
+#[no_mangle]
+pub extern "C" fn tokenizer_encode_with_offsets(
+    text: *const c_char,
+    out_ids: *mut c_int,
+    out_starts: *mut c_int,
+    out_ends: *mut c_int,
+    max_len: usize,
+) -> c_int {
+    catch_panic(|| {
+        if let Err(code) = guard::out_buffer(
+            "tokenizer_encode_with_offsets",
+            "out_ids",
+            out_ids,
+            max_len,
+            text,
+        ) {
+            return code;
+        }
+        if out_starts.is_null() || out_ends.is_null() {
+            return null_output("out_starts/out_ends");
+        }
+        let text_str = match text_arg(text) {
+            Ok(s) => s,
+            Err(code) => return code,
+        };
+
+        with_tokenizer(|tokenizer| {
+            let bytes = |start, end| (start, end);
+            encode_offsets_into(
+                encode_with_specials(tokenizer, &text_str),
+                out_ids,
+                out_starts,
+                out_ends,
+                max_len,
+                bytes,
+            )
+        })
+    })
+}
+
+/// `tokenizer_encode_with_offsets` with the ranges in UTF-16 code units
+/// `out_starts[i]`/`out_ends[i]` index the UTF-16 form of `text`, as a C#
+/// `string` does, so `text.Substring(start, end - start)` is the token's
+/// source; characters outside the BMP count as two units. A range that starts
+/// or ends inside a character covers the whole character, so the ranges stay
+/// in bounds and never run backwards.
+/// Returns number of tokens on success, negative on error (as `tokenizer_encode`)
+#[no_mangle]
+pub extern "C" fn tokenizer_encode_with_offsets_u16(
+    text: *const c_char,
+    out_ids: *mut c_int,
+    out_starts: *mut c_int,
+    out_ends: *mut c_int,
+    max_len: usize,
+) -> c_int {
+    catch_panic(|| {
+        if let Err(code) = guard::out_buffer(
+            "tokenizer_encode_with_offsets_u16",
+            "out_ids",
+            out_ids,
+            max_len,
+            text,
+        ) {
+            return code;
+        }
+        if out_starts.is_null() || out_ends.is_null() {
+            return null_output("out_starts/out_ends");
+        }
+        let text_str = match text_arg(text) {
+            Ok(s) => s,
+            Err(code) => return code,
+        };
+
+        // units[b] is the number of UTF-16 units before the character holding
+        // byte `b`, and units[len] the length of the whole text
+        let mut units = Vec::with_capacity(text_str.len() + 1);
+        let mut before = 0;
+        for c in text_str.chars() {
+            units.extend(std::iter::repeat_n(before, c.len_utf8()));
+            before += c.len_utf16();
+        }
+        units.push(before);
+        let to_units = |start: usize, end: usize| {
+            let end = (end.min(text_str.len())..=text_str.len())
+                .find(|&b| text_str.is_char_boundary(b))
+                .unwrap_or(text_str.len());
+            (units[start.min(end)], units[end])
+        };
+
+        with_tokenizer(|tokenizer| {
+            encode_offsets_into(
+                encode_with_specials(tokenizer, &text_str),
+                out_ids,
+                out_starts,
+                out_ends,
+                max_len,
+                to_units,
+            )
+        })
+    })
+}
+
+/// Offsets into the caller's text, as `tokenizer_encode_with_offsets` reports
+const OFFSETS_ORIGINAL: c_int = 0;
+/// Offsets into the text as `tokenizer_normalize` returns it
+const OFFSETS_NORMALIZED: c_int = 1;
+
+/// `tokenizer_encode_with_offsets` with the text the ranges index chosen
+/// `referential` 0 reports UTF-8 byte ranges of `text` exactly as
+/// `tokenizer_encode_with_offsets` does; 1 reports UTF-8 byte ranges of the
+/// normalized text, as `tokenizer_normalize` returns it, so lowercased,
+/// accent-stripped or NFKC-expanded pieces can be looked up where they stand
+/// (NFKC turns `ﬁ` into `fi`, which may then be two tokens of one byte each).
+/// Special tokens report (-1, -1) in both.
+/// Returns number of tokens on success, negative on error (as
+/// `tokenizer_encode`), or -9 for an unknown `referential`
+#[no_mangle]
+pub extern "C" fn tokenizer_encode_with_offsets_ex(
+    text: *const c_char,
+    referential: c_int,
+    out_ids: *mut c_int,
+    out_starts: *mut c_int,
+    out_ends: *mut c_int,
+    max_len: usize,
+) -> c_int {
+    catch_panic(|| {
+        if !matches!(referential, OFFSETS_ORIGINAL | OFFSETS_NORMALIZED) {
+            return fail(
+                ERR_INVALID_ARGUMENT,
+                format!("unknown offset referential {referential}; expected 0 or 1"),
+            );
+        }
+        if referential == OFFSETS_ORIGINAL {
+            return tokenizer_encode_with_offsets(text, out_ids, out_starts, out_ends, max_len);
+        }
+        if let Err(code) = guard::out_buffer(
+            "tokenizer_encode_with_offsets_ex",
+            "out_ids",
+            out_ids,
+            max_len,
+            text,
+        ) {
+            return code;
+        }
+        if out_starts.is_null() || out_ends.is_null() {
+            return null_output("out_starts/out_ends");
+        }
+        let text_str = match text_arg(text) {
+            Ok(s) => s,
+            Err(code) => return code,
+        };
 
-    // Encode with add_special_tokens = true (CRITICAL!)
-    let encoding = match tokenizer.encode(text_str, true) {
+        with_tokenizer(|tokenizer| {
+            let same = |start, end| (start, end);
+            encode_offsets_into(
+                inspect::encode_in_normalized(tokenizer, &text_str),
+                out_ids,
+                out_starts,
+                out_ends,
+                max_len,
+                same,
+            )
+        })
+    })
+}
+
+fn encode_with_specials(tokenizer: &Tokenizer, text: &str) -> Result<Encoding, c_int> {
+    tokenizer
+        .encode(text, true)
+        .map_err(|e| tokenizer_failed("encode", e))
+}
+
+/// Write the IDs and source ranges of `encoded`, each byte range passed
+/// through `map`
+fn encode_offsets_into(
+    encoded: Result<Encoding, c_int>,
+    out_ids: *mut c_int,
+    out_starts: *mut c_int,
+    out_ends: *mut c_int,
+    max_len: usize,
+    map: impl Fn(usize, usize) -> (usize, usize),
+) -> c_int {
+    let encoding = match encoded {
         Ok(enc) => enc,
-        Err(_) => return -4,
+        Err(code) => return code,
     };
 
-    let ids = encoding.get_ids();
-    let len = ids.len().min(max_len);
+    let len = copy_ids(encoding.get_ids(), out_ids, max_len);
+    if len < 0 {
+        return len;
+    }
+    let spans = encoding
+        .get_offsets()
+        .iter()
+        .zip(encoding.get_special_tokens_mask());
+    unsafe {
+        for (i, (&(start, end), &special)) in spans.take(len as usize).enumerate() {
+            let (start, end) = if special == 1 {
+                (-1, -1)
+            } else {
+                let (start, end) = map(start, end);
+                (start as c_int, end as c_int)
+            };
+            *out_starts.add(i) = start;
+            *out_ends.add(i) = end;
+        }
+    }
+
+    len
+}
+
+/// Encode with special tokens added and report which word each token belongs to
+/// Words are the pieces the tokenizer's own pre-tokenizer splits the text
+/// into, numbered from 0, so punctuation usually forms words of its own
+/// ("don't!" is `don`, `'`, `t`, `!` for BERT); a tokenizer without a
+/// pre-tokenizer sees the whole text as word 0. Special tokens inserted by the
+/// post-processor belong to no word and report -1. Combine with
+/// `tokenizer_encode_with_offsets` or `tokenizer_pretokenize` for the byte
+/// span of each word.
+/// Returns number of tokens on success, negative on error (as `tokenizer_encode`)
+#[no_mangle]
+pub extern "C" fn tokenizer_encode_with_word_ids(
+    text: *const c_char,
+    out_ids: *mut c_int,
+    out_word_ids: *mut c_int,
+    max_len: usize,
+) -> c_int {
+    catch_panic(|| {
+        if let Err(code) = guard::out_buffer(
+            "tokenizer_encode_with_word_ids",
+            "out_ids",
+            out_ids,
+            max_len,
+            text,
+        ) {
+            return code;
+        }
+        if out_word_ids.is_null() {
+            return null_output("out_word_ids");
+        }
+        let text_str = match text_arg(text) {
+            Ok(s) => s,
+            Err(code) => return code,
+        };
+
+        with_tokenizer(|tokenizer| {
+            let encoding = match tokenizer.encode(text_str, true) {
+                Ok(enc) => enc,
+                Err(e) => return tokenizer_failed("encode", e),
+            };
+
+            let len = copy_ids(encoding.get_ids(), out_ids, max_len);
+            if len < 0 {
+                return len;
+            }
+            unsafe {
+                for (i, word) in encoding
+                    .get_word_ids()
+                    .iter()
+                    .take(len as usize)
+                    .enumerate()
+                {
+                    *out_word_ids.add(i) = word.map_or(-1, |w| w as c_int);
+                }
+            }
+
+            len
+        })
+    })
+}
+
+/// Group the tokens of `text` by word, for masking whole words together
+/// Encodes with special tokens added, exactly as `tokenizer_encode`, and
+/// writes for each word (as numbered by `tokenizer_encode_with_word_ids`) the
+/// index of its first token into `out_group_starts` and its number of tokens
+/// into `out_group_lens`, in text order. Special tokens, padding included,
+/// belong to no group. Should a tokenizer ever spread one word over tokens
+/// that are not consecutive, each consecutive run is a group of its own, so
+/// `start..start + len` always covers the tokens of exactly one word. Only the
+/// first `max_groups` groups are written; both outputs may be null when
+/// `max_groups` is 0.
+/// Returns the total number of groups, negative on error:
+///   -1 null argument, -2 invalid UTF-8, -3 not initialized, -4 encode failed,
+///   -19/-20 as for `tokenizer_encode`
+#[no_mangle]
+pub extern "C" fn tokenizer_word_groups(
+    text: *const c_char,
+    out_group_starts: *mut c_int,
+    out_group_lens: *mut c_int,
+    max_groups: usize,
+) -> c_int {
+    catch_panic(|| {
+        if max_groups > 0 {
+            let buffers = [
+                ("out_group_starts", out_group_starts),
+                ("out_group_lens", out_group_lens),
+            ];
+            for (name, out) in buffers {
+                let checked =
+                    guard::out_buffer("tokenizer_word_groups", name, out, max_groups, text);
+                if let Err(code) = checked {
+                    return code;
+                }
+            }
+        }
+        let text_str = match text_arg(text) {
+            Ok(s) => s,
+            Err(code) => return code,
+        };
+
+        with_tokenizer(|tokenizer| {
+            let encoding = match tokenizer.encode(text_str, true) {
+                Ok(enc) => enc,
+                Err(e) => return tokenizer_failed("encode", e),
+            };
+
+            // (first token, token count) of each run of one word's tokens
+            let mut groups: Vec<(usize, usize)> = Vec::new();
+            let mut previous = None;
+            let words = encoding
+                .get_word_ids()
+                .iter()
+                .zip(encoding.get_special_tokens_mask());
+            for (i, (&word, &special)) in words.enumerate() {
+                let word = word.filter(|_| special == 0);
+                match groups.last_mut() {
+                    Some((_, len)) if word.is_some() && word == previous => *len += 1,
+                    _ if word.is_some() => groups.push((i, 1)),
+                    _ => {}
+                }
+                previous = word;
+            }
+
+            for (i, &(start, len)) in groups.iter().take(max_groups).enumerate() {
+                unsafe {
+                    *out_group_starts.add(i) = start as c_int;
+                    *out_group_lens.add(i) = len as c_int;
+                }
+            }
+            groups.len() as c_int
+        })
+    })
+}
+
+/// Encode with special tokens added and return each token's piece string
+/// Pieces come from the encoding as the model sees them (`▁Hello`, `Ġworld`,
+/// `<0xE2>`, `<|im_start|>`), packed NUL-terminated one after another into
+/// `out_tokens_buf`; `out_token_offsets[i]` receives the byte offset where
+/// piece `i` starts. Only the first `max_tokens` tokens are written. A null
+/// `out_tokens_buf` or zero `buf_capacity` is a size query: nothing is written
+/// and the bytes needed for those pieces, NULs included, are returned.
+/// Returns number of tokens (or the required size for a size query), negative
+/// on error (as `tokenizer_encode`, plus -6 buffer too small)
+#[no_mangle]
+pub extern "C" fn tokenizer_encode_with_tokens(
+    text: *const c_char,
+    out_ids: *mut c_int,
+    out_tokens_buf: *mut c_char,
+    out_token_offsets: *mut c_int,
+    buf_capacity: usize,
+    max_tokens: usize,
+) -> c_int {
+    catch_panic(|| {
+        if let Err(code) = guard::out_buffer(
+            "tokenizer_encode_with_tokens",
+            "out_ids",
+            out_ids,
+            max_tokens,
+            text,
+        ) {
+            return code;
+        }
+        if out_token_offsets.is_null() {
+            return null_output("out_token_offsets");
+        }
+        let text_str = match text_arg(text) {
+            Ok(s) => s,
+            Err(code) => return code,
+        };
+
+        with_tokenizer(|tokenizer| {
+            let encoding = match tokenizer.encode(text_str, true) {
+                Ok(enc) => enc,
+                Err(e) => return tokenizer_failed("encode", e),
+            };
+
+            let tokens = &encoding.get_tokens()[..encoding.len().min(max_tokens)];
+            let needed: usize = tokens.iter().map(|t| t.len() + 1).sum();
+            let Ok(needed_c) = c_int::try_from(needed) else {
+                return fail(
+                    ERR_BUFFER_TOO_SMALL,
+                    format!("{needed} bytes of pieces exceed the C int range"),
+                );
+            };
+            if out_tokens_buf.is_null() || buf_capacity == 0 {
+                return needed_c;
+            }
+            if buf_capacity < needed {
+                return fail(
+                    ERR_BUFFER_TOO_SMALL,
+                    format!("pieces need {needed} bytes, buffer holds {buf_capacity}"),
+                );
+            }
+
+            let len = copy_ids(encoding.get_ids(), out_ids, tokens.len());
+            if len < 0 {
+                return len;
+            }
+            let mut at = 0;
+            unsafe {
+                for (i, token) in tokens.iter().enumerate() {
+                    *out_token_offsets.add(i) = at as c_int;
+                    let dest = out_tokens_buf.add(at) as *mut u8;
+                    std::ptr::copy_nonoverlapping(token.as_ptr(), dest, token.len());
+                    *dest.add(token.len()) = 0;
+                    at += token.len() + 1;
+                }
+            }
+
+            len
+        })
+    })
+}
+
+/// Encode a (query, passage) pair as one sequence for cross-encoders/rerankers
+/// The post-processor adds the pair structure (e.g. `[CLS] a [SEP] b [SEP]`),
+/// and `out_type_ids` (may be null) receives the segment ID of each token.
+/// Truncation configured with `tokenizer_set_truncation` applies to the pair as
+/// a whole, trimming the longer sequence first.
+/// Returns number of tokens on success, negative on error (as `tokenizer_encode`)
+#[no_mangle]
+pub extern "C" fn tokenizer_encode_pair(
+    text_a: *const c_char,
+    text_b: *const c_char,
+    out_ids: *mut c_int,
+    out_type_ids: *mut c_int,
+    max_len: usize,
+) -> c_int {
+    catch_panic(|| tokenizer_encode_pair_opts(text_a, text_b, 1, out_ids, out_type_ids, max_len))
+}
+
+/// Same as `tokenizer_encode_pair`, with `add_special_tokens` chosen by the caller
+#[no_mangle]
+pub extern "C" fn tokenizer_encode_pair_opts(
+    text_a: *const c_char,
+    text_b: *const c_char,
+    add_special_tokens: c_int,
+    out_ids: *mut c_int,
+    out_type_ids: *mut c_int,
+    max_len: usize,
+) -> c_int {
+    catch_panic(|| {
+        if let Err(code) = guard::out_buffer(
+            "tokenizer_encode_pair_opts",
+            "out_ids",
+            out_ids,
+            max_len,
+            text_a,
+        ) {
+            return code;
+        }
+        if !out_type_ids.is_null() {
+            let checked = guard::out_buffer(
+                "tokenizer_encode_pair_opts",
+                "out_type_ids",
+                out_type_ids,
+                max_len,
+                text_a,
+            );
+            if let Err(code) = checked {
+                return code;
+            }
+        }
+        let (a, b) = match (text_arg(text_a), text_arg(text_b)) {
+            (Ok(a), Ok(b)) => (a, b),
+            (Err(code), _) | (_, Err(code)) => return code,
+        };
+
+        with_tokenizer(|tokenizer| {
+            let encoding = match tokenizer.encode((a, b), add_special_tokens != 0) {
+                Ok(enc) => enc,
+                Err(e) => return tokenizer_failed("pair encode", e),
+            };
+
+            let len = copy_ids(encoding.get_ids(), out_ids, max_len);
+            if len >= 0 && !out_type_ids.is_null() {
+                copy_ids(encoding.get_type_ids(), out_type_ids, max_len);
+            }
+            len
+        })
+    })
+}
+
+/// Count tokens without writing IDs anywhere
+/// No `max_len` clamp applies, so the true count of long documents is returned,
+/// and offsets are not tracked since only the length is needed
+/// Returns the token count on success, negative on error (as `tokenizer_encode`)
+#[no_mangle]
+pub extern "C" fn tokenizer_count_tokens(text: *const c_char, add_special_tokens: c_int) -> c_int {
+    catch_panic(|| {
+        let text_str = match text_arg(text) {
+            Ok(s) => s,
+            Err(code) => return code,
+        };
+
+        let add_special_tokens = add_special_tokens != 0;
+        with_tokenizer(|tokenizer| {
+            let encode = || tokenizer.encode_fast(&*text_str, add_special_tokens);
+            match cache::cached_ids(&text_str, add_special_tokens, encode) {
+                Ok(ids) => ids.len() as c_int,
+                Err(e) => tokenizer_failed("encode", e),
+            }
+        })
+    })
+}
+
+/// Encode and copy at most `max_len` IDs into `out_ids`
+pub(crate) fn encode_into(
+    tokenizer: &Tokenizer,
+    text: &str,
+    add_special_tokens: bool,
+    out_ids: *mut c_int,
+    max_len: usize,
+) -> c_int {
+    match tokenizer.encode(text, add_special_tokens) {
+        Ok(encoding) => copy_ids(encoding.get_ids(), out_ids, max_len),
+        Err(e) => tokenizer_failed("encode", e),
+    }
+}
+
+/// Copy at most `max_len` IDs into `out_ids`, returning how many were copied
+/// An ID above `c_int::MAX` fails with `ERR_ID_OUT_OF_RANGE` before anything
+/// is written, rather than wrapping to a negative value.
+pub(crate) fn copy_ids(ids: &[u32], out_ids: *mut c_int, max_len: usize) -> c_int {
+    let len = ids.len().min(max_len).min(c_int::MAX as usize);
+    let ids = &ids[..len];
+
+    if let Some((i, id)) = ids
+        .iter()
+        .enumerate()
+        .find(|(_, &id)| c_int::try_from(id).is_err())
+    {
+        return fail(
+            ERR_ID_OUT_OF_RANGE,
+            format!("token ID {id} at index {i} does not fit in a C int; use the _u32 functions"),
+        );
+    }
 
     unsafe {
-        for i in 0..len {
-            *out_ids.add(i) = ids[i] as c_int;
+        for (i, &id) in ids.iter().enumerate() {
+            *out_ids.add(i) = id as c_int;
         }
     }
 
     len as c_int
 }
 
+/// Encode text with special tokens added into unsigned 32-bit IDs
+/// Same as `tokenizer_encode`, but IDs are written as the tokenizer's native
+/// `u32`, so no ID can be out of range. C# callers pass a `uint[]`.
+/// The count is returned as `i64` so it cannot overflow for huge documents.
+/// Returns number of tokens on success, negative on error (as `tokenizer_encode`)
+#[no_mangle]
+pub extern "C" fn tokenizer_encode_u32(
+    text: *const c_char,
+    out_ids: *mut u32,
+    max_len: usize,
+) -> i64 {
+    catch_panic(|| {
+        if let Err(code) =
+            guard::out_buffer("tokenizer_encode_u32", "out_ids", out_ids, max_len, text)
+        {
+            return code as i64;
+        }
+        let text_str = match text_arg(text) {
+            Ok(s) => s,
+            Err(code) => return code as i64,
+        };
+
+        let mut written = 0i64;
+        let rc = with_tokenizer(|tokenizer| match tokenizer.encode(text_str, true) {
+            Ok(encoding) => {
+                let ids = encoding.get_ids();
+                let len = ids.len().min(max_len);
+                unsafe { std::ptr::copy_nonoverlapping(ids.as_ptr(), out_ids, len) };
+                written = len as i64;
+                0
+            }
+            Err(e) => tokenizer_failed("encode", e),
+        });
+
+        if rc < 0 {
+            rc as i64
+        } else {
+            written
+        }
+    })
+}
+
+/// Encode many texts in one call with special tokens added
+/// `out_ids` holds `count * max_len_per_item` slots; item `i` starts at
+/// `i * max_len_per_item` and its token count is written to `out_lengths[i]`
+/// (clamped to `max_len_per_item` like `tokenizer_encode`).
+/// A null or non-UTF-8 item does not abort the batch: its length is set to
+/// -1 or -2 respectively and the remaining items are still encoded. An item
+/// whose IDs do not fit in a `c_int` gets -11.
+/// Returns the number of items encoded successfully, negative on error:
+///   -1 null arguments, -3 not initialized, -4 encode failed,
+///   -19 `count * max_len_per_item` overflows or exceeds the length limit,
+///   -20 a misaligned output
+#[no_mangle]
+pub extern "C" fn tokenizer_encode_batch(
+    texts: *const *const c_char,
+    count: usize,
+    out_ids: *mut c_int,
+    out_lengths: *mut c_int,
+    max_len_per_item: usize,
+) -> c_int {
+    catch_panic(|| {
+        tokenizer_encode_batch_opts(texts, count, 1, out_ids, out_lengths, max_len_per_item)
+    })
+}
+
+/// Same as `tokenizer_encode_batch`, with `add_special_tokens` applied to every item
+#[no_mangle]
+pub extern "C" fn tokenizer_encode_batch_opts(
+    texts: *const *const c_char,
+    count: usize,
+    add_special_tokens: c_int,
+    out_ids: *mut c_int,
+    out_lengths: *mut c_int,
+    max_len_per_item: usize,
+) -> c_int {
+    catch_panic(|| {
+        encode_batch_into(
+            texts,
+            count,
+            add_special_tokens != 0,
+            out_ids,
+            std::ptr::null_mut(),
+            out_lengths,
+            max_len_per_item,
+        )
+    })
+}
+
+/// `tokenizer_encode_batch_opts` that also writes the attention mask
+/// `out_attention_mask` uses the same `count * max_len_per_item` layout as
+/// `out_ids`: 1 for real tokens (special tokens included), 0 for padding.
+/// With `tokenizer_set_padding` enabled every valid item has the same length.
+#[no_mangle]
+pub extern "C" fn tokenizer_encode_batch_with_mask(
+    texts: *const *const c_char,
+    count: usize,
+    add_special_tokens: c_int,
+    out_ids: *mut c_int,
+    out_attention_mask: *mut c_int,
+    out_lengths: *mut c_int,
+    max_len_per_item: usize,
+) -> c_int {
+    catch_panic(|| {
+        if count > 0 && out_attention_mask.is_null() {
+            return null_output("out_attention_mask");
+        }
+
+        encode_batch_into(
+            texts,
+            count,
+            add_special_tokens != 0,
+            out_ids,
+            out_attention_mask,
+            out_lengths,
+            max_len_per_item,
+        )
+    })
+}
+
+/// Shared batch implementation; `out_mask` may be null when not wanted
+fn encode_batch_into(
+    texts: *const *const c_char,
+    count: usize,
+    add_special_tokens: bool,
+    out_ids: *mut c_int,
+    out_mask: *mut c_int,
+    out_lengths: *mut c_int,
+    max_len_per_item: usize,
+) -> c_int {
+    if count == 0 {
+        return 0;
+    }
+    if texts.is_null() || out_ids.is_null() || out_lengths.is_null() {
+        return null_output("texts/out_ids/out_lengths");
+    }
+    let Some(slots) = count.checked_mul(max_len_per_item) else {
+        return fail(
+            ERR_INVALID_LENGTH,
+            format!("{count} items of {max_len_per_item} slots overflow the output size"),
+        );
+    };
+    let buffers = [("out_ids", out_ids), ("out_attention_mask", out_mask)];
+    for (name, out) in buffers.into_iter().filter(|(_, out)| !out.is_null()) {
+        let checked =
+            guard::out_buffer("tokenizer_encode_batch", name, out, slots, std::ptr::null());
+        if let Err(code) = checked {
+            return code;
+        }
+    }
+
+    let items = unsafe { std::slice::from_raw_parts(texts, count) };
+    let lengths = unsafe { std::slice::from_raw_parts_mut(out_lengths, count) };
+
+    // Keep track of which slot each valid input belongs to
+    let mut inputs = Vec::with_capacity(count);
+    let mut slots = Vec::with_capacity(count);
+    let mut first_bad = None;
+    let mut first_repair = None;
+    for (i, &item) in items.iter().enumerate() {
+        match utf8::repair_text(item) {
+            Ok((text, note)) => {
+                inputs.push(text);
+                slots.push(i);
+                if let Some(note) = note {
+                    first_repair.get_or_insert((i, note));
+                }
+            }
+            Err(code) => {
+                lengths[i] = code;
+                first_bad.get_or_insert((i, error::last_error_message()));
+            }
+        }
+    }
+
+    let encoded = with_tokenizer(|tokenizer| {
+        let encoded = threads::in_pool(|| tokenizer.encode_batch(inputs, add_special_tokens));
+        let encodings = match encoded {
+            Ok(encodings) => encodings,
+            Err(e) => return tokenizer_failed("batch encode", e),
+        };
+
+        for (encoding, &slot) in encodings.iter().zip(&slots) {
+            let offset = slot * max_len_per_item;
+            let dst = unsafe { out_ids.add(offset) };
+            lengths[slot] = copy_ids(encoding.get_ids(), dst, max_len_per_item);
+            if lengths[slot] >= 0 && !out_mask.is_null() {
+                let mask = unsafe { out_mask.add(offset) };
+                copy_ids(encoding.get_attention_mask(), mask, max_len_per_item);
+            }
+        }
+
+        slots.iter().filter(|&&slot| lengths[slot] >= 0).count() as c_int
+    });
+
+    // Rejected items leave the batch successful, but their reason stays retrievable
+    // Rejections are reported ahead of repairs
+    if let (true, Some((index, message))) = (encoded >= 0, first_bad.or(first_repair)) {
+        set_last_error(format!("batch item {index}: {message}"));
+    }
+
+    encoded
+}
+
+/// Decode token IDs back into text, skipping special tokens (BOS/EOS/PAD...)
+/// Writes a NUL-terminated UTF-8 string into `out_text`
+/// Returns bytes written (excluding NUL) on success, negative on error:
+///   -1 null `ids` with non-zero `len`, -3 not initialized, -4 decode failed
+///   (including negative IDs), -6 buffer too small
+/// Pass a null `out_text` or zero `out_capacity` to get the required size
+#[no_mangle]
+pub extern "C" fn tokenizer_decode(
+    ids: *const c_int,
+    len: usize,
+    out_text: *mut c_char,
+    out_capacity: usize,
+) -> c_int {
+    catch_panic(|| tokenizer_decode_ex(ids, len, 1, out_text, out_capacity))
+}
+
+/// Same as `tokenizer_decode`, with `skip_special_tokens` chosen by the caller
+/// (non-zero strips special tokens, zero keeps them for debugging)
+#[no_mangle]
+pub extern "C" fn tokenizer_decode_ex(
+    ids: *const c_int,
+    len: usize,
+    skip_special_tokens: c_int,
+    out_text: *mut c_char,
+    out_capacity: usize,
+) -> c_int {
+    catch_panic(|| tokenizer_decode_opts(ids, len, skip_special_tokens, 0, out_text, out_capacity))
+}
+
+/// `tokenizer_decode_ex` with an extra `clean_up_tokenization_spaces` pass
+/// `skip_special_tokens` strips every token the tokenizer marks as special,
+/// added tokens such as `<|im_end|>` included. Non-zero `cleanup` removes the
+/// spaces tokenization leaves before punctuation and English contractions
+/// ("hello , world" becomes "hello, world"), like `transformers` does. Zero
+/// leaves the decoder's output as is; decoders that clean up on their own,
+/// like BERT's WordPiece, still do.
+#[no_mangle]
+pub extern "C" fn tokenizer_decode_opts(
+    ids: *const c_int,
+    len: usize,
+    skip_special_tokens: c_int,
+    cleanup: c_int,
+    out_text: *mut c_char,
+    out_capacity: usize,
+) -> c_int {
+    catch_panic(|| {
+        let ids = match ids_arg(ids, len) {
+            Ok(ids) => ids,
+            Err(code) => return code,
+        };
+
+        with_tokenizer(
+            |tokenizer| match tokenizer.decode(&ids, skip_special_tokens != 0) {
+                Ok(text) if cleanup != 0 => {
+                    write_c_str(&clean_up_tokenization_spaces(&text), out_text, out_capacity)
+                }
+                Ok(text) => write_c_str(&text, out_text, out_capacity),
+                Err(e) => tokenizer_failed("decode", e),
+            },
+        )
+    })
+}
+
+/// The `clean_up_tokenization` replacements of `transformers`
+fn clean_up_tokenization_spaces(text: &str) -> String {
+    const REPLACEMENTS: [(&str, &str); 10] = [
+        (" .", "."),
+        (" ?", "?"),
+        (" !", "!"),
+        (" ,", ","),
+        (" ' ", "'"),
+        (" n't", "n't"),
+        (" 'm", "'m"),
+        (" 's", "'s"),
+        (" 've", "'ve"),
+        (" 're", "'re"),
+    ];
+    REPLACEMENTS
+        .iter()
+        .fold(text.to_owned(), |text, (from, to)| text.replace(from, to))
+}
+
+/// `tokenizer_decode_ex` for the unsigned IDs produced by `tokenizer_encode_u32`
+#[no_mangle]
+pub extern "C" fn tokenizer_decode_u32(
+    ids: *const u32,
+    len: usize,
+    skip_special_tokens: c_int,
+    out_text: *mut c_char,
+    out_capacity: usize,
+) -> c_int {
+    catch_panic(|| {
+        if len > 0 && ids.is_null() {
+            return null_output("ids");
+        }
+        let ids: &[u32] = if len == 0 {
+            &[]
+        } else {
+            unsafe { std::slice::from_raw_parts(ids, len) }
+        };
+
+        with_tokenizer(|tokenizer| {
+            decode_into(
+                tokenizer,
+                ids,
+                skip_special_tokens != 0,
+                out_text,
+                out_capacity,
+            )
+        })
+    })
+}
+
+/// Read `len` caller IDs, rejecting negative values as un-decodable
+pub(crate) fn ids_arg(ids: *const c_int, len: usize) -> Result<Vec<u32>, c_int> {
+    if len == 0 {
+        return Ok(Vec::new());
+    }
+    if ids.is_null() {
+        return Err(null_output("ids"));
+    }
+
+    let raw = unsafe { std::slice::from_raw_parts(ids, len) };
+    raw.iter()
+        .enumerate()
+        .map(|(i, &id)| {
+            u32::try_from(id).map_err(|_| {
+                fail(
+                    ERR_TOKENIZER_FAILED,
+                    format!("token ID {id} at index {i} is negative"),
+                )
+            })
+        })
+        .collect()
+}
+
+/// Decode `ids` and write the text using the `write_c_str` size negotiation
+pub(crate) fn decode_into(
+    tokenizer: &Tokenizer,
+    ids: &[u32],
+    skip_special_tokens: bool,
+    out_text: *mut c_char,
+    out_capacity: usize,
+) -> c_int {
+    match tokenizer.decode(ids, skip_special_tokens) {
+        Ok(text) => write_c_str(&text, out_text, out_capacity),
+        Err(e) => tokenizer_failed("decode", e),
+    }
+}
+
 /// Free the tokenizer and allow reinitialization
+/// An async load still in flight is discarded when it finishes.
 #[no_mangle]
 pub extern "C" fn tokenizer_free() {
-    if let Ok(mut guard) = TOKENIZER.lock() {
-        *guard = None;
+    catch_panic(|| {
+        background::supersede(|| replace_global(None));
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{bert_json, byte_level_json, install, llama_json, serial};
+    use std::ffi::{CStr, CString};
+
+    fn encode(text: &str) -> Vec<c_int> {
+        let text = CString::new(text).unwrap();
+        let mut ids = vec![0; 256];
+        let n = tokenizer_encode(text.as_ptr(), ids.as_mut_ptr(), ids.len());
+        assert!(n >= 0, "encode failed with {n}");
+        ids.truncate(n as usize);
+        ids
+    }
+
+    fn decode(ids: &[c_int], skip_special: bool) -> String {
+        let needed = tokenizer_decode_ex(
+            ids.as_ptr(),
+            ids.len(),
+            skip_special as c_int,
+            std::ptr::null_mut(),
+            0,
+        );
+        assert!(needed >= 0, "size query failed with {needed}");
+        let mut buf = vec![0u8; needed as usize + 1];
+        let written = tokenizer_decode_ex(
+            ids.as_ptr(),
+            ids.len(),
+            skip_special as c_int,
+            buf.as_mut_ptr() as *mut c_char,
+            buf.len(),
+        );
+        assert_eq!(written, needed);
+        CStr::from_bytes_with_nul(&buf)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    #[test]
+    fn string_outputs_negotiate_their_size() {
+        let _guard = serial();
+        let mut buf = [0x7f as c_char; 8];
+
+        // Zero-length result: the size query says 0, one byte holds the NUL
+        assert_eq!(write_c_str("", std::ptr::null_mut(), 0), 0);
+        assert_eq!(write_c_str("", buf.as_mut_ptr(), 0), 0);
+        assert_eq!(buf[0], 0x7f);
+        assert_eq!(write_c_str("", buf.as_mut_ptr(), 1), 0);
+        assert_eq!(buf[0], 0);
+
+        // Exact fit (capacity == len + 1) and one short (capacity == len)
+        let mut buf = [0x7f as c_char; 8];
+        assert_eq!(write_c_str("abcdefg", std::ptr::null_mut(), 8), 7);
+        assert_eq!(
+            write_c_str("abcdefg", buf.as_mut_ptr(), 7),
+            ERR_BUFFER_TOO_SMALL
+        );
+        assert!(
+            buf.iter().all(|&c| c == 0x7f),
+            "a failed write left bytes behind"
+        );
+        assert_eq!(write_c_str("abcdefg", buf.as_mut_ptr(), 8), 7);
+        assert_eq!(
+            unsafe { CStr::from_ptr(buf.as_ptr()) }.to_bytes(),
+            b"abcdefg"
+        );
+
+        // The same contract through an export: "▁hello" is 8 bytes
+        install(&llama_json());
+        let mut piece = [0x7f as c_char; 9];
+        let needed = crate::vocab::tokenizer_id_to_token(296, std::ptr::null_mut(), 0);
+        assert_eq!(needed, 8);
+        let rc = crate::vocab::tokenizer_id_to_token(296, piece.as_mut_ptr(), 8);
+        assert_eq!(rc, ERR_BUFFER_TOO_SMALL);
+        assert!(piece.iter().all(|&c| c == 0x7f));
+        assert_eq!(
+            crate::vocab::tokenizer_id_to_token(296, piece.as_mut_ptr(), 9),
+            8
+        );
+        assert_eq!(piece[8], 0);
+    }
+
+    #[test]
+    fn reinitializing_under_load_never_mixes_tokenizers() {
+        let _guard = serial();
+        install(&llama_json());
+        let (llama, bert) = (llama_json().into_bytes(), bert_json().into_bytes());
+        let done = std::sync::atomic::AtomicBool::new(false);
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    while !done.load(Ordering::Relaxed) {
+                        let ids = encode("hello world");
+                        assert!(ids == [1, 296, 301] || ids == [2, 5, 6, 3], "{ids:?}");
+                    }
+                });
+            }
+            for i in 0..40 {
+                let json = if i % 2 == 0 { &bert } else { &llama };
+                assert_eq!(
+                    tokenizer_initialize_from_bytes(json.as_ptr(), json.len()),
+                    0
+                );
+                // Any thread's next encode sees the tokenizer just installed
+                let expected: &[c_int] = if i % 2 == 0 {
+                    &[2, 5, 6, 3]
+                } else {
+                    &[1, 296, 301]
+                };
+                let seen = scope.spawn(|| encode("hello world")).join().unwrap();
+                assert_eq!(seen, expected);
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+
+        tokenizer_free();
+        let text = CString::new("hello").unwrap();
+        let mut ids = [0; 8];
+        let on_other_thread = std::thread::scope(|scope| {
+            scope
+                .spawn(|| tokenizer_encode(text.as_ptr(), ids.as_mut_ptr(), ids.len()))
+                .join()
+                .unwrap()
+        });
+        assert_eq!(on_other_thread, ERR_NOT_INITIALIZED);
+        install(&llama_json());
+    }
+
+    #[test]
+    fn encode_batch_matches_single_encodes() {
+        let _guard = serial();
+        install(&llama_json());
+
+        let texts = ["hello world", "hello", "world hello world"];
+        let c_texts: Vec<CString> = texts.iter().map(|t| CString::new(*t).unwrap()).collect();
+        let ptrs: Vec<*const c_char> = c_texts.iter().map(|t| t.as_ptr()).collect();
+        let max_len = 16;
+        let mut ids = vec![0; texts.len() * max_len];
+        let mut lengths = vec![0; texts.len()];
+
+        let rc = tokenizer_encode_batch(
+            ptrs.as_ptr(),
+            ptrs.len(),
+            ids.as_mut_ptr(),
+            lengths.as_mut_ptr(),
+            max_len,
+        );
+        assert_eq!(rc, 3);
+        for (i, text) in texts.iter().enumerate() {
+            let start = i * max_len;
+            let got = &ids[start..start + lengths[i] as usize];
+            assert_eq!(got, encode(text).as_slice());
+        }
+    }
+
+    #[test]
+    fn encode_batch_flags_bad_items_and_keeps_going() {
+        let _guard = serial();
+        install(&llama_json());
+
+        let good = CString::new("hello").unwrap();
+        let bad = CString::new(vec![0xffu8, 0xfe]).unwrap();
+        let ptrs = [good.as_ptr(), bad.as_ptr(), std::ptr::null(), good.as_ptr()];
+        let max_len = 8;
+        let mut ids = vec![0; ptrs.len() * max_len];
+        let mut lengths = vec![0; ptrs.len()];
+
+        let rc = tokenizer_encode_batch(
+            ptrs.as_ptr(),
+            ptrs.len(),
+            ids.as_mut_ptr(),
+            lengths.as_mut_ptr(),
+            max_len,
+        );
+        assert_eq!(rc, 2);
+        assert_eq!(lengths[1], ERR_INVALID_UTF8);
+        assert_eq!(lengths[2], ERR_NULL_POINTER);
+        assert_eq!(lengths[0], lengths[3]);
+        assert_eq!(&ids[..lengths[0] as usize], encode("hello").as_slice());
+    }
+
+    #[test]
+    fn encode_opts_can_leave_out_special_tokens() {
+        let _guard = serial();
+        install(&bert_json());
+
+        let text = CString::new("hello world").unwrap();
+        let mut ids = [0; 8];
+        let n = tokenizer_encode_opts(text.as_ptr(), 0, ids.as_mut_ptr(), ids.len());
+        assert_eq!(&ids[..n as usize], &[5, 6]);
+        let n = tokenizer_encode_opts(text.as_ptr(), 1, ids.as_mut_ptr(), ids.len());
+        assert_eq!(&ids[..n as usize], encode("hello world").as_slice());
+
+        let ptrs = [text.as_ptr()];
+        let mut lengths = [0; 1];
+        let rc = tokenizer_encode_batch_opts(
+            ptrs.as_ptr(),
+            1,
+            0,
+            ids.as_mut_ptr(),
+            lengths.as_mut_ptr(),
+            ids.len(),
+        );
+        assert_eq!(rc, 1);
+        assert_eq!(&ids[..lengths[0] as usize], &[5, 6]);
+    }
+
+    #[test]
+    fn count_tokens_is_not_clamped() {
+        let _guard = serial();
+        install(&llama_json());
+
+        let long = vec!["hello world"; 1000].join(" ");
+        let text = CString::new(long.as_str()).unwrap();
+        assert_eq!(tokenizer_count_tokens(text.as_ptr(), 1), 2001);
+        assert_eq!(tokenizer_count_tokens(text.as_ptr(), 0), 2000);
+
+        let mut ids = [0; 4];
+        assert_eq!(
+            tokenizer_encode(text.as_ptr(), ids.as_mut_ptr(), ids.len()),
+            4
+        );
+    }
+
+    #[test]
+    fn count_tokens_reports_encode_error_codes() {
+        let _guard = serial();
+        tokenizer_free();
+
+        let text = CString::new("hello").unwrap();
+        assert_eq!(
+            tokenizer_count_tokens(text.as_ptr(), 1),
+            ERR_NOT_INITIALIZED
+        );
+        assert_eq!(
+            tokenizer_count_tokens(std::ptr::null(), 1),
+            ERR_NULL_POINTER
+        );
+    }
+
+    type OffsetsFn =
+        extern "C" fn(*const c_char, *mut c_int, *mut c_int, *mut c_int, usize) -> c_int;
+
+    fn encode_offsets(text: &str) -> Vec<(c_int, c_int, c_int)> {
+        encode_offsets_with(tokenizer_encode_with_offsets, text)
+    }
+
+    fn encode_offsets_with(f: OffsetsFn, text: &str) -> Vec<(c_int, c_int, c_int)> {
+        let c_text = CString::new(text).unwrap();
+        let (mut ids, mut starts, mut ends) = (vec![0; 64], vec![0; 64], vec![0; 64]);
+        let n = f(
+            c_text.as_ptr(),
+            ids.as_mut_ptr(),
+            starts.as_mut_ptr(),
+            ends.as_mut_ptr(),
+            ids.len(),
+        );
+        assert!(n >= 0, "encode failed with {n}");
+        (0..n as usize)
+            .map(|i| (ids[i], starts[i], ends[i]))
+            .collect()
+    }
+
+    #[test]
+    fn offsets_mark_special_tokens_and_slice_the_input() {
+        let _guard = serial();
+        install(&bert_json());
+
+        let text = "Hello, world!";
+        let tokens = encode_offsets(text);
+        assert_eq!(tokens.first(), Some(&(2, -1, -1)));
+        assert_eq!(tokens.last(), Some(&(3, -1, -1)));
+        let pieces: Vec<&str> = tokens[1..tokens.len() - 1]
+            .iter()
+            .map(|&(_, s, e)| &text[s as usize..e as usize])
+            .collect();
+        assert_eq!(pieces, ["Hello", ",", "world", "!"]);
+    }
+
+    #[test]
+    fn offsets_are_byte_ranges_for_multibyte_text() {
+        let _guard = serial();
+        install(&llama_json());
+
+        let text = "zaż 🚀";
+        let tokens = encode_offsets(text);
+        assert_eq!(tokens[0], (1, -1, -1));
+        for &(_, start, end) in &tokens[1..] {
+            assert!(text.is_char_boundary(start as usize));
+            assert!(text.is_char_boundary(end as usize));
+        }
+        // "ż" is two UTF-8 bytes covered by two byte-fallback tokens
+        let z_tokens: Vec<_> = tokens.iter().filter(|t| (t.1, t.2) == (2, 4)).collect();
+        assert_eq!(z_tokens.len(), 2);
+        assert_eq!(tokens.last().unwrap().2, text.len() as c_int);
+    }
+
+    #[test]
+    fn u16_offsets_index_the_utf16_text() {
+        let _guard = serial();
+        install(&llama_json());
+
+        let text = "ż🚀 hello 😀x";
+        let units: Vec<u16> = text.encode_utf16().collect();
+        let tokens = encode_offsets_with(tokenizer_encode_with_offsets_u16, text);
+        assert_eq!(tokens.len(), encode_offsets(text).len());
+        assert_eq!(tokens[0], (1, -1, -1));
+
+        let mut last_start = 0;
+        for &(_, start, end) in &tokens[1..] {
+            assert!(last_start <= start && start <= end && end as usize <= units.len());
+            last_start = start;
+        }
+        // The rocket's four byte-fallback tokens all cover its surrogate pair
+        let rocket: Vec<_> = tokens.iter().filter(|t| (t.1, t.2) == (1, 3)).collect();
+        assert_eq!(rocket.len(), 4);
+        let hello = tokens.iter().find(|t| t.0 == 296).unwrap();
+        assert_eq!(
+            String::from_utf16(&units[hello.1 as usize..hello.2 as usize]).unwrap(),
+            " hello"
+        );
+        assert_eq!(tokens.last().unwrap().2 as usize, units.len());
+    }
+
+    /// `bert_json` with `normalizer` and the pieces the texts below need
+    fn normalizing_bert(normalizer: serde_json::Value) -> String {
+        let mut json: serde_json::Value = serde_json::from_str(&bert_json()).unwrap();
+        json["normalizer"] = normalizer;
+        let vocab = json["model"]["vocab"].as_object_mut().unwrap();
+        for piece in ["##l", "ge", "##a", "ja", "##n", "f", "##i", "##ne"] {
+            let id = vocab.len();
+            vocab.insert(piece.to_owned(), id.into());
+        }
+        json.to_string()
+    }
+
+    fn encode_offsets_ex(text: &str, referential: c_int) -> Vec<(c_int, c_int, c_int)> {
+        let c_text = CString::new(text).unwrap();
+        let (mut ids, mut starts, mut ends) = (vec![0; 64], vec![0; 64], vec![0; 64]);
+        let n = tokenizer_encode_with_offsets_ex(
+            c_text.as_ptr(),
+            referential,
+            ids.as_mut_ptr(),
+            starts.as_mut_ptr(),
+            ends.as_mut_ptr(),
+            ids.len(),
+        );
+        assert!(n >= 0, "encode failed with {n}");
+        (0..n as usize)
+            .map(|i| (ids[i], starts[i], ends[i]))
+            .collect()
+    }
+
+    fn token_ids(tokens: &[(c_int, c_int, c_int)]) -> Vec<c_int> {
+        tokens.iter().map(|t| t.0).collect()
+    }
+
+    #[test]
+    fn offsets_survive_normalizers_that_change_the_text() {
+        let _guard = serial();
+        let accents = serde_json::json!({
+            "type": "BertNormalizer",
+            "clean_text": true,
+            "handle_chinese_chars": true,
+            "strip_accents": true,
+            "lowercase": true
+        });
+        let nfkc = serde_json::json!({
+            "type": "Sequence",
+            "normalizers": [{ "type": "NFKC" }, { "type": "Lowercase" }]
+        });
+
+        for (normalizer, text) in [(accents, "Zażółć GĘŚLĄ jaźń"), (nfkc, "ﬁne Ｚａ ﬁ")]
+        {
+            let json = normalizing_bert(normalizer);
+            install(&json);
+            let tokenizer = Tokenizer::from_bytes(json.as_bytes()).unwrap();
+            let normalize = |s: &str| inspect::normalized(&tokenizer, s).unwrap().get().to_owned();
+            let normalized = normalize(text);
+
+            let original = encode_offsets(text);
+            assert_eq!(encode_offsets_ex(text, OFFSETS_ORIGINAL), original);
+            let in_normalized = encode_offsets_ex(text, OFFSETS_NORMALIZED);
+            assert_eq!(token_ids(&in_normalized), token_ids(&original));
+            assert_eq!((original[0].1, in_normalized[0].1), (-1, -1));
+
+            let inner = 1..original.len() - 1;
+            let (mut last_start, mut last_end) = (0, 0);
+            for (&(id, start, end), &(_, n_start, n_end)) in
+                original[inner.clone()].iter().zip(&in_normalized[inner])
+            {
+                let piece = tokenizer.id_to_token(id as u32).unwrap();
+                let piece = piece.trim_start_matches("##");
+                // In text order, and each range reads as its token once normalized
+                assert!(start >= last_start && end >= last_end && end > start);
+                (last_start, last_end) = (start, end);
+                let source = &text[start as usize..end as usize];
+                assert!(
+                    normalize(source).contains(piece),
+                    "{piece:?} from {source:?}"
+                );
+                assert_eq!(&normalized[n_start as usize..n_end as usize], piece);
+            }
+        }
+
+        // Pre-tokenizers that rewrite their splits still give in-order ranges
+        for json in [llama_json(), byte_level_json()] {
+            install(&json);
+            let text = "Hello wor ż";
+            let tokens = encode_offsets_ex(text, OFFSETS_NORMALIZED);
+            assert_eq!(token_ids(&tokens), token_ids(&encode_offsets(text)));
+            let ranges: Vec<_> = tokens.iter().filter(|t| t.1 >= 0).collect();
+            assert!(ranges
+                .windows(2)
+                .all(|w| w[0].1 <= w[1].1 && w[0].2 <= w[1].2));
+        }
+
+        install(&normalizing_bert(serde_json::json!({
+            "type": "Sequence",
+            "normalizers": [{ "type": "NFKC" }, { "type": "Lowercase" }]
+        })));
+        // `ﬁ` becomes `f` + `i`, both spanning the ligature's three bytes
+        let fine: Vec<_> = encode_offsets("ﬁne").iter().map(|t| (t.1, t.2)).collect();
+        assert_eq!(fine, [(-1, -1), (0, 3), (0, 3), (3, 5), (-1, -1)]);
+        let fine: Vec<_> = encode_offsets_ex("ﬁne", OFFSETS_NORMALIZED)
+            .iter()
+            .map(|t| (t.1, t.2))
+            .collect();
+        assert_eq!(fine, [(-1, -1), (0, 1), (1, 2), (2, 4), (-1, -1)]);
+
+        let text = CString::new("ﬁne").unwrap();
+        let mut ids = [0; 8];
+        let rc = tokenizer_encode_with_offsets_ex(
+            text.as_ptr(),
+            2,
+            ids.as_mut_ptr(),
+            ids.as_mut_ptr(),
+            ids.as_mut_ptr(),
+            ids.len(),
+        );
+        assert_eq!(rc, ERR_INVALID_ARGUMENT);
+    }
+
+    fn word_ids(text: &str) -> Vec<c_int> {
+        let c_text = CString::new(text).unwrap();
+        let (mut ids, mut words) = (vec![0; 64], vec![0; 64]);
+        let n = tokenizer_encode_with_word_ids(
+            c_text.as_ptr(),
+            ids.as_mut_ptr(),
+            words.as_mut_ptr(),
+            ids.len(),
+        );
+        assert!(n >= 0, "encode failed with {n}");
+        words.truncate(n as usize);
+        words
+    }
+
+    #[test]
+    fn word_ids_follow_the_pre_tokenizer() {
+        let _guard = serial();
+        install(&bert_json());
+        assert_eq!(word_ids("don't!"), [-1, 0, 1, 2, 3, -1]);
+        assert_eq!(word_ids("Hello, world!"), [-1, 0, 1, 2, 3, -1]);
+        // One word spread over several subword tokens
+        assert_eq!(word_ids("tokenizers"), [-1, 0, 0, 0, -1]);
+
+        install(&llama_json());
+        // No pre-tokenizer: the whole text is one word
+        let words = word_ids("hello zaż");
+        assert_eq!(words[0], -1);
+        assert!(words[1..].iter().all(|&w| w == 0), "{words:?}");
+    }
+
+    fn word_groups(text: &str, max_groups: usize) -> (c_int, Vec<(c_int, c_int)>) {
+        let c_text = CString::new(text).unwrap();
+        let (mut starts, mut lens) = (vec![-1; 64], vec![-1; 64]);
+        let n = tokenizer_word_groups(
+            c_text.as_ptr(),
+            starts.as_mut_ptr(),
+            lens.as_mut_ptr(),
+            max_groups,
+        );
+        let written = (n.max(0) as usize).min(max_groups);
+        (n, starts.into_iter().zip(lens).take(written).collect())
+    }
+
+    #[test]
+    fn word_groups_cover_each_word_once() {
+        let _guard = serial();
+        install(&bert_json());
+        // [CLS] token ##izer ##s , hello [SEP]
+        let (n, groups) = word_groups("tokenizers, hello", 8);
+        assert_eq!((n, groups), (3, vec![(1, 3), (4, 1), (5, 1)]));
+        assert_eq!(word_groups("tokenizers, hello", 1), (3, vec![(1, 3)]));
+        assert_eq!(word_groups("", 8), (0, vec![]));
+
+        // Padding joins no group
+        assert_eq!(crate::settings::tokenizer_set_padding(8, 0, 0), 0);
+        assert_eq!(word_groups("hello world", 8), (2, vec![(1, 1), (2, 1)]));
+
+        let text = CString::new("hello").unwrap();
+        let null = std::ptr::null_mut();
+        assert_eq!(tokenizer_word_groups(text.as_ptr(), null, null, 0), 1);
+        assert_eq!(
+            tokenizer_word_groups(text.as_ptr(), null, null, 1),
+            ERR_NULL_POINTER
+        );
+    }
+
+    fn encode_tokens(text: &str, max_tokens: usize) -> (Vec<c_int>, Vec<String>) {
+        let c_text = CString::new(text).unwrap();
+        let (mut ids, mut offsets) = (vec![0; 64], vec![0; 64]);
+        let needed = tokenizer_encode_with_tokens(
+            c_text.as_ptr(),
+            ids.as_mut_ptr(),
+            std::ptr::null_mut(),
+            offsets.as_mut_ptr(),
+            0,
+            max_tokens,
+        );
+        assert!(needed >= 0, "size query failed with {needed}");
+        let mut buf = vec![0u8; needed as usize];
+        let n = tokenizer_encode_with_tokens(
+            c_text.as_ptr(),
+            ids.as_mut_ptr(),
+            buf.as_mut_ptr() as *mut c_char,
+            offsets.as_mut_ptr(),
+            buf.len(),
+            max_tokens,
+        );
+        assert!(n >= 0, "encode failed with {n}");
+        let pieces = (0..n as usize)
+            .map(|i| {
+                let piece = CStr::from_bytes_until_nul(&buf[offsets[i] as usize..]).unwrap();
+                piece.to_str().unwrap().to_owned()
+            })
+            .collect();
+        ids.truncate(n as usize);
+        (ids, pieces)
+    }
+
+    #[test]
+    fn token_pieces_come_with_their_ids() {
+        let _guard = serial();
+        install(&llama_json());
+
+        let (ids, pieces) = encode_tokens("hello world", 64);
+        assert_eq!(ids, [1, 296, 301]);
+        assert_eq!(pieces, ["<s>", "▁hello", "▁world"]);
+        assert_eq!(encode_tokens("hello world", 2).1, ["<s>", "▁hello"]);
+
+        install(&crate::test_support::byte_level_json());
+        assert_eq!(encode_tokens("a b", 64).1, ["a", "Ġ", "b"]);
+
+        let text = CString::new("hello world").unwrap();
+        let (mut ids, mut offsets, mut buf) = ([0; 8], [0; 8], [0 as c_char; 4]);
+        let rc = tokenizer_encode_with_tokens(
+            text.as_ptr(),
+            ids.as_mut_ptr(),
+            buf.as_mut_ptr(),
+            offsets.as_mut_ptr(),
+            buf.len(),
+            8,
+        );
+        assert_eq!(rc, ERR_BUFFER_TOO_SMALL);
+    }
+
+    #[test]
+    fn initialize_from_bytes_matches_file_loading() {
+        let _guard = serial();
+        install(&llama_json());
+        let from_file = encode("hello world");
+
+        let json = llama_json().into_bytes();
+        tokenizer_free();
+        assert_eq!(
+            tokenizer_initialize_from_bytes(json.as_ptr(), json.len()),
+            0
+        );
+        drop(json);
+        assert_eq!(encode("hello world"), from_file);
+    }
+
+    #[test]
+    fn initialize_from_bytes_distinguishes_empty_from_malformed() {
+        let _guard = serial();
+        assert_eq!(
+            tokenizer_initialize_from_bytes(std::ptr::null(), 10),
+            ERR_NULL_POINTER
+        );
+        let junk = b"{ not json";
+        assert_eq!(
+            tokenizer_initialize_from_bytes(junk.as_ptr(), 0),
+            ERR_NULL_POINTER
+        );
+        assert_eq!(
+            tokenizer_initialize_from_bytes(junk.as_ptr(), junk.len()),
+            -3
+        );
+    }
+
+    fn encode_pair(a: &str, b: &str) -> (Vec<c_int>, Vec<c_int>) {
+        let (a, b) = (CString::new(a).unwrap(), CString::new(b).unwrap());
+        let (mut ids, mut types) = (vec![0; 32], vec![-1; 32]);
+        let n = tokenizer_encode_pair(
+            a.as_ptr(),
+            b.as_ptr(),
+            ids.as_mut_ptr(),
+            types.as_mut_ptr(),
+            ids.len(),
+        );
+        assert!(n >= 0, "pair encode failed with {n}");
+        ids.truncate(n as usize);
+        types.truncate(n as usize);
+        (ids, types)
+    }
+
+    #[test]
+    fn pair_encoding_has_bert_structure_and_segments() {
+        let _guard = serial();
+        install(&bert_json());
+
+        let (ids, types) = encode_pair("hello", "the world");
+        assert_eq!(ids, vec![2, 5, 3, 7, 6, 3]);
+        assert_eq!(types, vec![0, 0, 0, 1, 1, 1]);
+
+        // Type IDs are optional
+        let (a, b) = (
+            CString::new("hello").unwrap(),
+            CString::new("world").unwrap(),
+        );
+        let mut out = [0; 8];
+        let n = tokenizer_encode_pair(
+            a.as_ptr(),
+            b.as_ptr(),
+            out.as_mut_ptr(),
+            std::ptr::null_mut(),
+            8,
+        );
+        assert_eq!(n, 5);
+    }
+
+    #[test]
+    fn pair_truncation_trims_the_longer_side_first() {
+        let _guard = serial();
+        install(&bert_json());
+        assert_eq!(settings::tokenizer_set_truncation(6, 0, 0), 0);
+
+        let (ids, types) = encode_pair("hello world", "the token the token");
+        assert_eq!(ids, vec![2, 5, 3, 7, 8, 3]);
+        assert_eq!(types, vec![0, 0, 0, 1, 1, 1]);
+    }
+
+    #[test]
+    fn decode_round_trips_llama_text_and_strips_bos() {
+        let _guard = serial();
+        install(&llama_json());
+
+        let ids = encode("hello world");
+        assert_eq!(ids[0], 1);
+        assert_eq!(decode(&ids, true), "hello world");
+        assert_eq!(decode(&ids, false), "<s> hello world");
+    }
+
+    #[test]
+    fn decode_keeps_bert_special_tokens_on_request() {
+        let _guard = serial();
+        install(&bert_json());
+
+        let ids = encode("Hello world");
+        assert_eq!(decode(&ids, true), "hello world");
+        assert_eq!(decode(&ids, false), "[CLS] hello world [SEP]");
+    }
+
+    #[test]
+    fn decode_reports_small_buffers_without_writing() {
+        let _guard = serial();
+        install(&llama_json());
+
+        let ids = encode("hello");
+        let mut buf = [b'x' as c_char; 4];
+        let rc = tokenizer_decode(ids.as_ptr(), ids.len(), buf.as_mut_ptr(), buf.len());
+        assert_eq!(rc, ERR_BUFFER_TOO_SMALL);
+        assert!(buf.iter().all(|&b| b == b'x' as c_char));
+    }
+
+    #[test]
+    fn decode_requires_initialized_tokenizer() {
+        let _guard = serial();
+        tokenizer_free();
+
+        let ids = [1, 2, 3];
+        let rc = tokenizer_decode(ids.as_ptr(), ids.len(), std::ptr::null_mut(), 0);
+        assert_eq!(rc, ERR_NOT_INITIALIZED);
+    }
+
+    #[test]
+    fn concurrent_encodes_survive_reinitialization() {
+        let _guard = serial();
+        install(&llama_json());
+        let llama = encode("hello world");
+        install(&bert_json());
+        let bert = encode("hello world");
+
+        let llama_path = test_support::write_temp("stress_llama", &llama_json());
+        let bert_path = test_support::write_temp("stress_bert", &bert_json());
+        let paths = [llama_path, bert_path].map(|p| CString::new(p.to_str().unwrap()).unwrap());
+        let done = std::sync::atomic::AtomicBool::new(false);
+
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..8)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let text = CString::new("hello world").unwrap();
+                        let mut ids = [0; 16];
+                        let mut encoded = 0;
+                        while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                            let n = tokenizer_encode(text.as_ptr(), ids.as_mut_ptr(), ids.len());
+                            if n == ERR_NOT_INITIALIZED {
+                                continue;
+                            }
+                            let got = &ids[..n as usize];
+                            assert!(got == llama || got == bert, "torn encode {got:?}");
+                            encoded += 1;
+                        }
+                        encoded
+                    })
+                })
+                .collect();
+
+            for round in 0..200 {
+                if round % 10 == 9 {
+                    tokenizer_free();
+                }
+                assert_eq!(tokenizer_initialize(paths[round % 2].as_ptr()), 0);
+            }
+            done.store(true, std::sync::atomic::Ordering::Relaxed);
+
+            for worker in workers {
+                assert!(worker.join().unwrap() > 0);
+            }
+        });
+    }
+
+    /// `bert_json` with "hello" moved to an ID beyond `c_int::MAX`
+    fn bert_with_huge_id() -> String {
+        let mut json: serde_json::Value = serde_json::from_str(&bert_json()).unwrap();
+        json["model"]["vocab"]["hello"] = serde_json::json!(3_000_000_000u32);
+        json.to_string()
+    }
+
+    #[test]
+    fn ids_beyond_c_int_fail_instead_of_wrapping() {
+        let _guard = serial();
+        install(&bert_with_huge_id());
+
+        let text = CString::new("hello world").unwrap();
+        let mut ids = [7; 8];
+        let rc = tokenizer_encode(text.as_ptr(), ids.as_mut_ptr(), ids.len());
+        assert_eq!(rc, ERR_ID_OUT_OF_RANGE);
+        assert_eq!(ids, [7; 8]);
+
+        let ptrs = [text.as_ptr()];
+        let mut lengths = [0; 1];
+        let rc = tokenizer_encode_batch(
+            ptrs.as_ptr(),
+            1,
+            ids.as_mut_ptr(),
+            lengths.as_mut_ptr(),
+            ids.len(),
+        );
+        assert_eq!((rc, lengths[0]), (0, ERR_ID_OUT_OF_RANGE));
+    }
+
+    #[test]
+    fn u32_functions_round_trip_large_ids() {
+        let _guard = serial();
+        install(&bert_with_huge_id());
+
+        let text = CString::new("hello world").unwrap();
+        let mut ids = [0u32; 8];
+        let n = tokenizer_encode_u32(text.as_ptr(), ids.as_mut_ptr(), ids.len());
+        assert_eq!(&ids[..n as usize], &[2, 3_000_000_000, 6, 3]);
+
+        let needed = tokenizer_decode_u32(ids.as_ptr(), n as usize, 1, std::ptr::null_mut(), 0);
+        let mut buf = vec![0u8; needed as usize + 1];
+        let written = tokenizer_decode_u32(
+            ids.as_ptr(),
+            n as usize,
+            1,
+            buf.as_mut_ptr() as *mut c_char,
+            buf.len(),
+        );
+        assert_eq!(written, needed);
+        assert_eq!(
+            CStr::from_bytes_with_nul(&buf).unwrap().to_str(),
+            Ok("hello world")
+        );
+    }
+
+    #[test]
+    fn encode_v2_reports_the_full_length_instead_of_truncating() {
+        let _guard = serial();
+        install(&bert_json());
+
+        let text = CString::new("hello world the token").unwrap();
+        let needed = tokenizer_encode_v2(text.as_ptr(), 1, std::ptr::null_mut(), 0);
+        assert_eq!(needed, 6);
+
+        let mut short = [-1; 6 - 1];
+        let rc = tokenizer_encode_v2(text.as_ptr(), 1, short.as_mut_ptr(), short.len());
+        assert_eq!(rc, needed);
+        assert!(short.iter().all(|&id| id == -1));
+
+        let mut ids = vec![0; needed as usize];
+        let rc = tokenizer_encode_v2(text.as_ptr(), 1, ids.as_mut_ptr(), ids.len());
+        assert_eq!(rc, needed);
+        assert_eq!(ids, encode("hello world the token"));
+
+        assert_eq!(
+            tokenizer_encode_v2(text.as_ptr(), 1, std::ptr::null_mut(), 4),
+            ERR_NULL_POINTER
+        );
+    }
+
+    fn decode_opts(ids: &[c_int], skip_special: bool, cleanup: bool) -> String {
+        let mut buf = vec![0u8; 256];
+        let n = tokenizer_decode_opts(
+            ids.as_ptr(),
+            ids.len(),
+            skip_special as c_int,
+            cleanup as c_int,
+            buf.as_mut_ptr() as *mut c_char,
+            buf.len(),
+        );
+        assert!(n >= 0, "decode failed with {n}");
+        String::from_utf8(buf[..n as usize].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn decode_opts_skips_added_special_tokens_and_cleans_up_llama_text() {
+        let _guard = serial();
+        let mut json: serde_json::Value = serde_json::from_str(&llama_json()).unwrap();
+        let next_id = json["model"]["vocab"].as_object().unwrap().len();
+        json["added_tokens"]
+            .as_array_mut()
+            .unwrap()
+            .push(serde_json::json!({
+                "id": next_id, "content": "<|im_end|>", "single_word": false, "lstrip": false,
+                "rstrip": false, "normalized": false, "special": true
+            }));
+        install(&json.to_string());
+
+        let ids = encode("hello , world !<|im_end|>");
+        assert_eq!(ids.last(), Some(&(next_id as c_int)));
+        assert_eq!(
+            decode_opts(&ids, false, false),
+            "<s> hello , world !<|im_end|>"
+        );
+        assert_eq!(decode_opts(&ids, true, false), "hello , world !");
+        assert_eq!(decode_opts(&ids, true, true), "hello, world!");
+    }
+
+    #[test]
+    fn decode_opts_keeps_bert_special_tokens_on_request() {
+        let _guard = serial();
+        install(&bert_json());
+
+        let ids = encode("don't, hello");
+        assert_eq!(decode_opts(&ids, true, true), "don't, hello");
+        assert_eq!(decode_opts(&ids, false, true), "[CLS] don't, hello [SEP]");
     }
 }
@@ -0,0 +1,142 @@
+//! Forwarding of diagnostics to a caller-supplied log callback.
+//!
+//! Every recorded error is logged at error level, repairs and other warnings
+//! at warn level, tokenizer loads at info level and cache evictions at debug
+//! level. Messages are only formatted when a callback wants them.
+
+use std::cell::Cell;
+use std::ffi::{c_char, c_int, CString};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{PoisonError, RwLock};
+
+use crate::{catch_panic, fail, ERR_INVALID_ARGUMENT};
+
+pub(crate) const LOG_DEBUG: c_int = 0;
+pub(crate) const LOG_INFO: c_int = 1;
+pub(crate) const LOG_WARN: c_int = 2;
+pub(crate) const LOG_ERROR: c_int = 3;
+
+/// Null when no callback is set
+type LogCallback = Option<extern "C" fn(level: c_int, message: *const c_char)>;
+
+static CALLBACK: RwLock<LogCallback> = RwLock::new(None);
+/// Lowest level passed to the callback; above every level while none is set
+static MIN_LEVEL: AtomicI32 = AtomicI32::new(c_int::MAX);
+
+thread_local! {
+    /// Set while this thread runs the callback, so anything it logs is dropped
+    static IN_CALLBACK: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Send the message built by `message` to the callback, if it wants `level`
+pub(crate) fn log(level: c_int, message: impl FnOnce() -> String) {
+    if level < MIN_LEVEL.load(Ordering::Relaxed) || IN_CALLBACK.get() {
+        return;
+    }
+    // Copied out so the callback runs without the lock held
+    let Some(callback) = *CALLBACK.read().unwrap_or_else(PoisonError::into_inner) else {
+        return;
+    };
+
+    let message = message().replace('\0', "\\0");
+    let message = CString::new(message).unwrap_or_default();
+    IN_CALLBACK.set(true);
+    callback(level, message.as_ptr());
+    IN_CALLBACK.set(false);
+}
+
+/// Route library diagnostics to `callback`, or stop logging with a null one
+/// `min_level` is the least severe level delivered: 0 debug, 1 info (loads,
+/// with source, vocab size and elapsed time), 2 warn (repairs such as lossy
+/// UTF-8), 3 error (every failure recorded for `tokenizer_last_error`). The
+/// callback may be called on any thread, including the background loader,
+/// and concurrently from several threads. `message` is NUL-terminated UTF-8
+/// that is only valid during the call, so copy it before returning. The
+/// callback must not call back into the library; what the library would log
+/// meanwhile on that thread is dropped.
+/// Returns 0 on success, -9 for a level outside 0..=3
+#[no_mangle]
+pub extern "C" fn tokenizer_set_log_callback(callback: LogCallback, min_level: c_int) -> c_int {
+    catch_panic(|| {
+        if !(LOG_DEBUG..=LOG_ERROR).contains(&min_level) {
+            return fail(
+                ERR_INVALID_ARGUMENT,
+                format!("unknown log level {min_level} (expected 0..=3)"),
+            );
+        }
+
+        let mut guard = CALLBACK.write().unwrap_or_else(PoisonError::into_inner);
+        *guard = callback;
+        let level = if callback.is_some() {
+            min_level
+        } else {
+            c_int::MAX
+        };
+        MIN_LEVEL.store(level, Ordering::Relaxed);
+        0
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{install, llama_json, serial, write_temp};
+    use std::ffi::{CStr, CString};
+    use std::sync::Mutex;
+
+    static RECEIVED: Mutex<Vec<(c_int, String)>> = Mutex::new(Vec::new());
+
+    extern "C" fn record(level: c_int, message: *const c_char) {
+        let message = unsafe { CStr::from_ptr(message) }
+            .to_str()
+            .unwrap()
+            .to_owned();
+        RECEIVED.lock().unwrap().push((level, message));
+    }
+
+    /// Messages received so far that mention `needle`
+    fn received(needle: &str) -> Vec<(c_int, String)> {
+        let received = RECEIVED.lock().unwrap();
+        received
+            .iter()
+            .filter(|(_, message)| message.contains(needle))
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn errors_and_loads_reach_the_callback() {
+        let _guard = serial();
+        // Other tests log concurrently, so only messages naming paths of this
+        // test are looked at
+        let installed = write_temp("install", "");
+        let installed = installed.to_str().unwrap();
+        let missing = "/definitely/missing_log_test.json";
+        let initialize_missing = || {
+            let path = CString::new(missing).unwrap();
+            assert_eq!(crate::tokenizer_initialize(path.as_ptr()), -3);
+        };
+
+        assert_eq!(tokenizer_set_log_callback(Some(record), LOG_INFO), 0);
+        install(&llama_json());
+        let loads = received(installed);
+        assert_eq!(loads.len(), 1);
+        assert_eq!(loads[0].0, LOG_INFO);
+        assert!(loads[0].1.contains("vocab size 302"), "{loads:?}");
+
+        initialize_missing();
+        let errors = received(missing);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, LOG_ERROR);
+
+        // Below the minimum level, then with logging switched off
+        assert_eq!(tokenizer_set_log_callback(Some(record), LOG_ERROR), 0);
+        install(&llama_json());
+        assert_eq!(received(installed).len(), 1);
+        assert_eq!(tokenizer_set_log_callback(None, LOG_DEBUG), 0);
+        initialize_missing();
+        assert_eq!(received(missing).len(), 1);
+
+        assert_eq!(tokenizer_set_log_callback(None, 7), ERR_INVALID_ARGUMENT);
+    }
+}
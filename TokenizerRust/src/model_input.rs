@@ -0,0 +1,251 @@
+//! Fixed-length model inputs: `input_ids`, `attention_mask` and
+//! `token_type_ids` as the i64 arrays ONNX Runtime tensors take, truncated or
+//! padded to one sequence length.
+
+use std::ffi::{c_char, c_int};
+use tokenizers::utils::truncation::truncate_encodings;
+use tokenizers::{Encoding, PaddingDirection, PostProcessor, Tokenizer, TruncationParams};
+
+use crate::chunk::without_limits;
+use crate::config::configured_padding_side;
+use crate::special::{special_token, SpecialKind};
+use crate::{
+    catch_panic, fail, guard, text_arg, tokenizer_failed, with_tokenizer, ERR_INVALID_ARGUMENT,
+    ERR_NO_PAD_TOKEN,
+};
+
+/// Encode one or two segments with special tokens to exactly `seq_len` tokens
+/// Text tokens are truncated as `tokenizer_set_truncation` configured (longest
+/// segment first, from the right, if nothing is), so the special tokens
+/// always survive; padding uses the configured side, else the one from
+/// tokenizer_config.json, else the right.
+fn encode_for_model(
+    tokenizer: &Tokenizer,
+    text_a: &str,
+    text_b: Option<&str>,
+    seq_len: usize,
+) -> Result<Encoding, c_int> {
+    let added = tokenizer
+        .get_post_processor()
+        .map_or(0, |p| p.added_tokens(text_b.is_some()));
+    if seq_len <= added || seq_len > c_int::MAX as usize {
+        return Err(fail(
+            ERR_INVALID_ARGUMENT,
+            format!("seq_len {seq_len} must exceed the {added} special tokens and fit a C int"),
+        ));
+    }
+    let (pad_token, pad_id) = match special_token(tokenizer, SpecialKind::Pad) {
+        Some(pad) => pad,
+        None => {
+            return Err(fail(
+                ERR_NO_PAD_TOKEN,
+                "the loaded tokenizer defines no pad token",
+            ))
+        }
+    };
+    let padding = tokenizer.get_padding();
+    let direction = padding
+        .map(|p| p.direction)
+        .or_else(configured_padding_side)
+        .unwrap_or(PaddingDirection::Right);
+    let pad_type_id = padding.map_or(0, |p| p.pad_type_id);
+
+    let params = TruncationParams {
+        max_length: seq_len - added,
+        ..tokenizer.get_truncation().cloned().unwrap_or_default()
+    };
+    let unlimited = without_limits(tokenizer);
+    let encode = |text: &str| unlimited.encode(text, false);
+    let encoded = encode(text_a)
+        .and_then(|a| Ok((a, text_b.map(encode).transpose()?)))
+        .and_then(|(a, b)| truncate_encodings(a, b, &params))
+        .and_then(|(a, b)| unlimited.post_process(a, b, true));
+    let mut encoding = match encoded {
+        Ok(encoding) => encoding,
+        Err(e) => return Err(tokenizer_failed("encode", e)),
+    };
+
+    // Overflow windows would otherwise come along with the copy
+    let _ = encoding.take_overflowing();
+    encoding.pad(seq_len, pad_id, pad_type_id, &pad_token, direction);
+    Ok(encoding)
+}
+
+fn copy_i64(values: &[u32], out: *mut i64) {
+    for (i, &value) in values.iter().enumerate() {
+        unsafe { *out.add(i) = value as i64 };
+    }
+}
+
+fn model_input_into(
+    text_a: *const c_char,
+    text_b: Option<*const c_char>,
+    seq_len: usize,
+    out_ids: *mut i64,
+    out_mask: *mut i64,
+    out_type_ids: *mut i64,
+) -> c_int {
+    let buffers = [
+        ("out_ids", out_ids),
+        ("out_mask", out_mask),
+        ("out_type_ids", out_type_ids),
+    ];
+    for (name, out) in buffers {
+        if out.is_null() && name == "out_type_ids" {
+            continue;
+        }
+        if let Err(code) =
+            guard::out_buffer("tokenizer_encode_for_model", name, out, seq_len, text_a)
+        {
+            return code;
+        }
+    }
+    let text_a = match text_arg(text_a) {
+        Ok(s) => s,
+        Err(code) => return code,
+    };
+    let text_b = match text_b.map(text_arg).transpose() {
+        Ok(s) => s,
+        Err(code) => return code,
+    };
+
+    with_tokenizer(|tokenizer| {
+        let encoding = match encode_for_model(tokenizer, &text_a, text_b.as_deref(), seq_len) {
+            Ok(encoding) => encoding,
+            Err(code) => return code,
+        };
+
+        copy_i64(encoding.get_ids(), out_ids);
+        copy_i64(encoding.get_attention_mask(), out_mask);
+        if !out_type_ids.is_null() {
+            copy_i64(encoding.get_type_ids(), out_type_ids);
+        }
+        encoding
+            .get_attention_mask()
+            .iter()
+            .filter(|&&m| m == 1)
+            .count() as c_int
+    })
+}
+
+/// Encode `text` with special tokens into `seq_len` slots of each output,
+/// ready to be wrapped as ONNX Runtime tensors
+/// Longer text is truncated (special tokens are kept), shorter text padded
+/// with the tokenizer's pad token on the side `tokenizer_set_padding` or
+/// tokenizer_config.json chose, the right by default. `out_mask` is 1 for
+/// real tokens, special tokens included, and 0 for padding; `out_type_ids`
+/// (may be null, for models without that input) holds the post-processor's
+/// segment IDs. All three arrays must hold `seq_len` values.
+/// Returns the number of real tokens, negative on error:
+///   -1 null argument, -2 invalid UTF-8, -3 not initialized, -4 encode failed,
+///   -9 `seq_len` leaves no room for text, -10 the tokenizer has no pad token,
+///   -19/-20 an output fails the checks of `tokenizer_encode`
+#[no_mangle]
+pub extern "C" fn tokenizer_encode_for_model(
+    text: *const c_char,
+    seq_len: usize,
+    out_ids: *mut i64,
+    out_mask: *mut i64,
+    out_type_ids: *mut i64,
+) -> c_int {
+    catch_panic(|| model_input_into(text, None, seq_len, out_ids, out_mask, out_type_ids))
+}
+
+/// `tokenizer_encode_for_model` for a (query, passage) pair
+/// The post-processor lays out both segments (e.g. `[CLS] a [SEP] b [SEP]`,
+/// with type ID 1 for the second), and truncation trims the longer segment
+/// first unless configured otherwise.
+/// Returns the number of real tokens, negative on error (as
+/// `tokenizer_encode_for_model`)
+#[no_mangle]
+pub extern "C" fn tokenizer_encode_pair_for_model(
+    text_a: *const c_char,
+    text_b: *const c_char,
+    seq_len: usize,
+    out_ids: *mut i64,
+    out_mask: *mut i64,
+    out_type_ids: *mut i64,
+) -> c_int {
+    catch_panic(|| {
+        model_input_into(
+            text_a,
+            Some(text_b),
+            seq_len,
+            out_ids,
+            out_mask,
+            out_type_ids,
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{bert_json, install, llama_json, serial};
+    use std::ffi::CString;
+
+    type Arrays = (c_int, Vec<i64>, Vec<i64>, Vec<i64>);
+
+    fn for_model(a: &str, b: Option<&str>, seq_len: usize) -> Arrays {
+        let (a, b) = (
+            CString::new(a).unwrap(),
+            b.map(|b| CString::new(b).unwrap()),
+        );
+        let (mut ids, mut mask, mut types) =
+            (vec![-1; seq_len], vec![-1; seq_len], vec![-1; seq_len]);
+        let (ids_ptr, mask_ptr, types_ptr) =
+            (ids.as_mut_ptr(), mask.as_mut_ptr(), types.as_mut_ptr());
+        let n = match &b {
+            None => tokenizer_encode_for_model(a.as_ptr(), seq_len, ids_ptr, mask_ptr, types_ptr),
+            Some(b) => tokenizer_encode_pair_for_model(
+                a.as_ptr(),
+                b.as_ptr(),
+                seq_len,
+                ids_ptr,
+                mask_ptr,
+                types_ptr,
+            ),
+        };
+        (n, ids, mask, types)
+    }
+
+    #[test]
+    fn single_and_pair_inputs_fill_every_slot() {
+        let _guard = serial();
+        install(&bert_json());
+
+        let (n, ids, mask, types) = for_model("hello world", None, 6);
+        assert_eq!(n, 4);
+        assert_eq!(ids, [2, 5, 6, 3, 0, 0]);
+        assert_eq!(mask, [1, 1, 1, 1, 0, 0]);
+        assert_eq!(types, [0; 6]);
+
+        // Truncation keeps [SEP]
+        let (n, ids, mask, _) = for_model("hello world the token", None, 4);
+        assert_eq!((n, ids, mask), (4, vec![2, 5, 6, 3], vec![1; 4]));
+
+        let (n, ids, mask, types) = for_model("hello", Some("world the"), 8);
+        assert_eq!(n, 6);
+        assert_eq!(ids, [2, 5, 3, 6, 7, 3, 0, 0]);
+        assert_eq!(mask, [1, 1, 1, 1, 1, 1, 0, 0]);
+        assert_eq!(types, [0, 0, 0, 1, 1, 1, 0, 0]);
+    }
+
+    #[test]
+    fn padding_side_and_failures() {
+        let _guard = serial();
+        install(&bert_json());
+        assert_eq!(crate::settings::tokenizer_set_padding(-1, 0, 1), 0);
+        // The configured length is ignored, the side is kept
+        let (n, ids, mask, _) = for_model("hello", None, 5);
+        assert_eq!(
+            (n, ids, mask),
+            (3, vec![0, 0, 2, 5, 3], vec![0, 0, 1, 1, 1])
+        );
+
+        assert_eq!(for_model("hello", None, 2).0, ERR_INVALID_ARGUMENT);
+        assert_eq!(for_model("hello", Some("world"), 3).0, ERR_INVALID_ARGUMENT);
+        install(&llama_json());
+        assert_eq!(for_model("hello", None, 8).0, ERR_NO_PAD_TOKEN);
+    }
+}
@@ -0,0 +1,304 @@
+//! Tokenizers registered under a name, for apps that juggle several models.
+//!
+//! Like handles, each name maps to an immutable `Arc<Tokenizer>` and calls only
+//! hold the registry read lock long enough to clone it. Registering a name
+//! again swaps the `Arc`, so calls already running on the old tokenizer finish
+//! on it while new calls see the replacement.
+
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int};
+use std::sync::{Arc, OnceLock, PoisonError, RwLock};
+use tokenizers::Tokenizer;
+
+use crate::{
+    c_str_arg, catch_panic, decode_into, encode_into, fail, guard, ids_arg, load_from_path,
+    text_arg, tokenizer_failed, write_c_str, ERR_INVALID_ARGUMENT, ERR_INVALID_HANDLE,
+};
+
+fn registry() -> &'static RwLock<HashMap<String, Arc<Tokenizer>>> {
+    static NAMED: OnceLock<RwLock<HashMap<String, Arc<Tokenizer>>>> = OnceLock::new();
+    NAMED.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+fn lookup(name: *const c_char) -> Result<Arc<Tokenizer>, c_int> {
+    let name = c_str_arg(name)?;
+    let named = registry().read().unwrap_or_else(PoisonError::into_inner);
+    named.get(name).cloned().ok_or_else(|| unknown(name))
+}
+
+fn unknown(name: &str) -> c_int {
+    fail(
+        ERR_INVALID_HANDLE,
+        format!("no tokenizer is registered as '{name}'"),
+    )
+}
+
+/// Load a tokenizer.json and register it under `name`
+/// An existing registration with the same name is replaced atomically; calls
+/// already running on the old tokenizer complete normally. If loading fails the
+/// old registration is kept. Names must be non-empty and free of newlines.
+/// Returns 0 on success, negative on error:
+///   -1 null argument, -2 invalid UTF-8, -3 load failed, -9 invalid name
+#[no_mangle]
+pub extern "C" fn tokenizer_register(name: *const c_char, path: *const c_char) -> c_int {
+    catch_panic(|| {
+        let name = match c_str_arg(name) {
+            Ok(name) => name,
+            Err(code) => return code,
+        };
+        if name.is_empty() || name.contains('\n') {
+            return fail(
+                ERR_INVALID_ARGUMENT,
+                format!("{name:?} is not a valid tokenizer name"),
+            );
+        }
+        let tokenizer = match load_from_path(path) {
+            Ok(t) => t,
+            Err(code) => return code,
+        };
+
+        registry()
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(name.to_owned(), Arc::new(tokenizer));
+        0
+    })
+}
+
+/// Remove the tokenizer registered under `name`
+/// Calls already using it finish on their own reference.
+/// Returns 0 on success, negative on error:
+///   -1 null name, -2 invalid UTF-8, -7 nothing registered under `name`
+#[no_mangle]
+pub extern "C" fn tokenizer_unregister(name: *const c_char) -> c_int {
+    catch_panic(|| {
+        let name = match c_str_arg(name) {
+            Ok(name) => name,
+            Err(code) => return code,
+        };
+
+        let mut named = registry().write().unwrap_or_else(PoisonError::into_inner);
+        match named.remove(name) {
+            Some(_) => 0,
+            None => unknown(name),
+        }
+    })
+}
+
+/// `tokenizer_encode_opts` for the tokenizer registered under `name`
+/// Returns number of tokens on success, negative on error:
+///   -1 null pointer, -2 invalid UTF-8, -4 encode failed,
+///   -7 nothing registered under `name`, -11 ID out of `c_int` range
+#[no_mangle]
+pub extern "C" fn tokenizer_encode_named(
+    name: *const c_char,
+    text: *const c_char,
+    add_special_tokens: c_int,
+    out_ids: *mut c_int,
+    max_len: usize,
+) -> c_int {
+    catch_panic(|| {
+        if let Err(code) =
+            guard::out_buffer("tokenizer_encode_named", "out_ids", out_ids, max_len, text)
+        {
+            return code;
+        }
+        let text = match text_arg(text) {
+            Ok(s) => s,
+            Err(code) => return code,
+        };
+        let tokenizer = match lookup(name) {
+            Ok(t) => t,
+            Err(code) => return code,
+        };
+
+        encode_into(&tokenizer, &text, add_special_tokens != 0, out_ids, max_len)
+    })
+}
+
+/// `tokenizer_count_tokens` for the tokenizer registered under `name`
+/// Returns the token count on success, negative on error
+/// (as `tokenizer_encode_named`)
+#[no_mangle]
+pub extern "C" fn tokenizer_count_tokens_named(
+    name: *const c_char,
+    text: *const c_char,
+    add_special_tokens: c_int,
+) -> c_int {
+    catch_panic(|| {
+        let text = match text_arg(text) {
+            Ok(s) => s,
+            Err(code) => return code,
+        };
+        let tokenizer = match lookup(name) {
+            Ok(t) => t,
+            Err(code) => return code,
+        };
+
+        match tokenizer.encode_fast(text, add_special_tokens != 0) {
+            Ok(encoding) => encoding.len() as c_int,
+            Err(e) => tokenizer_failed("encode", e),
+        }
+    })
+}
+
+/// `tokenizer_decode_ex` for the tokenizer registered under `name`
+/// Returns bytes written (excluding NUL) or the required size for a null
+/// `out_text`, negative on error (as `tokenizer_decode`, plus -7 unknown name)
+#[no_mangle]
+pub extern "C" fn tokenizer_decode_named(
+    name: *const c_char,
+    ids: *const c_int,
+    len: usize,
+    skip_special_tokens: c_int,
+    out_text: *mut c_char,
+    out_capacity: usize,
+) -> c_int {
+    catch_panic(|| {
+        let ids = match ids_arg(ids, len) {
+            Ok(ids) => ids,
+            Err(code) => return code,
+        };
+        let tokenizer = match lookup(name) {
+            Ok(t) => t,
+            Err(code) => return code,
+        };
+
+        decode_into(
+            &tokenizer,
+            &ids,
+            skip_special_tokens != 0,
+            out_text,
+            out_capacity,
+        )
+    })
+}
+
+/// Write the registered names, sorted and separated by `\n`, into `out`
+/// Returns bytes written (excluding NUL), the required size for a null `out`
+/// or zero `capacity`, or -6 if the buffer is too small; 0 when nothing is
+/// registered
+#[no_mangle]
+pub extern "C" fn tokenizer_registered_names(out: *mut c_char, capacity: usize) -> c_int {
+    catch_panic(|| {
+        let mut names: Vec<String> = registry()
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .keys()
+            .cloned()
+            .collect();
+        names.sort_unstable();
+        write_c_str(&names.join("\n"), out, capacity)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{bert_json, llama_json, write_temp};
+    use std::ffi::CString;
+
+    fn register(name: &str, json: &str) -> c_int {
+        let path = write_temp(&format!("named_{name}"), json);
+        let path = CString::new(path.to_str().unwrap()).unwrap();
+        let name = CString::new(name).unwrap();
+        tokenizer_register(name.as_ptr(), path.as_ptr())
+    }
+
+    fn encode(name: &str, text: &str) -> Vec<c_int> {
+        let (name, text) = (CString::new(name).unwrap(), CString::new(text).unwrap());
+        let mut ids = vec![0; 64];
+        let n = tokenizer_encode_named(name.as_ptr(), text.as_ptr(), 1, ids.as_mut_ptr(), 64);
+        assert!(n >= 0, "encode failed with {n}");
+        ids.truncate(n as usize);
+        ids
+    }
+
+    fn names() -> String {
+        let mut buf = vec![0 as c_char; 256];
+        let n = tokenizer_registered_names(buf.as_mut_ptr(), buf.len());
+        assert!(n >= 0);
+        let bytes: Vec<u8> = buf[..n as usize].iter().map(|&c| c as u8).collect();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn names_address_their_own_tokenizer() {
+        assert_eq!(register("named_chat", &llama_json()), 0);
+        assert_eq!(register("named_embed", &bert_json()), 0);
+
+        assert_eq!(encode("named_chat", "hello world"), [1, 296, 301]);
+        assert_eq!(encode("named_embed", "hello world"), [2, 5, 6, 3]);
+
+        let (name, text) = (
+            CString::new("named_embed").unwrap(),
+            CString::new("hello").unwrap(),
+        );
+        assert_eq!(
+            tokenizer_count_tokens_named(name.as_ptr(), text.as_ptr(), 0),
+            1
+        );
+        let ids = [5, 6];
+        let mut out = [0 as c_char; 32];
+        let n = tokenizer_decode_named(name.as_ptr(), ids.as_ptr(), 2, 1, out.as_mut_ptr(), 32);
+        assert_eq!(n, "hello world".len() as c_int);
+
+        let listed = names();
+        assert!(listed.split('\n').any(|n| n == "named_chat"), "{listed}");
+        assert!(listed.split('\n').any(|n| n == "named_embed"), "{listed}");
+    }
+
+    #[test]
+    fn registering_again_replaces_and_unregister_removes() {
+        assert_eq!(register("named_swap", &llama_json()), 0);
+        assert_eq!(encode("named_swap", "hello")[0], 1);
+        assert_eq!(register("named_swap", &bert_json()), 0);
+        assert_eq!(encode("named_swap", "hello"), [2, 5, 3]);
+
+        // A failed load keeps the current registration
+        let name = CString::new("named_swap").unwrap();
+        let missing = CString::new("/definitely/missing/tokenizer.json").unwrap();
+        assert_eq!(tokenizer_register(name.as_ptr(), missing.as_ptr()), -3);
+        assert_eq!(encode("named_swap", "hello"), [2, 5, 3]);
+
+        assert_eq!(tokenizer_unregister(name.as_ptr()), 0);
+        assert_eq!(tokenizer_unregister(name.as_ptr()), ERR_INVALID_HANDLE);
+        let text = CString::new("hello").unwrap();
+        let mut ids = [0; 8];
+        let rc = tokenizer_encode_named(name.as_ptr(), text.as_ptr(), 1, ids.as_mut_ptr(), 8);
+        assert_eq!(rc, ERR_INVALID_HANDLE);
+    }
+
+    #[test]
+    fn names_must_be_usable_in_the_listing() {
+        assert_eq!(register("", &llama_json()), ERR_INVALID_ARGUMENT);
+        assert_eq!(register("two\nlines", &llama_json()), ERR_INVALID_ARGUMENT);
+    }
+
+    #[test]
+    fn replacing_during_encodes_never_breaks_them() {
+        assert_eq!(register("named_busy", &llama_json()), 0);
+
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    for _ in 0..200 {
+                        let ids = encode("named_busy", "hello world");
+                        assert!(ids == [1, 296, 301] || ids == [2, 5, 6, 3], "{ids:?}");
+                    }
+                })
+            })
+            .collect();
+        for i in 0..20 {
+            let json = if i % 2 == 0 {
+                bert_json()
+            } else {
+                llama_json()
+            };
+            assert_eq!(register("named_busy", &json), 0);
+        }
+        for worker in workers {
+            worker.join().unwrap();
+        }
+    }
+}
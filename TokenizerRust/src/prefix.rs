@@ -0,0 +1,232 @@
+//! Shared token prefixes of two prompts, so an inference backend knows how
+//! much of its KV cache for the previous prompt carries over to the next, and
+//! whether drafted tokens still spell out a target text.
+
+use std::ffi::{c_char, c_int};
+
+use crate::bytes::decode_bytes;
+use crate::{
+    c_str_arg, cache, catch_panic, fail, ids_arg, text_arg, tokenizer_failed, with_tokenizer,
+    ERR_NULL_POINTER,
+};
+
+fn common_prefix<T: PartialEq>(a: &[T], b: &[T]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+/// Number of leading token IDs the encodings of `text_a` and `text_b` share,
+/// with `add_special_tokens` as for `tokenizer_encode_opts`
+/// When the two tokenizations part ways with tokens left on both sides (not
+/// just one being a prefix of the other), the result is one less than the
+/// shared run: the last shared token sits at the point where merges start to
+/// differ ("Hello wor" vs "Hello world" end in `▁wor` and `▁world`), and
+/// re-running it costs one token of prefill while keeping the reuse safe for
+/// tokenizers whose merges reach back across that point. If one encoding is a
+/// prefix of the other, its full length is returned.
+/// Returns the prefix length, negative on error:
+///   -1 null text, -2 invalid UTF-8, -3 not initialized, -4 encode failed
+#[no_mangle]
+pub extern "C" fn tokenizer_common_prefix_len(
+    text_a: *const c_char,
+    text_b: *const c_char,
+    add_special_tokens: c_int,
+) -> c_int {
+    catch_panic(|| {
+        let (text_a, text_b) = match (text_arg(text_a), text_arg(text_b)) {
+            (Ok(a), Ok(b)) => (a, b),
+            (Err(code), _) | (_, Err(code)) => return code,
+        };
+
+        let add_special_tokens = add_special_tokens != 0;
+        with_tokenizer(|tokenizer| {
+            let encode = |text: &str| {
+                cache::cached_ids(text, add_special_tokens, || {
+                    tokenizer.encode(text, add_special_tokens)
+                })
+            };
+            let (a, b) = match (encode(&text_a), encode(&text_b)) {
+                (Ok(a), Ok(b)) => (a, b),
+                (Err(e), _) | (_, Err(e)) => return tokenizer_failed("encode", e),
+            };
+
+            let shared = common_prefix(&a, &b);
+            if shared < a.len() && shared < b.len() {
+                shared.saturating_sub(1) as c_int
+            } else {
+                shared as c_int
+            }
+        })
+    })
+}
+
+/// Number of leading IDs two token sequences share
+/// Plain comparison with no back-off, since the IDs are already fixed.
+/// Returns the prefix length, -1 for a null array with non-zero length
+#[no_mangle]
+pub extern "C" fn tokenizer_ids_common_prefix_len(
+    ids_a: *const c_int,
+    len_a: usize,
+    ids_b: *const c_int,
+    len_b: usize,
+) -> c_int {
+    catch_panic(|| {
+        if (ids_a.is_null() && len_a > 0) || (ids_b.is_null() && len_b > 0) {
+            return fail(ERR_NULL_POINTER, "ids_a/ids_b is null");
+        }
+        let slice = |ids: *const c_int, len: usize| match len {
+            0 => &[][..],
+            _ => unsafe { std::slice::from_raw_parts(ids, len) },
+        };
+        common_prefix(slice(ids_a, len_a), slice(ids_b, len_b)) as c_int
+    })
+}
+
+/// Whether `ids` decode to a prefix of (or exactly) `target_text`
+/// The IDs are decoded to raw bytes as by `tokenizer_decode_bytes`, but with
+/// special tokens kept and no cleanup, so `<s>` must be in the target if it
+/// is in the IDs, and the first token loses its marker space as it does in
+/// `tokenizer_decode` (Metaspace) or keeps it (byte-level). A decode that ends
+/// partway through a character of the target still matches. The number of
+/// target characters (Unicode scalar values, not UTF-16 units) covered by the
+/// matching bytes, a character cut short not included, is written to
+/// `out_chars_matched` when it is not null: for a mismatch this is where the
+/// texts diverge.
+/// Returns 1 for a prefix match, 0 for a mismatch, negative on error:
+///   -1 null argument, -2 invalid UTF-8, -3 not initialized, -4 decode failed
+///   (including negative IDs)
+#[no_mangle]
+pub extern "C" fn tokenizer_match_prefix(
+    ids: *const c_int,
+    len: usize,
+    target_text: *const c_char,
+    out_chars_matched: *mut c_int,
+) -> c_int {
+    catch_panic(|| {
+        let ids = match ids_arg(ids, len) {
+            Ok(ids) => ids,
+            Err(code) => return code,
+        };
+        let target = match c_str_arg(target_text) {
+            Ok(t) => t,
+            Err(code) => return code,
+        };
+
+        with_tokenizer(|tokenizer| {
+            let decoded = match decode_bytes(tokenizer, &ids, false) {
+                Ok(bytes) => bytes,
+                Err(e) => return tokenizer_failed("decode", e),
+            };
+
+            let matched = common_prefix(&decoded, target.as_bytes());
+            if !out_chars_matched.is_null() {
+                let boundary = (0..=matched)
+                    .rev()
+                    .find(|&i| target.is_char_boundary(i))
+                    .unwrap_or(0);
+                let chars = target[..boundary].chars().count();
+                unsafe { *out_chars_matched = chars.min(c_int::MAX as usize) as c_int };
+            }
+            (matched == decoded.len()) as c_int
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{byte_level_json, install, llama_json, serial};
+    use std::ffi::CString;
+
+    fn encode(text: &str) -> Vec<c_int> {
+        let text = CString::new(text).unwrap();
+        let mut ids = vec![0; 64];
+        let n = crate::tokenizer_encode_opts(text.as_ptr(), 0, ids.as_mut_ptr(), ids.len());
+        assert!(n >= 0, "encode failed with {n}");
+        ids.truncate(n as usize);
+        ids
+    }
+
+    fn match_prefix(ids: &[c_int], target: &str) -> (c_int, c_int) {
+        let target = CString::new(target).unwrap();
+        let mut chars = -1;
+        let rc = tokenizer_match_prefix(ids.as_ptr(), ids.len(), target.as_ptr(), &mut chars);
+        (rc, chars)
+    }
+
+    fn prefix_len(a: &str, b: &str, add_special_tokens: c_int) -> c_int {
+        let (a, b) = (CString::new(a).unwrap(), CString::new(b).unwrap());
+        tokenizer_common_prefix_len(a.as_ptr(), b.as_ptr(), add_special_tokens)
+    }
+
+    #[test]
+    fn backs_off_where_the_tokenizations_part() {
+        let _guard = serial();
+        install(&llama_json());
+
+        // <s> ▁ H el lo, then ▁wor against ▁world
+        assert_eq!(prefix_len("Hello wor", "Hello world", 1), 4);
+        assert_eq!(prefix_len("Hello wor", "Hello world", 0), 3);
+        // One encoding extends the other: nothing to back off from
+        assert_eq!(prefix_len("hello", "hello world", 1), 2);
+        assert_eq!(prefix_len("hello world", "hello world", 1), 3);
+        assert_eq!(prefix_len("", "hello", 0), 0);
+    }
+
+    #[test]
+    fn ids_compare_as_given() {
+        let (a, b) = ([2, 5, 6, 3], [2, 5, 7, 3]);
+        assert_eq!(
+            tokenizer_ids_common_prefix_len(a.as_ptr(), 4, b.as_ptr(), 4),
+            2
+        );
+        assert_eq!(
+            tokenizer_ids_common_prefix_len(a.as_ptr(), 4, a.as_ptr(), 2),
+            2
+        );
+        assert_eq!(
+            tokenizer_ids_common_prefix_len(std::ptr::null(), 0, b.as_ptr(), 4),
+            0
+        );
+        assert_eq!(
+            tokenizer_ids_common_prefix_len(std::ptr::null(), 1, b.as_ptr(), 4),
+            ERR_NULL_POINTER
+        );
+    }
+
+    #[test]
+    fn drafts_match_as_prefixes_of_the_target() {
+        let _guard = serial();
+        install(&llama_json());
+
+        // The decoder drops the first marker space, as in a plain decode
+        let ids = encode("hello wor");
+        assert_eq!(match_prefix(&ids, "hello world"), (1, 9));
+        assert_eq!(match_prefix(&ids, "hello wor"), (1, 9));
+        assert_eq!(match_prefix(&ids, "hello"), (0, 5));
+        assert_eq!(match_prefix(&ids, "help"), (0, 3));
+        assert_eq!(match_prefix(&[], "anything"), (1, 0));
+
+        // Two of the rocket's four bytes: a match that covers no character yet
+        let ids = encode("zażółć 🚀");
+        assert_eq!(match_prefix(&ids[..ids.len() - 2], "zażółć 🚀!"), (1, 7));
+        assert_eq!(match_prefix(&ids, "zażółć 🚁"), (0, 7));
+        // Special tokens decode to their content
+        assert_eq!(match_prefix(&[1, 296], "hello"), (0, 0));
+    }
+
+    #[test]
+    fn byte_level_drafts_keep_their_leading_space() {
+        let _guard = serial();
+        install(&byte_level_json());
+
+        let ids = encode(" hello");
+        assert_eq!(match_prefix(&ids, " hello there"), (1, 6));
+        assert_eq!(match_prefix(&ids, "hello there"), (0, 0));
+        let ids = encode("żółw");
+        assert_eq!(match_prefix(&ids[..3], "żółw"), (1, 1));
+
+        let target = CString::new("x").unwrap();
+        let rc = tokenizer_match_prefix(std::ptr::null(), 2, target.as_ptr(), std::ptr::null_mut());
+        assert_eq!(rc, ERR_NULL_POINTER);
+    }
+}
@@ -0,0 +1,239 @@
+//! Token pieces rendered for people: as stored, as decoded, or with spaces and
+//! control characters made visible, for token visualizers and tooltips.
+
+use std::borrow::Cow;
+use std::ffi::{c_char, c_int};
+use std::sync::{PoisonError, RwLock};
+use tokenizers::Tokenizer;
+
+use crate::bytes::{decode_bytes, piece_bytes};
+use crate::{
+    c_str_arg, catch_panic, fail, tokenizer_failed, with_tokenizer, write_c_str,
+    ERR_INVALID_ARGUMENT,
+};
+
+const RENDER_RAW: c_int = 0;
+const RENDER_DECODED: c_int = 1;
+const RENDER_DISPLAY: c_int = 2;
+
+/// What spaces, newlines and tabs look like in display mode
+struct Symbols {
+    space: Cow<'static, str>,
+    newline: Cow<'static, str>,
+    tab: Cow<'static, str>,
+}
+
+const DEFAULT_SPACE: &str = "·";
+const DEFAULT_NEWLINE: &str = "⏎";
+const DEFAULT_TAB: &str = "→";
+
+static SYMBOLS: RwLock<Symbols> = RwLock::new(Symbols {
+    space: Cow::Borrowed(DEFAULT_SPACE),
+    newline: Cow::Borrowed(DEFAULT_NEWLINE),
+    tab: Cow::Borrowed(DEFAULT_TAB),
+});
+
+/// Bytes as text, with each byte that is not part of valid UTF-8 as `<0xNN>`
+fn hex_escaped(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        text.push_str(chunk.valid());
+        for byte in chunk.invalid() {
+            text.push_str(&format!("<0x{byte:02X}>"));
+        }
+    }
+    text
+}
+
+/// `text` with the configured symbols for spaces, newlines and tabs, and
+/// other control characters as their Unicode control pictures (`␍`, `␀`)
+fn visible(text: &str) -> String {
+    let symbols = SYMBOLS.read().unwrap_or_else(PoisonError::into_inner);
+    let mut shown = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            ' ' => shown.push_str(&symbols.space),
+            '\n' => shown.push_str(&symbols.newline),
+            '\t' => shown.push_str(&symbols.tab),
+            '\0'..='\x1f' => shown.push(char::from_u32(0x2400 + c as u32).unwrap_or(c)),
+            '\x7f' => shown.push('␡'),
+            c if c.is_control() => shown.push_str(&format!("<U+{:04X}>", c as u32)),
+            c => shown.push(c),
+        }
+    }
+    shown
+}
+
+fn render(
+    tokenizer: &Tokenizer,
+    id: u32,
+    piece: String,
+    mode: c_int,
+) -> tokenizers::Result<String> {
+    let added = tokenizer
+        .get_added_vocabulary()
+        .get_vocab()
+        .contains_key(&piece);
+    Ok(match mode {
+        RENDER_RAW => piece,
+        RENDER_DECODED => hex_escaped(&decode_bytes(tokenizer, &[id], false)?),
+        // Added tokens read whole, markers and all, as in token search
+        _ if added => visible(&piece),
+        _ => visible(&hex_escaped(&piece_bytes(tokenizer, &piece)?)),
+    })
+}
+
+/// Write token `id` rendered for display into `out` as NUL-terminated UTF-8
+/// `mode` 0 gives the raw piece (as `tokenizer_id_to_token`, markers such as
+/// `▁`, `Ġ`, `<0x0A>` intact); 1 what `tokenizer_decode_ex` gives for this one
+/// ID with special tokens kept, through the tokenizer's decoder, so
+/// byte-fallback and byte-level pieces become the bytes they stand for; 2 the
+/// piece as it reads inside a text (a marker space stays a space) with spaces,
+/// newlines and tabs shown as the symbols set by `tokenizer_set_render_symbols`
+/// and other control characters as Unicode control pictures. In modes 1 and 2
+/// bytes that are not valid UTF-8 on their own, such as half of a character,
+/// appear as `<0xNN>`. A null `out` or zero `capacity` is a size query.
+/// Returns bytes written (excluding NUL) or the required size, negative on error:
+///   -3 not initialized, -4 decode failed, -6 buffer too small,
+///   -9 unknown mode, or `id` is negative or outside the vocabulary
+#[no_mangle]
+pub extern "C" fn tokenizer_render_token(
+    id: c_int,
+    mode: c_int,
+    out: *mut c_char,
+    capacity: usize,
+) -> c_int {
+    catch_panic(|| {
+        if !matches!(mode, RENDER_RAW | RENDER_DECODED | RENDER_DISPLAY) {
+            return fail(
+                ERR_INVALID_ARGUMENT,
+                format!("unknown render mode {mode}; expected 0, 1 or 2"),
+            );
+        }
+
+        with_tokenizer(|tokenizer| {
+            let Some((id, piece)) = u32::try_from(id)
+                .ok()
+                .and_then(|id| Some((id, tokenizer.id_to_token(id)?)))
+            else {
+                return fail(
+                    ERR_INVALID_ARGUMENT,
+                    format!("token ID {id} is not in the vocabulary"),
+                );
+            };
+            match render(tokenizer, id, piece, mode) {
+                Ok(text) => write_c_str(&text, out, capacity),
+                Err(e) => tokenizer_failed("decode", e),
+            }
+        })
+    })
+}
+
+/// Choose the symbols display mode of `tokenizer_render_token` shows for a
+/// space, a newline and a tab
+/// Each is any UTF-8 string, empty included; a null argument restores that
+/// one's default ("·", "⏎", "→"). The symbols apply process-wide.
+/// Returns 0 on success, negative on error (nothing changes):
+///   -2 invalid UTF-8
+#[no_mangle]
+pub extern "C" fn tokenizer_set_render_symbols(
+    space: *const c_char,
+    newline: *const c_char,
+    tab: *const c_char,
+) -> c_int {
+    catch_panic(|| {
+        let symbol = |ptr: *const c_char, default: &'static str| {
+            if ptr.is_null() {
+                Ok(Cow::Borrowed(default))
+            } else {
+                c_str_arg(ptr).map(|s| Cow::Owned(s.to_owned()))
+            }
+        };
+        let symbols = (
+            symbol(space, DEFAULT_SPACE),
+            symbol(newline, DEFAULT_NEWLINE),
+            symbol(tab, DEFAULT_TAB),
+        );
+        let (space, newline, tab) = match symbols {
+            (Ok(space), Ok(newline), Ok(tab)) => (space, newline, tab),
+            (Err(code), _, _) | (_, Err(code), _) | (_, _, Err(code)) => return code,
+        };
+
+        *SYMBOLS.write().unwrap_or_else(PoisonError::into_inner) = Symbols {
+            space,
+            newline,
+            tab,
+        };
+        0
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{byte_level_json, install, llama_json, serial};
+    use std::ffi::{CStr, CString};
+
+    fn rendered(id: c_int, mode: c_int) -> String {
+        let needed = tokenizer_render_token(id, mode, std::ptr::null_mut(), 0);
+        assert!(needed >= 0, "size query failed with {needed}");
+        let mut buf = vec![0u8; needed as usize + 1];
+        let written = tokenizer_render_token(id, mode, buf.as_mut_ptr() as *mut c_char, buf.len());
+        assert_eq!(written, needed);
+        CStr::from_bytes_with_nul(&buf)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    #[test]
+    fn the_three_modes_for_sentencepiece_pieces() {
+        let _guard = serial();
+        install(&llama_json());
+
+        // ▁world, then the byte-fallback newline and the first byte of "ż"
+        let newline = 3 + 0x0A;
+        let lead = 3 + 0xC5;
+        assert_eq!(rendered(301, RENDER_RAW), "▁world");
+        assert_eq!(rendered(301, RENDER_DECODED), "world");
+        assert_eq!(rendered(301, RENDER_DISPLAY), "·world");
+        assert_eq!(rendered(newline, RENDER_RAW), "<0x0A>");
+        assert_eq!(rendered(newline, RENDER_DECODED), "\n");
+        assert_eq!(rendered(newline, RENDER_DISPLAY), "⏎");
+        assert_eq!(rendered(lead, RENDER_DECODED), "<0xC5>");
+        assert_eq!(rendered(1, RENDER_DISPLAY), "<s>");
+
+        assert_eq!(
+            tokenizer_render_token(301, 3, std::ptr::null_mut(), 0),
+            ERR_INVALID_ARGUMENT
+        );
+        assert_eq!(
+            tokenizer_render_token(-1, 0, std::ptr::null_mut(), 0),
+            ERR_INVALID_ARGUMENT
+        );
+    }
+
+    #[test]
+    fn display_symbols_are_configurable() {
+        let _guard = serial();
+        install(&byte_level_json());
+        // Byte-level IDs are the bytes themselves
+        assert_eq!(rendered(b' ' as c_int, RENDER_RAW), "Ġ");
+        assert_eq!(rendered(b'\t' as c_int, RENDER_DISPLAY), "→");
+        assert_eq!(rendered(b'\r' as c_int, RENDER_DISPLAY), "␍");
+
+        let (space, tab) = (CString::new("_").unwrap(), CString::new("\\t").unwrap());
+        assert_eq!(
+            tokenizer_set_render_symbols(space.as_ptr(), std::ptr::null(), tab.as_ptr()),
+            0
+        );
+        assert_eq!(rendered(b' ' as c_int, RENDER_DISPLAY), "_");
+        assert_eq!(rendered(b'\t' as c_int, RENDER_DISPLAY), "\\t");
+        assert_eq!(rendered(b'\n' as c_int, RENDER_DISPLAY), "⏎");
+
+        let null = std::ptr::null();
+        assert_eq!(tokenizer_set_render_symbols(null, null, null), 0);
+        assert_eq!(rendered(b' ' as c_int, RENDER_DISPLAY), "·");
+    }
+}
@@ -0,0 +1,101 @@
+//! Writing the loaded tokenizer back out, runtime changes included.
+
+use std::ffi::{c_char, c_int};
+
+use crate::{c_str_arg, catch_panic, fail, with_tokenizer, ERR_WRITE_FAILED};
+
+/// Save the loaded tokenizer to `path` as a standard tokenizer.json
+/// Tokens added with `tokenizer_add_tokens`/`tokenizer_add_special_tokens`
+/// are written into its `added_tokens`, and the settings of
+/// `tokenizer_set_truncation`/`tokenizer_set_padding` into its `truncation`
+/// and `padding` sections, so `tokenizer_initialize` on the file restores all
+/// of them and every text encodes to the same IDs as before. A padding side
+/// taken from tokenizer_config.json is saved as the side itself. Settings
+/// outside the tokenizer (tokenizer_config.json, chat template, cache, UTF-8
+/// policy) are not saved. `pretty` non-zero indents the JSON. An existing
+/// file is overwritten.
+/// Returns 0 on success, negative on error:
+///   -1 null `path`, -2 invalid UTF-8, -3 not initialized,
+///   -16 the file could not be written
+#[no_mangle]
+pub extern "C" fn tokenizer_save(path: *const c_char, pretty: c_int) -> c_int {
+    catch_panic(|| {
+        let path = match c_str_arg(path) {
+            Ok(p) => p,
+            Err(code) => return code,
+        };
+
+        with_tokenizer(|tokenizer| match tokenizer.save(path, pretty != 0) {
+            Ok(()) => 0,
+            Err(e) => fail(
+                ERR_WRITE_FAILED,
+                format!("could not save tokenizer to {path}: {e}"),
+            ),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{bert_json, install, serial, write_temp};
+    use std::ffi::CString;
+
+    fn encode_all(texts: &[&str]) -> Vec<Vec<c_int>> {
+        texts
+            .iter()
+            .map(|text| {
+                let text = CString::new(*text).unwrap();
+                let mut ids = vec![0; 64];
+                let n = crate::tokenizer_encode(text.as_ptr(), ids.as_mut_ptr(), ids.len());
+                assert!(n >= 0, "encode failed with {n}");
+                ids.truncate(n as usize);
+                ids
+            })
+            .collect()
+    }
+
+    #[test]
+    fn saved_tokenizers_reload_with_runtime_changes() {
+        let _guard = serial();
+        install(&bert_json());
+        let tokens = [
+            CString::new("<|tool|>").unwrap(),
+            CString::new("helloworld").unwrap(),
+        ];
+        let special = [tokens[0].as_ptr()];
+        let plain = [tokens[1].as_ptr()];
+        assert_eq!(
+            crate::vocab::tokenizer_add_special_tokens(special.as_ptr(), 1),
+            1
+        );
+        assert_eq!(crate::vocab::tokenizer_add_tokens(plain.as_ptr(), 1), 1);
+        assert_eq!(crate::settings::tokenizer_set_truncation(6, 1, 0), 0);
+        assert_eq!(crate::settings::tokenizer_set_padding(6, 0, 1), 0);
+
+        let texts = [
+            "hello",
+            "hello<|tool|>world",
+            "the token helloworld the token the token",
+            "",
+            "unknown words [MASK] here",
+        ];
+        let before = encode_all(&texts);
+        // Left padding, left truncation, the added tokens matched whole
+        assert_eq!(before[0], [0, 0, 0, 2, 5, 3]);
+        let tool = crate::vocab::tokenizer_token_to_id(tokens[0].as_ptr());
+        assert_eq!(before[1], [0, 2, 5, tool, 6, 3]);
+
+        let path = write_temp("save", "");
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(tokenizer_save(c_path.as_ptr(), 1), 0);
+        install(&bert_json());
+        assert_ne!(encode_all(&texts), before);
+        assert_eq!(crate::tokenizer_initialize(c_path.as_ptr()), 0);
+        assert_eq!(encode_all(&texts), before);
+
+        assert_eq!(tokenizer_save(std::ptr::null(), 0), crate::ERR_NULL_POINTER);
+        let dir = CString::new(std::env::temp_dir().to_str().unwrap()).unwrap();
+        assert_eq!(tokenizer_save(dir.as_ptr(), 0), ERR_WRITE_FAILED);
+    }
+}
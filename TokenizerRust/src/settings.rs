@@ -0,0 +1,379 @@
+//! Truncation and padding settings applied by the loaded tokenizer itself.
+//!
+//! These live on the global `Tokenizer`, so they survive until it is replaced
+//! by another `tokenizer_initialize*` call or cleared explicitly.
+
+use std::ffi::c_int;
+use tokenizers::{
+    PaddingDirection, PaddingParams, PaddingStrategy, PostProcessor, TruncationDirection,
+    TruncationParams,
+};
+
+use crate::config::configured_padding_side;
+use crate::special::{special_token, SpecialKind};
+use crate::{catch_panic, fail, with_tokenizer_mut, ERR_INVALID_ARGUMENT, ERR_NO_PAD_TOKEN};
+
+/// Direction values accepted by the settings functions
+const DIRECTION_RIGHT: c_int = 0;
+const DIRECTION_LEFT: c_int = 1;
+
+pub(crate) fn truncation_direction(direction: c_int) -> Result<TruncationDirection, c_int> {
+    match direction {
+        DIRECTION_RIGHT => Ok(TruncationDirection::Right),
+        DIRECTION_LEFT => Ok(TruncationDirection::Left),
+        other => Err(fail(
+            ERR_INVALID_ARGUMENT,
+            format!("unknown truncation direction {other} (expected 0 = right, 1 = left)"),
+        )),
+    }
+}
+
+/// Make every encode return at most `max_length` tokens, special tokens included
+/// `direction`: 0 drops tokens from the end (keeps the start), 1 drops them
+/// from the start (keeps the end). `stride` is the overlap kept between
+/// overflowing windows and must be smaller than the room left after special tokens.
+/// Truncation happens inside the tokenizer before special tokens are added, so
+/// BOS/CLS/SEP are never cut off.
+/// Returns 0 on success, negative on error:
+///   -3 not initialized, -9 invalid direction, `max_length` not above the
+///   special tokens' count, or stride not below the room left
+#[no_mangle]
+pub extern "C" fn tokenizer_set_truncation(
+    max_length: usize,
+    direction: c_int,
+    stride: usize,
+) -> c_int {
+    catch_panic(|| {
+        let direction = match truncation_direction(direction) {
+            Ok(d) => d,
+            Err(code) => return code,
+        };
+
+        with_tokenizer_mut(|tokenizer| {
+            let processor = tokenizer.get_post_processor();
+            let added = processor.map_or(0, |p| p.added_tokens(false));
+            let room = max_length.saturating_sub(added);
+            if room == 0 || stride >= room {
+                return fail(
+                    ERR_INVALID_ARGUMENT,
+                    format!(
+                        "max_length {max_length} leaves {room} tokens after {added} special \
+                         tokens, which must be more than 0 and the stride {stride}"
+                    ),
+                );
+            }
+            let params = TruncationParams {
+                max_length,
+                direction,
+                stride,
+                ..Default::default()
+            };
+            match tokenizer.with_truncation(Some(params)) {
+                Ok(_) => 0,
+                Err(e) => fail(ERR_INVALID_ARGUMENT, format!("invalid truncation: {e}")),
+            }
+        })
+    })
+}
+
+/// Turn truncation off again
+/// Returns 0 on success, -3 not initialized
+#[no_mangle]
+pub extern "C" fn tokenizer_clear_truncation() -> c_int {
+    catch_panic(|| {
+        with_tokenizer_mut(|tokenizer| match tokenizer.with_truncation(None) {
+            Ok(_) => 0,
+            Err(e) => fail(ERR_INVALID_ARGUMENT, format!("invalid truncation: {e}")),
+        })
+    })
+}
+
+/// Pad every encoding to a common length using the tokenizer's own pad token
+/// `length`: a fixed length, or -1 to pad to the longest item of each batch.
+/// `pad_to_multiple_of`: round the padded length up to a multiple, 0 for none.
+/// `direction`: 0 pads at the end, 1 pads at the start, -1 pads on the
+/// `padding_side` of the loaded tokenizer_config.json (the end without one).
+/// The pad token is looked up like `tokenizer_pad_id`.
+/// Returns 0 on success, negative on error:
+///   -3 not initialized, -9 invalid argument,
+///   -10 the tokenizer defines no pad token
+#[no_mangle]
+pub extern "C" fn tokenizer_set_padding(
+    length: c_int,
+    pad_to_multiple_of: c_int,
+    direction: c_int,
+) -> c_int {
+    catch_panic(|| {
+        let strategy = match length {
+            -1 => PaddingStrategy::BatchLongest,
+            n if n > 0 => PaddingStrategy::Fixed(n as usize),
+            other => {
+                return fail(
+                    ERR_INVALID_ARGUMENT,
+                    format!("invalid padding length {other} (expected -1 or a positive length)"),
+                )
+            }
+        };
+        let pad_to_multiple_of = match pad_to_multiple_of {
+            0 => None,
+            n if n > 0 => Some(n as usize),
+            other => {
+                return fail(
+                    ERR_INVALID_ARGUMENT,
+                    format!("invalid pad_to_multiple_of {other}"),
+                )
+            }
+        };
+        let direction = if direction == -1 {
+            configured_padding_side().unwrap_or(PaddingDirection::Right)
+        } else {
+            match truncation_direction(direction) {
+                Ok(TruncationDirection::Right) => PaddingDirection::Right,
+                Ok(TruncationDirection::Left) => PaddingDirection::Left,
+                Err(code) => return code,
+            }
+        };
+
+        with_tokenizer_mut(|tokenizer| {
+            let (pad_token, pad_id) = match special_token(tokenizer, SpecialKind::Pad) {
+                Some(pad) => pad,
+                None => {
+                    return fail(
+                        ERR_NO_PAD_TOKEN,
+                        "the loaded tokenizer defines no pad token",
+                    )
+                }
+            };
+
+            tokenizer.with_padding(Some(PaddingParams {
+                strategy,
+                direction,
+                pad_to_multiple_of,
+                pad_id,
+                pad_type_id: 0,
+                pad_token,
+            }));
+            0
+        })
+    })
+}
+
+/// Turn padding off again
+/// Returns 0 on success, -3 not initialized
+#[no_mangle]
+pub extern "C" fn tokenizer_clear_padding() -> c_int {
+    catch_panic(|| {
+        with_tokenizer_mut(|tokenizer| {
+            tokenizer.with_padding(None);
+            0
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{bert_json, install, llama_json, serial};
+    use crate::{
+        tokenizer_encode, tokenizer_encode_batch_with_mask, tokenizer_free, ERR_NOT_INITIALIZED,
+    };
+    use std::ffi::CString;
+
+    fn encode(text: &str) -> Vec<c_int> {
+        let text = CString::new(text).unwrap();
+        let mut ids = vec![0; 64];
+        let n = tokenizer_encode(text.as_ptr(), ids.as_mut_ptr(), ids.len());
+        assert!(n >= 0);
+        ids.truncate(n as usize);
+        ids
+    }
+
+    #[test]
+    fn truncation_keeps_special_tokens() {
+        let _guard = serial();
+        install(&bert_json());
+
+        assert_eq!(tokenizer_set_truncation(4, DIRECTION_RIGHT, 0), 0);
+        assert_eq!(encode("hello world the token"), vec![2, 5, 6, 3]);
+
+        assert_eq!(tokenizer_set_truncation(4, DIRECTION_LEFT, 0), 0);
+        assert_eq!(encode("hello world the token"), vec![2, 7, 8, 3]);
+
+        assert_eq!(tokenizer_clear_truncation(), 0);
+        assert_eq!(encode("hello world the token"), vec![2, 5, 6, 7, 8, 3]);
+    }
+
+    #[test]
+    fn configured_padding_side_records_no_error() {
+        let _guard = serial();
+        install(&bert_json());
+
+        crate::error::set_last_error("");
+        assert_eq!(tokenizer_set_padding(4, 0, -1), 0);
+        assert_eq!(crate::error::last_error_message(), "");
+        assert_eq!(encode("hello"), vec![2, 5, 3, 0]);
+        assert_eq!(tokenizer_set_padding(4, 0, 2), ERR_INVALID_ARGUMENT);
+    }
+
+    #[test]
+    fn left_truncation_keeps_the_latest_message_after_bos() {
+        let _guard = serial();
+        install(&llama_json());
+
+        let last = "user: and what about the world?";
+        let history = [
+            "user: hello",
+            "assistant: hello, how can i help?",
+            "user: tell me about the world.",
+            "assistant: the world is big.",
+            last,
+        ]
+        .join("\n");
+        let budget = encode(last).len() + 4;
+        let c_history = CString::new(history.as_str()).unwrap();
+        assert!(crate::tokenizer_count_tokens(c_history.as_ptr(), 1) as usize > 2 * budget);
+
+        assert_eq!(tokenizer_set_truncation(budget, DIRECTION_LEFT, 0), 0);
+        let ids = encode(&history);
+        assert_eq!(ids.len(), budget);
+        assert_eq!(ids[0], 1, "BOS is added after truncation, not cut off");
+
+        let mut text = vec![0u8; 256];
+        let n = crate::tokenizer_decode(
+            ids.as_ptr(),
+            ids.len(),
+            text.as_mut_ptr() as *mut std::ffi::c_char,
+            text.len(),
+        );
+        assert!(n > 0);
+        let text = std::str::from_utf8(&text[..n as usize]).unwrap();
+        assert!(text.ends_with(last), "{text:?} lost the last message");
+        assert!(!text.contains("tell me"));
+    }
+
+    #[test]
+    fn truncation_rejects_bad_arguments() {
+        let _guard = serial();
+        install(&bert_json());
+        let generation = crate::current_generation();
+        assert_eq!(tokenizer_set_truncation(4, 7, 0), ERR_INVALID_ARGUMENT);
+        assert_eq!(
+            tokenizer_set_truncation(4, DIRECTION_RIGHT, 10),
+            ERR_INVALID_ARGUMENT
+        );
+        // [CLS] and [SEP] take two of the tokens: one left is not above a stride
+        // of 1, and none left cannot hold any text
+        assert_eq!(
+            tokenizer_set_truncation(3, DIRECTION_RIGHT, 1),
+            ERR_INVALID_ARGUMENT
+        );
+        assert_eq!(
+            tokenizer_set_truncation(1, DIRECTION_RIGHT, 0),
+            ERR_INVALID_ARGUMENT
+        );
+        assert_eq!(
+            tokenizer_set_truncation(2, DIRECTION_RIGHT, 0),
+            ERR_INVALID_ARGUMENT
+        );
+        // A rejected change leaves snapshots and caches as they are
+        assert_eq!(crate::current_generation(), generation);
+        assert_eq!(tokenizer_set_truncation(4, DIRECTION_RIGHT, 0), 0);
+        assert_eq!(crate::current_generation(), generation + 1);
+
+        tokenizer_free();
+        assert_eq!(
+            tokenizer_set_truncation(4, DIRECTION_RIGHT, 0),
+            ERR_NOT_INITIALIZED
+        );
+        assert_eq!(tokenizer_clear_truncation(), ERR_NOT_INITIALIZED);
+    }
+
+    #[test]
+    fn failed_changes_leave_the_tokenizer_alone() {
+        let _guard = serial();
+        install(&bert_json());
+        let generation = crate::current_generation();
+        let pad_then = |outcome: c_int| {
+            crate::with_tokenizer_mut(|tokenizer| {
+                tokenizer.with_padding(Some(PaddingParams::default()));
+                assert!(outcome >= 0, "failing after the change");
+                outcome
+            })
+        };
+        assert_eq!(pad_then(-1), crate::ERR_PANICKED);
+        let unpadded = crate::with_tokenizer(|t| t.get_padding().is_none() as c_int);
+        assert_eq!(unpadded, 1);
+        assert_eq!(crate::current_generation(), generation);
+
+        assert_eq!(pad_then(0), 0);
+        let padded = crate::with_tokenizer(|t| t.get_padding().is_some() as c_int);
+        assert_eq!(padded, 1);
+        assert_eq!(crate::current_generation(), generation + 1);
+    }
+
+    fn encode_batch(texts: &[&str], max_len: usize) -> Vec<(Vec<c_int>, Vec<c_int>)> {
+        let c_texts: Vec<CString> = texts.iter().map(|t| CString::new(*t).unwrap()).collect();
+        let ptrs: Vec<_> = c_texts.iter().map(|t| t.as_ptr()).collect();
+        let mut ids = vec![-1; texts.len() * max_len];
+        let mut mask = vec![-1; texts.len() * max_len];
+        let mut lengths = vec![0; texts.len()];
+        let rc = tokenizer_encode_batch_with_mask(
+            ptrs.as_ptr(),
+            ptrs.len(),
+            1,
+            ids.as_mut_ptr(),
+            mask.as_mut_ptr(),
+            lengths.as_mut_ptr(),
+            max_len,
+        );
+        assert_eq!(rc, texts.len() as c_int);
+        (0..texts.len())
+            .map(|i| {
+                let range = i * max_len..i * max_len + lengths[i] as usize;
+                (ids[range.clone()].to_vec(), mask[range].to_vec())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn padding_to_longest_uses_the_pad_id_and_mask() {
+        let _guard = serial();
+        install(&bert_json());
+        assert_eq!(tokenizer_set_padding(-1, 0, DIRECTION_RIGHT), 0);
+
+        let batch = encode_batch(&["hello", "hello world the"], 16);
+        assert_eq!(batch[0].0, vec![2, 5, 3, 0, 0]);
+        assert_eq!(batch[0].1, vec![1, 1, 1, 0, 0]);
+        assert_eq!(batch[1].0, vec![2, 5, 6, 7, 3]);
+        assert_eq!(batch[1].1, vec![1, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn fixed_left_padding_and_multiples() {
+        let _guard = serial();
+        install(&bert_json());
+
+        assert_eq!(tokenizer_set_padding(5, 0, DIRECTION_LEFT), 0);
+        assert_eq!(encode_batch(&["hello"], 16)[0].0, vec![0, 0, 2, 5, 3]);
+
+        assert_eq!(tokenizer_set_padding(-1, 4, DIRECTION_RIGHT), 0);
+        assert_eq!(encode_batch(&["hello"], 16)[0].1, vec![1, 1, 1, 0]);
+
+        assert_eq!(tokenizer_clear_padding(), 0);
+        assert_eq!(encode("hello"), vec![2, 5, 3]);
+    }
+
+    #[test]
+    fn padding_requires_a_pad_token() {
+        let _guard = serial();
+        install(&llama_json());
+        assert_eq!(
+            tokenizer_set_padding(-1, 0, DIRECTION_RIGHT),
+            ERR_NO_PAD_TOKEN
+        );
+        assert_eq!(
+            tokenizer_set_padding(0, 0, DIRECTION_RIGHT),
+            ERR_INVALID_ARGUMENT
+        );
+    }
+}
@@ -0,0 +1,363 @@
+//! Lookup of BOS/EOS/PAD/UNK special tokens from the loaded tokenizer.
+//!
+//! BERT-style tokenizers declare them through `[CLS]`/`[SEP]` post-processors,
+//! Llama-style ones through a template post-processor, and many only through
+//! conventionally named vocabulary entries, so each source is tried in turn.
+
+use serde_json::Value;
+use std::ffi::{c_char, c_int};
+use tokenizers::{ModelWrapper, PaddingDirection, PaddingStrategy, Tokenizer, TruncationDirection};
+
+use crate::chunk::without_limits;
+use crate::{
+    catch_panic, config, copy_ids, fail, guard, text_arg, tokenizer_failed, with_tokenizer,
+    ERR_INVALID_ARGUMENT, ERR_NO_SPECIAL_TOKEN,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SpecialKind {
+    Bos,
+    Eos,
+    Pad,
+    Unk,
+}
+
+impl SpecialKind {
+    fn from_c(kind: c_int) -> Option<Self> {
+        match kind {
+            0 => Some(Self::Bos),
+            1 => Some(Self::Eos),
+            2 => Some(Self::Pad),
+            3 => Some(Self::Unk),
+            _ => None,
+        }
+    }
+
+    /// Conventional spellings, checked in order against the vocabulary
+    fn conventional_names(self) -> &'static [&'static str] {
+        match self {
+            Self::Bos => &[
+                "<s>",
+                "<|begin_of_text|>",
+                "<bos>",
+                "<|startoftext|>",
+                "[CLS]",
+            ],
+            Self::Eos => &["</s>", "<|end_of_text|>", "<|endoftext|>", "<eos>", "[SEP]"],
+            Self::Pad => &["[PAD]", "<pad>", "<|pad|>", "<|padding|>"],
+            Self::Unk => &["<unk>", "[UNK]", "<|unk|>"],
+        }
+    }
+}
+
+/// Find the (content, id) of a special token, or `None` if the tokenizer has none
+/// A BOS/EOS token named by a loaded tokenizer_config.json wins if it is in
+/// the vocabulary.
+pub(crate) fn special_token(tokenizer: &Tokenizer, kind: SpecialKind) -> Option<(String, u32)> {
+    if let Some(token) = config::token_override(kind) {
+        if let Some(id) = tokenizer.token_to_id(&token) {
+            return Some((token, id));
+        }
+    }
+
+    let configured = match kind {
+        SpecialKind::Bos | SpecialKind::Eos => tokenizer
+            .get_post_processor()
+            .and_then(|pp| serde_json::to_value(pp).ok())
+            .and_then(|pp| from_post_processor(&pp, kind)),
+        SpecialKind::Pad => tokenizer
+            .get_padding()
+            .map(|p| (p.pad_token.clone(), p.pad_id)),
+        SpecialKind::Unk => model_unk_token(tokenizer.get_model())
+            .and_then(|unk| tokenizer.token_to_id(&unk).map(|id| (unk, id))),
+    };
+
+    configured.or_else(|| {
+        kind.conventional_names()
+            .iter()
+            .find_map(|name| tokenizer.token_to_id(name).map(|id| (name.to_string(), id)))
+    })
+}
+
+fn model_unk_token(model: &ModelWrapper) -> Option<String> {
+    match model {
+        ModelWrapper::BPE(bpe) => bpe.unk_token.clone(),
+        ModelWrapper::WordPiece(wp) => Some(wp.unk_token.clone()),
+        ModelWrapper::WordLevel(wl) => Some(wl.unk_token.clone()),
+        // Unigram keeps its unk id private; the conventional names cover it
+        ModelWrapper::Unigram(_) => None,
+    }
+}
+
+/// Read BOS (leading) or EOS (trailing) specials from a serialized post-processor
+fn from_post_processor(pp: &Value, kind: SpecialKind) -> Option<(String, u32)> {
+    let pair = |v: &Value| Some((v[0].as_str()?.to_owned(), v[1].as_u64()? as u32));
+
+    match pp["type"].as_str()? {
+        "BertProcessing" | "RobertaProcessing" => match kind {
+            SpecialKind::Bos => pair(&pp["cls"]),
+            _ => pair(&pp["sep"]),
+        },
+        "TemplateProcessing" => {
+            let single = pp["single"].as_array()?;
+            let first_seq = single.iter().position(|p| p.get("Sequence").is_some())?;
+            let piece = match kind {
+                SpecialKind::Bos => single[..first_seq].first(),
+                _ => single[first_seq + 1..].last(),
+            }?;
+            let name = piece["SpecialToken"]["id"].as_str()?;
+            let special = &pp["special_tokens"][name];
+            Some((
+                special["tokens"][0].as_str()?.to_owned(),
+                special["ids"][0].as_u64()? as u32,
+            ))
+        }
+        "Sequence" => pp["processors"]
+            .as_array()?
+            .iter()
+            .find_map(|p| from_post_processor(p, kind)),
+        _ => None,
+    }
+}
+
+fn lookup(kind: SpecialKind) -> c_int {
+    with_tokenizer(|tokenizer| match special_token(tokenizer, kind) {
+        Some((_, id)) => id as c_int,
+        None => -1,
+    })
+}
+
+/// Look up a special token ID: `kind` 0 = BOS, 1 = EOS, 2 = PAD, 3 = UNK
+/// BOS/EOS come from a loaded tokenizer_config.json, else the post-processor
+/// (`[CLS]`/`[SEP]` for BERT, template specials for Llama-style), PAD from the padding config, UNK from the model;
+/// each falls back to conventionally named tokens in the vocabulary.
+/// Returns the ID, -1 when this tokenizer has no such token, or an error:
+///   -3 not initialized, -9 unknown `kind`
+#[no_mangle]
+pub extern "C" fn tokenizer_get_special_token_id(kind: c_int) -> c_int {
+    catch_panic(|| match SpecialKind::from_c(kind) {
+        Some(kind) => lookup(kind),
+        None => fail(
+            ERR_INVALID_ARGUMENT,
+            format!("unknown special token kind {kind} (expected 0..=3)"),
+        ),
+    })
+}
+
+/// BOS ID, as `tokenizer_get_special_token_id(0)`
+#[no_mangle]
+pub extern "C" fn tokenizer_bos_id() -> c_int {
+    catch_panic(|| lookup(SpecialKind::Bos))
+}
+
+/// EOS ID, as `tokenizer_get_special_token_id(1)`
+#[no_mangle]
+pub extern "C" fn tokenizer_eos_id() -> c_int {
+    catch_panic(|| lookup(SpecialKind::Eos))
+}
+
+/// PAD ID, as `tokenizer_get_special_token_id(2)`
+#[no_mangle]
+pub extern "C" fn tokenizer_pad_id() -> c_int {
+    catch_panic(|| lookup(SpecialKind::Pad))
+}
+
+/// UNK ID, as `tokenizer_get_special_token_id(3)`
+#[no_mangle]
+pub extern "C" fn tokenizer_unk_id() -> c_int {
+    catch_panic(|| lookup(SpecialKind::Unk))
+}
+
+/// Encode `text` without the post-processor's special tokens, then put BOS in
+/// front and/or EOS at the end as `add_bos`/`add_eos` ask
+/// BOS and EOS are the tokens `tokenizer_get_special_token_id` reports.
+/// With `add_bos` = 1 and `add_eos` = 0 a Llama tokenizer gives what
+/// llama.cpp produces for the same text; 0/0 suits continuation chunks.
+/// Truncation set with `tokenizer_set_truncation` counts BOS and EOS, so the
+/// text is cut further to leave room for them, and padding set with
+/// `tokenizer_set_padding` is added around the result, never between the text
+/// and EOS. Only the first `max_len` IDs are copied, as with `tokenizer_encode`.
+/// Returns number of tokens copied, negative on error:
+///   -1 null pointer, -2 invalid UTF-8, -3 not initialized, -4 encode failed,
+///   -11 ID out of range, -17 BOS or EOS requested but the tokenizer has none
+#[no_mangle]
+pub extern "C" fn tokenizer_encode_bos_eos(
+    text: *const c_char,
+    add_bos: c_int,
+    add_eos: c_int,
+    out_ids: *mut c_int,
+    max_len: usize,
+) -> c_int {
+    catch_panic(|| {
+        if let Err(code) = guard::out_buffer(
+            "tokenizer_encode_bos_eos",
+            "out_ids",
+            out_ids,
+            max_len,
+            text,
+        ) {
+            return code;
+        }
+        let text_str = match text_arg(text) {
+            Ok(s) => s,
+            Err(code) => return code,
+        };
+
+        with_tokenizer(|tokenizer| {
+            let wanted = |requested: c_int, kind: SpecialKind, name: &str| match requested {
+                0 => Ok(None),
+                _ => match special_token(tokenizer, kind) {
+                    Some((_, id)) => Ok(Some(id)),
+                    None => Err(fail(
+                        ERR_NO_SPECIAL_TOKEN,
+                        format!("{name} requested but the tokenizer has no {name} token"),
+                    )),
+                },
+            };
+            let (bos, eos) = match (
+                wanted(add_bos, SpecialKind::Bos, "BOS"),
+                wanted(add_eos, SpecialKind::Eos, "EOS"),
+            ) {
+                (Ok(bos), Ok(eos)) => (bos, eos),
+                (Err(code), _) | (_, Err(code)) => return code,
+            };
+
+            let body = match without_limits(tokenizer).encode(&*text_str, false) {
+                Ok(encoding) => encoding,
+                Err(e) => return tokenizer_failed("encode", e),
+            };
+            let mut text_ids = body.get_ids();
+            if let Some(truncation) = tokenizer.get_truncation() {
+                let specials = usize::from(bos.is_some()) + usize::from(eos.is_some());
+                let excess = (text_ids.len() + specials).saturating_sub(truncation.max_length);
+                let excess = excess.min(text_ids.len());
+                text_ids = match truncation.direction {
+                    TruncationDirection::Right => &text_ids[..text_ids.len() - excess],
+                    TruncationDirection::Left => &text_ids[excess..],
+                };
+            }
+            let mut ids: Vec<u32> = bos
+                .into_iter()
+                .chain(text_ids.iter().copied())
+                .chain(eos)
+                .collect();
+            if let Some(padding) = tokenizer.get_padding() {
+                let mut length = match padding.strategy {
+                    PaddingStrategy::Fixed(length) => length,
+                    PaddingStrategy::BatchLongest => ids.len(),
+                };
+                if let Some(multiple) = padding.pad_to_multiple_of.filter(|&m| m > 0) {
+                    length = length.div_ceil(multiple) * multiple;
+                }
+                let pads = std::iter::repeat_n(padding.pad_id, length.saturating_sub(ids.len()));
+                match padding.direction {
+                    PaddingDirection::Left => drop(ids.splice(..0, pads)),
+                    PaddingDirection::Right => ids.extend(pads),
+                }
+            }
+            copy_ids(&ids, out_ids, max_len)
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{bert_json, install, llama_json, serial};
+
+    #[test]
+    fn bert_specials_come_from_the_post_processor() {
+        let _guard = serial();
+        install(&bert_json());
+        assert_eq!(tokenizer_bos_id(), 2);
+        assert_eq!(tokenizer_eos_id(), 3);
+        assert_eq!(tokenizer_pad_id(), 0);
+        assert_eq!(tokenizer_unk_id(), 1);
+    }
+
+    #[test]
+    fn llama_specials_and_missing_pad() {
+        let _guard = serial();
+        install(&llama_json());
+        assert_eq!(tokenizer_get_special_token_id(0), 1);
+        // The template only adds BOS, so EOS is found by its conventional name
+        assert_eq!(tokenizer_get_special_token_id(1), 2);
+        assert_eq!(tokenizer_get_special_token_id(2), -1);
+        assert_eq!(tokenizer_get_special_token_id(3), 0);
+        assert_eq!(tokenizer_get_special_token_id(9), ERR_INVALID_ARGUMENT);
+    }
+
+    fn encode_bos_eos(text: &str, add_bos: c_int, add_eos: c_int) -> Result<Vec<c_int>, c_int> {
+        let text = std::ffi::CString::new(text).unwrap();
+        let mut ids = vec![0; 64];
+        let n = tokenizer_encode_bos_eos(text.as_ptr(), add_bos, add_eos, ids.as_mut_ptr(), 64);
+        if n < 0 {
+            return Err(n);
+        }
+        ids.truncate(n as usize);
+        Ok(ids)
+    }
+
+    #[test]
+    fn bos_and_eos_are_added_as_asked() {
+        let _guard = serial();
+        install(&llama_json());
+        // BOS only is what llama.cpp produces, and what the template adds
+        assert_eq!(encode_bos_eos("hello world", 1, 0), Ok(vec![1, 296, 301]));
+        assert_eq!(encode_bos_eos("hello world", 0, 0), Ok(vec![296, 301]));
+        assert_eq!(encode_bos_eos("hello world", 0, 1), Ok(vec![296, 301, 2]));
+        assert_eq!(
+            encode_bos_eos("hello world", 1, 1),
+            Ok(vec![1, 296, 301, 2])
+        );
+
+        // Drop the conventionally named EOS, so there is none to add
+        let mut json: Value = serde_json::from_str(&llama_json()).unwrap();
+        json["added_tokens"]
+            .as_array_mut()
+            .unwrap()
+            .retain(|token| token["content"] != "</s>");
+        json["model"]["vocab"]
+            .as_object_mut()
+            .unwrap()
+            .remove("</s>");
+        install(&json.to_string());
+        assert_eq!(encode_bos_eos("hello", 1, 1), Err(ERR_NO_SPECIAL_TOKEN));
+        assert_eq!(encode_bos_eos("hello", 1, 0), Ok(vec![1, 296]));
+    }
+
+    #[test]
+    fn truncation_leaves_room_for_bos_and_eos() {
+        let _guard = serial();
+        install(&llama_json());
+        assert_eq!(crate::settings::tokenizer_set_truncation(3, 0, 0), 0);
+        assert_eq!(encode_bos_eos("hello world", 1, 1), Ok(vec![1, 296, 2]));
+        assert_eq!(encode_bos_eos("hello world", 1, 0), Ok(vec![1, 296, 301]));
+
+        assert_eq!(crate::settings::tokenizer_set_truncation(3, 1, 0), 0);
+        assert_eq!(encode_bos_eos("hello world", 1, 1), Ok(vec![1, 301, 2]));
+    }
+
+    #[test]
+    fn padding_goes_around_bos_and_eos() {
+        let _guard = serial();
+        install(&bert_json());
+        assert_eq!(crate::settings::tokenizer_set_padding(6, 0, 0), 0);
+        assert_eq!(
+            encode_bos_eos("hello world", 1, 1),
+            Ok(vec![2, 5, 6, 3, 0, 0])
+        );
+        assert_eq!(crate::settings::tokenizer_set_padding(6, 0, 1), 0);
+        assert_eq!(
+            encode_bos_eos("hello world", 1, 1),
+            Ok(vec![0, 0, 2, 5, 6, 3])
+        );
+
+        // Truncation cuts the text, not the padding
+        assert_eq!(crate::settings::tokenizer_set_truncation(3, 0, 0), 0);
+        assert_eq!(
+            encode_bos_eos("hello world", 1, 1),
+            Ok(vec![0, 0, 0, 2, 5, 3])
+        );
+    }
+}
@@ -0,0 +1,582 @@
+//! Loading SentencePiece `tokenizer.model` files.
+//!
+//! The protobuf is decoded by hand (only the handful of fields we need) and
+//! converted to tokenizer.json form the way `transformers`'
+//! convert_slow_tokenizer does: BPE models become a byte-fallback BPE with
+//! merges ranked by piece score (as for Llama), Unigram models keep their score
+//! table and normalizer (as for T5). The GGUF loader reuses these conversions,
+//! since llama.cpp stores the same piece table.
+
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int};
+use std::time::Instant;
+use tokenizers::normalizers::{NormalizerWrapper, Precompiled};
+use tokenizers::Tokenizer;
+
+use crate::{c_str_arg, catch_panic, fail, set_global, ERR_UNSUPPORTED_MODEL};
+
+/// Piece types, numbered the same in SentencePiece and llama.cpp
+pub(crate) const PIECE_NORMAL: i64 = 1;
+pub(crate) const PIECE_UNKNOWN: i64 = 2;
+pub(crate) const PIECE_CONTROL: i64 = 3;
+pub(crate) const PIECE_USER_DEFINED: i64 = 4;
+pub(crate) const PIECE_BYTE: i64 = 6;
+
+/// `TrainerSpec.model_type`
+const MODEL_UNIGRAM: i64 = 1;
+const MODEL_BPE: i64 = 2;
+
+/// Unknown, control and user-defined pieces become added tokens, so they are
+/// matched whole; only user-defined ones are not special
+pub(crate) fn added_tokens(tokens: &[&str], types: &[i64]) -> Vec<Value> {
+    tokens
+        .iter()
+        .zip(types)
+        .enumerate()
+        .filter(|(_, (_, &ty))| matches!(ty, PIECE_UNKNOWN | PIECE_CONTROL | PIECE_USER_DEFINED))
+        .map(|(id, (content, &ty))| {
+            json!({
+                "id": id,
+                "content": content,
+                "single_word": false,
+                "lstrip": false,
+                "rstrip": false,
+                "normalized": false,
+                "special": ty != PIECE_USER_DEFINED
+            })
+        })
+        .collect()
+}
+
+/// SentencePiece BPE merges the adjacent pair whose result scores highest,
+/// which is BPE with merges ranked by the score of the merged piece
+pub(crate) fn bpe_from_scores(
+    tokens: &[&str],
+    types: &[i64],
+    scores: &[f64],
+    unk: Option<usize>,
+    add_space_prefix: bool,
+    byte_fallback: bool,
+) -> Value {
+    let vocab: HashMap<&str, usize> = tokens.iter().enumerate().map(|(i, t)| (*t, i)).collect();
+
+    let mut ranked = Vec::new();
+    for (id, piece) in tokens.iter().enumerate() {
+        if types.get(id) == Some(&PIECE_BYTE) {
+            continue;
+        }
+        for (split, _) in piece.char_indices().skip(1) {
+            let (left, right) = piece.split_at(split);
+            if let (Some(&l), Some(&r)) = (vocab.get(left), vocab.get(right)) {
+                ranked.push((scores.get(id).copied().unwrap_or(0.0), l, r));
+            }
+        }
+    }
+    ranked.sort_by(|a, b| b.0.total_cmp(&a.0).then((a.1, a.2).cmp(&(b.1, b.2))));
+    let merges: Vec<Value> = ranked
+        .iter()
+        .map(|&(_, l, r)| json!([tokens[l], tokens[r]]))
+        .collect();
+
+    let mut normalizers = vec![];
+    if add_space_prefix {
+        normalizers.push(json!({ "type": "Prepend", "prepend": "▁" }));
+    }
+    normalizers.push(json!({ "type": "Replace", "pattern": { "String": " " }, "content": "▁" }));
+    let mut decoders = vec![
+        json!({ "type": "Replace", "pattern": { "String": "▁" }, "content": " " }),
+        json!({ "type": "ByteFallback" }),
+        json!({ "type": "Fuse" }),
+    ];
+    if add_space_prefix {
+        decoders.push(json!({ "type": "Strip", "content": " ", "start": 1, "stop": 0 }));
+    }
+
+    json!({
+        "normalizer": { "type": "Sequence", "normalizers": normalizers },
+        "pre_tokenizer": null,
+        "decoder": { "type": "Sequence", "decoders": decoders },
+        "model": {
+            "type": "BPE",
+            "dropout": null,
+            "unk_token": unk.map(|id| tokens[id]),
+            "continuing_subword_prefix": null,
+            "end_of_word_suffix": null,
+            "fuse_unk": true,
+            "byte_fallback": byte_fallback,
+            "ignore_merges": false,
+            "vocab": vocab_map(tokens),
+            "merges": merges
+        }
+    })
+}
+
+/// Unigram over the score table, splitting on `▁` like SentencePiece
+pub(crate) fn unigram(
+    tokens: &[&str],
+    scores: &[f64],
+    unk: Option<usize>,
+    normalizer: Value,
+    add_dummy_prefix: bool,
+    byte_fallback: bool,
+) -> Value {
+    let vocab: Vec<Value> = tokens
+        .iter()
+        .enumerate()
+        .map(|(i, t)| json!([t, scores.get(i).copied().unwrap_or(0.0)]))
+        .collect();
+    let metaspace = json!({
+        "type": "Metaspace",
+        "replacement": "▁",
+        "prepend_scheme": if add_dummy_prefix { "always" } else { "never" },
+        "split": true
+    });
+
+    json!({
+        "normalizer": normalizer,
+        "pre_tokenizer": metaspace,
+        "decoder": metaspace,
+        "model": {
+            "type": "Unigram",
+            "unk_id": unk,
+            "vocab": vocab,
+            "byte_fallback": byte_fallback
+        }
+    })
+}
+
+pub(crate) fn vocab_map(tokens: &[&str]) -> Map<String, Value> {
+    tokens
+        .iter()
+        .enumerate()
+        .map(|(i, t)| (t.to_string(), json!(i)))
+        .collect()
+}
+
+/// Template post-processor adding `bos`/`eos` (when given) around each sequence
+pub(crate) fn post_processor(tokens: &[&str], bos: Option<usize>, eos: Option<usize>) -> Value {
+    if bos.is_none() && eos.is_none() {
+        return Value::Null;
+    }
+
+    let special = |id: usize, type_id: u32| json!({ "SpecialToken": { "id": tokens[id], "type_id": type_id } });
+    let sequence =
+        |name: &str, type_id: u32| json!({ "Sequence": { "id": name, "type_id": type_id } });
+    let template = |pair: bool| {
+        let mut pieces = Vec::new();
+        for (name, type_id) in [("A", 0), ("B", 1)].into_iter().take(1 + pair as usize) {
+            pieces.extend(bos.map(|id| special(id, type_id)));
+            pieces.push(sequence(name, type_id));
+            pieces.extend(eos.map(|id| special(id, type_id)));
+        }
+        pieces
+    };
+    let special_tokens: Map<String, Value> = bos
+        .into_iter()
+        .chain(eos)
+        .map(|id| {
+            let token = tokens[id];
+            (
+                token.to_owned(),
+                json!({ "id": token, "ids": [id], "tokens": [token] }),
+            )
+        })
+        .collect();
+
+    json!({
+        "type": "TemplateProcessing",
+        "single": template(false),
+        "pair": template(true),
+        "special_tokens": special_tokens
+    })
+}
+
+/// One protobuf field value; fixed64 fields are never needed and only skipped
+enum Field<'a> {
+    Varint(u64),
+    Fixed32(u32),
+    Bytes(&'a [u8]),
+    Skipped,
+}
+
+/// Iterate the (field number, value) pairs of one protobuf message
+fn fields(mut buf: &[u8]) -> impl Iterator<Item = Result<(u64, Field<'_>), String>> {
+    fn varint(buf: &mut &[u8]) -> Result<u64, String> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = buf.split_first().ok_or("truncated varint")?;
+            *buf = rest;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("varint is too long".to_owned())
+    }
+    fn take<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8], String> {
+        if buf.len() < len {
+            return Err("truncated field".to_owned());
+        }
+        let (head, rest) = buf.split_at(len);
+        *buf = rest;
+        Ok(head)
+    }
+
+    std::iter::from_fn(move || {
+        if buf.is_empty() {
+            return None;
+        }
+        let field = (|| {
+            let key = varint(&mut buf)?;
+            let value = match key & 7 {
+                0 => Field::Varint(varint(&mut buf)?),
+                1 => take(&mut buf, 8).map(|_| Field::Skipped)?,
+                2 => {
+                    let len = usize::try_from(varint(&mut buf)?).map_err(|e| e.to_string())?;
+                    Field::Bytes(take(&mut buf, len)?)
+                }
+                5 => {
+                    let bytes = take(&mut buf, 4)?;
+                    Field::Fixed32(u32::from_le_bytes(bytes.try_into().unwrap()))
+                }
+                other => return Err(format!("unsupported wire type {other}")),
+            };
+            Ok((key >> 3, value))
+        })();
+        if field.is_err() {
+            buf = &[];
+        }
+        Some(field)
+    })
+}
+
+/// The parts of a SentencePiece `ModelProto` the conversion needs
+struct SpmModel {
+    pieces: Vec<(String, f64, i64)>,
+    model_type: i64,
+    byte_fallback: bool,
+    unk_id: i64,
+    bos_id: i64,
+    eos_id: i64,
+    precompiled_charsmap: Vec<u8>,
+    add_dummy_prefix: bool,
+    remove_extra_whitespaces: bool,
+}
+
+fn parse_model(buf: &[u8]) -> Result<SpmModel, String> {
+    // Defaults from sentencepiece_model.proto
+    let mut model = SpmModel {
+        pieces: Vec::new(),
+        model_type: MODEL_UNIGRAM,
+        byte_fallback: false,
+        unk_id: 0,
+        bos_id: 1,
+        eos_id: 2,
+        precompiled_charsmap: Vec::new(),
+        add_dummy_prefix: true,
+        remove_extra_whitespaces: true,
+    };
+    // int32 fields are sign-extended varints
+    let int = |v: u64| v as i64 as i32 as i64;
+
+    for field in fields(buf) {
+        match field? {
+            (1, Field::Bytes(piece)) => {
+                let (mut text, mut score, mut ty) = (String::new(), 0.0, PIECE_NORMAL);
+                for field in fields(piece) {
+                    match field? {
+                        (1, Field::Bytes(b)) => {
+                            text = String::from_utf8(b.to_vec())
+                                .map_err(|_| "piece is not UTF-8".to_owned())?
+                        }
+                        (2, Field::Fixed32(bits)) => score = f32::from_bits(bits) as f64,
+                        (3, Field::Varint(v)) => ty = int(v),
+                        _ => {}
+                    }
+                }
+                model.pieces.push((text, score, ty));
+            }
+            (2, Field::Bytes(trainer)) => {
+                for field in fields(trainer) {
+                    match field? {
+                        (3, Field::Varint(v)) => model.model_type = int(v),
+                        (35, Field::Varint(v)) => model.byte_fallback = v != 0,
+                        (40, Field::Varint(v)) => model.unk_id = int(v),
+                        (41, Field::Varint(v)) => model.bos_id = int(v),
+                        (42, Field::Varint(v)) => model.eos_id = int(v),
+                        _ => {}
+                    }
+                }
+            }
+            (3, Field::Bytes(normalizer)) => {
+                for field in fields(normalizer) {
+                    match field? {
+                        (2, Field::Bytes(b)) => model.precompiled_charsmap = b.to_vec(),
+                        (3, Field::Varint(v)) => model.add_dummy_prefix = v != 0,
+                        (4, Field::Varint(v)) => model.remove_extra_whitespaces = v != 0,
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if model.pieces.is_empty() {
+        return Err("no pieces; not a SentencePiece model".to_owned());
+    }
+    Ok(model)
+}
+
+/// Convert a parsed model to tokenizer.json; `Err(None)` means an unsupported model type
+fn spm_json(model: &SpmModel) -> Result<Value, Option<String>> {
+    let tokens: Vec<&str> = model.pieces.iter().map(|p| p.0.as_str()).collect();
+    let scores: Vec<f64> = model.pieces.iter().map(|p| p.1).collect();
+    let types: Vec<i64> = model.pieces.iter().map(|p| p.2).collect();
+    let id = |id: i64| usize::try_from(id).ok().filter(|&id| id < tokens.len());
+    let unk = id(model.unk_id);
+    let byte_fallback = model.byte_fallback || types.contains(&PIECE_BYTE);
+
+    let (mut json, bos, eos) = match model.model_type {
+        MODEL_BPE => (
+            bpe_from_scores(
+                &tokens,
+                &types,
+                &scores,
+                unk,
+                model.add_dummy_prefix,
+                byte_fallback,
+            ),
+            id(model.bos_id),
+            None,
+        ),
+        MODEL_UNIGRAM => {
+            let mut normalizers = Vec::new();
+            if !model.precompiled_charsmap.is_empty() {
+                let precompiled = Precompiled::from(&model.precompiled_charsmap)
+                    .map_err(|e| Some(format!("invalid precompiled_charsmap: {e}")))?;
+                let precompiled = serde_json::to_value(NormalizerWrapper::from(precompiled))
+                    .map_err(|e| Some(e.to_string()))?;
+                normalizers.push(precompiled);
+            }
+            if model.remove_extra_whitespaces {
+                normalizers.push(json!({
+                    "type": "Replace", "pattern": { "Regex": " {2,}" }, "content": " "
+                }));
+            }
+            let normalizer = json!({ "type": "Sequence", "normalizers": normalizers });
+            (
+                unigram(
+                    &tokens,
+                    &scores,
+                    unk,
+                    normalizer,
+                    model.add_dummy_prefix,
+                    byte_fallback,
+                ),
+                None,
+                id(model.eos_id),
+            )
+        }
+        _ => return Err(None),
+    };
+
+    json["version"] = json!("1.0");
+    json["truncation"] = Value::Null;
+    json["padding"] = Value::Null;
+    json["added_tokens"] = json!(added_tokens(&tokens, &types));
+    json["post_processor"] = post_processor(&tokens, bos, eos);
+    Ok(json)
+}
+
+/// Load a SentencePiece model file
+/// Errors: -1 null path, -2 invalid UTF-8, -3 unreadable or malformed,
+/// -14 unsupported model type
+pub(crate) fn load_from_spm(path: *const c_char) -> Result<Tokenizer, c_int> {
+    let path = c_str_arg(path)?;
+    let load_failed = |e: String| fail(-3, format!("failed to load SentencePiece '{path}': {e}"));
+
+    let bytes = std::fs::read(path).map_err(|e| load_failed(e.to_string()))?;
+    let model = parse_model(&bytes).map_err(load_failed)?;
+    let json = spm_json(&model).map_err(|e| match e {
+        Some(e) => load_failed(e),
+        None => fail(
+            ERR_UNSUPPORTED_MODEL,
+            format!(
+                "SentencePiece model type {} is not supported (only Unigram and BPE)",
+                model.model_type
+            ),
+        ),
+    })?;
+    json.to_string()
+        .parse::<Tokenizer>()
+        .map_err(|e| load_failed(e.to_string()))
+}
+
+/// Initialize the global tokenizer from a SentencePiece `tokenizer.model`
+/// BPE models (Llama, Mistral) get BOS added and byte fallback for unknown
+/// characters; Unigram models (T5 and friends) keep their normalizer and get
+/// EOS appended, as `transformers` converts them. Reinitialization and
+/// `tokenizer_free` behave as with `tokenizer_initialize`.
+/// Returns 0 on success, negative on error:
+///   -1 null path, -2 invalid UTF-8, -3 unreadable or not a SentencePiece model,
+///   -14 unsupported model type (word or char models)
+#[no_mangle]
+pub extern "C" fn tokenizer_initialize_from_spm(path: *const c_char) -> c_int {
+    catch_panic(|| {
+        let started = Instant::now();
+        match load_from_spm(path) {
+            Ok(tokenizer) => set_global(tokenizer, c_str_arg(path).unwrap_or_default(), started),
+            Err(code) => code,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{install, llama_json, serial, write_temp};
+    use std::ffi::CString;
+
+    fn varint(buf: &mut Vec<u8>, mut v: u64) {
+        while v >= 0x80 {
+            buf.push(v as u8 | 0x80);
+            v >>= 7;
+        }
+        buf.push(v as u8);
+    }
+
+    fn bytes_field(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+        varint(buf, field << 3 | 2);
+        varint(buf, bytes.len() as u64);
+        buf.extend(bytes);
+    }
+
+    fn varint_field(buf: &mut Vec<u8>, field: u64, v: i64) {
+        varint(buf, field << 3);
+        varint(buf, v as u64);
+    }
+
+    /// Serialize a `ModelProto` with the given pieces and spec fields
+    fn model_proto(
+        pieces: &[(&str, f32, i64)],
+        trainer: &[(u64, i64)],
+        normalizer: &[(u64, i64)],
+    ) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for &(text, score, ty) in pieces {
+            let mut piece = Vec::new();
+            bytes_field(&mut piece, 1, text.as_bytes());
+            varint(&mut piece, 2 << 3 | 5);
+            piece.extend(score.to_le_bytes());
+            varint_field(&mut piece, 3, ty);
+            bytes_field(&mut buf, 1, &piece);
+        }
+        for (field, spec) in [(2, trainer), (3, normalizer)] {
+            let mut msg = Vec::new();
+            for &(f, v) in spec {
+                varint_field(&mut msg, f, v);
+            }
+            bytes_field(&mut buf, field, &msg);
+        }
+        buf
+    }
+
+    fn load(proto: &[u8], name: &str) -> c_int {
+        let path = write_temp(name, "");
+        std::fs::write(&path, proto).unwrap();
+        let path = CString::new(path.to_str().unwrap()).unwrap();
+        tokenizer_initialize_from_spm(path.as_ptr())
+    }
+
+    fn encode(text: &str) -> Vec<c_int> {
+        let text = CString::new(text).unwrap();
+        let mut ids = vec![0; 128];
+        let n = crate::tokenizer_encode(text.as_ptr(), ids.as_mut_ptr(), ids.len());
+        assert!(n >= 0, "encode failed with {n}");
+        ids.truncate(n as usize);
+        ids
+    }
+
+    #[test]
+    fn bpe_model_matches_the_llama_fixture_on_accents_and_emoji() {
+        let _guard = serial();
+        let fixture: Value = serde_json::from_str(&llama_json()).unwrap();
+        let vocab = fixture["model"]["vocab"].as_object().unwrap();
+        let mut tokens = vec![String::new(); vocab.len()];
+        for (piece, id) in vocab {
+            tokens[id.as_u64().unwrap() as usize] = piece.clone();
+        }
+        let merged: Vec<String> = fixture["model"]["merges"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| m.as_str().unwrap().replace(' ', ""))
+            .collect();
+        let pieces: Vec<(&str, f32, i64)> = tokens
+            .iter()
+            .enumerate()
+            .map(|(id, t)| {
+                let ty = match id {
+                    0 => PIECE_UNKNOWN,
+                    1 | 2 => PIECE_CONTROL,
+                    3..=258 => PIECE_BYTE,
+                    _ => PIECE_NORMAL,
+                };
+                let score = merged
+                    .iter()
+                    .position(|m| m == t)
+                    .map_or(-1000.0, |r| -(r as f32));
+                (t.as_str(), score, ty)
+            })
+            .collect();
+        let proto = model_proto(&pieces, &[(3, MODEL_BPE), (35, 1)], &[(3, 1), (4, 0)]);
+
+        let corpus = [
+            "hello world",
+            "Zażółć gęślą jaźń",
+            "hello 🚀 world!",
+            " world",
+        ];
+        install(&llama_json());
+        let expected: Vec<_> = corpus.iter().map(|t| encode(t)).collect();
+        assert_eq!(load(&proto, "llama_spm"), 0);
+        let got: Vec<_> = corpus.iter().map(|t| encode(t)).collect();
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn unigram_model_uses_scores_and_appends_eos() {
+        let _guard = serial();
+        let pieces = [
+            ("<pad>", 0.0, PIECE_CONTROL),
+            ("</s>", 0.0, PIECE_CONTROL),
+            ("<unk>", 0.0, PIECE_UNKNOWN),
+            ("▁hello", -1.0, PIECE_NORMAL),
+            ("▁world", -1.0, PIECE_NORMAL),
+            ("▁", -3.0, PIECE_NORMAL),
+            ("hel", -2.0, PIECE_NORMAL),
+            ("lo", -2.0, PIECE_NORMAL),
+            ("ó", -4.0, PIECE_NORMAL),
+        ];
+        let proto = model_proto(
+            &pieces,
+            &[(3, MODEL_UNIGRAM), (40, 2), (41, -1), (42, 1)],
+            &[],
+        );
+        assert_eq!(load(&proto, "t5_spm"), 0);
+
+        assert_eq!(encode("hello world"), [3, 4, 1]);
+        // Extra whitespace is collapsed and unknown characters map to <unk>
+        assert_eq!(encode("hello   ó x"), [3, 5, 8, 5, 2, 1]);
+    }
+
+    #[test]
+    fn word_models_report_the_unsupported_code() {
+        let _guard = serial();
+        let proto = model_proto(&[("<unk>", 0.0, PIECE_UNKNOWN)], &[(3, 3)], &[]);
+        assert_eq!(load(&proto, "word_spm"), ERR_UNSUPPORTED_MODEL);
+        assert_eq!(load(b"\xff\xff garbage", "garbage_spm"), -3);
+    }
+}
@@ -0,0 +1,625 @@
+//! Incremental decoding of generated tokens, one ID at a time.
+//!
+//! Decoding tokens one by one mangles characters split across tokens (emoji,
+//! Polish diacritics) and loses the prefix-space handling of the decoder. A
+//! stream keeps a small window of recent IDs, decodes them to raw bytes (so
+//! byte-fallback and byte-level pieces of one character are never lost), and
+//! only releases complete UTF-8 characters, carrying incomplete bytes over to
+//! the next push. The concatenation of every fragment equals a one-shot
+//! `tokenizer_decode` of the whole sequence.
+//!
+//! Streams are independent: each has its own lock, so parallel generations do
+//! not contend with each other. They decode with the global tokenizer that is
+//! loaded at the time of each push.
+//!
+//! A stream can also watch for stop sequences such as "\nUser:" or
+//! "<|im_end|>". Text that might be the start of a stop sequence is held back
+//! until it either completes the sequence (generation should stop, and the
+//! stop text is never delivered) or stops matching (it is released).
+
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use tokenizers::Tokenizer;
+
+use crate::bytes::decode_bytes;
+use crate::{
+    c_str_arg, catch_panic, fail, null_output, tokenizer_failed, with_tokenizer, write_c_str,
+    ERR_INVALID_ARGUMENT, ERR_INVALID_HANDLE,
+};
+
+#[derive(Default)]
+struct StreamState {
+    skip_special_tokens: bool,
+    /// Window of IDs still needed to decode the next fragment correctly
+    ids: Vec<u32>,
+    /// Bytes already produced from `ids`, before the latest push
+    prefix: Vec<u8>,
+    prefix_index: usize,
+    /// Leading bytes of a character whose remaining bytes are still to come
+    partial: Vec<u8>,
+    /// Text produced but not yet delivered because the caller's buffer was too small
+    pending: String,
+    stop_sequences: Vec<String>,
+    /// Text that could still turn out to be the start of a stop sequence
+    held: String,
+    /// Index of the matched stop sequence and how many bytes were dropped for it
+    stopped: Option<(usize, usize)>,
+}
+
+impl StreamState {
+    /// Push `id` and return the bytes it adds, sliding the window of IDs so
+    /// the decoder keeps one token of context (for prefix spaces and joins)
+    fn step(&mut self, tokenizer: &Tokenizer, id: u32) -> tokenizers::Result<Option<Vec<u8>>> {
+        self.ids.push(id);
+        let bytes = decode_bytes(tokenizer, &self.ids, self.skip_special_tokens)?;
+        if bytes.len() <= self.prefix.len() {
+            return Ok(None);
+        }
+        let Some(new) = bytes.strip_prefix(self.prefix.as_slice()) else {
+            return Err("decoding more tokens changed earlier output".into());
+        };
+        let new = new.to_vec();
+
+        let window = self.ids.len() - self.prefix_index;
+        self.ids.drain(..self.prefix_index);
+        self.prefix = decode_bytes(tokenizer, &self.ids, self.skip_special_tokens)?;
+        self.prefix_index = window;
+        Ok(Some(new))
+    }
+
+    /// Turn `bytes` into text, holding back a trailing incomplete character
+    /// Invalid sequences become U+FFFD, as in `tokenizer_decode`.
+    fn complete_text(&mut self, bytes: &[u8]) -> String {
+        self.partial.extend_from_slice(bytes);
+        let mut text = String::new();
+        let mut rest = self.partial.as_slice();
+        while !rest.is_empty() {
+            match std::str::from_utf8(rest) {
+                Ok(valid) => {
+                    text.push_str(valid);
+                    rest = &[];
+                }
+                Err(e) => {
+                    let (valid, invalid) = rest.split_at(e.valid_up_to());
+                    text.push_str(std::str::from_utf8(valid).unwrap());
+                    match e.error_len() {
+                        Some(len) => {
+                            text.push('\u{FFFD}');
+                            rest = &invalid[len..];
+                        }
+                        // Could still be completed by the next token
+                        None => break,
+                    }
+                }
+            }
+        }
+        let done = self.partial.len() - rest.len();
+        self.partial.drain(..done);
+        text
+    }
+
+    /// Route freshly decoded text through stop-sequence detection into `pending`
+    fn accept(&mut self, text: &str) {
+        if self.stopped.is_some() {
+            return;
+        }
+
+        let mut buf = std::mem::take(&mut self.held);
+        buf.push_str(text);
+
+        // Earliest match wins; ties go to the lowest sequence index
+        let hit = self
+            .stop_sequences
+            .iter()
+            .enumerate()
+            .filter_map(|(i, seq)| buf.find(seq.as_str()).map(|pos| (pos, i)))
+            .min();
+        if let Some((pos, index)) = hit {
+            self.pending.push_str(&buf[..pos]);
+            self.stopped = Some((index, buf.len() - pos));
+            return;
+        }
+
+        let keep = self.partial_stop_len(&buf);
+        self.pending.push_str(&buf[..buf.len() - keep]);
+        self.held = buf[buf.len() - keep..].to_owned();
+    }
+
+    /// Length of the longest suffix of `text` that is a proper prefix of a stop sequence
+    fn partial_stop_len(&self, text: &str) -> usize {
+        self.stop_sequences
+            .iter()
+            .flat_map(|seq| {
+                (1..seq.len().min(text.len() + 1))
+                    .rev()
+                    .filter(|&n| seq.is_char_boundary(n) && text.ends_with(&seq[..n]))
+                    .take(1)
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Release held-back text, used when no further tokens can complete a match
+    fn release_held(&mut self) {
+        let held = std::mem::take(&mut self.held);
+        self.pending.push_str(&held);
+    }
+}
+
+type Streams = Mutex<HashMap<i64, Arc<Mutex<StreamState>>>>;
+
+static NEXT_STREAM: AtomicI64 = AtomicI64::new(1);
+
+fn streams() -> &'static Streams {
+    static STREAMS: OnceLock<Streams> = OnceLock::new();
+    STREAMS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn lookup(stream: i64) -> Result<Arc<Mutex<StreamState>>, c_int> {
+    let streams = streams().lock().unwrap_or_else(PoisonError::into_inner);
+    streams.get(&stream).cloned().ok_or_else(|| {
+        fail(
+            ERR_INVALID_HANDLE,
+            format!("stream {stream} is unknown or already freed"),
+        )
+    })
+}
+
+/// Deliver `state.pending` through `write_c_str`, keeping it if it did not fit
+fn deliver(state: &mut StreamState, out_text: *mut c_char, capacity: usize) -> c_int {
+    let written = write_c_str(&state.pending, out_text, capacity);
+    if written >= 0 && !out_text.is_null() && capacity > 0 {
+        state.pending.clear();
+    }
+    written
+}
+
+/// Start a stream that skips special tokens (BOS/EOS are not shown)
+/// Returns a positive stream handle
+#[no_mangle]
+pub extern "C" fn tokenizer_stream_create() -> i64 {
+    catch_panic(|| tokenizer_stream_create_ex(1))
+}
+
+/// `tokenizer_stream_create` with `skip_special_tokens` chosen by the caller
+#[no_mangle]
+pub extern "C" fn tokenizer_stream_create_ex(skip_special_tokens: c_int) -> i64 {
+    catch_panic(|| {
+        let state = StreamState {
+            skip_special_tokens: skip_special_tokens != 0,
+            ..Default::default()
+        };
+
+        let stream = NEXT_STREAM.fetch_add(1, Ordering::Relaxed);
+        streams()
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(stream, Arc::new(Mutex::new(state)));
+        stream
+    })
+}
+
+/// Feed one generated token and receive the text it completes, if any
+/// Writes the newly printable text (possibly empty while a multi-byte
+/// character is still incomplete) into `out_text` as NUL-terminated UTF-8 and
+/// returns its length. If `out_text` is null or too small the text is kept
+/// queued: the call returns the required size (null) or -6 (too small), and
+/// the text is delivered by the next push or `tokenizer_stream_flush`.
+/// Returns negative on error:
+///   -3 not initialized, -4 decode failed, -6 buffer too
+///   small, -7 unknown stream, -9 negative `token_id`
+///
+/// Once a stop sequence has matched, further pushes decode nothing and
+/// deliver only what is still queued; see `tokenizer_stream_push_ex` and
+/// `tokenizer_stream_stop_index`.
+#[no_mangle]
+pub extern "C" fn tokenizer_stream_push(
+    stream: i64,
+    token_id: c_int,
+    out_text: *mut c_char,
+    capacity: usize,
+) -> c_int {
+    catch_panic(|| {
+        tokenizer_stream_push_ex(
+            stream,
+            token_id,
+            out_text,
+            capacity,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    })
+}
+
+/// `tokenizer_stream_push` that also reports stop-sequence matches
+/// `out_stop_index` (optional) receives the index of the matched stop sequence
+/// or -1. `out_trimmed` (optional) receives how many bytes were cut off at the
+/// match: the stop sequence plus anything decoded after it. Because possible
+/// stop prefixes are held back, delivered text never contains any part of a
+/// stop sequence and never needs trimming on the caller's side.
+#[no_mangle]
+pub extern "C" fn tokenizer_stream_push_ex(
+    stream: i64,
+    token_id: c_int,
+    out_text: *mut c_char,
+    capacity: usize,
+    out_stop_index: *mut c_int,
+    out_trimmed: *mut c_int,
+) -> c_int {
+    catch_panic(|| {
+        let id = match u32::try_from(token_id) {
+            Ok(id) => id,
+            Err(_) => {
+                return fail(
+                    ERR_INVALID_ARGUMENT,
+                    format!("token ID {token_id} is negative"),
+                )
+            }
+        };
+        let state = match lookup(stream) {
+            Ok(s) => s,
+            Err(code) => return code,
+        };
+        let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let produced = with_tokenizer(|tokenizer| {
+            let state = &mut *state;
+            if state.stopped.is_some() {
+                return 0;
+            }
+            match state.step(tokenizer, id) {
+                Ok(Some(bytes)) => {
+                    let text = state.complete_text(&bytes);
+                    state.accept(&text);
+                    0
+                }
+                Ok(None) => 0,
+                Err(e) => tokenizer_failed("stream decode", e),
+            }
+        });
+        if produced < 0 {
+            return produced;
+        }
+
+        let (stop_index, trimmed) = match state.stopped {
+            Some((index, trimmed)) => (index as c_int, trimmed as c_int),
+            None => (-1, 0),
+        };
+        unsafe {
+            if !out_stop_index.is_null() {
+                *out_stop_index = stop_index;
+            }
+            if !out_trimmed.is_null() {
+                *out_trimmed = trimmed;
+            }
+        }
+
+        deliver(&mut state, out_text, capacity)
+    })
+}
+
+/// Watch the stream for `count` stop sequences (replacing any previous set)
+/// Passing `count` 0 removes them. Text held back as a possible stop prefix
+/// under the old set is released.
+/// Returns 0 on success, negative on error:
+///   -1 null `sequences` or entry, -2 invalid UTF-8,
+///   -7 unknown stream, -9 empty stop sequence
+#[no_mangle]
+pub extern "C" fn tokenizer_stream_set_stop_sequences(
+    stream: i64,
+    sequences: *const *const c_char,
+    count: usize,
+) -> c_int {
+    catch_panic(|| {
+        if count > 0 && sequences.is_null() {
+            return null_output("sequences");
+        }
+
+        let mut stops = Vec::with_capacity(count);
+        if count > 0 {
+            for (i, &seq) in unsafe { std::slice::from_raw_parts(sequences, count) }
+                .iter()
+                .enumerate()
+            {
+                match c_str_arg(seq) {
+                    Ok("") => {
+                        return fail(ERR_INVALID_ARGUMENT, format!("stop sequence {i} is empty"))
+                    }
+                    Ok(seq) => stops.push(seq.to_owned()),
+                    Err(code) => return code,
+                }
+            }
+        }
+
+        let state = match lookup(stream) {
+            Ok(s) => s,
+            Err(code) => return code,
+        };
+        let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        state.release_held();
+        state.stop_sequences = stops;
+        0
+    })
+}
+
+/// Index of the stop sequence that ended the stream, or -1 if none matched yet
+/// Returns -7 unknown stream
+#[no_mangle]
+pub extern "C" fn tokenizer_stream_stop_index(stream: i64) -> c_int {
+    catch_panic(|| {
+        let state = match lookup(stream) {
+            Ok(s) => s,
+            Err(code) => return code,
+        };
+        let state = state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        state.stopped.map_or(-1, |(index, _)| index as c_int)
+    })
+}
+
+/// Deliver everything still held by the stream, including an incomplete
+/// trailing character (rendered with U+FFFD) and a never-completed stop
+/// prefix, and reset it for reuse (stop sequences are kept)
+/// Same buffer contract and errors as `tokenizer_stream_push`
+#[no_mangle]
+pub extern "C" fn tokenizer_stream_flush(
+    stream: i64,
+    out_text: *mut c_char,
+    capacity: usize,
+) -> c_int {
+    catch_panic(|| {
+        let state = match lookup(stream) {
+            Ok(s) => s,
+            Err(code) => return code,
+        };
+        let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if !state.ids.is_empty() && state.stopped.is_none() {
+            let rest = with_tokenizer(|tokenizer| {
+                match decode_bytes(tokenizer, &state.ids, state.skip_special_tokens) {
+                    Ok(bytes) => {
+                        let tail = bytes
+                            .strip_prefix(state.prefix.as_slice())
+                            .unwrap_or(&bytes);
+                        state.partial.extend_from_slice(tail);
+                        0
+                    }
+                    Err(e) => tokenizer_failed("stream decode", e),
+                }
+            });
+            if rest < 0 {
+                return rest;
+            }
+        }
+        if state.stopped.is_none() && !state.partial.is_empty() {
+            let partial = std::mem::take(&mut state.partial);
+            state.accept(&String::from_utf8_lossy(&partial));
+        }
+        if state.stopped.is_none() {
+            state.release_held();
+        }
+        state.ids.clear();
+        state.prefix.clear();
+        state.prefix_index = 0;
+        state.partial.clear();
+        state.stopped = None;
+
+        deliver(&mut state, out_text, capacity)
+    })
+}
+
+/// Release a stream
+/// Returns 0 on success, -7 unknown or already freed
+#[no_mangle]
+pub extern "C" fn tokenizer_stream_free(stream: i64) -> c_int {
+    catch_panic(|| {
+        let mut streams = streams().lock().unwrap_or_else(PoisonError::into_inner);
+        match streams.remove(&stream) {
+            Some(_) => 0,
+            None => fail(
+                ERR_INVALID_HANDLE,
+                format!("stream {stream} is unknown or already freed"),
+            ),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{bert_json, byte_level_json, install, llama_json, serial};
+    use crate::{tokenizer_decode, ERR_BUFFER_TOO_SMALL};
+    use std::ffi::{CStr, CString};
+
+    fn encode(text: &str) -> Vec<c_int> {
+        let text = CString::new(text).unwrap();
+        let mut ids = vec![0; 256];
+        let n = crate::tokenizer_encode(text.as_ptr(), ids.as_mut_ptr(), ids.len());
+        ids.truncate(n as usize);
+        ids
+    }
+
+    fn read(buf: &[u8]) -> String {
+        CStr::from_bytes_until_nul(buf)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    fn stream_all(ids: &[c_int]) -> (Vec<String>, String) {
+        let stream = tokenizer_stream_create();
+        let mut buf = [0u8; 64];
+        let mut fragments = Vec::new();
+        for &id in ids {
+            let n = tokenizer_stream_push(stream, id, buf.as_mut_ptr() as *mut c_char, buf.len());
+            assert!(n >= 0, "push failed with {n}");
+            fragments.push(read(&buf[..=n as usize]));
+        }
+        let n = tokenizer_stream_flush(stream, buf.as_mut_ptr() as *mut c_char, buf.len());
+        assert!(n >= 0);
+        fragments.push(read(&buf[..=n as usize]));
+        assert_eq!(tokenizer_stream_free(stream), 0);
+        let joined = fragments.concat();
+        (fragments, joined)
+    }
+
+    fn one_shot(ids: &[c_int]) -> String {
+        let mut buf = [0u8; 256];
+        let n = tokenizer_decode(
+            ids.as_ptr(),
+            ids.len(),
+            buf.as_mut_ptr() as *mut c_char,
+            buf.len(),
+        );
+        read(&buf[..=n as usize])
+    }
+
+    #[test]
+    fn split_characters_are_held_back_until_complete() {
+        let _guard = serial();
+        install(&llama_json());
+
+        let ids = encode("hello zażółć 🚀 world");
+        let (fragments, joined) = stream_all(&ids);
+        assert_eq!(joined, one_shot(&ids));
+        assert!(fragments.iter().all(|f| !f.contains('\u{FFFD}')));
+        assert!(fragments.contains(&"🚀".to_string()));
+    }
+
+    #[test]
+    fn byte_tokens_stream_byte_identical_to_one_shot() {
+        let _guard = serial();
+        for json in [llama_json(), byte_level_json()] {
+            install(&json);
+            let ids = encode("zażółć gęślą jaźń 🚀");
+            let (fragments, joined) = stream_all(&ids);
+            assert_eq!(joined.as_bytes(), one_shot(&ids).as_bytes());
+            assert_eq!(joined, "zażółć gęślą jaźń 🚀");
+            assert!(fragments.iter().all(|f| !f.contains('\u{FFFD}')));
+        }
+    }
+
+    #[test]
+    fn wordpiece_prefix_spacing_survives_streaming() {
+        let _guard = serial();
+        install(&bert_json());
+
+        let ids = encode("hello tokenizers world");
+        assert_eq!(stream_all(&ids).1, one_shot(&ids));
+    }
+
+    #[test]
+    fn undelivered_text_is_queued_not_lost() {
+        let _guard = serial();
+        install(&llama_json());
+
+        let ids = encode("hello world");
+        let stream = tokenizer_stream_create();
+        let mut tiny = [0u8; 2];
+        let mut buf = [0u8; 32];
+        assert_eq!(
+            tokenizer_stream_push(stream, ids[0], buf.as_mut_ptr() as *mut c_char, 32),
+            0
+        );
+        let rc = tokenizer_stream_push(stream, ids[1], tiny.as_mut_ptr() as *mut c_char, 2);
+        assert_eq!(rc, ERR_BUFFER_TOO_SMALL);
+        let n = tokenizer_stream_push(stream, ids[2], buf.as_mut_ptr() as *mut c_char, 32);
+        assert_eq!(read(&buf[..=n as usize]), "hello world");
+
+        assert_eq!(tokenizer_stream_free(stream), 0);
+        assert_eq!(tokenizer_stream_free(stream), ERR_INVALID_HANDLE);
+    }
+
+    #[test]
+    fn streams_run_concurrently() {
+        let _guard = serial();
+        install(&llama_json());
+
+        let ids = encode("zażółć gęślą jaźń 🚀");
+        let expected = one_shot(&ids);
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let ids = ids.clone();
+                std::thread::spawn(move || stream_all(&ids).1)
+            })
+            .collect();
+        for worker in workers {
+            assert_eq!(worker.join().unwrap(), expected);
+        }
+    }
+
+    fn set_stops(stream: i64, stops: &[&str]) {
+        let c_stops: Vec<CString> = stops.iter().map(|s| CString::new(*s).unwrap()).collect();
+        let ptrs: Vec<_> = c_stops.iter().map(|s| s.as_ptr()).collect();
+        assert_eq!(
+            tokenizer_stream_set_stop_sequences(stream, ptrs.as_ptr(), ptrs.len()),
+            0
+        );
+    }
+
+    /// Push every ID, returning the delivered text and the first stop report
+    fn push_until_stop(stream: i64, ids: &[c_int]) -> (String, c_int, c_int) {
+        let mut buf = [0u8; 64];
+        let mut text = String::new();
+        for &id in ids {
+            let (mut index, mut trimmed) = (0, 0);
+            let n = tokenizer_stream_push_ex(
+                stream,
+                id,
+                buf.as_mut_ptr() as *mut c_char,
+                buf.len(),
+                &mut index,
+                &mut trimmed,
+            );
+            assert!(n >= 0);
+            text.push_str(&read(&buf[..=n as usize]));
+            if index >= 0 {
+                return (text, index, trimmed);
+            }
+        }
+        (text, -1, 0)
+    }
+
+    #[test]
+    fn stop_sequences_split_across_many_tokens_are_caught() {
+        let _guard = serial();
+        install(&llama_json());
+
+        // Every letter is its own token, so "abcd" straddles four pushes
+        let ids = encode("xyzabcdef");
+        let stream = tokenizer_stream_create();
+        set_stops(stream, &["nope", "abcd"]);
+        let (text, index, trimmed) = push_until_stop(stream, &ids);
+        assert_eq!((text.as_str(), index, trimmed), ("xyz", 1, 4));
+        assert_eq!(tokenizer_stream_stop_index(stream), 1);
+
+        // Later tokens are ignored until the stream is flushed
+        let mut buf = [0u8; 16];
+        let n = tokenizer_stream_push(stream, ids[1], buf.as_mut_ptr() as *mut c_char, 16);
+        assert_eq!(n, 0);
+        tokenizer_stream_free(stream);
+    }
+
+    #[test]
+    fn unfinished_stop_prefixes_are_released() {
+        let _guard = serial();
+        install(&llama_json());
+
+        let stream = tokenizer_stream_create();
+        set_stops(stream, &["abc"]);
+        let (text, index, _) = push_until_stop(stream, &encode("xabxab"));
+        // The first "ab" is released once "x" rules the match out
+        assert_eq!((text.as_str(), index), ("xabx", -1));
+
+        // The trailing "ab" is still held and comes out on flush
+        let mut buf = [0u8; 16];
+        let n = tokenizer_stream_flush(stream, buf.as_mut_ptr() as *mut c_char, 16);
+        assert_eq!(read(&buf[..=n as usize]), "ab");
+        assert_eq!(tokenizer_stream_stop_index(stream), -1);
+        tokenizer_stream_free(stream);
+    }
+}
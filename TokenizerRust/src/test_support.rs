@@ -0,0 +1,217 @@
+//! Tiny in-memory tokenizers and helpers shared by the unit tests.
+
+use serde_json::{json, Map, Value};
+use std::ffi::CString;
+use std::sync::{Mutex, MutexGuard};
+
+/// The FFI keeps a process-wide tokenizer, so tests touching it run one at a time.
+pub fn serial() -> MutexGuard<'static, ()> {
+    static LOCK: Mutex<()> = Mutex::new(());
+    LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Write `json` to a temp file and load it through `tokenizer_initialize`.
+pub fn install(json: &str) {
+    let path = write_temp("install", json);
+    let c_path = CString::new(path.to_str().unwrap()).unwrap();
+    assert_eq!(crate::tokenizer_initialize(c_path.as_ptr()), 0);
+}
+
+pub fn write_temp(name: &str, contents: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "hf_tokenizer_test_{}_{:?}_{}.json",
+        std::process::id(),
+        std::thread::current().id(),
+        name
+    ));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+fn special(id: u32, content: &str) -> Value {
+    json!({
+        "id": id,
+        "content": content,
+        "single_word": false,
+        "lstrip": false,
+        "rstrip": false,
+        "normalized": false,
+        "special": true
+    })
+}
+
+/// Llama-2 style: byte-fallback BPE, `▁` whitespace, BOS via post-processor.
+///
+/// IDs: `<unk>`=0, `<s>`=1, `</s>`=2, `<0x00>`..`<0xFF>`=3..258, then pieces.
+pub fn llama_json() -> String {
+    let mut vocab = Map::new();
+    for (i, piece) in ["<unk>", "<s>", "</s>"].iter().enumerate() {
+        vocab.insert(piece.to_string(), json!(i));
+    }
+    for b in 0..=255u32 {
+        vocab.insert(format!("<0x{b:02X}>"), json!(3 + b));
+    }
+    let pieces = [
+        "▁", "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "o", "p", "q",
+        "r", "s", "t", "u", "v", "w", "x", "y", "z", "H", "W", ",", ".", "!", "?", "▁h", "el",
+        "lo", "▁hel", "▁hello", "▁w", "or", "▁wor", "ld", "▁world",
+    ];
+    for piece in pieces {
+        let id = vocab.len();
+        vocab.insert(piece.to_string(), json!(id));
+    }
+    let merges = [
+        "▁ h",
+        "e l",
+        "l o",
+        "▁h el",
+        "▁hel lo",
+        "▁ w",
+        "o r",
+        "▁w or",
+        "l d",
+        "▁wor ld",
+    ];
+
+    json!({
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": [special(0, "<unk>"), special(1, "<s>"), special(2, "</s>")],
+        "normalizer": {
+            "type": "Sequence",
+            "normalizers": [
+                { "type": "Prepend", "prepend": "▁" },
+                { "type": "Replace", "pattern": { "String": " " }, "content": "▁" }
+            ]
+        },
+        "pre_tokenizer": null,
+        "post_processor": {
+            "type": "TemplateProcessing",
+            "single": [
+                { "SpecialToken": { "id": "<s>", "type_id": 0 } },
+                { "Sequence": { "id": "A", "type_id": 0 } }
+            ],
+            "pair": [
+                { "SpecialToken": { "id": "<s>", "type_id": 0 } },
+                { "Sequence": { "id": "A", "type_id": 0 } },
+                { "SpecialToken": { "id": "<s>", "type_id": 1 } },
+                { "Sequence": { "id": "B", "type_id": 1 } }
+            ],
+            "special_tokens": {
+                "<s>": { "id": "<s>", "ids": [1], "tokens": ["<s>"] }
+            }
+        },
+        "decoder": {
+            "type": "Sequence",
+            "decoders": [
+                { "type": "Replace", "pattern": { "String": "▁" }, "content": " " },
+                { "type": "ByteFallback" },
+                { "type": "Fuse" },
+                { "type": "Strip", "content": " ", "start": 1, "stop": 0 }
+            ]
+        },
+        "model": {
+            "type": "BPE",
+            "dropout": null,
+            "unk_token": "<unk>",
+            "continuing_subword_prefix": null,
+            "end_of_word_suffix": null,
+            "fuse_unk": true,
+            "byte_fallback": true,
+            "ignore_merges": false,
+            "vocab": vocab,
+            "merges": merges
+        }
+    })
+    .to_string()
+}
+
+/// BERT style: lowercasing WordPiece with `[CLS] ... [SEP]` post-processing.
+///
+/// IDs: `[PAD]`=0, `[UNK]`=1, `[CLS]`=2, `[SEP]`=3, `[MASK]`=4, then words.
+pub fn bert_json() -> String {
+    let words = [
+        "[PAD]", "[UNK]", "[CLS]", "[SEP]", "[MASK]", "hello", "world", "the", "token", "##izer",
+        "##s", "za", "##z", "##o", "##ł", "##c", ",", ".", "!", "'", "don", "t", "a", "b",
+    ];
+    let vocab: Map<String, Value> = words
+        .iter()
+        .enumerate()
+        .map(|(i, w)| (w.to_string(), json!(i)))
+        .collect();
+
+    json!({
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": [
+            special(0, "[PAD]"), special(1, "[UNK]"), special(2, "[CLS]"),
+            special(3, "[SEP]"), special(4, "[MASK]")
+        ],
+        "normalizer": {
+            "type": "BertNormalizer",
+            "clean_text": true,
+            "handle_chinese_chars": true,
+            "strip_accents": null,
+            "lowercase": true
+        },
+        "pre_tokenizer": { "type": "BertPreTokenizer" },
+        "post_processor": {
+            "type": "BertProcessing",
+            "sep": ["[SEP]", 3],
+            "cls": ["[CLS]", 2]
+        },
+        "decoder": { "type": "WordPiece", "prefix": "##", "cleanup": true },
+        "model": {
+            "type": "WordPiece",
+            "unk_token": "[UNK]",
+            "continuing_subword_prefix": "##",
+            "max_input_chars_per_word": 100,
+            "vocab": vocab
+        }
+    })
+    .to_string()
+}
+
+/// GPT-2 style byte-level BPE without merges, so every byte is its own token.
+///
+/// IDs: byte `b` = `b`, `<|endoftext|>` = 256.
+pub fn byte_level_json() -> String {
+    let mut vocab: Map<String, Value> = crate::tiktoken::byte_chars()
+        .iter()
+        .enumerate()
+        .map(|(b, c)| (c.to_string(), json!(b)))
+        .collect();
+    vocab.insert("<|endoftext|>".to_owned(), json!(256));
+    let byte_level = json!({
+        "type": "ByteLevel",
+        "add_prefix_space": false,
+        "trim_offsets": true,
+        "use_regex": true
+    });
+
+    json!({
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": [special(256, "<|endoftext|>")],
+        "normalizer": null,
+        "pre_tokenizer": byte_level,
+        "post_processor": null,
+        "decoder": byte_level,
+        "model": {
+            "type": "BPE",
+            "dropout": null,
+            "unk_token": null,
+            "continuing_subword_prefix": null,
+            "end_of_word_suffix": null,
+            "fuse_unk": false,
+            "byte_fallback": false,
+            "ignore_merges": false,
+            "vocab": vocab,
+            "merges": []
+        }
+    })
+    .to_string()
+}
@@ -0,0 +1,130 @@
+//! The thread pool batch calls tokenize on.
+//!
+//! Batches run on rayon, as in the tokenizers crate. By default that is rayon's
+//! global pool with one thread per core; `tokenizer_set_num_threads` swaps in a
+//! dedicated pool so tokenization can leave cores to inference running in the
+//! same process. Single-text calls never touch a pool, and setting
+//! `TOKENIZERS_PARALLELISM=false` makes batches serial as well.
+
+use std::ffi::c_int;
+use std::sync::{Arc, PoisonError, RwLock};
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use crate::{catch_panic, fail, ERR_INVALID_ARGUMENT};
+
+/// `None` while batches use rayon's global pool
+static POOL: RwLock<Option<Arc<ThreadPool>>> = RwLock::new(None);
+
+/// Run `f` on the configured pool, so the batch work it starts stays there
+pub(crate) fn in_pool<R: Send>(f: impl FnOnce() -> R + Send) -> R {
+    let pool = POOL.read().unwrap_or_else(PoisonError::into_inner).clone();
+    match pool {
+        Some(pool) => pool.install(f),
+        None => f(),
+    }
+}
+
+/// Limit batch calls to `n` tokenization threads, or with 0 use all cores
+/// Takes effect for the next batch call; batches already running finish on
+/// the previous pool.
+/// Returns 0 on success, -9 for a negative `n` or when the threads cannot be
+/// started
+#[no_mangle]
+pub extern "C" fn tokenizer_set_num_threads(n: c_int) -> c_int {
+    catch_panic(|| {
+        let pool = match n {
+            0 => None,
+            n if n < 0 => {
+                return fail(
+                    ERR_INVALID_ARGUMENT,
+                    format!("thread count {n} is negative"),
+                )
+            }
+            n => match ThreadPoolBuilder::new()
+                .num_threads(n as usize)
+                .thread_name(|i| format!("tokenizer-{i}"))
+                .build()
+            {
+                Ok(pool) => Some(Arc::new(pool)),
+                Err(e) => {
+                    return fail(
+                        ERR_INVALID_ARGUMENT,
+                        format!("could not start {n} tokenization threads: {e}"),
+                    )
+                }
+            },
+        };
+
+        *POOL.write().unwrap_or_else(PoisonError::into_inner) = pool;
+        0
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{install, llama_json, serial};
+    use std::ffi::{c_char, CString};
+    use std::time::{Duration, Instant};
+
+    fn time_batch(texts: &[CString]) -> Duration {
+        let ptrs: Vec<*const c_char> = texts.iter().map(|t| t.as_ptr()).collect();
+        let mut ids = vec![0; texts.len() * 64];
+        let mut lengths = vec![0; texts.len()];
+        let start = Instant::now();
+        let rc = crate::tokenizer_encode_batch(
+            ptrs.as_ptr(),
+            ptrs.len(),
+            ids.as_mut_ptr(),
+            lengths.as_mut_ptr(),
+            64,
+        );
+        assert_eq!(rc, texts.len() as c_int);
+        start.elapsed()
+    }
+
+    #[test]
+    fn batches_run_on_the_configured_pool() {
+        let _guard = serial();
+        install(&llama_json());
+
+        assert_eq!(tokenizer_set_num_threads(1), 0);
+        assert_eq!(in_pool(rayon::current_num_threads), 1);
+        assert_eq!(tokenizer_set_num_threads(3), 0);
+        assert_eq!(in_pool(rayon::current_num_threads), 3);
+        assert_eq!(tokenizer_set_num_threads(-1), ERR_INVALID_ARGUMENT);
+        assert_eq!(in_pool(rayon::current_num_threads), 3);
+        assert_eq!(tokenizer_set_num_threads(0), 0);
+        assert_eq!(
+            in_pool(rayon::current_num_threads),
+            rayon::current_num_threads()
+        );
+    }
+
+    /// Wall-clock timing, so a busy machine can fail it; run with `--ignored`
+    #[test]
+    #[ignore = "timing benchmark"]
+    fn more_threads_encode_large_batches_faster() {
+        let _guard = serial();
+        install(&llama_json());
+        let texts: Vec<CString> = (0..2000)
+            .map(|i| CString::new(format!("hello world zażółć {i} ").repeat(8)).unwrap())
+            .collect();
+
+        assert_eq!(tokenizer_set_num_threads(1), 0);
+        let single = time_batch(&texts);
+        assert_eq!(tokenizer_set_num_threads(4), 0);
+        let four = time_batch(&texts);
+        assert_eq!(tokenizer_set_num_threads(0), 0);
+
+        // A speedup needs the cores to show it
+        let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+        if cores >= 4 {
+            assert!(
+                four < single,
+                "4 threads took {four:?}, 1 thread {single:?}"
+            );
+        }
+    }
+}
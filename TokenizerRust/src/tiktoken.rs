@@ -0,0 +1,352 @@
+//! Loading OpenAI tiktoken rank files (`cl100k_base.tiktoken` and friends).
+//!
+//! A rank file lists every mergeable byte string as `base64 rank`. tiktoken
+//! repeatedly merges the adjacent pair whose concatenation has the lowest rank,
+//! which is byte-level BPE with one merge per way of splitting each token,
+//! ordered by the token's rank; the conversion builds exactly that, as
+//! `transformers`' TikTokenConverter does. Special tokens are not in the rank
+//! file, so they come from the table of well-known encodings below.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int};
+use std::time::Instant;
+use tokenizers::Tokenizer;
+
+use crate::{c_str_arg, catch_panic, fail, set_global, ERR_INVALID_ARGUMENT};
+
+/// cl100k_base's split regex, without tiktoken's possessive quantifiers
+pub(crate) const CL100K_PATTERN: &str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+const O200K_PATTERN: &str = concat!(
+    r"[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]*[\p{Ll}\p{Lm}\p{Lo}\p{M}]+(?i:'s|'t|'re|'ve|'m|'ll|'d)?",
+    r"|[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]+[\p{Ll}\p{Lm}\p{Lo}\p{M}]*(?i:'s|'t|'re|'ve|'m|'ll|'d)?",
+    r"|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n/]*|\s*[\r\n]+|\s+(?!\S)|\s+"
+);
+const P50K_PATTERN: &str =
+    r"'(?:[sdmt]|ll|ve|re)| ?\p{L}+| ?\p{N}+| ?[^\s\p{L}\p{N}]+|\s+(?!\S)|\s+";
+
+const ENDOFTEXT: &str = "<|endoftext|>";
+
+/// A well-known encoding: its name, split regex, number of ranks in its file,
+/// and special tokens
+struct KnownEncoding {
+    name: &'static str,
+    pattern: &'static str,
+    ranks: usize,
+    special_tokens: &'static [(&'static str, u32)],
+}
+
+const KNOWN_ENCODINGS: &[KnownEncoding] = &[
+    KnownEncoding {
+        name: "o200k_base",
+        pattern: O200K_PATTERN,
+        ranks: 199998,
+        special_tokens: &[(ENDOFTEXT, 199999), ("<|endofprompt|>", 200018)],
+    },
+    KnownEncoding {
+        name: "cl100k_base",
+        pattern: CL100K_PATTERN,
+        ranks: 100256,
+        special_tokens: &[
+            (ENDOFTEXT, 100257),
+            ("<|fim_prefix|>", 100258),
+            ("<|fim_middle|>", 100259),
+            ("<|fim_suffix|>", 100260),
+            ("<|endofprompt|>", 100276),
+        ],
+    },
+    KnownEncoding {
+        name: "p50k_base",
+        pattern: P50K_PATTERN,
+        ranks: 50280,
+        special_tokens: &[(ENDOFTEXT, 50256)],
+    },
+    KnownEncoding {
+        name: "r50k_base",
+        pattern: P50K_PATTERN,
+        ranks: 50256,
+        special_tokens: &[(ENDOFTEXT, 50256)],
+    },
+];
+
+/// GPT-2's reversible byte-to-character mapping used by byte-level BPE
+pub(crate) fn byte_chars() -> [char; 256] {
+    let mut chars = ['\0'; 256];
+    let mut next = 256;
+    for (byte, slot) in chars.iter_mut().enumerate() {
+        let printable = matches!(byte, 0x21..=0x7e | 0xa1..=0xac | 0xae..=0xff);
+        *slot = if printable {
+            char::from(byte as u8)
+        } else {
+            next += 1;
+            char::from_u32(next - 1).unwrap()
+        };
+    }
+    chars
+}
+
+/// Parse the `base64 rank` lines of a rank file
+fn parse_ranks(contents: &str) -> Result<Vec<(Vec<u8>, u32)>, String> {
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(n, line)| {
+            let malformed = || format!("line {} is not `base64 rank`", n + 1);
+            let (token, rank) = line.trim().split_once(' ').ok_or_else(malformed)?;
+            let token = STANDARD.decode(token).map_err(|_| malformed())?;
+            let rank = rank.parse().map_err(|_| malformed())?;
+            Ok((token, rank))
+        })
+        .collect()
+}
+
+/// Build tokenizer.json for the ranks, splitting with `pattern` and adding
+/// `special_tokens` with their fixed IDs
+fn tiktoken_json(ranks: &[(Vec<u8>, u32)], pattern: &str, special_tokens: &[(&str, u32)]) -> Value {
+    let chars = byte_chars();
+    let piece = |bytes: &[u8]| -> String { bytes.iter().map(|&b| chars[b as usize]).collect() };
+    let rank_of: HashMap<&[u8], u32> = ranks.iter().map(|(t, r)| (t.as_slice(), *r)).collect();
+
+    let mut merges = Vec::new();
+    for (token, rank) in ranks {
+        let mut splits: Vec<(u32, u32, &[u8], &[u8])> = (1..token.len())
+            .filter_map(|i| {
+                let (left, right) = token.split_at(i);
+                Some((*rank_of.get(left)?, *rank_of.get(right)?, left, right))
+            })
+            .collect();
+        splits.sort_unstable();
+        merges.extend(splits.into_iter().map(|(_, _, l, r)| (*rank, l, r)));
+    }
+    merges.sort_by_key(|&(rank, _, _)| rank);
+    let merges: Vec<Value> = merges
+        .iter()
+        .map(|&(_, l, r)| json!([piece(l), piece(r)]))
+        .collect();
+
+    let mut vocab: Map<String, Value> = ranks.iter().map(|(t, r)| (piece(t), json!(r))).collect();
+    let mut added_tokens = Vec::new();
+    for &(content, id) in special_tokens {
+        vocab.insert(content.to_owned(), json!(id));
+        added_tokens.push(json!({
+            "id": id,
+            "content": content,
+            "single_word": false,
+            "lstrip": false,
+            "rstrip": false,
+            "normalized": false,
+            "special": true
+        }));
+    }
+
+    let byte_level = |use_regex| {
+        json!({
+            "type": "ByteLevel",
+            "add_prefix_space": false,
+            "trim_offsets": true,
+            "use_regex": use_regex
+        })
+    };
+    json!({
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": added_tokens,
+        "normalizer": null,
+        "pre_tokenizer": {
+            "type": "Sequence",
+            "pretokenizers": [
+                {
+                    "type": "Split",
+                    "pattern": { "Regex": pattern },
+                    "behavior": "Isolated",
+                    "invert": false
+                },
+                byte_level(false)
+            ]
+        },
+        "post_processor": null,
+        "decoder": byte_level(true),
+        "model": {
+            "type": "BPE",
+            "dropout": null,
+            "unk_token": null,
+            "continuing_subword_prefix": null,
+            "end_of_word_suffix": null,
+            "fuse_unk": false,
+            "byte_fallback": false,
+            "ignore_merges": true,
+            "vocab": vocab,
+            "merges": merges
+        }
+    })
+}
+
+/// Load a tiktoken rank file; `encoding` fixes the pattern and special tokens,
+/// otherwise `pattern` is used and the special tokens are those of the
+/// well-known encoding with as many ranks, or just `<|endoftext|>` after the
+/// last rank
+fn load_from_tiktoken(
+    path: &str,
+    pattern: &str,
+    encoding: Option<&KnownEncoding>,
+) -> Result<Tokenizer, c_int> {
+    let load_failed = |e: String| fail(-3, format!("failed to load tiktoken '{path}': {e}"));
+
+    let contents = std::fs::read_to_string(path).map_err(|e| load_failed(e.to_string()))?;
+    let ranks = parse_ranks(&contents).map_err(load_failed)?;
+    if ranks.is_empty() {
+        return Err(load_failed("no ranks".to_owned()));
+    }
+
+    let next_id = ranks.iter().map(|&(_, r)| r).max().unwrap_or(0) + 1;
+    let fallback = [(ENDOFTEXT, next_id)];
+    let special_tokens = encoding
+        .or_else(|| KNOWN_ENCODINGS.iter().find(|e| e.ranks == ranks.len()))
+        .map_or(&fallback[..], |e| e.special_tokens);
+    tiktoken_json(&ranks, pattern, special_tokens)
+        .to_string()
+        .parse::<Tokenizer>()
+        .map_err(|e| load_failed(e.to_string()))
+}
+
+/// Initialize the global tokenizer from a tiktoken rank file
+/// `pattern` is the regex that splits text before BPE (e.g. cl100k_base's).
+/// The special tokens of the well-known encoding with the same number of
+/// ranks are added (`<|endoftext|>`, `<|fim_prefix|>`, ...); for other files
+/// only `<|endoftext|>` is added, right after the last rank. Counts and IDs
+/// then match tiktoken's `encode` with all special tokens allowed.
+/// Returns 0 on success, negative on error:
+///   -1 null arguments, -2 invalid UTF-8, -3 unreadable or malformed file or
+///   invalid pattern
+#[no_mangle]
+pub extern "C" fn tokenizer_initialize_tiktoken(
+    path: *const c_char,
+    pattern: *const c_char,
+) -> c_int {
+    catch_panic(|| {
+        let started = Instant::now();
+        let (path, pattern) = match (c_str_arg(path), c_str_arg(pattern)) {
+            (Ok(path), Ok(pattern)) => (path, pattern),
+            (Err(code), _) | (_, Err(code)) => return code,
+        };
+        match load_from_tiktoken(path, pattern, None) {
+            Ok(tokenizer) => set_global(tokenizer, path, started),
+            Err(code) => code,
+        }
+    })
+}
+
+/// Initialize the global tokenizer from the rank file of a well-known encoding
+/// `name` is "o200k_base", "cl100k_base", "p50k_base" or "r50k_base" and picks
+/// the split regex and special tokens; `path` is that encoding's rank file.
+/// Returns 0 on success, negative on error:
+///   -1 null arguments, -2 invalid UTF-8, -3 unreadable or malformed file,
+///   -9 unknown encoding name
+#[no_mangle]
+pub extern "C" fn tokenizer_initialize_tiktoken_named(
+    path: *const c_char,
+    name: *const c_char,
+) -> c_int {
+    catch_panic(|| {
+        let started = Instant::now();
+        let (path, name) = match (c_str_arg(path), c_str_arg(name)) {
+            (Ok(path), Ok(name)) => (path, name),
+            (Err(code), _) | (_, Err(code)) => return code,
+        };
+        let Some(encoding) = KNOWN_ENCODINGS.iter().find(|e| e.name == name) else {
+            return fail(
+                ERR_INVALID_ARGUMENT,
+                format!("unknown tiktoken encoding '{name}'"),
+            );
+        };
+        match load_from_tiktoken(path, encoding.pattern, Some(encoding)) {
+            Ok(tokenizer) => set_global(tokenizer, path, started),
+            Err(code) => code,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{serial, write_temp};
+    use std::ffi::CString;
+
+    /// Every single byte at its own value, then these merged tokens from 256 on
+    fn rank_file(merged: &[&str]) -> String {
+        let singles = (0..=255u8).map(|b| vec![b]);
+        let merged = merged.iter().map(|t| t.as_bytes().to_vec());
+        singles
+            .chain(merged)
+            .enumerate()
+            .map(|(rank, token)| format!("{} {rank}\n", STANDARD.encode(token)))
+            .collect()
+    }
+
+    fn load(contents: &str, name: Option<&str>) -> c_int {
+        let path = write_temp("tiktoken", contents);
+        let path = CString::new(path.to_str().unwrap()).unwrap();
+        match name {
+            Some(name) => {
+                let name = CString::new(name).unwrap();
+                tokenizer_initialize_tiktoken_named(path.as_ptr(), name.as_ptr())
+            }
+            None => {
+                let pattern = CString::new(CL100K_PATTERN).unwrap();
+                tokenizer_initialize_tiktoken(path.as_ptr(), pattern.as_ptr())
+            }
+        }
+    }
+
+    fn encode(text: &str) -> Vec<c_int> {
+        let text = CString::new(text).unwrap();
+        let mut ids = vec![0; 64];
+        let n = crate::tokenizer_encode(text.as_ptr(), ids.as_mut_ptr(), ids.len());
+        assert!(n >= 0, "encode failed with {n}");
+        ids.truncate(n as usize);
+        ids
+    }
+
+    #[test]
+    fn merges_follow_rank_order_like_tiktoken() {
+        let _guard = serial();
+        // "bc" outranks "ab", so "xabc" becomes x + abc rather than x + ab + c
+        assert_eq!(load(&rank_file(&["bc", "ab", "abc", "ł", "ó"]), None), 0);
+
+        assert_eq!(encode("xabc"), [b'x' as c_int, 258]);
+        assert_eq!(encode("abc ab"), [258, b' ' as c_int, 257]);
+        // Polish letters merge from their UTF-8 bytes; unmerged ones stay bytes
+        assert_eq!(encode(" łódź"), [32, 259, 260, 100, 0xc5, 0xba]);
+    }
+
+    #[test]
+    fn endoftext_follows_the_last_rank_for_unknown_encodings() {
+        let _guard = serial();
+        assert_eq!(load(&rank_file(&["hi"]), None), 0);
+
+        assert_eq!(encode("hi<|endoftext|>hi"), [256, 257, 256]);
+        let ids = [256, 32, 0xc5, 0x82];
+        let mut out = [0 as c_char; 16];
+        let rc = crate::tokenizer_decode(ids.as_ptr(), ids.len(), out.as_mut_ptr(), 16);
+        assert!(rc >= 0);
+        let text = unsafe { std::ffi::CStr::from_ptr(out.as_ptr()) };
+        assert_eq!(text.to_str().unwrap(), "hi ł");
+    }
+
+    #[test]
+    fn named_encodings_fix_the_special_token_ids() {
+        let _guard = serial();
+        assert_eq!(load(&rank_file(&[]), Some("cl100k_base")), 0);
+        assert_eq!(encode("a<|fim_middle|>"), [97, 100259]);
+
+        assert_eq!(
+            load(&rank_file(&[]), Some("cl200k_base")),
+            ERR_INVALID_ARGUMENT
+        );
+        assert_eq!(load("not base64 at all\n", None), -3);
+    }
+}
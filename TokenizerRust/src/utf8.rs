@@ -0,0 +1,194 @@
+//! What text arguments do with invalid UTF-8.
+//!
+//! By default a text that is not valid UTF-8 is rejected with -2. The lossy
+//! policies repair it instead, so a stray bad byte from a marshaling bug or odd
+//! pasted content does not fail the whole request; the repair is still noted
+//! as the last error so it can be logged. Offsets reported for repaired texts
+//! (e.g. by `tokenizer_encode_with_offsets`) refer to the repaired UTF-8.
+
+use std::borrow::Cow;
+use std::ffi::{c_char, c_int, CStr};
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use crate::{
+    catch_panic, fail, set_last_error, ERR_INVALID_ARGUMENT, ERR_INVALID_UTF8, ERR_NULL_POINTER,
+};
+
+const POLICY_REJECT: c_int = 0;
+const POLICY_REPLACE: c_int = 1;
+const POLICY_SKIP: c_int = 2;
+
+static POLICY: AtomicI32 = AtomicI32::new(POLICY_REJECT);
+
+/// Choose how text arguments with invalid UTF-8 are handled
+/// `policy`: 0 rejects them with -2 (the default), 1 replaces each invalid
+/// sequence with U+FFFD, 2 drops the invalid bytes. In the lossy modes the
+/// call succeeds and `tokenizer_last_error` reports that a repair happened and
+/// the byte offset of the first invalid sequence. The policy covers every
+/// function that tokenizes caller text (encode, count, pair, batch, chunked,
+/// handle and named variants, normalize/pretokenize); paths and names are
+/// always strict.
+/// Returns 0 on success, -9 for an unknown policy
+#[no_mangle]
+pub extern "C" fn tokenizer_set_invalid_utf8_policy(policy: c_int) -> c_int {
+    catch_panic(|| {
+        if !matches!(policy, POLICY_REJECT | POLICY_REPLACE | POLICY_SKIP) {
+            return fail(
+                ERR_INVALID_ARGUMENT,
+                format!("unknown invalid UTF-8 policy {policy}; expected 0, 1 or 2"),
+            );
+        }
+        POLICY.store(policy, Ordering::Relaxed);
+        0
+    })
+}
+
+/// A text argument as UTF-8 under the current policy
+/// On repair, also returns a note naming the first invalid byte offset.
+pub(crate) fn repair_text<'a>(ptr: *const c_char) -> Result<(Cow<'a, str>, Option<String>), c_int> {
+    if ptr.is_null() {
+        return Err(fail(ERR_NULL_POINTER, "string argument is null"));
+    }
+    let bytes = unsafe { CStr::from_ptr(ptr) }.to_bytes();
+    let bad_at = match std::str::from_utf8(bytes) {
+        Ok(text) => return Ok((Cow::Borrowed(text), None)),
+        Err(e) => e.valid_up_to(),
+    };
+
+    match POLICY.load(Ordering::Relaxed) {
+        POLICY_REPLACE => Ok((
+            String::from_utf8_lossy(bytes),
+            Some(format!(
+                "replaced invalid UTF-8 starting at byte {bad_at} with U+FFFD"
+            )),
+        )),
+        POLICY_SKIP => {
+            let text: String = bytes.utf8_chunks().map(|chunk| chunk.valid()).collect();
+            Ok((
+                Cow::Owned(text),
+                Some(format!("skipped invalid UTF-8 starting at byte {bad_at}")),
+            ))
+        }
+        _ => Err(fail(
+            ERR_INVALID_UTF8,
+            format!("invalid UTF-8 at byte {bad_at}"),
+        )),
+    }
+}
+
+/// `repair_text`, recording any repair note as the last error
+pub(crate) fn text_arg<'a>(ptr: *const c_char) -> Result<Cow<'a, str>, c_int> {
+    let (text, note) = repair_text(ptr)?;
+    if let Some(note) = note {
+        crate::log::log(crate::log::LOG_WARN, || note.clone());
+        set_last_error(note);
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{install, llama_json, serial};
+    use std::ffi::CString;
+
+    /// Run `f` under `policy`, restoring the strict default afterwards
+    fn with_policy(policy: c_int, f: impl FnOnce()) {
+        struct Restore;
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                tokenizer_set_invalid_utf8_policy(POLICY_REJECT);
+            }
+        }
+        let _restore = Restore;
+        assert_eq!(tokenizer_set_invalid_utf8_policy(policy), 0);
+        f();
+    }
+
+    fn repaired(bytes: &[u8]) -> Result<String, c_int> {
+        let text = CString::new(bytes).unwrap();
+        text_arg(text.as_ptr()).map(Cow::into_owned)
+    }
+
+    #[test]
+    fn strict_policy_rejects_with_the_byte_offset() {
+        let _guard = serial();
+        assert_eq!(repaired(b"ab\xc3c"), Err(ERR_INVALID_UTF8));
+        assert_eq!(crate::last_error_message(), "invalid UTF-8 at byte 2");
+        assert_eq!(tokenizer_set_invalid_utf8_policy(3), ERR_INVALID_ARGUMENT);
+    }
+
+    #[test]
+    fn replace_policy_substitutes_each_invalid_sequence() {
+        let _guard = serial();
+        with_policy(POLICY_REPLACE, || {
+            // Overlong '/' and a WTF-8 lone surrogate (U+D800)
+            assert_eq!(repaired(b"a\xc0\xafb").unwrap(), "a\u{fffd}\u{fffd}b");
+            assert_eq!(
+                crate::last_error_message(),
+                "replaced invalid UTF-8 starting at byte 1 with U+FFFD"
+            );
+            assert_eq!(
+                repaired(b"x\xed\xa0\x80").unwrap(),
+                "x\u{fffd}\u{fffd}\u{fffd}"
+            );
+            assert_eq!(repaired("zażółć".as_bytes()).unwrap(), "zażółć");
+        });
+    }
+
+    #[test]
+    fn skip_policy_drops_invalid_bytes() {
+        let _guard = serial();
+        with_policy(POLICY_SKIP, || {
+            assert_eq!(repaired(b"a\xc0\xafb\xed\xa0\x80c").unwrap(), "abc");
+            assert_eq!(
+                crate::last_error_message(),
+                "skipped invalid UTF-8 starting at byte 1"
+            );
+        });
+    }
+
+    #[test]
+    fn lossy_policy_applies_to_encode_count_pair_and_batch() {
+        let _guard = serial();
+        install(&llama_json());
+        let bad = CString::new(b"hello\xed\xa0\x80 world".to_vec()).unwrap();
+        let good = CString::new("hello world").unwrap();
+        let mut ids = [0; 32];
+
+        let rc = crate::tokenizer_encode(bad.as_ptr(), ids.as_mut_ptr(), ids.len());
+        assert_eq!(rc, ERR_INVALID_UTF8);
+
+        with_policy(POLICY_SKIP, || {
+            let n = crate::tokenizer_encode(bad.as_ptr(), ids.as_mut_ptr(), ids.len());
+            assert_eq!(&ids[..n as usize], [1, 296, 301]);
+            assert_eq!(crate::tokenizer_count_tokens(bad.as_ptr(), 1), 3);
+            let mut type_ids = [0; 32];
+            let n = crate::tokenizer_encode_pair(
+                bad.as_ptr(),
+                good.as_ptr(),
+                ids.as_mut_ptr(),
+                type_ids.as_mut_ptr(),
+                ids.len(),
+            );
+            assert!(n > 0);
+
+            let texts = [good.as_ptr(), bad.as_ptr()];
+            let mut batch = [0; 16];
+            let mut lengths = [0; 2];
+            let rc = crate::tokenizer_encode_batch(
+                texts.as_ptr(),
+                2,
+                batch.as_mut_ptr(),
+                lengths.as_mut_ptr(),
+                8,
+            );
+            assert_eq!(rc, 2);
+            assert_eq!(lengths, [3, 3]);
+            assert_eq!(
+                crate::last_error_message(),
+                "batch item 1: skipped invalid UTF-8 starting at byte 5"
+            );
+        });
+    }
+}
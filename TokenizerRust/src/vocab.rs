@@ -0,0 +1,545 @@
+//! Vocabulary lookups: size, token -> ID, ID -> raw token piece and search.
+//!
+//! Pieces are returned exactly as stored, with `▁`/`Ġ` markers intact; use
+//! `tokenizer_decode` for cleaned-up text. Added tokens are always included.
+//!
+//! Tokens added at runtime live on the global tokenizer, so loading another
+//! one with `tokenizer_initialize*` drops them.
+
+use std::ffi::{c_char, c_int};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use tokenizers::{AddedToken, Tokenizer};
+
+use crate::bytes::piece_bytes;
+use crate::{
+    c_str_arg, catch_panic, fail, null_output, tokenizer_failed, with_tokenizer,
+    with_tokenizer_mut, write_c_str, ERR_INVALID_ARGUMENT, ERR_WRITE_FAILED,
+};
+
+const EXPORT_JSON: c_int = 0;
+const EXPORT_TSV: c_int = 1;
+
+const MATCH_EXACT: c_int = 0;
+const MATCH_PREFIX: c_int = 1;
+const MATCH_CONTAINS_IGNORE_CASE: c_int = 2;
+
+/// Every vocabulary entry as readable text, sorted by ID
+pub(crate) struct PieceTable {
+    pub(crate) pieces: Vec<(u32, String)>,
+    /// `pieces` lowercased, for case-insensitive matching
+    lowercase: Vec<String>,
+}
+
+/// Built on the first search and, like the encode cache, dropped whenever the
+/// global tokenizer changes; tagged with the tokenizer generation it was
+/// built from, since a thread may still search an older snapshot
+static PIECES: Mutex<Option<(u64, Arc<PieceTable>)>> = Mutex::new(None);
+
+fn piece_table() -> MutexGuard<'static, Option<(u64, Arc<PieceTable>)>> {
+    PIECES.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Drop the piece table; called whenever the global tokenizer changes
+pub(crate) fn clear_pieces() {
+    *piece_table() = None;
+}
+
+impl PieceTable {
+    fn build(tokenizer: &Tokenizer) -> tokenizers::Result<Self> {
+        let added = tokenizer.get_added_vocabulary();
+        let mut pieces = Vec::new();
+        for (token, id) in tokenizer.get_vocab(true) {
+            // Added tokens are matched and decoded whole, markers and all
+            let text = if added.get_vocab().contains_key(&token) {
+                token
+            } else {
+                String::from_utf8_lossy(&piece_bytes(tokenizer, &token)?).into_owned()
+            };
+            pieces.push((id, text));
+        }
+        pieces.sort_unstable_by_key(|&(id, _)| id);
+        let lowercase = pieces.iter().map(|(_, text)| text.to_lowercase()).collect();
+        Ok(Self { pieces, lowercase })
+    }
+}
+
+/// Number of entries in the vocabulary
+/// Non-zero `with_added_tokens` includes tokens from the added-token table.
+/// Returns the size, or -3 not initialized
+#[no_mangle]
+pub extern "C" fn tokenizer_vocab_size(with_added_tokens: c_int) -> c_int {
+    catch_panic(|| {
+        with_tokenizer(|tokenizer| tokenizer.get_vocab_size(with_added_tokens != 0) as c_int)
+    })
+}
+
+/// ID of an exact token piece (e.g. "▁hello", "<|im_end|>")
+/// Returns the ID, -1 if the piece is not in the vocabulary (or `token` is
+/// null), -2 invalid UTF-8, -3 not initialized
+#[no_mangle]
+pub extern "C" fn tokenizer_token_to_id(token: *const c_char) -> c_int {
+    catch_panic(|| {
+        let token = match c_str_arg(token) {
+            Ok(t) => t,
+            Err(code) => return code,
+        };
+
+        with_tokenizer(|tokenizer| match tokenizer.token_to_id(token) {
+            Some(id) => id as c_int,
+            None => -1,
+        })
+    })
+}
+
+/// Write the raw piece for `id` into `out` as NUL-terminated UTF-8
+/// Returns bytes written (excluding NUL) or the required size for a null
+/// `out`/zero `capacity`, negative on error:
+///   -3 not initialized, -6 buffer too small,
+///   -9 `id` is negative or outside the vocabulary
+#[no_mangle]
+pub extern "C" fn tokenizer_id_to_token(id: c_int, out: *mut c_char, capacity: usize) -> c_int {
+    catch_panic(|| {
+        with_tokenizer(|tokenizer| {
+            let piece = u32::try_from(id)
+                .ok()
+                .and_then(|id| tokenizer.id_to_token(id));
+            match piece {
+                Some(piece) => write_c_str(&piece, out, capacity),
+                None => fail(
+                    ERR_INVALID_ARGUMENT,
+                    format!("token ID {id} is not in the vocabulary"),
+                ),
+            }
+        })
+    })
+}
+
+/// Write the whole vocabulary, added tokens included, to the file at `path`
+/// Entries are sorted by ID and carry the raw piece (markers intact), whether
+/// it is an added token and whether it is special. `format` 0 writes a JSON
+/// array of `{"id", "piece", "added", "special"}` objects; 1 writes TSV with a
+/// header line and `\\`, `\t`, `\n` and `\r` escaped inside pieces, so every
+/// entry stays on one line. An existing file is overwritten.
+/// Returns the number of entries written, negative on error:
+///   -1 null path, -2 invalid UTF-8, -3 not initialized, -9 unknown format,
+///   -16 the file could not be written
+#[no_mangle]
+pub extern "C" fn tokenizer_export_vocab(path: *const c_char, format: c_int) -> c_int {
+    catch_panic(|| {
+        let path = match c_str_arg(path) {
+            Ok(p) => p,
+            Err(code) => return code,
+        };
+        if !matches!(format, EXPORT_JSON | EXPORT_TSV) {
+            return fail(
+                ERR_INVALID_ARGUMENT,
+                format!("unknown vocabulary format {format}; expected 0 (JSON) or 1 (TSV)"),
+            );
+        }
+
+        with_tokenizer(|tokenizer| {
+            let added = tokenizer.get_added_vocabulary();
+            let mut entries: Vec<(u32, String)> = tokenizer
+                .get_vocab(true)
+                .into_iter()
+                .map(|(piece, id)| (id, piece))
+                .collect();
+            entries.sort_unstable_by_key(|&(id, _)| id);
+
+            let written = File::create(path).and_then(|file| {
+                let mut out = BufWriter::new(file);
+                if format == EXPORT_JSON {
+                    out.write_all(b"[")?;
+                } else {
+                    out.write_all(b"id\tpiece\tadded\tspecial\n")?;
+                }
+                for (i, (id, piece)) in entries.iter().enumerate() {
+                    let is_added = added.get_vocab().contains_key(piece);
+                    let is_special = added.is_special_token(piece);
+                    if format == EXPORT_JSON {
+                        let separator = if i == 0 { "\n" } else { ",\n" };
+                        let entry = serde_json::json!({
+                            "id": id,
+                            "piece": piece,
+                            "added": is_added,
+                            "special": is_special,
+                        });
+                        write!(out, "{separator}  {entry}")?;
+                    } else {
+                        let escaped = escape_tsv(piece);
+                        writeln!(
+                            out,
+                            "{id}\t{escaped}\t{}\t{}",
+                            is_added as u8, is_special as u8
+                        )?;
+                    }
+                }
+                if format == EXPORT_JSON {
+                    out.write_all(b"\n]\n")?;
+                }
+                out.flush()
+            });
+
+            match written {
+                Ok(()) => entries.len() as c_int,
+                Err(e) => fail(
+                    ERR_WRITE_FAILED,
+                    format!("could not write vocabulary to {path}: {e}"),
+                ),
+            }
+        })
+    })
+}
+
+fn escape_tsv(piece: &str) -> String {
+    let mut escaped = String::with_capacity(piece.len());
+    for c in piece.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The piece table of `tokenizer`, built on first use
+/// Must be called inside `with_tokenizer`, like the encode cache.
+/// Errors: -4 a piece could not be decoded
+pub(crate) fn pieces(tokenizer: &Tokenizer) -> Result<Arc<PieceTable>, c_int> {
+    let generation = crate::snapshot_generation();
+    let mut cached = piece_table();
+    match &*cached {
+        Some((built_for, table)) if *built_for == generation => Ok(table.clone()),
+        _ => {
+            let table =
+                PieceTable::build(tokenizer).map_err(|e| tokenizer_failed("piece decode", e))?;
+            let table = Arc::new(table);
+            // A search on an outdated snapshot must not evict the table of
+            // the current tokenizer
+            if generation == crate::current_generation() {
+                *cached = Some((generation, table.clone()));
+            }
+            Ok(table)
+        }
+    }
+}
+
+/// Find every token whose piece, read as text, matches `query`
+/// Pieces are compared as they read inside a text, so byte-level and
+/// SentencePiece markers become spaces: " world" finds `Ġworld`/`▁world`,
+/// while "world" finds the in-word continuation. Added tokens compare as
+/// written. `match_mode`: 0 exact, 1 the piece starts with `query`, 2 the
+/// piece contains `query` ignoring case. IDs are written in ascending order,
+/// at most `max_out` of them. The first search after loading builds a table
+/// of all pieces, which later searches reuse.
+/// Returns the total number of matching tokens, negative on error:
+///   -1 null argument, -2 invalid UTF-8, -3 not initialized, -4 a piece could
+///   not be decoded, -9 unknown `match_mode` or empty `query`
+#[no_mangle]
+pub extern "C" fn tokenizer_find_token_ids(
+    query: *const c_char,
+    match_mode: c_int,
+    out_ids: *mut c_int,
+    max_out: usize,
+) -> c_int {
+    catch_panic(|| {
+        if out_ids.is_null() && max_out > 0 {
+            return null_output("out_ids");
+        }
+        let query = match c_str_arg(query) {
+            Ok(q) => q,
+            Err(code) => return code,
+        };
+        if query.is_empty() {
+            return fail(ERR_INVALID_ARGUMENT, "query is empty");
+        }
+        if !matches!(
+            match_mode,
+            MATCH_EXACT | MATCH_PREFIX | MATCH_CONTAINS_IGNORE_CASE
+        ) {
+            return fail(
+                ERR_INVALID_ARGUMENT,
+                format!("unknown match mode {match_mode}; expected 0, 1 or 2"),
+            );
+        }
+
+        with_tokenizer(|tokenizer| {
+            let table = match pieces(tokenizer) {
+                Ok(table) => table,
+                Err(code) => return code,
+            };
+
+            let lowercase_query = query.to_lowercase();
+            let matches = table
+                .pieces
+                .iter()
+                .zip(&table.lowercase)
+                .filter(|((_, piece), lowercase)| match match_mode {
+                    MATCH_EXACT => piece == query,
+                    MATCH_PREFIX => piece.starts_with(query),
+                    _ => lowercase.contains(&lowercase_query),
+                })
+                .map(|((id, _), _)| *id);
+
+            let mut total = 0;
+            for id in matches {
+                if total < max_out {
+                    unsafe { *out_ids.add(total) = id as c_int };
+                }
+                total += 1;
+            }
+            total as c_int
+        })
+    })
+}
+
+/// Add `count` special tokens such as `<|tool_call|>` to the loaded tokenizer
+/// They are matched whole before normal tokenization, so each occurrence in
+/// a prompt encodes to exactly one ID, and skip-special decoding drops them.
+/// New tokens get the next free IDs; look them up with `tokenizer_token_to_id`.
+/// Returns how many were actually added (tokens already known are not counted),
+/// negative on error:
+///   -1 null `tokens` or entry, -2 invalid UTF-8, -3 not initialized,
+///   -9 empty token
+#[no_mangle]
+pub extern "C" fn tokenizer_add_special_tokens(
+    tokens: *const *const c_char,
+    count: usize,
+) -> c_int {
+    catch_panic(|| add_tokens(tokens, count, true))
+}
+
+/// Same as `tokenizer_add_special_tokens` for ordinary tokens, which are
+/// matched whole as well but kept by skip-special decoding
+#[no_mangle]
+pub extern "C" fn tokenizer_add_tokens(tokens: *const *const c_char, count: usize) -> c_int {
+    catch_panic(|| add_tokens(tokens, count, false))
+}
+
+fn add_tokens(tokens: *const *const c_char, count: usize, special: bool) -> c_int {
+    if count == 0 {
+        return 0;
+    }
+    if tokens.is_null() {
+        return null_output("tokens");
+    }
+
+    let mut added = Vec::with_capacity(count);
+    for (i, &token) in unsafe { std::slice::from_raw_parts(tokens, count) }
+        .iter()
+        .enumerate()
+    {
+        match c_str_arg(token) {
+            Ok("") => return fail(ERR_INVALID_ARGUMENT, format!("token {i} is empty")),
+            Ok(token) => added.push(AddedToken::from(token, special)),
+            Err(code) => return code,
+        }
+    }
+
+    with_tokenizer_mut(|tokenizer| {
+        let count = if special {
+            tokenizer.add_special_tokens(&added)
+        } else {
+            tokenizer.add_tokens(&added)
+        };
+        count as c_int
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{bert_json, install, llama_json, serial};
+    use std::ffi::{CStr, CString};
+
+    fn piece(id: c_int) -> Result<String, c_int> {
+        let needed = tokenizer_id_to_token(id, std::ptr::null_mut(), 0);
+        if needed < 0 {
+            return Err(needed);
+        }
+        let mut buf = vec![0u8; needed as usize + 1];
+        tokenizer_id_to_token(id, buf.as_mut_ptr() as *mut c_char, buf.len());
+        Ok(CStr::from_bytes_with_nul(&buf)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_owned())
+    }
+
+    fn id(token: &str) -> c_int {
+        tokenizer_token_to_id(CString::new(token).unwrap().as_ptr())
+    }
+
+    #[test]
+    fn raw_pieces_round_trip_with_markers() {
+        let _guard = serial();
+        install(&llama_json());
+
+        let hello = id("▁hello");
+        assert!(hello > 0);
+        assert_eq!(piece(hello).unwrap(), "▁hello");
+        assert_eq!(piece(3).unwrap(), "<0x00>");
+        assert_eq!(id("<s>"), 1);
+        assert_eq!(id("not-a-token"), -1);
+    }
+
+    #[test]
+    fn out_of_range_ids_are_rejected() {
+        let _guard = serial();
+        install(&bert_json());
+
+        let size = tokenizer_vocab_size(1);
+        assert_eq!(size, tokenizer_vocab_size(0));
+        assert_eq!(piece(size - 1).unwrap(), "b");
+        assert_eq!(piece(size), Err(ERR_INVALID_ARGUMENT));
+        assert_eq!(piece(-5), Err(ERR_INVALID_ARGUMENT));
+    }
+
+    #[test]
+    fn added_special_tokens_encode_atomically_and_are_skippable() {
+        let _guard = serial();
+        install(&llama_json());
+
+        let tokens = [
+            CString::new("<|tool_call|>").unwrap(),
+            CString::new("<|memory|>").unwrap(),
+        ];
+        let ptrs: Vec<*const c_char> = tokens.iter().map(|t| t.as_ptr()).collect();
+        assert_eq!(tokenizer_add_special_tokens(ptrs.as_ptr(), ptrs.len()), 2);
+        // Adding them again is a no-op
+        assert_eq!(tokenizer_add_special_tokens(ptrs.as_ptr(), ptrs.len()), 0);
+
+        let tool_call = id("<|tool_call|>");
+        assert_eq!(tool_call, tokenizer_vocab_size(0));
+
+        let text = CString::new("hello<|tool_call|>world").unwrap();
+        let mut ids = [0; 16];
+        let n = crate::tokenizer_encode(text.as_ptr(), ids.as_mut_ptr(), ids.len());
+        let ids = &ids[..n as usize];
+        assert_eq!(ids.iter().filter(|&&i| i == tool_call).count(), 1);
+
+        let mut buf = [0u8; 64];
+        let written = crate::tokenizer_decode(
+            ids.as_ptr(),
+            ids.len(),
+            buf.as_mut_ptr() as *mut c_char,
+            buf.len(),
+        );
+        let decoded = std::str::from_utf8(&buf[..written as usize]).unwrap();
+        assert!(!decoded.contains("<|tool_call|>"), "{decoded}");
+
+        // A fresh tokenizer does not know them
+        install(&llama_json());
+        assert_eq!(id("<|tool_call|>"), -1);
+    }
+
+    fn find(query: &str, mode: c_int) -> Vec<c_int> {
+        let query = CString::new(query).unwrap();
+        let mut ids = vec![0; 512];
+        let n = tokenizer_find_token_ids(query.as_ptr(), mode, ids.as_mut_ptr(), ids.len());
+        assert!(n >= 0, "find failed with {n}");
+        ids.truncate(n as usize);
+        ids
+    }
+
+    #[test]
+    fn marker_spaces_are_matched_as_spaces() {
+        let _guard = serial();
+        install(&llama_json());
+        assert_eq!(find(" world", MATCH_EXACT), [id("▁world")]);
+        assert_eq!(find(" wor", MATCH_PREFIX), [id("▁wor"), id("▁world")]);
+        assert_eq!(
+            find("LD", MATCH_CONTAINS_IGNORE_CASE),
+            [id("ld"), id("▁world")]
+        );
+        // Byte-fallback pieces match the byte they stand for
+        assert_eq!(find("A", MATCH_EXACT), [id("<0x41>")]);
+        assert_eq!(find("<s>", MATCH_EXACT), [1]);
+
+        install(&bert_json());
+        assert_eq!(find(" world", MATCH_EXACT), [6]);
+        assert_eq!(find("izer", MATCH_EXACT), [id("##izer")]);
+
+        install(&crate::test_support::byte_level_json());
+        assert_eq!(find(" ", MATCH_EXACT), [32]);
+    }
+
+    #[test]
+    fn find_reports_the_total_and_rejects_bad_queries() {
+        let _guard = serial();
+        install(&llama_json());
+        let query = CString::new("o").unwrap();
+        let mut ids = [0; 2];
+        let total = tokenizer_find_token_ids(
+            query.as_ptr(),
+            MATCH_CONTAINS_IGNORE_CASE,
+            ids.as_mut_ptr(),
+            2,
+        );
+        assert_eq!(total as usize, find("o", MATCH_CONTAINS_IGNORE_CASE).len());
+        assert!(total > 2);
+
+        assert_eq!(
+            tokenizer_find_token_ids(query.as_ptr(), 3, ids.as_mut_ptr(), 2),
+            ERR_INVALID_ARGUMENT
+        );
+        let empty = CString::new("").unwrap();
+        assert_eq!(
+            tokenizer_find_token_ids(empty.as_ptr(), 0, ids.as_mut_ptr(), 2),
+            ERR_INVALID_ARGUMENT
+        );
+
+        // Runtime additions invalidate the piece table
+        let token = CString::new("<|ban|>").unwrap();
+        assert_eq!(tokenizer_add_special_tokens(&token.as_ptr(), 1), 1);
+        assert_eq!(find("<|ban|>", MATCH_EXACT), [id("<|ban|>")]);
+    }
+
+    fn export(format: c_int) -> (c_int, String) {
+        let path = std::env::temp_dir().join(format!("vocab_export_{format}"));
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+        let n = tokenizer_export_vocab(c_path.as_ptr(), format);
+        (n, std::fs::read_to_string(path).unwrap_or_default())
+    }
+
+    #[test]
+    fn exported_vocab_marks_added_tokens_and_escapes_pieces() {
+        let _guard = serial();
+        install(&llama_json());
+        let token = CString::new("<|tab\there|>\n\"").unwrap();
+        assert_eq!(tokenizer_add_special_tokens(&token.as_ptr(), 1), 1);
+        let size = tokenizer_vocab_size(1);
+
+        let (n, json) = export(EXPORT_JSON);
+        assert_eq!(n, size);
+        let entries: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+        assert_eq!(entries.len(), size as usize);
+        assert_eq!(entries[296]["piece"], "▁hello");
+        assert_eq!(entries[1]["special"], true);
+        let last = entries.last().unwrap();
+        assert_eq!(last["piece"], "<|tab\there|>\n\"");
+        assert_eq!(
+            (&last["added"], &last["special"]),
+            (&true.into(), &true.into())
+        );
+
+        let (n, tsv) = export(EXPORT_TSV);
+        assert_eq!(n, size);
+        let lines: Vec<&str> = tsv.lines().collect();
+        assert_eq!(lines.len(), size as usize + 1);
+        assert_eq!(lines[297], "296\t▁hello\t0\t0");
+        assert_eq!(
+            lines.last().unwrap(),
+            &format!("{}\t<|tab\\there|>\\n\"\t1\t1", size - 1)
+        );
+
+        assert_eq!(export(2).0, ERR_INVALID_ARGUMENT);
+        let dir = CString::new(std::env::temp_dir().to_str().unwrap()).unwrap();
+        assert_eq!(tokenizer_export_vocab(dir.as_ptr(), 0), ERR_WRITE_FAILED);
+    }
+}
@@ -0,0 +1,184 @@
+//! UTF-16 entry points for callers whose strings are natively wide (C#/Win32),
+//! usable with `[DllImport(CharSet = CharSet.Unicode)]` and no manual encoding.
+//!
+//! Paths and texts are NUL-terminated `u16` strings. Unpaired surrogates are
+//! rejected with -8 instead of being replaced.
+
+use std::ffi::{c_char, c_int};
+use std::time::Instant;
+
+use crate::{
+    catch_panic, encode_into, fail, guard, ids_arg, load_from_file, set_global, tokenizer_failed,
+    with_tokenizer, ERR_BUFFER_TOO_SMALL, ERR_INVALID_UTF16, ERR_NULL_POINTER,
+};
+
+/// Read a NUL-terminated UTF-16 argument into an owned `String`
+fn wide_arg(ptr: *const u16) -> Result<String, c_int> {
+    if ptr.is_null() {
+        return Err(fail(ERR_NULL_POINTER, "string argument is null"));
+    }
+
+    let units = unsafe {
+        let mut len = 0;
+        while *ptr.add(len) != 0 {
+            len += 1;
+        }
+        std::slice::from_raw_parts(ptr, len)
+    };
+    String::from_utf16(units).map_err(|_| {
+        let index = char::decode_utf16(units.iter().copied())
+            .take_while(Result::is_ok)
+            .map(|c| c.map_or(1, char::len_utf16))
+            .sum::<usize>();
+        fail(
+            ERR_INVALID_UTF16,
+            format!("unpaired surrogate at code unit {index}"),
+        )
+    })
+}
+
+/// Copy `s` into a caller buffer as NUL-terminated UTF-16
+/// Capacity and the return value are counted in `u16` code units (excluding
+/// NUL); a null buffer or zero capacity returns the required size
+fn write_wide(s: &str, out: *mut u16, capacity: usize) -> c_int {
+    let units: Vec<u16> = s.encode_utf16().collect();
+    let len = match c_int::try_from(units.len()) {
+        Ok(len) => len,
+        Err(_) => return ERR_BUFFER_TOO_SMALL,
+    };
+
+    if out.is_null() || capacity == 0 {
+        return len;
+    }
+    if capacity <= units.len() {
+        return fail(
+            ERR_BUFFER_TOO_SMALL,
+            format!("output needs {len} code units plus NUL, buffer holds {capacity}"),
+        );
+    }
+
+    unsafe {
+        std::ptr::copy_nonoverlapping(units.as_ptr(), out, units.len());
+        *out.add(units.len()) = 0;
+    }
+
+    len
+}
+
+/// `tokenizer_initialize` taking a UTF-16 path
+/// Returns 0 on success, negative on error:
+///   -1 null path, -3 load failed, -8 invalid UTF-16
+#[no_mangle]
+pub extern "C" fn tokenizer_initialize_w(path: *const u16) -> c_int {
+    catch_panic(|| {
+        let started = Instant::now();
+        let path = match wide_arg(path) {
+            Ok(p) => p,
+            Err(code) => return code,
+        };
+
+        match load_from_file(&path) {
+            Ok(tokenizer) => set_global(tokenizer, &path, started),
+            Err(code) => code,
+        }
+    })
+}
+
+/// `tokenizer_encode` taking UTF-16 text
+/// Returns number of tokens on success, negative on error
+/// (as `tokenizer_encode`, with -8 for invalid UTF-16 instead of -2)
+#[no_mangle]
+pub extern "C" fn tokenizer_encode_w(
+    text: *const u16,
+    out_ids: *mut c_int,
+    max_len: usize,
+) -> c_int {
+    catch_panic(|| {
+        if let Err(code) = guard::out_buffer(
+            "tokenizer_encode_w",
+            "out_ids",
+            out_ids,
+            max_len,
+            text as *const c_char,
+        ) {
+            return code;
+        }
+        let text = match wide_arg(text) {
+            Ok(t) => t,
+            Err(code) => return code,
+        };
+
+        with_tokenizer(|tokenizer| encode_into(tokenizer, &text, true, out_ids, max_len))
+    })
+}
+
+/// `tokenizer_decode_ex` writing UTF-16 into `out_text`
+/// `out_capacity` and the return value are in `u16` code units (excluding NUL);
+/// a null `out_text` or zero capacity returns the required size
+/// Errors as `tokenizer_decode`
+#[no_mangle]
+pub extern "C" fn tokenizer_decode_w(
+    ids: *const c_int,
+    len: usize,
+    skip_special_tokens: c_int,
+    out_text: *mut u16,
+    out_capacity: usize,
+) -> c_int {
+    catch_panic(|| {
+        let ids = match ids_arg(ids, len) {
+            Ok(ids) => ids,
+            Err(code) => return code,
+        };
+
+        with_tokenizer(
+            |tokenizer| match tokenizer.decode(&ids, skip_special_tokens != 0) {
+                Ok(text) => write_wide(&text, out_text, out_capacity),
+                Err(e) => tokenizer_failed("decode", e),
+            },
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{install, llama_json, serial, write_temp};
+
+    fn wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().chain(std::iter::once(0)).collect()
+    }
+
+    #[test]
+    fn wide_round_trip_matches_utf8_path() {
+        let _guard = serial();
+        let path = write_temp("wide_init", &llama_json());
+        assert_eq!(
+            tokenizer_initialize_w(wide(path.to_str().unwrap()).as_ptr()),
+            0
+        );
+
+        let text = "zażółć 🚀 hello";
+        let mut ids = [0; 64];
+        let n = tokenizer_encode_w(wide(text).as_ptr(), ids.as_mut_ptr(), ids.len());
+        assert!(n > 0);
+
+        let needed = tokenizer_decode_w(ids.as_ptr(), n as usize, 1, std::ptr::null_mut(), 0);
+        assert_eq!(needed as usize, text.encode_utf16().count());
+        let mut out = vec![0u16; needed as usize + 1];
+        let written = tokenizer_decode_w(ids.as_ptr(), n as usize, 1, out.as_mut_ptr(), out.len());
+        assert_eq!(written, needed);
+        assert_eq!(String::from_utf16(&out[..written as usize]).unwrap(), text);
+    }
+
+    #[test]
+    fn lone_surrogates_are_rejected() {
+        let _guard = serial();
+        install(&llama_json());
+
+        let text = [0x0068u16, 0xD800, 0x0069, 0];
+        let mut ids = [0; 8];
+        let rc = tokenizer_encode_w(text.as_ptr(), ids.as_mut_ptr(), ids.len());
+        assert_eq!(rc, ERR_INVALID_UTF16);
+        assert_eq!(tokenizer_initialize_w(text.as_ptr()), ERR_INVALID_UTF16);
+    }
+}
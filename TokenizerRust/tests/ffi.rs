@@ -0,0 +1,736 @@
+//! End-to-end checks of the built cdylib through its C ABI, the way the C#
+//! side sees it: every function in include/tokenizer_ffi.h must resolve, the
+//! signatures declared here must match the header, and the core calls must
+//! behave on the committed fixture.
+//!
+//! The declarations below cover every export, as bindings such as the
+//! DllImports in Services/RustTokenizer.cs see them, so changing the
+//! signature of one, or adding one, fails here until the bindings and this
+//! list are reviewed and updated. The header itself must match what the
+//! build generates from the sources.
+
+use libloading::Library;
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int, c_uchar, CStr, CString};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+const FIXTURE: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/fixtures/tiny_tokenizer.json"
+);
+const HEADER: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/include/tokenizer_ffi.h");
+
+/// The callback type of `tokenizer_set_log_callback`
+type LogCallback = Option<extern "C" fn(level: c_int, message: *const c_char)>;
+
+const ERR_NULL_POINTER: c_int = -1;
+const ERR_NOT_INITIALIZED: c_int = -3;
+const ERR_BUFFER_TOO_SMALL: c_int = -6;
+
+macro_rules! exports {
+    ($($name:ident($($arg:ident: $ty:ty),*) $(-> $ret:ty)?;)*) => {
+        // Every export is resolved at load time; only some are called below
+        #[allow(non_snake_case, dead_code)]
+        struct Api {
+            $($name: unsafe extern "C" fn($($ty),*) $(-> $ret)?,)*
+            library: Library,
+        }
+
+        impl Api {
+            fn load(library: Library) -> Api {
+                unsafe {
+                    Api {
+                        $($name: *library
+                            .get(concat!(stringify!($name), "\0").as_bytes())
+                            .expect(stringify!($name)),)*
+                        library,
+                    }
+                }
+            }
+        }
+
+        /// Name, return type and parameter types as written above
+        const DECLARED: &[(&str, &str, &[&str])] = &[
+            $((stringify!($name), concat!("" $(, stringify!($ret))?), &[$(stringify!($ty)),*]),)*
+        ];
+    };
+}
+
+exports! {
+tokenizer_initialize(path: *const c_char) -> c_int;
+    tokenizer_initialize_from_bytes(data: *const u8, len: usize) -> c_int;
+    tokenizer_encode(text: *const c_char, out_ids: *mut c_int, max_len: usize) -> c_int;
+    tokenizer_encode_opts(
+        text: *const c_char,
+        add_special_tokens: c_int,
+        out_ids: *mut c_int,
+        max_len: usize
+    ) -> c_int;
+    tokenizer_encode_v2(
+        text: *const c_char,
+        add_special_tokens: c_int,
+        out_ids: *mut c_int,
+        max_len: usize
+    ) -> c_int;
+    tokenizer_encode_with_offsets(
+        text: *const c_char,
+        out_ids: *mut c_int,
+        out_starts: *mut c_int,
+        out_ends: *mut c_int,
+        max_len: usize
+    ) -> c_int;
+    tokenizer_encode_with_offsets_u16(
+        text: *const c_char,
+        out_ids: *mut c_int,
+        out_starts: *mut c_int,
+        out_ends: *mut c_int,
+        max_len: usize
+    ) -> c_int;
+    tokenizer_encode_with_offsets_ex(
+        text: *const c_char,
+        referential: c_int,
+        out_ids: *mut c_int,
+        out_starts: *mut c_int,
+        out_ends: *mut c_int,
+        max_len: usize
+    ) -> c_int;
+    tokenizer_encode_with_word_ids(
+        text: *const c_char,
+        out_ids: *mut c_int,
+        out_word_ids: *mut c_int,
+        max_len: usize
+    ) -> c_int;
+    tokenizer_word_groups(
+        text: *const c_char,
+        out_group_starts: *mut c_int,
+        out_group_lens: *mut c_int,
+        max_groups: usize
+    ) -> c_int;
+    tokenizer_encode_with_tokens(
+        text: *const c_char,
+        out_ids: *mut c_int,
+        out_tokens_buf: *mut c_char,
+        out_token_offsets: *mut c_int,
+        buf_capacity: usize,
+        max_tokens: usize
+    ) -> c_int;
+    tokenizer_encode_pair(
+        text_a: *const c_char,
+        text_b: *const c_char,
+        out_ids: *mut c_int,
+        out_type_ids: *mut c_int,
+        max_len: usize
+    ) -> c_int;
+    tokenizer_encode_pair_opts(
+        text_a: *const c_char,
+        text_b: *const c_char,
+        add_special_tokens: c_int,
+        out_ids: *mut c_int,
+        out_type_ids: *mut c_int,
+        max_len: usize
+    ) -> c_int;
+    tokenizer_encode_u32(text: *const c_char, out_ids: *mut u32, max_len: usize) -> i64;
+    tokenizer_encode_batch(
+        texts: *const *const c_char,
+        count: usize,
+        out_ids: *mut c_int,
+        out_lengths: *mut c_int,
+        max_len_per_item: usize
+    ) -> c_int;
+    tokenizer_encode_batch_opts(
+        texts: *const *const c_char,
+        count: usize,
+        add_special_tokens: c_int,
+        out_ids: *mut c_int,
+        out_lengths: *mut c_int,
+        max_len_per_item: usize
+    ) -> c_int;
+    tokenizer_encode_batch_with_mask(
+        texts: *const *const c_char,
+        count: usize,
+        add_special_tokens: c_int,
+        out_ids: *mut c_int,
+        out_attention_mask: *mut c_int,
+        out_lengths: *mut c_int,
+        max_len_per_item: usize
+    ) -> c_int;
+    tokenizer_decode(
+        ids: *const c_int,
+        len: usize,
+        out_text: *mut c_char,
+        out_capacity: usize
+    ) -> c_int;
+    tokenizer_decode_ex(
+        ids: *const c_int,
+        len: usize,
+        skip_special_tokens: c_int,
+        out_text: *mut c_char,
+        out_capacity: usize
+    ) -> c_int;
+    tokenizer_decode_opts(
+        ids: *const c_int,
+        len: usize,
+        skip_special_tokens: c_int,
+        cleanup: c_int,
+        out_text: *mut c_char,
+        out_capacity: usize
+    ) -> c_int;
+    tokenizer_decode_u32(
+        ids: *const u32,
+        len: usize,
+        skip_special_tokens: c_int,
+        out_text: *mut c_char,
+        out_capacity: usize
+    ) -> c_int;
+    tokenizer_free();
+    tokenizer_initialize_async(path: *const c_char) -> c_int;
+    tokenizer_init_status() -> c_int;
+    tokenizer_set_wait_for_load(wait: bool);
+    tokenizer_decode_bytes(
+        ids: *const c_int,
+        len: usize,
+        out_bytes: *mut c_uchar,
+        capacity: usize
+    ) -> c_int;
+    tokenizer_set_cache_capacity(n_entries: usize);
+    tokenizer_set_cache_max_bytes(max_bytes: usize);
+    tokenizer_cache_stats(out_hits: *mut u64, out_misses: *mut u64) -> c_int;
+    tokenizer_load_chat_template(path_to_config: *const c_char) -> c_int;
+    tokenizer_apply_chat_template(
+        roles: *const *const c_char,
+        contents: *const *const c_char,
+        count: usize,
+        add_generation_prompt: c_int,
+        out_text: *mut c_char,
+        capacity: usize
+    ) -> c_int;
+    tokenizer_apply_chat_template_ids(
+        roles: *const *const c_char,
+        contents: *const *const c_char,
+        count: usize,
+        add_generation_prompt: c_int,
+        out_ids: *mut c_int,
+        max_len: usize
+    ) -> c_int;
+    tokenizer_count_chat_tokens(
+        roles: *const *const c_char,
+        contents: *const *const c_char,
+        count: usize,
+        add_generation_prompt: c_int
+    ) -> c_int;
+    tokenizer_count_chat_tokens_per_message(
+        roles: *const *const c_char,
+        contents: *const *const c_char,
+        count: usize,
+        add_generation_prompt: c_int,
+        out_counts: *mut c_int
+    ) -> c_int;
+    tokenizer_fit_chat(
+        roles: *const *const c_char,
+        contents: *const *const c_char,
+        count: usize,
+        max_tokens: usize,
+        keep_system: c_int,
+        out_keep_flags: *mut c_int
+    ) -> c_int;
+    tokenizer_encode_chunked(
+        text: *const c_char,
+        max_tokens: usize,
+        stride: usize,
+        out_ids: *mut c_int,
+        out_chunk_lengths: *mut c_int,
+        max_chunks: usize,
+        out_chunk_offsets: *mut c_int
+    ) -> c_int;
+    tokenizer_encode_chunked_ex(
+        text: *const c_char,
+        max_tokens: usize,
+        stride: usize,
+        direction: c_int,
+        out_ids: *mut c_int,
+        out_chunk_lengths: *mut c_int,
+        max_chunks: usize,
+        out_chunk_offsets: *mut c_int
+    ) -> c_int;
+    tokenizer_chunk_text(
+        text: *const c_char,
+        max_tokens: usize,
+        overlap_tokens: usize,
+        out_starts: *mut c_int,
+        out_ends: *mut c_int,
+        max_chunks: usize
+    ) -> c_int;
+    tokenizer_truncate_text(
+        text: *const c_char,
+        max_tokens: usize,
+        direction: c_int,
+        out_text: *mut c_char,
+        capacity: usize,
+        out_token_count: *mut c_int
+    ) -> c_int;
+    tokenizer_compare(
+        path_a: *const c_char,
+        path_b: *const c_char,
+        sample_texts: *const *const c_char,
+        sample_count: usize,
+        out_report_json: *mut c_char,
+        capacity: usize
+    ) -> c_int;
+    tokenizer_load_config(path: *const c_char) -> c_int;
+    tokenizer_model_max_length() -> c_int;
+    tokenizer_encode_new(text: *const c_char, add_special_tokens: c_int) -> i64;
+    encoding_len(handle: i64) -> c_int;
+    encoding_copy_ids(handle: i64, out_ids: *mut c_int, max_len: usize) -> c_int;
+    encoding_copy_offsets(
+        handle: i64,
+        out_starts: *mut c_int,
+        out_ends: *mut c_int,
+        max_len: usize
+    ) -> c_int;
+    encoding_copy_word_ids(handle: i64, out_word_ids: *mut c_int, max_len: usize) -> c_int;
+    encoding_copy_type_ids(handle: i64, out_type_ids: *mut c_int, max_len: usize) -> c_int;
+    encoding_free(handle: i64) -> c_int;
+    tokenizer_last_error(out_buf: *mut c_char, capacity: usize) -> c_int;
+    tokenizer_count_tokens(text: *const c_char, add_special_tokens: c_int) -> c_int;
+    tokenizer_estimate_tokens(text: *const c_char) -> c_int;
+    tokenizer_initialize_from_gguf(path: *const c_char) -> c_int;
+    tokenizer_set_strict_mode(enabled: c_int) -> c_int;
+    tokenizer_set_length_limit(limit: usize) -> c_int;
+    tokenizer_create(path: *const c_char) -> i64;
+    tokenizer_encode_h(
+        handle: i64,
+        text: *const c_char,
+        out_ids: *mut c_int,
+        max_len: usize
+    ) -> c_int;
+    tokenizer_decode_h(
+        handle: i64,
+        ids: *const c_int,
+        len: usize,
+        skip_special_tokens: c_int,
+        out_text: *mut c_char,
+        out_capacity: usize
+    ) -> c_int;
+    tokenizer_destroy(handle: i64) -> c_int;
+    tokenizer_prepare_healing(
+        text: *const c_char,
+        out_ids: *mut c_int,
+        max_len: usize,
+        out_suffix: *mut c_char,
+        suffix_capacity: usize
+    ) -> c_int;
+    tokenizer_healing_candidates(
+        suffix: *const c_char,
+        out_ids: *mut c_int,
+        max_out: usize
+    ) -> c_int;
+    tokenizer_get_info(out_json: *mut c_char, capacity: usize) -> c_int;
+    tokenizer_library_version(out: *mut c_char, capacity: usize) -> c_int;
+    tokenizer_normalize(text: *const c_char, out: *mut c_char, capacity: usize) -> c_int;
+    tokenizer_pretokenize(
+        text: *const c_char,
+        out_starts: *mut c_int,
+        out_ends: *mut c_int,
+        max_items: usize
+    ) -> c_int;
+    tokenizer_set_log_callback(callback: LogCallback, min_level: c_int) -> c_int;
+    tokenizer_encode_for_model(
+        text: *const c_char,
+        seq_len: usize,
+        out_ids: *mut i64,
+        out_mask: *mut i64,
+        out_type_ids: *mut i64
+    ) -> c_int;
+    tokenizer_encode_pair_for_model(
+        text_a: *const c_char,
+        text_b: *const c_char,
+        seq_len: usize,
+        out_ids: *mut i64,
+        out_mask: *mut i64,
+        out_type_ids: *mut i64
+    ) -> c_int;
+    tokenizer_register(name: *const c_char, path: *const c_char) -> c_int;
+    tokenizer_unregister(name: *const c_char) -> c_int;
+    tokenizer_encode_named(
+        name: *const c_char,
+        text: *const c_char,
+        add_special_tokens: c_int,
+        out_ids: *mut c_int,
+        max_len: usize
+    ) -> c_int;
+    tokenizer_count_tokens_named(
+        name: *const c_char,
+        text: *const c_char,
+        add_special_tokens: c_int
+    ) -> c_int;
+    tokenizer_decode_named(
+        name: *const c_char,
+        ids: *const c_int,
+        len: usize,
+        skip_special_tokens: c_int,
+        out_text: *mut c_char,
+        out_capacity: usize
+    ) -> c_int;
+    tokenizer_registered_names(out: *mut c_char, capacity: usize) -> c_int;
+    tokenizer_common_prefix_len(
+        text_a: *const c_char,
+        text_b: *const c_char,
+        add_special_tokens: c_int
+    ) -> c_int;
+    tokenizer_ids_common_prefix_len(
+        ids_a: *const c_int,
+        len_a: usize,
+        ids_b: *const c_int,
+        len_b: usize
+    ) -> c_int;
+    tokenizer_match_prefix(
+        ids: *const c_int,
+        len: usize,
+        target_text: *const c_char,
+        out_chars_matched: *mut c_int
+    ) -> c_int;
+    tokenizer_render_token(id: c_int, mode: c_int, out: *mut c_char, capacity: usize) -> c_int;
+    tokenizer_set_render_symbols(
+        space: *const c_char,
+        newline: *const c_char,
+        tab: *const c_char
+    ) -> c_int;
+    tokenizer_save(path: *const c_char, pretty: c_int) -> c_int;
+    tokenizer_set_truncation(max_length: usize, direction: c_int, stride: usize) -> c_int;
+    tokenizer_clear_truncation() -> c_int;
+    tokenizer_set_padding(length: c_int, pad_to_multiple_of: c_int, direction: c_int) -> c_int;
+    tokenizer_clear_padding() -> c_int;
+    tokenizer_bos_id() -> c_int;
+    tokenizer_eos_id() -> c_int;
+    tokenizer_pad_id() -> c_int;
+    tokenizer_get_special_token_id(kind: c_int) -> c_int;
+    tokenizer_unk_id() -> c_int;
+    tokenizer_encode_bos_eos(
+        text: *const c_char,
+        add_bos: c_int,
+        add_eos: c_int,
+        out_ids: *mut c_int,
+        max_len: usize
+    ) -> c_int;
+    tokenizer_initialize_from_spm(path: *const c_char) -> c_int;
+    tokenizer_stream_create() -> i64;
+    tokenizer_stream_create_ex(skip_special_tokens: c_int) -> i64;
+    tokenizer_stream_push(
+        stream: i64,
+        token_id: c_int,
+        out_text: *mut c_char,
+        capacity: usize
+    ) -> c_int;
+    tokenizer_stream_push_ex(
+        stream: i64,
+        token_id: c_int,
+        out_text: *mut c_char,
+        capacity: usize,
+        out_stop_index: *mut c_int,
+        out_trimmed: *mut c_int
+    ) -> c_int;
+    tokenizer_stream_set_stop_sequences(
+        stream: i64,
+        sequences: *const *const c_char,
+        count: usize
+    ) -> c_int;
+    tokenizer_stream_stop_index(stream: i64) -> c_int;
+    tokenizer_stream_flush(stream: i64, out_text: *mut c_char, capacity: usize) -> c_int;
+    tokenizer_stream_free(stream: i64) -> c_int;
+    tokenizer_set_num_threads(n: c_int) -> c_int;
+    tokenizer_initialize_tiktoken(path: *const c_char, pattern: *const c_char) -> c_int;
+    tokenizer_initialize_tiktoken_named(path: *const c_char, name: *const c_char) -> c_int;
+    tokenizer_set_invalid_utf8_policy(policy: c_int) -> c_int;
+    tokenizer_vocab_size(with_added_tokens: c_int) -> c_int;
+    tokenizer_token_to_id(token: *const c_char) -> c_int;
+    tokenizer_id_to_token(id: c_int, out: *mut c_char, capacity: usize) -> c_int;
+    tokenizer_export_vocab(path: *const c_char, format: c_int) -> c_int;
+    tokenizer_find_token_ids(
+        query: *const c_char,
+        match_mode: c_int,
+        out_ids: *mut c_int,
+        max_out: usize
+    ) -> c_int;
+    tokenizer_add_special_tokens(tokens: *const *const c_char, count: usize) -> c_int;
+    tokenizer_add_tokens(tokens: *const *const c_char, count: usize) -> c_int;
+    tokenizer_initialize_w(path: *const u16) -> c_int;
+    tokenizer_encode_w(text: *const u16, out_ids: *mut c_int, max_len: usize) -> c_int;
+    tokenizer_decode_w(
+        ids: *const c_int,
+        len: usize,
+        skip_special_tokens: c_int,
+        out_text: *mut u16,
+        out_capacity: usize
+    ) -> c_int;
+}
+
+/// C spelling of the Rust types used in `exports!`
+fn c_type(rust: &str) -> &'static str {
+    match rust.replace(' ', "").as_str() {
+        "" => "void",
+        "bool" => "bool",
+        "c_int" => "int",
+        "usize" => "uintptr_t",
+        "i64" => "int64_t",
+        "LogCallback" => "LogCallback",
+        "*constc_char" => "const char *",
+        "*const*constc_char" => "const char *const *",
+        "*mutc_char" => "char *",
+        "*mutc_uchar" => "unsigned char *",
+        "*constc_int" => "const int *",
+        "*mutc_int" => "int *",
+        "*constu8" => "const uint8_t *",
+        "*constu16" => "const uint16_t *",
+        "*mutu16" => "uint16_t *",
+        "*constu32" => "const uint32_t *",
+        "*mutu32" => "uint32_t *",
+        "*muti64" => "int64_t *",
+        "*mutu64" => "uint64_t *",
+        other => panic!("no C spelling for `{other}`"),
+    }
+}
+
+/// Every function prototype in the header: name to return and parameter types
+fn header_prototypes() -> HashMap<String, (String, Vec<String>)> {
+    let header = std::fs::read_to_string(HEADER).expect("the header is committed");
+    let code: String = header
+        .lines()
+        .filter(|line| {
+            !["//", "/*", "#", "extern", "}"]
+                .iter()
+                .any(|p| line.starts_with(p))
+        })
+        .map(|line| format!("{} ", line.trim()))
+        .collect();
+
+    let mut prototypes = HashMap::new();
+    for declaration in code.split(';') {
+        let declaration = declaration.trim();
+        let Some((head, params)) = declaration.split_once('(') else {
+            continue;
+        };
+        if head.starts_with("typedef") {
+            continue;
+        }
+        let (ret, name) = head.rsplit_once(' ').expect("return type and name");
+        let params = params.trim_end_matches(')');
+        let params = match params {
+            "void" => Vec::new(),
+            params => params
+                .split(',')
+                .map(|param| {
+                    // Drop the parameter name, keep the type
+                    let param = param.trim();
+                    let end = param.rfind([' ', '*']).unwrap();
+                    param[..=end].trim().to_owned()
+                })
+                .collect(),
+        };
+        prototypes.insert(name.to_owned(), (ret.trim().to_owned(), params));
+    }
+    prototypes
+}
+
+fn library_path() -> PathBuf {
+    // `cargo test` builds the cdylib into target/<profile>/deps next to this
+    // test; `cargo build` also copies it one level up
+    let exe = std::env::current_exe().unwrap();
+    let name = libloading::library_filename("hf_tokenizer");
+    exe.ancestors()
+        .skip(1)
+        .take(2)
+        .map(|dir| dir.join(&name))
+        .find(|path| path.exists())
+        .unwrap_or_else(|| panic!("{} was not built", name.to_string_lossy()))
+}
+
+fn api() -> &'static Api {
+    static API: OnceLock<Api> = OnceLock::new();
+    API.get_or_init(|| {
+        let path = library_path();
+        let library = unsafe { Library::new(&path) }
+            .unwrap_or_else(|e| panic!("failed to load {}: {e}", path.display()));
+        Api::load(library)
+    })
+}
+
+#[test]
+fn every_header_function_is_exported() {
+    let api = api();
+    let prototypes = header_prototypes();
+    assert!(prototypes.len() > 50, "header looks truncated");
+
+    for name in prototypes.keys() {
+        let symbol = format!("{name}\0");
+        let found = unsafe { api.library.get::<unsafe extern "C" fn()>(symbol.as_bytes()) };
+        assert!(found.is_ok(), "`{name}` is in the header but not exported");
+    }
+}
+
+#[test]
+fn committed_header_is_up_to_date() {
+    let generated = include_str!(concat!(env!("OUT_DIR"), "/tokenizer_ffi.h"));
+    let committed = std::fs::read_to_string(HEADER).expect("the header is committed");
+    assert!(
+        generated == committed,
+        "include/tokenizer_ffi.h is out of date; rebuild with UPDATE_FFI_HEADER=1 and \
+         review the diff"
+    );
+}
+
+#[test]
+fn declarations_match_the_header() {
+    let prototypes = header_prototypes();
+    let mut undeclared: Vec<&String> = prototypes
+        .keys()
+        .filter(|name| DECLARED.iter().all(|(declared, _, _)| declared != name))
+        .collect();
+    undeclared.sort();
+    assert!(
+        undeclared.is_empty(),
+        "declare {undeclared:?} in `exports!`"
+    );
+    for (name, ret, params) in DECLARED {
+        let (header_ret, header_params) = prototypes
+            .get(*name)
+            .unwrap_or_else(|| panic!("`{name}` is not in the header"));
+        let params: Vec<&str> = params.iter().map(|p| c_type(p)).collect();
+        assert_eq!(
+            (header_ret.as_str(), header_params),
+            (c_type(ret), &params.iter().map(|p| p.to_string()).collect()),
+            "signature of `{name}` changed"
+        );
+    }
+}
+
+fn encode(api: &Api, text: &str, add_special_tokens: c_int) -> Vec<c_int> {
+    let text = CString::new(text).unwrap();
+    let mut ids = vec![0; 64];
+    let n = unsafe {
+        (api.tokenizer_encode_opts)(
+            text.as_ptr(),
+            add_special_tokens,
+            ids.as_mut_ptr(),
+            ids.len(),
+        )
+    };
+    assert!(n >= 0, "encode failed with {n}");
+    ids.truncate(n as usize);
+    ids
+}
+
+fn decode(api: &Api, ids: &[c_int]) -> String {
+    let mut out = vec![0u8; 128];
+    let n = unsafe {
+        (api.tokenizer_decode)(
+            ids.as_ptr(),
+            ids.len(),
+            out.as_mut_ptr() as *mut c_char,
+            out.len(),
+        )
+    };
+    assert!(n >= 0, "decode failed with {n}");
+    CStr::from_bytes_until_nul(&out)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_owned()
+}
+
+fn last_error(api: &Api) -> String {
+    let mut out = vec![0u8; 512];
+    let n = unsafe { (api.tokenizer_last_error)(out.as_mut_ptr() as *mut c_char, out.len()) };
+    assert!(n >= 0);
+    CStr::from_bytes_until_nul(&out)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_owned()
+}
+
+/// One test, since the library keeps a single process-wide tokenizer
+#[test]
+fn lifecycle_on_the_fixture() {
+    let api = api();
+    let text = CString::new("hello world").unwrap();
+    let mut ids = [0; 16];
+    let mut out = [0u8; 64];
+    let out_text = out.as_mut_ptr() as *mut c_char;
+
+    unsafe {
+        // Before anything is loaded
+        assert_eq!(
+            (api.tokenizer_encode)(text.as_ptr(), ids.as_mut_ptr(), ids.len()),
+            ERR_NOT_INITIALIZED
+        );
+        assert_eq!(
+            (api.tokenizer_decode)(ids.as_ptr(), 1, out_text, out.len()),
+            ERR_NOT_INITIALIZED
+        );
+        assert_eq!((api.tokenizer_vocab_size)(0), ERR_NOT_INITIALIZED);
+        assert!(!last_error(api).is_empty());
+        assert!((api.tokenizer_library_version)(out_text, out.len()) > 0);
+
+        let path = CString::new(FIXTURE).unwrap();
+        assert_eq!(
+            (api.tokenizer_initialize)(std::ptr::null()),
+            ERR_NULL_POINTER
+        );
+        assert_eq!((api.tokenizer_initialize)(path.as_ptr()), 0);
+        assert_eq!((api.tokenizer_vocab_size)(1), 19);
+
+        // <s> ▁hel lo ▁wor ld
+        assert_eq!(encode(api, "hello world", 1), [1, 13, 14, 17, 18]);
+        assert_eq!(encode(api, "hello world", 0), [13, 14, 17, 18]);
+        assert_eq!(encode(api, "held", 0), [13, 4]);
+        assert_eq!(encode(api, "xyz", 0), [3, 0]);
+        assert_eq!(decode(api, &encode(api, "hello world", 1)), "hello world");
+        assert_eq!(decode(api, &[11, 8, 15, 8, 9, 7, 4]), "ho world");
+        assert_eq!((api.tokenizer_count_tokens)(text.as_ptr(), 1), 5);
+
+        // Size query, then a buffer that is too small
+        let needed = (api.tokenizer_encode_v2)(text.as_ptr(), 1, std::ptr::null_mut(), 0);
+        assert_eq!(needed, 5);
+        let ids_4 = ids.as_mut_ptr();
+        assert_eq!((api.tokenizer_encode_v2)(text.as_ptr(), 1, ids_4, 4), 5);
+        let rc = (api.tokenizer_decode)([13, 14].as_ptr(), 2, out_text, 3);
+        assert_eq!(rc, ERR_BUFFER_TOO_SMALL);
+
+        let piece = CString::new("▁wor").unwrap();
+        assert_eq!((api.tokenizer_token_to_id)(piece.as_ptr()), 17);
+        assert_eq!((api.tokenizer_id_to_token)(17, out_text, out.len()), 6);
+        assert_eq!(CStr::from_ptr(out_text).to_str().unwrap(), "▁wor");
+
+        // Null pointers
+        assert_eq!(
+            (api.tokenizer_encode)(std::ptr::null(), ids.as_mut_ptr(), ids.len()),
+            ERR_NULL_POINTER
+        );
+        assert_eq!(
+            (api.tokenizer_encode)(text.as_ptr(), std::ptr::null_mut(), 4),
+            ERR_NULL_POINTER
+        );
+        assert_eq!(
+            (api.tokenizer_decode)(std::ptr::null(), 2, out_text, out.len()),
+            ERR_NULL_POINTER
+        );
+        assert_eq!(
+            (api.tokenizer_initialize_from_bytes)(std::ptr::null(), 0),
+            ERR_NULL_POINTER
+        );
+
+        // Reloading from memory, then freeing
+        let json = std::fs::read(FIXTURE).unwrap();
+        assert_eq!(
+            (api.tokenizer_initialize_from_bytes)(json.as_ptr(), json.len()),
+            0
+        );
+        assert_eq!(encode(api, "hello", 1), [1, 13, 14]);
+        (api.tokenizer_free)();
+        assert_eq!(
+            (api.tokenizer_count_tokens)(text.as_ptr(), 1),
+            ERR_NOT_INITIALIZED
+        );
+    }
+}